@@ -1,4 +0,0 @@
-pub fn assembler(data: Vec<u8>) -> Vec<u8> {
-    // TODO: assembler duh
-    return data
-}
\ No newline at end of file
@@ -0,0 +1,700 @@
+use super::expression::{Expr, Value, Symbols};
+use super::parser::{Operand, OperandKind, Register, Memory, Size, Distance, PREFIXES};
+
+// arithmetic instructions, the index is the code of the instruction in its group
+const ARITHMETIC: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFTS: [(&str, u8); 8] = [("rol", 0), ("ror", 1), ("rcl", 2), ("rcr", 3),
+    ("shl", 4), ("sal", 4), ("shr", 5), ("sar", 7)];
+const GROUP3: [(&str, u8); 6] = [("not", 2), ("neg", 3), ("mul", 4), ("imul", 5), ("div", 6), ("idiv", 7)];
+const CONDITIONAL_JUMPS: [(&str, u8); 30] = [
+    ("jo", 0x0), ("jno", 0x1), ("jb", 0x2), ("jc", 0x2), ("jnae", 0x2),
+    ("jnb", 0x3), ("jae", 0x3), ("jnc", 0x3), ("je", 0x4), ("jz", 0x4),
+    ("jne", 0x5), ("jnz", 0x5), ("jbe", 0x6), ("jna", 0x6), ("ja", 0x7),
+    ("jnbe", 0x7), ("js", 0x8), ("jns", 0x9), ("jp", 0xA), ("jpe", 0xA),
+    ("jnp", 0xB), ("jpo", 0xB), ("jl", 0xC), ("jnge", 0xC), ("jge", 0xD),
+    ("jnl", 0xD), ("jle", 0xE), ("jng", 0xE), ("jg", 0xF), ("jnle", 0xF),
+];
+// instructions with a short relative jump only
+const SHORT_JUMPS: [(&str, u8); 6] = [("loopne", 0xE0), ("loopnz", 0xE0), ("loope", 0xE1),
+    ("loopz", 0xE1), ("loop", 0xE2), ("jcxz", 0xE3)];
+// instructions without operands
+const SIMPLE: [(&str, u8); 49] = [
+    ("nop", 0x90), ("pushf", 0x9C), ("popf", 0x9D), ("sahf", 0x9E), ("lahf", 0x9F),
+    ("cbw", 0x98), ("cwd", 0x99), ("hlt", 0xF4), ("cmc", 0xF5), ("clc", 0xF8),
+    ("stc", 0xF9), ("cli", 0xFA), ("sti", 0xFB), ("cld", 0xFC), ("std", 0xFD),
+    ("aaa", 0x37), ("aas", 0x3F), ("daa", 0x27), ("das", 0x2F), ("xlat", 0xD7),
+    ("xlatb", 0xD7), ("wait", 0x9B), ("fwait", 0x9B), ("into", 0xCE), ("int3", 0xCC),
+    ("int1", 0xF1), ("icebp", 0xF1), ("pusha", 0x60), ("popa", 0x61), ("leave", 0xC9),
+    ("iret", 0xCF), ("iretw", 0xCF), ("salc", 0xD6),
+    ("movsb", 0xA4), ("movsw", 0xA5), ("cmpsb", 0xA6), ("cmpsw", 0xA7),
+    ("stosb", 0xAA), ("stosw", 0xAB), ("lodsb", 0xAC), ("lodsw", 0xAD),
+    ("scasb", 0xAE), ("scasw", 0xAF), ("insb", 0x6C), ("insw", 0x6D),
+    ("outsb", 0x6E), ("outsw", 0x6F), ("pushaw", 0x60), ("popaw", 0x61),
+];
+const OTHERS: [&str; 19] = ["mov", "test", "xchg", "inc", "dec", "push", "pop", "jmp", "call",
+    "ret", "retn", "retf", "int", "in", "out", "lea", "lds", "les", "enter"];
+
+const INVALID: &str = "invalid combination of opcode and operands";
+
+/**
+ * Returns true if the name is a known mnemonic.
+ */
+pub fn is_mnemonic(name: &str) -> bool {
+    ARITHMETIC.contains(&name) || OTHERS.contains(&name) || name == "aam" || name == "aad"
+        || SHIFTS.iter().any(|(n, _)| *n == name)
+        || GROUP3.iter().any(|(n, _)| *n == name)
+        || CONDITIONAL_JUMPS.iter().any(|(n, _)| *n == name)
+        || SHORT_JUMPS.iter().any(|(n, _)| *n == name)
+        || SIMPLE.iter().any(|(n, _)| *n == name)
+}
+
+fn find(table: &[(&str, u8)], name: &str) -> Option<u8> {
+    table.iter().find(|(n, _)| *n == name).map(|(_, code)| *code)
+}
+
+/**
+ * Everything the encoder needs to know about where the instruction is.
+ */
+pub struct Context<'a> {
+    pub symbols: &'a dyn Symbols,
+    // address of the first byte of the instruction
+    pub address: i64,
+    // on the last pass every symbol must be defined
+    pub final_pass: bool,
+}
+
+/**
+ * Returns true if a word value can be encoded as a sign extended byte.
+ */
+fn fits_sbyte(value: i64) -> bool {
+    let word = value & 0xFFFF;
+    word <= 0x7F || word >= 0xFF80
+}
+
+fn little_endian(value: i64, size: usize) -> Vec<u8> {
+    (0..size).map(|i| (value >> (i * 8)) as u8).collect()
+}
+
+struct Encoder<'a> {
+    ctx: &'a Context<'a>,
+    // amount of prefix bytes before the opcode
+    prefix_len: usize,
+}
+
+impl<'a> Encoder<'a> {
+    /**
+     * Evaluates an expression, on the last pass unknown symbols are an error.
+     */
+    fn value(&self, expr: &Expr) -> Result<Value, String> {
+        let value = expr.eval(self.ctx.symbols)?;
+        if self.ctx.final_pass && value == Value::Unknown {
+            return Err(expr.undefined_error(self.ctx.symbols))
+        }
+        Ok(value)
+    }
+
+    /**
+     * Returns the bytes of an immediate value.
+     */
+    fn immediate(&self, expr: &Expr, size: usize) -> Result<Vec<u8>, String> {
+        Ok(match self.value(expr)? {
+            Value::Known(val, _) => little_endian(val, size),
+            Value::Unknown => vec![0; size],
+        })
+    }
+
+    /**
+     * Returns the ModR/M byte and the displacement of an operand.
+     * @param operand: the register or memory operand of the r/m field.
+     * @param reg: value of the reg field.
+     */
+    fn modrm(&self, operand: &Operand, reg: u8) -> Result<Vec<u8>, String> {
+        let memory = match &operand.kind {
+            OperandKind::Register(Register::Word(r)) | OperandKind::Register(Register::Byte(r)) => {
+                return Ok(vec![0xC0 | reg << 3 | r])
+            },
+            OperandKind::Memory(memory) => memory,
+            _ => return Err(INVALID.to_string()),
+        };
+        let rm = match (memory.base, memory.index) {
+            (Some(3), Some(6)) => 0,
+            (Some(3), Some(7)) => 1,
+            (Some(5), Some(6)) => 2,
+            (Some(5), Some(7)) => 3,
+            (None, Some(6)) => 4,
+            (None, Some(7)) => 5,
+            (Some(5), None) => 6,
+            (Some(3), None) => 7,
+            (None, None) => {
+                // direct address
+                let mut bytes = vec![0x06 | reg << 3];
+                bytes.extend(self.immediate(memory.displacement.as_ref().unwrap(), 2)?);
+                return Ok(bytes)
+            },
+            _ => return Err("invalid effective address".to_string()),
+        };
+        let value = match &memory.displacement {
+            Some(expr) => self.value(expr)?,
+            None => Value::Known(0, 0),
+        };
+        let displacement = match value {
+            Value::Known(val, _) => (val & 0xFFFF) as u16 as i16,
+            Value::Unknown => 0,
+        };
+        // [bp] has no encoding without a displacement
+        if value.is_simple() && displacement == 0 && rm != 6 {
+            Ok(vec![rm | reg << 3])
+        } else if value.is_simple() && displacement >= -128 && displacement <= 127 {
+            Ok(vec![0x40 | rm | reg << 3, displacement as u8])
+        } else {
+            let mut bytes = vec![0x80 | rm | reg << 3];
+            bytes.extend(little_endian(displacement as i64, 2));
+            Ok(bytes)
+        }
+    }
+
+    /**
+     * Returns the size of the operation in bytes, taken from the registers and
+     * the size keywords of the operands.
+     */
+    fn size(&self, operands: &[&Operand]) -> Result<usize, String> {
+        let mut size = None;
+        for operand in operands {
+            let operand_size = match (&operand.kind, operand.size) {
+                (OperandKind::Register(Register::Byte(_)), _) => Some(1),
+                (OperandKind::Register(_), _) => Some(2),
+                (_, Some(Size::Byte)) => Some(1),
+                (_, Some(Size::Word)) => Some(2),
+                (_, Some(Size::Dword)) => Some(4),
+                _ => None,
+            };
+            match (size, operand_size) {
+                (Some(a), Some(b)) if a != b => return Err("mismatch in operand sizes".to_string()),
+                (None, Some(b)) => size = Some(b),
+                _ => (),
+            }
+        }
+        match size {
+            Some(4) => Err("32 bit operands are not supported".to_string()),
+            Some(size) => Ok(size),
+            None => Err("operation size not specified".to_string()),
+        }
+    }
+
+    /**
+     * Returns the offset of a relative jump from the end of the instruction.
+     * @param target: the expression of the target.
+     * @param len: length of the instruction without prefixes.
+     */
+    fn relative(&self, target: &Expr, len: usize) -> Result<Value, String> {
+        let end = self.ctx.address + (self.prefix_len + len) as i64;
+        Ok(match self.value(target)? {
+            Value::Known(val, _) => Value::Known(val - end, 0),
+            Value::Unknown => Value::Unknown,
+        })
+    }
+
+    /**
+     * Encodes a short jump, which can't be changed in to a near one.
+     */
+    fn short_jump(&self, opcode: Vec<u8>, target: &Expr) -> Result<Vec<u8>, String> {
+        let len = opcode.len() + 1;
+        let mut bytes = opcode;
+        match self.relative(target, len)? {
+            Value::Known(offset, _) => {
+                if self.ctx.final_pass && (offset < -128 || offset > 127) {
+                    return Err("short jump is out of range".to_string())
+                }
+                bytes.push(offset as u8);
+            },
+            Value::Unknown => bytes.push(0),
+        }
+        Ok(bytes)
+    }
+
+    /**
+     * Encodes a near jump with a 16 bit offset.
+     */
+    fn near_jump(&self, opcode: Vec<u8>, target: &Expr) -> Result<Vec<u8>, String> {
+        let len = opcode.len() + 2;
+        let mut bytes = opcode;
+        match self.relative(target, len)? {
+            Value::Known(offset, _) => bytes.extend(little_endian(offset, 2)),
+            Value::Unknown => bytes.extend([0, 0]),
+        }
+        Ok(bytes)
+    }
+
+    /**
+     * Returns true if a jump without a distance keyword can be short.
+     * Like nasm, forward references are assumed to be short until known.
+     */
+    fn can_be_short(&self, target: &Expr, len: usize) -> Result<bool, String> {
+        Ok(match self.relative(target, len)? {
+            Value::Known(offset, _) => offset >= -128 && offset <= 127,
+            Value::Unknown => true,
+        })
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Operand]) -> Result<Vec<u8>, String> {
+        use OperandKind::*;
+        use super::parser::Register as Reg;
+        let ops: Vec<&OperandKind> = operands.iter().map(|op| &op.kind).collect();
+
+        // instructions without operands
+        if let Some(opcode) = find(&SIMPLE, mnemonic) {
+            if !operands.is_empty() {
+                return Err(INVALID.to_string())
+            }
+            return Ok(vec![opcode])
+        }
+        if PREFIXES.contains(&mnemonic) && operands.is_empty() {
+            return Ok(vec![match mnemonic {
+                "lock" => 0xF0,
+                "repne" | "repnz" => 0xF2,
+                _ => 0xF3,
+            }])
+        }
+
+        //# ADD OR ADC SBB AND SUB XOR CMP
+        if let Some(code) = ARITHMETIC.iter().position(|n| *n == mnemonic) {
+            let code = code as u8;
+            let base = code * 8;
+            return match ops.as_slice() {
+                [Register(Reg::Segment(_)), _] | [_, Register(Reg::Segment(_))] => Err(INVALID.to_string()),
+                [Register(_) | Memory(_), Register(src)] => {
+                    let word = self.size(&[&operands[0], &operands[1]])? == 2;
+                    let mut bytes = vec![base + word as u8];
+                    bytes.extend(self.modrm(&operands[0], register_code(src))?);
+                    Ok(bytes)
+                },
+                [Register(dst), Memory(_)] => {
+                    let word = self.size(&[&operands[0], &operands[1]])? == 2;
+                    let mut bytes = vec![base + 2 + word as u8];
+                    bytes.extend(self.modrm(&operands[1], register_code(dst))?);
+                    Ok(bytes)
+                },
+                [Register(_) | Memory(_), Immediate(expr)] => {
+                    let word = self.size(&[&operands[0]])? == 2;
+                    let value = self.value(expr)?;
+                    let accumulator = matches!(ops[0], Register(Reg::Word(0)) | Register(Reg::Byte(0)));
+                    let mut bytes = Vec::new();
+                    if !word {
+                        if accumulator {
+                            bytes.push(base + 4);
+                        } else {
+                            bytes.push(0x80);
+                            bytes.extend(self.modrm(&operands[0], code)?);
+                        }
+                        bytes.extend(self.immediate(expr, 1)?);
+                    } else if value.is_simple() && fits_sbyte(match value { Value::Known(v, _) => v, _ => 0 }) {
+                        bytes.push(0x83);
+                        bytes.extend(self.modrm(&operands[0], code)?);
+                        bytes.extend(self.immediate(expr, 1)?);
+                    } else {
+                        if accumulator {
+                            bytes.push(base + 5);
+                        } else {
+                            bytes.push(0x81);
+                            bytes.extend(self.modrm(&operands[0], code)?);
+                        }
+                        bytes.extend(self.immediate(expr, 2)?);
+                    }
+                    Ok(bytes)
+                },
+                _ => Err(INVALID.to_string()),
+            }
+        }
+
+        //# NOT NEG MUL IMUL DIV IDIV
+        if let Some(code) = find(&GROUP3, mnemonic) {
+            return match ops.as_slice() {
+                [Register(Reg::Word(_)) | Register(Reg::Byte(_)) | Memory(_)] => {
+                    let word = self.size(&[&operands[0]])? == 2;
+                    let mut bytes = vec![0xF6 + word as u8];
+                    bytes.extend(self.modrm(&operands[0], code)?);
+                    Ok(bytes)
+                },
+                _ => Err(INVALID.to_string()),
+            }
+        }
+
+        //# ROL ROR RCL RCR SHL SAL SHR SAR
+        if let Some(code) = find(&SHIFTS, mnemonic) {
+            return match ops.as_slice() {
+                [Register(Reg::Word(_)) | Register(Reg::Byte(_)) | Memory(_), count] => {
+                    let word = self.size(&[&operands[0]])? == 2;
+                    match count {
+                        Register(Reg::Byte(1)) => {
+                            let mut bytes = vec![0xD2 + word as u8];
+                            bytes.extend(self.modrm(&operands[0], code)?);
+                            Ok(bytes)
+                        },
+                        Immediate(expr) => {
+                            let value = self.value(expr)?;
+                            if value.is_simple() && value == Value::Known(1, 0) {
+                                let mut bytes = vec![0xD0 + word as u8];
+                                bytes.extend(self.modrm(&operands[0], code)?);
+                                Ok(bytes)
+                            } else {
+                                let mut bytes = vec![0xC0 + word as u8];
+                                bytes.extend(self.modrm(&operands[0], code)?);
+                                bytes.extend(self.immediate(expr, 1)?);
+                                Ok(bytes)
+                            }
+                        },
+                        _ => Err(INVALID.to_string()),
+                    }
+                },
+                _ => Err(INVALID.to_string()),
+            }
+        }
+
+        //# Jcc
+        if let Some(condition) = find(&CONDITIONAL_JUMPS, mnemonic) {
+            let target = match ops.as_slice() {
+                [Immediate(expr)] => expr,
+                _ => return Err(INVALID.to_string()),
+            };
+            return match operands[0].distance {
+                Some(Distance::Short) => self.short_jump(vec![0x70 + condition], target),
+                Some(Distance::Near) => self.near_jump(vec![0x0F, 0x80 + condition], target),
+                Some(Distance::Far) => Err(INVALID.to_string()),
+                None => if self.can_be_short(target, 2)? {
+                    self.short_jump(vec![0x70 + condition], target)
+                } else {
+                    self.near_jump(vec![0x0F, 0x80 + condition], target)
+                },
+            }
+        }
+
+        //# LOOP LOOPZ LOOPNZ JCXZ
+        if let Some(opcode) = find(&SHORT_JUMPS, mnemonic) {
+            return match ops.as_slice() {
+                [Immediate(expr)] => self.short_jump(vec![opcode], expr),
+                _ => Err(INVALID.to_string()),
+            }
+        }
+
+        match mnemonic {
+            //# MOV
+            "mov" => match ops.as_slice() {
+                // accumulator and a direct address
+                [Register(reg), Memory(memory)] | [Memory(memory), Register(reg)]
+                    if is_accumulator(reg) && is_direct(memory) => {
+                    let word = self.size(&[&operands[0], &operands[1]])? == 2;
+                    let to_memory = matches!(ops[0], Memory(_));
+                    let mut bytes = vec![0xA0 + (to_memory as u8) * 2 + word as u8];
+                    bytes.extend(self.immediate(memory.displacement.as_ref().unwrap(), 2)?);
+                    Ok(bytes)
+                },
+                [Register(Reg::Word(_)) | Memory(_), Register(Reg::Segment(seg))] => {
+                    self.size(&[&operands[0], &operands[1]])?;
+                    let mut bytes = vec![0x8C];
+                    bytes.extend(self.modrm(&operands[0], *seg)?);
+                    Ok(bytes)
+                },
+                [Register(Reg::Segment(seg)), Register(Reg::Word(_)) | Memory(_)] => {
+                    self.size(&[&operands[0], &operands[1]])?;
+                    let mut bytes = vec![0x8E];
+                    bytes.extend(self.modrm(&operands[1], *seg)?);
+                    Ok(bytes)
+                },
+                [Register(Reg::Segment(_)), _] | [_, Register(Reg::Segment(_))] => Err(INVALID.to_string()),
+                [Register(_) | Memory(_), Register(src)] => {
+                    let word = self.size(&[&operands[0], &operands[1]])? == 2;
+                    let mut bytes = vec![0x88 + word as u8];
+                    bytes.extend(self.modrm(&operands[0], register_code(src))?);
+                    Ok(bytes)
+                },
+                [Register(dst), Memory(_)] => {
+                    let word = self.size(&[&operands[0], &operands[1]])? == 2;
+                    let mut bytes = vec![0x8A + word as u8];
+                    bytes.extend(self.modrm(&operands[1], register_code(dst))?);
+                    Ok(bytes)
+                },
+                [Register(Reg::Byte(reg)), Immediate(expr)] => {
+                    let mut bytes = vec![0xB0 + reg];
+                    bytes.extend(self.immediate(expr, 1)?);
+                    Ok(bytes)
+                },
+                [Register(Reg::Word(reg)), Immediate(expr)] => {
+                    let mut bytes = vec![0xB8 + reg];
+                    bytes.extend(self.immediate(expr, 2)?);
+                    Ok(bytes)
+                },
+                [Memory(_), Immediate(expr)] => {
+                    let size = self.size(&[&operands[0]])?;
+                    let mut bytes = vec![0xC6 + (size == 2) as u8];
+                    bytes.extend(self.modrm(&operands[0], 0)?);
+                    bytes.extend(self.immediate(expr, size)?);
+                    Ok(bytes)
+                },
+                _ => Err(INVALID.to_string()),
+            },
+
+            //# TEST
+            "test" => match ops.as_slice() {
+                [Register(Reg::Segment(_)), _] | [_, Register(Reg::Segment(_))] => Err(INVALID.to_string()),
+                [Register(_) | Memory(_), Register(reg)] | [Register(reg), Memory(_)] => {
+                    let word = self.size(&[&operands[0], &operands[1]])? == 2;
+                    let rm = if matches!(ops[1], Register(_)) {&operands[0]} else {&operands[1]};
+                    let mut bytes = vec![0x84 + word as u8];
+                    bytes.extend(self.modrm(rm, register_code(reg))?);
+                    Ok(bytes)
+                },
+                [Register(_) | Memory(_), Immediate(expr)] => {
+                    let size = self.size(&[&operands[0]])?;
+                    let mut bytes = if let Register(reg) = ops[0] {
+                        if is_accumulator(reg) {vec![0xA8 + (size == 2) as u8]} else {Vec::new()}
+                    } else {Vec::new()};
+                    if bytes.is_empty() {
+                        bytes.push(0xF6 + (size == 2) as u8);
+                        bytes.extend(self.modrm(&operands[0], 0)?);
+                    }
+                    bytes.extend(self.immediate(expr, size)?);
+                    Ok(bytes)
+                },
+                _ => Err(INVALID.to_string()),
+            },
+
+            //# XCHG
+            "xchg" => match ops.as_slice() {
+                [Register(Reg::Word(0)), Register(Reg::Word(reg))]
+                | [Register(Reg::Word(reg)), Register(Reg::Word(0))] => Ok(vec![0x90 + reg]),
+                [Register(Reg::Segment(_)), _] | [_, Register(Reg::Segment(_))] => Err(INVALID.to_string()),
+                [Register(reg), Register(_) | Memory(_)] => {
+                    let word = self.size(&[&operands[0], &operands[1]])? == 2;
+                    let mut bytes = vec![0x86 + word as u8];
+                    bytes.extend(self.modrm(&operands[1], register_code(reg))?);
+                    Ok(bytes)
+                },
+                [Memory(_), Register(reg)] => {
+                    let word = self.size(&[&operands[0], &operands[1]])? == 2;
+                    let mut bytes = vec![0x86 + word as u8];
+                    bytes.extend(self.modrm(&operands[0], register_code(reg))?);
+                    Ok(bytes)
+                },
+                _ => Err(INVALID.to_string()),
+            },
+
+            //# INC DEC
+            "inc" | "dec" => {
+                let code = (mnemonic == "dec") as u8;
+                match ops.as_slice() {
+                    [Register(Reg::Word(reg))] => Ok(vec![0x40 + code * 8 + reg]),
+                    [Register(Reg::Byte(_)) | Memory(_)] => {
+                        let word = self.size(&[&operands[0]])? == 2;
+                        let mut bytes = vec![0xFE + word as u8];
+                        bytes.extend(self.modrm(&operands[0], code)?);
+                        Ok(bytes)
+                    },
+                    _ => Err(INVALID.to_string()),
+                }
+            },
+
+            //# PUSH POP
+            "push" | "pop" => {
+                let push = mnemonic == "push";
+                match ops.as_slice() {
+                    [Register(Reg::Word(reg))] => Ok(vec![if push {0x50} else {0x58} + reg]),
+                    [Register(Reg::Segment(seg))] => {
+                        if !push && *seg == 1 {
+                            return Err("`pop cs' is not valid".to_string())
+                        }
+                        Ok(vec![0x06 + seg * 8 + !push as u8])
+                    },
+                    [Memory(_)] => {
+                        if operands[0].size.is_some() {
+                            self.size(&[&operands[0]]).and_then(|size| if size == 2 {Ok(())}
+                                else {Err(INVALID.to_string())})?;
+                        }
+                        let mut bytes = vec![if push {0xFF} else {0x8F}];
+                        bytes.extend(self.modrm(&operands[0], if push {6} else {0})?);
+                        Ok(bytes)
+                    },
+                    [Immediate(expr)] if push => {
+                        let value = self.value(expr)?;
+                        let short = value.is_simple() && fits_sbyte(match value { Value::Known(v, _) => v, _ => 0 });
+                        if operands[0].size == Some(Size::Byte) || (operands[0].size.is_none() && short) {
+                            let mut bytes = vec![0x6A];
+                            bytes.extend(self.immediate(expr, 1)?);
+                            Ok(bytes)
+                        } else {
+                            let mut bytes = vec![0x68];
+                            bytes.extend(self.immediate(expr, 2)?);
+                            Ok(bytes)
+                        }
+                    },
+                    _ => Err(INVALID.to_string()),
+                }
+            },
+
+            //# JMP CALL
+            "jmp" | "call" => {
+                let call = mnemonic == "call";
+                match ops.as_slice() {
+                    [FarPointer(segment, offset)] => {
+                        let mut bytes = vec![if call {0x9A} else {0xEA}];
+                        bytes.extend(self.immediate(offset, 2)?);
+                        bytes.extend(self.immediate(segment, 2)?);
+                        Ok(bytes)
+                    },
+                    [Immediate(target)] => match (operands[0].distance, call) {
+                        (Some(Distance::Far), _) => Err("far jumps need a segment:offset target".to_string()),
+                        (Some(Distance::Short), true) => Err(INVALID.to_string()),
+                        (_, true) => self.near_jump(vec![0xE8], target),
+                        (Some(Distance::Short), false) => self.short_jump(vec![0xEB], target),
+                        (Some(Distance::Near), false) => self.near_jump(vec![0xE9], target),
+                        (None, false) => if self.can_be_short(target, 2)? {
+                            self.short_jump(vec![0xEB], target)
+                        } else {
+                            self.near_jump(vec![0xE9], target)
+                        },
+                    },
+                    [Register(Reg::Word(_)) | Memory(_)] => {
+                        let far = operands[0].distance == Some(Distance::Far);
+                        if far && matches!(ops[0], Register(_)) {
+                            return Err(INVALID.to_string())
+                        }
+                        let code = match (call, far) {
+                            (true, false) => 2,
+                            (true, true) => 3,
+                            (false, false) => 4,
+                            (false, true) => 5,
+                        };
+                        let mut bytes = vec![0xFF];
+                        bytes.extend(self.modrm(&operands[0], code)?);
+                        Ok(bytes)
+                    },
+                    _ => Err(INVALID.to_string()),
+                }
+            },
+
+            //# RET RETN RETF
+            "ret" | "retn" | "retf" => {
+                let far = mnemonic == "retf";
+                match ops.as_slice() {
+                    [] => Ok(vec![if far {0xCB} else {0xC3}]),
+                    [Immediate(expr)] => {
+                        let mut bytes = vec![if far {0xCA} else {0xC2}];
+                        bytes.extend(self.immediate(expr, 2)?);
+                        Ok(bytes)
+                    },
+                    _ => Err(INVALID.to_string()),
+                }
+            },
+
+            //# INT
+            "int" => match ops.as_slice() {
+                [Immediate(expr)] => {
+                    let mut bytes = vec![0xCD];
+                    bytes.extend(self.immediate(expr, 1)?);
+                    Ok(bytes)
+                },
+                _ => Err(INVALID.to_string()),
+            },
+
+            //# IN OUT
+            "in" | "out" => {
+                let out = mnemonic == "out";
+                let (accumulator, port) = if out {(ops.get(1), ops.get(0))} else {(ops.get(0), ops.get(1))};
+                let word = match accumulator {
+                    Some(Register(Reg::Byte(0))) => 0,
+                    Some(Register(Reg::Word(0))) => 1,
+                    _ => return Err(INVALID.to_string()),
+                };
+                match port {
+                    Some(Register(Reg::Word(2))) => Ok(vec![0xEC + out as u8 * 2 + word]),
+                    Some(Immediate(expr)) => {
+                        let mut bytes = vec![0xE4 + out as u8 * 2 + word];
+                        bytes.extend(self.immediate(expr, 1)?);
+                        Ok(bytes)
+                    },
+                    _ => Err(INVALID.to_string()),
+                }
+            },
+
+            //# LEA LDS LES
+            "lea" | "lds" | "les" => match ops.as_slice() {
+                [Register(Reg::Word(reg)), Memory(_)] => {
+                    let mut bytes = vec![match mnemonic {"lea" => 0x8D, "lds" => 0xC5, _ => 0xC4}];
+                    bytes.extend(self.modrm(&operands[1], *reg)?);
+                    Ok(bytes)
+                },
+                _ => Err(INVALID.to_string()),
+            },
+
+            //# AAM AAD
+            "aam" | "aad" => {
+                let opcode = if mnemonic == "aam" {0xD4} else {0xD5};
+                match ops.as_slice() {
+                    [] => Ok(vec![opcode, 0x0A]),
+                    [Immediate(expr)] => {
+                        let mut bytes = vec![opcode];
+                        bytes.extend(self.immediate(expr, 1)?);
+                        Ok(bytes)
+                    },
+                    _ => Err(INVALID.to_string()),
+                }
+            },
+
+            //# ENTER
+            "enter" => match ops.as_slice() {
+                [Immediate(size), Immediate(level)] => {
+                    let mut bytes = vec![0xC8];
+                    bytes.extend(self.immediate(size, 2)?);
+                    bytes.extend(self.immediate(level, 1)?);
+                    Ok(bytes)
+                },
+                _ => Err(INVALID.to_string()),
+            },
+            _ => Err(format!("unknown instruction `{}'", mnemonic)),
+        }
+    }
+}
+
+fn register_code(register: &Register) -> u8 {
+    match register {
+        Register::Word(code) | Register::Byte(code) | Register::Segment(code) => *code,
+    }
+}
+
+fn is_accumulator(register: &Register) -> bool {
+    matches!(register, Register::Word(0) | Register::Byte(0))
+}
+
+fn is_direct(memory: &Memory) -> bool {
+    memory.base.is_none() && memory.index.is_none()
+}
+
+/**
+ * Encodes an instruction.
+ * @param mnemonic: the lowercase mnemonic.
+ * @param prefixes: rep and lock prefixes written before the mnemonic.
+ * @param operands: the operands of the instruction.
+ * @param ctx: where the instruction is and the values of the symbols.
+ * @return: the bytes of the instruction.
+ */
+pub fn encode(mnemonic: &str, prefixes: &[String], operands: &[Operand], ctx: &Context) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for prefix in prefixes {
+        bytes.push(match prefix.as_str() {
+            "lock" => 0xF0,
+            "repne" | "repnz" => 0xF2,
+            _ => 0xF3,
+        });
+    }
+    // segment override of a memory operand
+    for operand in operands {
+        if let OperandKind::Memory(Memory { segment: Some(segment), .. }) = &operand.kind {
+            bytes.push(0x26 + segment * 8);
+        }
+    }
+    let encoder = Encoder {
+        ctx: ctx,
+        prefix_len: bytes.len(),
+    };
+    bytes.extend(encoder.instruction(mnemonic, operands)?);
+    Ok(bytes)
+}
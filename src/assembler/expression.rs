@@ -0,0 +1,276 @@
+use super::parser::Token;
+
+/**
+ * Binary operators of expressions.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    SignedDiv,
+    Mod,
+    SignedMod,
+}
+
+impl Operator {
+    /**
+     * Returns the operator and its precedence, higher binds stronger.
+     * The precedences are the ones of nasm.
+     * @param token: the token to check.
+     */
+    fn from_token(token: &Token) -> Option<(Self, u8)> {
+        match token {
+            Token::Op(op) => match op.as_str() {
+                "|" => Some((Self::Or, 1)),
+                "^" => Some((Self::Xor, 2)),
+                "&" => Some((Self::And, 3)),
+                "<<" => Some((Self::Shl, 4)),
+                ">>" => Some((Self::Shr, 4)),
+                "+" => Some((Self::Add, 5)),
+                "-" => Some((Self::Sub, 5)),
+                "*" => Some((Self::Mul, 6)),
+                "/" => Some((Self::Div, 6)),
+                "//" => Some((Self::SignedDiv, 6)),
+                "%" => Some((Self::Mod, 6)),
+                "%%" => Some((Self::SignedMod, 6)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn symbol(&self) -> &str {
+        match self {
+            Self::Or => "|",
+            Self::Xor => "^",
+            Self::And => "&",
+            Self::Shl => "<<",
+            Self::Shr => ">>",
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::SignedDiv => "//",
+            Self::Mod => "%",
+            Self::SignedMod => "%%",
+        }
+    }
+
+    /**
+     * Applies the operator on two values with their relocation counts.
+     * Only + and - can be used on addresses.
+     */
+    fn apply(&self, left: (i64, i32), right: (i64, i32)) -> Result<Value, String> {
+        match self {
+            Self::Add => return Ok(Value::Known(left.0.wrapping_add(right.0), left.1 + right.1)),
+            Self::Sub => return Ok(Value::Known(left.0.wrapping_sub(right.0), left.1 - right.1)),
+            _ => (),
+        }
+        if left.1 != 0 || right.1 != 0 {
+            return Err(format!("`{}' operator may only be applied to scalar values", self.symbol()))
+        }
+        let (a, b) = (left.0, right.0);
+        if b == 0 && [Self::Div, Self::SignedDiv, Self::Mod, Self::SignedMod].contains(self) {
+            return Err("division by zero".to_string())
+        }
+        Ok(Value::Known(match self {
+            Self::Or => a | b,
+            Self::Xor => a ^ b,
+            Self::And => a & b,
+            Self::Shl => a.wrapping_shl(b as u32),
+            Self::Shr => ((a as u64).wrapping_shr(b as u32)) as i64,
+            Self::Mul => a.wrapping_mul(b),
+            Self::Div => ((a as u64) / (b as u64)) as i64,
+            Self::SignedDiv => a.wrapping_div(b),
+            Self::Mod => ((a as u64) % (b as u64)) as i64,
+            Self::SignedMod => a.wrapping_rem(b),
+            Self::Add | Self::Sub => unreachable!(),
+        }, 0))
+    }
+}
+
+/**
+ * A parsed expression, evaluated once the symbols are known.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    LogicalNot(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+/**
+ * The value of an expression. Unknown when it uses a symbol that isn't
+ * defined yet, which happens on the first passes with forward references.
+ * Known values count how many label addresses were added into them,
+ * nasm uses the short encodings only for values without addresses (0).
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Known(i64, i32),
+    Unknown,
+}
+
+impl Value {
+    /**
+     * Returns true if the value is a plain number and not an address.
+     */
+    pub fn is_simple(&self) -> bool {
+        match self {
+            Self::Known(_, relocations) => *relocations == 0,
+            Self::Unknown => false,
+        }
+    }
+}
+
+/**
+ * Gives the values of the symbols to the evaluated expressions.
+ */
+pub trait Symbols {
+    /**
+     * Returns the value of a symbol, or None if it isn't defined yet.
+     */
+    fn symbol(&self, name: &str) -> Option<Value>;
+}
+
+impl Expr {
+    /**
+     * Parses an expression out of tokens.
+     * @param tokens: the tokens of the expression.
+     * @return: the expression, or an error if the tokens aren't a single expression.
+     */
+    pub fn parse(tokens: &[Token]) -> Result<Self, String> {
+        if tokens.is_empty() {
+            return Err("expression expected".to_string())
+        }
+        let mut pos = 0;
+        let expr = Self::parse_binary(tokens, &mut pos, 0)?;
+        if pos != tokens.len() {
+            return Err(format!("unexpected `{}' in expression", tokens[pos]))
+        }
+        Ok(expr)
+    }
+
+    /**
+     * Precedence climbing: parses operators binding stronger than `min_precedence`.
+     */
+    fn parse_binary(tokens: &[Token], pos: &mut usize, min_precedence: u8) -> Result<Self, String> {
+        let mut left = Self::parse_unary(tokens, pos)?;
+        while *pos < tokens.len() {
+            let (op, precedence) = match Operator::from_token(&tokens[*pos]) {
+                Some(op) if op.1 > min_precedence => op,
+                _ => break,
+            };
+            *pos += 1;
+            let right = Self::parse_binary(tokens, pos, precedence)?;
+            left = Self::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(tokens: &[Token], pos: &mut usize) -> Result<Self, String> {
+        let token = match tokens.get(*pos) {
+            Some(token) => token,
+            None => return Err("expression expected".to_string()),
+        };
+        *pos += 1;
+        match token {
+            Token::Number(val) => Ok(Self::Number(*val)),
+            Token::Str(bytes) => {
+                // a string is a little endian number, like nasm does
+                if bytes.len() > 8 {
+                    return Err("character constant too long".to_string())
+                }
+                let mut val: i64 = 0;
+                for (i, byte) in bytes.iter().enumerate() {
+                    val |= (*byte as i64) << (i * 8);
+                }
+                Ok(Self::Number(val))
+            },
+            Token::Ident(name) => Ok(Self::Symbol(name.clone())),
+            Token::Op(op) if op == "-" => Ok(Self::Negate(Box::new(Self::parse_unary(tokens, pos)?))),
+            Token::Op(op) if op == "+" => Self::parse_unary(tokens, pos),
+            Token::Op(op) if op == "~" => Ok(Self::Not(Box::new(Self::parse_unary(tokens, pos)?))),
+            Token::Op(op) if op == "!" => Ok(Self::LogicalNot(Box::new(Self::parse_unary(tokens, pos)?))),
+            Token::Op(op) if op == "(" => {
+                let expr = Self::parse_binary(tokens, pos, 0)?;
+                match tokens.get(*pos) {
+                    Some(Token::Op(op)) if op == ")" => {
+                        *pos += 1;
+                        Ok(expr)
+                    },
+                    _ => Err("expecting `)'".to_string()),
+                }
+            },
+            _ => Err(format!("unexpected `{}' in expression", token)),
+        }
+    }
+
+    /**
+     * Evaluates the expression.
+     * @param symbols: the values of the symbols.
+     * @return: the value, Unknown if a symbol isn't defined yet.
+     */
+    pub fn eval(&self, symbols: &dyn Symbols) -> Result<Value, String> {
+        Ok(match self {
+            Self::Number(val) => Value::Known(*val, 0),
+            Self::Symbol(name) => match symbols.symbol(name) {
+                Some(val) => val,
+                None => Value::Unknown,
+            },
+            Self::Negate(expr) => match expr.eval(symbols)? {
+                Value::Known(val, relocations) => Value::Known(val.wrapping_neg(), -relocations),
+                Value::Unknown => Value::Unknown,
+            },
+            Self::Not(expr) | Self::LogicalNot(expr) => match expr.eval(symbols)? {
+                Value::Known(val, 0) => match self {
+                    Self::Not(_) => Value::Known(!val, 0),
+                    _ => Value::Known((val == 0) as i64, 0),
+                },
+                Value::Known(..) => return Err("unary operator may only be applied to scalar values".to_string()),
+                Value::Unknown => Value::Unknown,
+            },
+            Self::Binary(op, left, right) => {
+                match (left.eval(symbols)?, right.eval(symbols)?) {
+                    (Value::Known(left, left_rel), Value::Known(right, right_rel)) => {
+                        op.apply((left, left_rel), (right, right_rel))?
+                    },
+                    _ => Value::Unknown,
+                }
+            },
+        })
+    }
+
+    /**
+     * Returns the message for an expression which couldn't be evaluated on the last pass.
+     */
+    pub fn undefined_error(&self, symbols: &dyn Symbols) -> String {
+        match self.undefined_symbol(symbols) {
+            Some(name) => format!("symbol `{}' not defined", name),
+            None => "expression is not constant".to_string(),
+        }
+    }
+
+    /**
+     * Returns the first symbol of the expression which isn't defined.
+     */
+    fn undefined_symbol(&self, symbols: &dyn Symbols) -> Option<String> {
+        match self {
+            Self::Symbol(name) if symbols.symbol(name).is_none() => Some(name.clone()),
+            Self::Negate(expr) | Self::Not(expr) | Self::LogicalNot(expr) => expr.undefined_symbol(symbols),
+            Self::Binary(_, left, right) => left.undefined_symbol(symbols)
+                .or_else(|| right.undefined_symbol(symbols)),
+            _ => None,
+        }
+    }
+}
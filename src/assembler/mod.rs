@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+
+use crate::program_loader::Diagnostic;
+use crate::debug_info::{DebugInfo, ListingLine, Symbol};
+use self::expression::{Expr, Value, Symbols};
+use self::parser::{Body, DataItem, Parser, Statement};
+use self::preprocessor::Preprocessor;
+use self::encoder::Context;
+
+pub mod expression;
+mod parser;
+mod encoder;
+mod preprocessor;
+
+// sections have to settle before this many passes, like nasm's phase errors
+const MAX_PASSES: usize = 32;
+// alignment of the sections following .text in the output
+const SECTION_ALIGN: i64 = 4;
+const TEXT_SECTION: &str = ".text";
+// sections which take no space in the output
+const NOBITS_SECTIONS: [&str; 1] = [".bss"];
+
+/**
+ * The symbols defined by the previous pass, and the address of the line.
+ */
+struct Labels<'a> {
+    labels: &'a HashMap<String, Value>,
+    // `$`
+    here: i64,
+    // `$$`
+    section_start: i64,
+}
+
+impl<'a> Symbols for Labels<'a> {
+    fn symbol(&self, name: &str) -> Option<Value> {
+        // addresses count as one relocation
+        match name {
+            "$" => Some(Value::Known(self.here, 1)),
+            "$$" => Some(Value::Known(self.section_start, 1)),
+            _ => self.labels.get(name).copied(),
+        }
+    }
+}
+
+/**
+ * Everything a pass finds out about the layout of the program.
+ */
+#[derive(Clone, PartialEq)]
+struct Layout {
+    origin: i64,
+    // value of each label and equ
+    labels: HashMap<String, Value>,
+    // start address of each section
+    starts: HashMap<String, i64>,
+}
+
+/**
+ * The result of a pass.
+ */
+struct Pass {
+    layout: Layout,
+    // bytes of each section, in the order the sections appear in the output
+    sections: Vec<(String, Vec<u8>)>,
+    // index of the statement and the message
+    errors: Vec<(usize, String)>,
+    // section of each statement, with the offset and length of its bytes in it
+    placement: Vec<(String, usize, usize)>,
+}
+
+/**
+ * Returns the names of the sections in the order of the output:
+ * .text first, then the others the way they appear, nobits sections last.
+ */
+fn section_order(statements: &[Statement]) -> Vec<String> {
+    let mut order = vec![TEXT_SECTION.to_string()];
+    for statement in statements {
+        if let Body::Section(name) = &statement.body {
+            if !order.contains(name) {
+                order.push(name.clone());
+            }
+        }
+    }
+    let (mut progbits, nobits): (Vec<String>, Vec<String>) = order.into_iter()
+        .partition(|name| !NOBITS_SECTIONS.contains(&name.as_str()));
+    progbits.extend(nobits);
+    progbits
+}
+
+/**
+ * Returns the bytes of a data declaration.
+ */
+fn data_bytes(size: usize, items: &[DataItem], symbols: &dyn Symbols, final_pass: bool) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for item in items {
+        match item {
+            DataItem::Bytes(string) => {
+                bytes.extend(string);
+                // strings are padded to whole items
+                while bytes.len() % size != 0 {
+                    bytes.push(0);
+                }
+            },
+            DataItem::Value(expr) => match expr.eval(symbols)? {
+                Value::Known(val, _) => bytes.extend((0..size).map(|i| (val >> (i * 8)) as u8)),
+                Value::Unknown => {
+                    if final_pass {
+                        return Err(expr.undefined_error(symbols))
+                    }
+                    bytes.extend(vec![0; size]);
+                },
+            },
+        }
+    }
+    Ok(bytes)
+}
+
+/**
+ * Returns the bytes of a statement.
+ * @param body: what the statement does.
+ * @param symbols: the symbols, with the address of the statement.
+ * @param layout: the layout being found by the pass, org and equ change it.
+ * @param label: the label of the statement.
+ * @param final_pass: on the final pass every symbol must be known.
+ */
+fn assemble_body(body: &Body, symbols: &Labels, layout: &mut Layout, label: &Option<String>, final_pass: bool) -> Result<Vec<u8>, String> {
+    // the count of times and resb, and the alignment of align
+    let count = |expr: &Expr| -> Result<i64, String> {
+        match expr.eval(symbols)? {
+            Value::Known(val, 0) => Ok(val),
+            Value::Known(..) => Err("expression is not a simple value".to_string()),
+            Value::Unknown if final_pass => Err(expr.undefined_error(symbols)),
+            Value::Unknown => Ok(0),
+        }
+    };
+    Ok(match body {
+        Body::Empty | Body::Section(_) => Vec::new(),
+        Body::Org(expr) => match expr.eval(symbols)? {
+            Value::Known(val, 0) => {
+                layout.origin = val;
+                Vec::new()
+            },
+            _ => return Err("program origin must be a constant".to_string()),
+        },
+        Body::Equ(expr) => {
+            match expr.eval(symbols)? {
+                Value::Unknown if final_pass => return Err(expr.undefined_error(symbols)),
+                Value::Unknown => (),
+                value => {
+                    layout.labels.insert(label.clone().unwrap(), value);
+                },
+            }
+            Vec::new()
+        },
+        Body::Data(size, items) => data_bytes(*size, items, symbols, final_pass)?,
+        Body::Reserve(size, expr) => match count(expr)? {
+            val if val < 0 => return Err(format!("invalid reserve count {}", val)),
+            val => vec![0; val as usize * size],
+        },
+        Body::Align(expr, fill) => match count(expr)? {
+            0 if !final_pass => Vec::new(),
+            val if val <= 0 || val & (val - 1) != 0 => return Err("alignment must be a power of two".to_string()),
+            val => {
+                // aligned from the start of the section, like nasm
+                let offset = symbols.here - symbols.section_start;
+                vec![*fill; ((val - offset % val) % val) as usize]
+            },
+        },
+        Body::Times(expr, body) => {
+            let count = match count(expr)? {
+                val if val < 0 => return Err(format!("TIMES value {} is negative", val)),
+                val => val,
+            };
+            // each repetition is assembled at its own address
+            let mut bytes = Vec::new();
+            for _ in 0..count {
+                let symbols = Labels {
+                    labels: symbols.labels,
+                    here: symbols.here + bytes.len() as i64,
+                    section_start: symbols.section_start,
+                };
+                bytes.extend(assemble_body(body, &symbols, layout, label, final_pass)?);
+            }
+            bytes
+        },
+        Body::Instruction(mnemonic, prefixes, operands) => {
+            let ctx = Context {
+                symbols: symbols,
+                address: symbols.here,
+                final_pass: final_pass,
+            };
+            encoder::encode(mnemonic, prefixes, operands, &ctx)?
+        },
+    })
+}
+
+/**
+ * Runs a pass over the program, using the layout found by the previous pass.
+ * @param statements: the parsed program.
+ * @param order: the sections in the order of the output.
+ * @param previous: the layout of the previous pass.
+ * @param final_pass: on the final pass every symbol must be known.
+ */
+fn run_pass(statements: &[Statement], order: &[String], previous: &Layout, final_pass: bool) -> Pass {
+    let mut layout = Layout {
+        origin: previous.origin,
+        labels: HashMap::new(),
+        starts: HashMap::new(),
+    };
+    let mut sections: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut errors = Vec::new();
+    let mut placement = Vec::new();
+    let mut section = TEXT_SECTION.to_string();
+
+    for (i, statement) in statements.iter().enumerate() {
+        if let Body::Section(name) = &statement.body {
+            section = name.clone();
+        }
+        let start = previous.starts.get(&section).copied().unwrap_or(previous.origin);
+        let bytes = sections.entry(section.clone()).or_insert_with(Vec::new);
+        let symbols = Labels {
+            labels: &previous.labels,
+            here: start + bytes.len() as i64,
+            section_start: start,
+        };
+        match (&statement.label, &statement.body) {
+            (Some(label), body) if !matches!(body, Body::Equ(_)) => {
+                layout.labels.insert(label.clone(), Value::Known(symbols.here, 1));
+            },
+            _ => (),
+        }
+        let offset = bytes.len();
+        match assemble_body(&statement.body, &symbols, &mut layout, &statement.label, final_pass) {
+            Ok(res) => bytes.extend(res),
+            Err(msg) => errors.push((i, msg)),
+        }
+        placement.push((section.clone(), offset, bytes.len() - offset));
+    }
+
+    // place the sections one after the other
+    let mut address = layout.origin;
+    let mut output = Vec::new();
+    for (i, name) in order.iter().enumerate() {
+        if i > 0 {
+            address = (address + SECTION_ALIGN - 1) / SECTION_ALIGN * SECTION_ALIGN;
+        }
+        layout.starts.insert(name.clone(), address);
+        let bytes = sections.remove(name).unwrap_or_default();
+        address += bytes.len() as i64;
+        output.push((name.clone(), bytes));
+    }
+    Pass {
+        layout: layout,
+        sections: output,
+        errors: errors,
+        placement: placement,
+    }
+}
+
+/**
+ * Assembles a program into a flat binary, the way `nasm -f bin` does.
+ * Passes are repeated until the addresses of the labels settle,
+ * so jumps to labels further down are short whenever they can be.
+ * @param source: the text of the program.
+ * @param file_name: the name of the file, used in the messages.
+ * @return: the assembled program with its listing and labels, or the errors found in it.
+ */
+pub fn assembler(source: &str, file_name: &str) -> Result<(Vec<u8>, DebugInfo), Vec<Diagnostic>> {
+    let lines = Preprocessor::new().run(source, file_name)?;
+    let diagnostic = |index: usize, message: String| Diagnostic {
+        file: lines[index].file.clone(),
+        line: lines[index].line,
+        message: format!("error: {}", message),
+    };
+
+    // parse
+    let mut parser = Parser::new();
+    let mut statements = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut defined: HashMap<String, usize> = HashMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        match parser.parse_line(&line.text, line.line) {
+            Ok(statement) => {
+                if let Some(label) = &statement.label {
+                    if let Some(first) = defined.insert(label.clone(), i) {
+                        diagnostics.push(diagnostic(i, format!("label `{}' inconsistently redefined (first defined on {}:{})",
+                            label, lines[first].file, lines[first].line)));
+                    }
+                }
+                statements.push(statement);
+            },
+            Err(msg) => diagnostics.push(diagnostic(i, msg)),
+        }
+    }
+    if !diagnostics.is_empty() {
+        return Err(diagnostics)
+    }
+
+    // repeat the passes until the layout doesn't change
+    let order = section_order(&statements);
+    let mut layout = Layout {
+        origin: 0,
+        labels: HashMap::new(),
+        starts: HashMap::new(),
+    };
+    for _ in 0..MAX_PASSES {
+        let pass = run_pass(&statements, &order, &layout, false);
+        if pass.layout == layout {
+            break
+        }
+        layout = pass.layout;
+    }
+
+    let pass = run_pass(&statements, &order, &layout, true);
+    if !pass.errors.is_empty() {
+        return Err(pass.errors.into_iter().map(|(i, msg)| diagnostic(i, msg)).collect())
+    }
+    if pass.layout != layout {
+        return Err(vec![Diagnostic {
+            file: file_name.to_string(),
+            line: 0,
+            message: "error: phase error: label addresses never settled".to_string(),
+        }])
+    }
+
+    // the listing and the labels, without the labels made by macros
+    let listing = statements.iter().zip(&pass.placement).zip(&lines)
+        .map(|((statement, (section, offset, len)), line)| {
+            let data = &pass.sections.iter().find(|(name, _)| name == section).unwrap().1;
+            let bytes = if NOBITS_SECTIONS.contains(&section.as_str()) {
+                Vec::new()
+            } else {
+                data[*offset..offset + len].to_vec()
+            };
+            ListingLine {
+                address: (pass.layout.starts[section] + *offset as i64) as u16,
+                bytes: bytes,
+                file: line.file.clone(),
+                line: statement.line,
+                source: line.text.trim().to_string(),
+            }
+        })
+        .collect();
+    let symbols = pass.layout.labels.iter()
+        .filter(|(name, value)| !name.starts_with("..@") && matches!(value, Value::Known(_, 1)))
+        .map(|(name, value)| Symbol {
+            name: name.clone(),
+            address: match value {
+                Value::Known(address, _) => *address as u16,
+                Value::Unknown => 0,
+            },
+        })
+        .collect();
+    let debug_info = DebugInfo::new(pass.layout.origin as u16, listing, symbols);
+
+    // the sections in the file, padded to their alignment
+    let mut output = Vec::new();
+    for (name, bytes) in pass.sections {
+        if NOBITS_SECTIONS.contains(&name.as_str()) || bytes.is_empty() {
+            continue
+        }
+        let start = (pass.layout.starts[&name] - pass.layout.origin) as usize;
+        output.resize(start, 0);
+        output.extend(bytes);
+    }
+    Ok((output, debug_info))
+}
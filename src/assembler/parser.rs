@@ -0,0 +1,624 @@
+use std::fmt;
+
+use crate::cpu::helperModules::string_to_number;
+use super::expression::Expr;
+
+// operators made of two characters, checked before the single ones
+const LONG_OPERATORS: [&str; 4] = ["<<", ">>", "//", "%%"];
+const OPERATORS: &str = "+-*/%|&^~!()[],:";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Op(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ident(name) => write!(f, "{}", name),
+            Self::Number(val) => write!(f, "{}", val),
+            Self::Str(bytes) => write!(f, "'{}'", String::from_utf8_lossy(bytes)),
+            Self::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+impl Token {
+    fn is_op(&self, op: &str) -> bool {
+        match self {
+            Self::Op(val) => val == op,
+            _ => false,
+        }
+    }
+
+    /**
+     * Returns the lowercase name of an identifier token.
+     */
+    fn keyword(&self) -> Option<String> {
+        match self {
+            Self::Ident(name) => Some(name.to_lowercase()),
+            _ => None,
+        }
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || "_.?@".contains(c)
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.?@$#~".contains(c)
+}
+
+/**
+ * Splits a line of source into tokens, stopping at a comment.
+ * @param line: the line to split.
+ * @return: the tokens of the line.
+ */
+pub fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ';' {
+            break
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue
+        }
+        // strings
+        if c == '\'' || c == '"' || c == '`' {
+            let mut bytes = Vec::new();
+            i += 1;
+            loop {
+                if i >= chars.len() {
+                    return Err("unterminated string".to_string())
+                }
+                if chars[i] == c {
+                    i += 1;
+                    break
+                }
+                // backquoted strings support escapes
+                if c == '`' && chars[i] == '\\' && i + 1 < chars.len() {
+                    i += 1;
+                    bytes.push(match chars[i] {
+                        'n' => b'\n',
+                        'r' => b'\r',
+                        't' => b'\t',
+                        '0' => 0,
+                        'e' => 0x1B,
+                        other => other as u8,
+                    });
+                    i += 1;
+                    continue
+                }
+                let mut buffer = [0u8; 4];
+                bytes.extend_from_slice(chars[i].encode_utf8(&mut buffer).as_bytes());
+                i += 1;
+            }
+            tokens.push(Token::Str(bytes));
+            continue
+        }
+        // `$` is the address of the line and `$$` the start of the section
+        if c == '$' && !(i + 1 < chars.len() && (chars[i + 1].is_ascii_digit() || is_ident_start(chars[i + 1]))) {
+            if i + 1 < chars.len() && chars[i + 1] == '$' {
+                tokens.push(Token::Ident("$$".to_string()));
+                i += 2;
+            } else {
+                tokens.push(Token::Ident("$".to_string()));
+                i += 1;
+            }
+            continue
+        }
+        // numbers
+        if c.is_ascii_digit() || (c == '$' && i + 1 < chars.len() && chars[i + 1].is_ascii_digit()) {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            // `$` prefix is an old way to write hex numbers
+            let number = if text.starts_with('$') {
+                string_to_number(&format!("0x{}", &text[1..]))
+            } else {
+                string_to_number(&text)
+            };
+            match number {
+                Ok(val) => tokens.push(Token::Number(val as i64)),
+                Err(_) => return Err(format!("invalid number `{}'", text)),
+            }
+            continue
+        }
+        // identifiers, `$` escapes an identifier that looks like a keyword
+        if is_ident_start(c) || (c == '$' && i + 1 < chars.len() && is_ident_start(chars[i + 1])) {
+            let start = if c == '$' {i + 1} else {i};
+            i = start + 1;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            continue
+        }
+        // operators
+        let rest: String = chars[i..].iter().take(2).collect();
+        if let Some(op) = LONG_OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op.to_string()));
+            i += op.len();
+            continue
+        }
+        if OPERATORS.contains(c) {
+            tokens.push(Token::Op(c.to_string()));
+            i += 1;
+            continue
+        }
+        return Err(format!("unexpected character `{}'", c))
+    }
+    Ok(tokens)
+}
+
+/**
+ * Size given to an operand with a size keyword.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Size {
+    Byte,
+    Word,
+    Dword,
+}
+
+/**
+ * Distance given to a jump with a distance keyword.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distance {
+    Short,
+    Near,
+    Far,
+}
+
+// 16 bit and 8 bit registers share the codes of `helperModules::Registers`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    Word(u8),
+    Byte(u8),
+    Segment(u8),
+}
+
+impl Register {
+    pub fn from(name: &str) -> Option<Self> {
+        let words = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di"];
+        let bytes = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+        let segments = ["es", "cs", "ss", "ds"];
+        let name = name.to_lowercase();
+        if let Some(i) = words.iter().position(|r| *r == name) {
+            return Some(Self::Word(i as u8))
+        }
+        if let Some(i) = bytes.iter().position(|r| *r == name) {
+            return Some(Self::Byte(i as u8))
+        }
+        if let Some(i) = segments.iter().position(|r| *r == name) {
+            return Some(Self::Segment(i as u8))
+        }
+        None
+    }
+}
+
+/**
+ * A memory operand `[base + index + displacement]`.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Memory {
+    pub segment: Option<u8>,
+    pub base: Option<u8>,
+    pub index: Option<u8>,
+    pub displacement: Option<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OperandKind {
+    Register(Register),
+    Immediate(Expr),
+    Memory(Memory),
+    // segment:offset of far jumps and calls
+    FarPointer(Expr, Expr),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operand {
+    pub kind: OperandKind,
+    pub size: Option<Size>,
+    pub distance: Option<Distance>,
+}
+
+/**
+ * A single item of db/dw/dd.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum DataItem {
+    Value(Expr),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Body {
+    Empty,
+    // mnemonic, prefixes and operands
+    Instruction(String, Vec<String>, Vec<Operand>),
+    // size of each item and the items
+    Data(usize, Vec<DataItem>),
+    // size of each item and the amount of items
+    Reserve(usize, Expr),
+    // the label of the line gets the value
+    Equ(Expr),
+    // the body repeated a number of times
+    Times(Expr, Box<Body>),
+    // the alignment and the byte used for the padding
+    Align(Expr, u8),
+    Org(Expr),
+    Section(String),
+}
+
+/**
+ * A parsed line of the source.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub line: usize,
+    pub label: Option<String>,
+    pub body: Body,
+}
+
+pub const PREFIXES: [&str; 6] = ["rep", "repe", "repz", "repne", "repnz", "lock"];
+
+/**
+ * Returns true if the name is a mnemonic or a directive, and so can't start
+ * a label which is written without a colon.
+ */
+fn is_keyword(name: &str) -> bool {
+    let name = name.to_lowercase();
+    super::encoder::is_mnemonic(&name) || PREFIXES.contains(&name.as_str())
+        || ["db", "dw", "dd", "resb", "resw", "resd", "equ", "times", "align", "alignb", "org",
+            "section", "segment", "bits", "use16", "cpu", "global", "extern"].contains(&name.as_str())
+}
+
+/**
+ * Parses the lines of a program.
+ */
+pub struct Parser {
+    // last label that isn't local, the prefix of local labels
+    global_label: String,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            global_label: String::new(),
+        }
+    }
+
+    /**
+     * Turns a local label (starting with '.') into a full name.
+     */
+    fn qualify(&self, name: &str) -> String {
+        if name.starts_with('.') && !name.starts_with("..") {
+            format!("{}{}", self.global_label, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /**
+     * Qualifies the local labels in a list of tokens.
+     */
+    fn qualify_tokens(&self, tokens: &[Token]) -> Vec<Token> {
+        tokens.iter().map(|token| match token {
+            Token::Ident(name) => Token::Ident(self.qualify(name)),
+            other => other.clone(),
+        }).collect()
+    }
+
+    /**
+     * Parses a single line of source.
+     * @param line: the text of the line.
+     * @param number: the number of the line in its file.
+     * @return: the statement of the line.
+     */
+    pub fn parse_line(&mut self, line: &str, number: usize) -> Result<Statement, String> {
+        let tokens = tokenize(line)?;
+        let mut statement = Statement {
+            line: number,
+            label: None,
+            body: Body::Empty,
+        };
+        if tokens.is_empty() {
+            return Ok(statement)
+        }
+        let mut tokens = &tokens[..];
+
+        // directives written in brackets, like [org 100h]
+        if tokens[0].is_op("[") && tokens.last().unwrap().is_op("]") && tokens.len() > 2 {
+            tokens = &tokens[1..tokens.len() - 1];
+        }
+
+        // label
+        if let Token::Ident(name) = &tokens[0] {
+            let colon = tokens.len() > 1 && tokens[1].is_op(":");
+            if colon || !is_keyword(name) {
+                if !name.starts_with('.') {
+                    self.global_label = name.clone();
+                }
+                statement.label = Some(self.qualify(name));
+                tokens = &tokens[if colon {2} else {1}..];
+            }
+        }
+        if tokens.is_empty() {
+            return Ok(statement)
+        }
+
+        let keyword = match tokens[0].keyword() {
+            Some(keyword) => keyword,
+            None => return Err("label or instruction expected".to_string()),
+        };
+        let args = self.qualify_tokens(&tokens[1..]);
+        if keyword == "equ" && statement.label.is_none() {
+            return Err("`equ' without a label".to_string())
+        }
+        statement.body = parse_body(keyword, &args)?;
+        Ok(statement)
+    }
+}
+
+/**
+ * Parses what follows the label of a line.
+ * @param keyword: the lowercase mnemonic or directive.
+ * @param args: the tokens after the keyword, with qualified local labels.
+ */
+fn parse_body(keyword: String, args: &[Token]) -> Result<Body, String> {
+    Ok(match keyword.as_str() {
+        "db" => Body::Data(1, parse_data(args)?),
+        "dw" => Body::Data(2, parse_data(args)?),
+        "dd" => Body::Data(4, parse_data(args)?),
+        "resb" => Body::Reserve(1, Expr::parse(args)?),
+        "resw" => Body::Reserve(2, Expr::parse(args)?),
+        "resd" => Body::Reserve(4, Expr::parse(args)?),
+        "equ" => Body::Equ(Expr::parse(args)?),
+        // code is aligned with nops, data with zeros
+        "align" => Body::Align(Expr::parse(args)?, 0x90),
+        "alignb" => Body::Align(Expr::parse(args)?, 0),
+        "times" => {
+            // the count ends where the repeated instruction starts
+            let start = args.iter()
+                .position(|token| token.keyword().map_or(false, |name| is_keyword(&name)));
+            let start = match start {
+                Some(start) if start > 0 => start,
+                _ => return Err("instruction expected after `times'".to_string()),
+            };
+            let body = parse_body(args[start].keyword().unwrap(), &args[start + 1..])?;
+            Body::Times(Expr::parse(&args[..start])?, Box::new(body))
+        },
+        "org" => Body::Org(Expr::parse(args)?),
+        "section" | "segment" => match args.first() {
+            Some(Token::Ident(name)) => Body::Section(name.clone()),
+            _ => return Err("section name expected".to_string()),
+        },
+        "bits" => match args {
+            [Token::Number(16)] => Body::Empty,
+            _ => return Err("only 16 bit code is supported".to_string()),
+        },
+        "use16" | "cpu" | "global" | "extern" => Body::Empty,
+        _ => {
+            // prefixes come before the mnemonic
+            let mut prefixes = Vec::new();
+            let mut keyword = keyword;
+            let mut rest = args;
+            while PREFIXES.contains(&keyword.as_str()) {
+                prefixes.push(keyword.clone());
+                match rest.first().and_then(|token| token.keyword()) {
+                    Some(next) => {
+                        keyword = next;
+                        rest = &rest[1..];
+                    },
+                    None => break,
+                }
+            }
+            if PREFIXES.contains(&keyword.as_str()) {
+                // a prefix alone on a line
+                Body::Instruction(prefixes.pop().unwrap(), prefixes, Vec::new())
+            } else if super::encoder::is_mnemonic(&keyword) {
+                Body::Instruction(keyword, prefixes, parse_operands(rest)?)
+            } else {
+                return Err(format!("unknown instruction `{}'", keyword))
+            }
+        }
+    })
+}
+
+/**
+ * Splits tokens on the commas which aren't in parentheses or brackets.
+ */
+pub fn split_commas(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.is_op("(") || token.is_op("[") {
+            depth += 1;
+        } else if token.is_op(")") || token.is_op("]") {
+            depth -= 1;
+        } else if token.is_op(",") && depth == 0 {
+            parts.push(&tokens[start..i]);
+            start = i + 1;
+        }
+    }
+    if start < tokens.len() || !parts.is_empty() {
+        parts.push(&tokens[start..]);
+    }
+    parts
+}
+
+fn parse_data(tokens: &[Token]) -> Result<Vec<DataItem>, String> {
+    let mut items = Vec::new();
+    for part in split_commas(tokens) {
+        match part {
+            [Token::Str(bytes)] => items.push(DataItem::Bytes(bytes.clone())),
+            _ => items.push(DataItem::Value(Expr::parse(part)?)),
+        }
+    }
+    if items.is_empty() {
+        return Err("no operand for data declaration".to_string())
+    }
+    Ok(items)
+}
+
+fn parse_operands(tokens: &[Token]) -> Result<Vec<Operand>, String> {
+    split_commas(tokens).into_iter().map(parse_operand).collect()
+}
+
+/**
+ * Parses a single operand, with its size and distance keywords.
+ */
+fn parse_operand(tokens: &[Token]) -> Result<Operand, String> {
+    let mut operand = Operand {
+        kind: OperandKind::Immediate(Expr::Number(0)),
+        size: None,
+        distance: None,
+    };
+    let mut tokens = tokens;
+    // size and distance keywords
+    while let Some(keyword) = tokens.first().and_then(|token| token.keyword()) {
+        match keyword.as_str() {
+            "byte" => operand.size = Some(Size::Byte),
+            "word" => operand.size = Some(Size::Word),
+            "dword" => operand.size = Some(Size::Dword),
+            "short" => operand.distance = Some(Distance::Short),
+            "near" => operand.distance = Some(Distance::Near),
+            "far" => operand.distance = Some(Distance::Far),
+            "strict" => (),
+            _ => break,
+        }
+        tokens = &tokens[1..];
+    }
+    if tokens.is_empty() {
+        return Err("operand expected".to_string())
+    }
+
+    // segment override written before the brackets: es:[bx]
+    let mut segment = None;
+    if tokens.len() > 2 && tokens[1].is_op(":") && tokens[2].is_op("[") {
+        match tokens[0].keyword().and_then(|name| Register::from(&name)) {
+            Some(Register::Segment(seg)) => segment = Some(seg),
+            _ => return Err("invalid segment override".to_string()),
+        }
+        tokens = &tokens[2..];
+    }
+
+    // memory
+    if tokens[0].is_op("[") {
+        if !tokens.last().unwrap().is_op("]") {
+            return Err("expecting `]'".to_string())
+        }
+        let mut memory = parse_memory(&tokens[1..tokens.len() - 1])?;
+        if segment.is_some() {
+            memory.segment = segment;
+        }
+        operand.kind = OperandKind::Memory(memory);
+        return Ok(operand)
+    }
+
+    // register
+    if tokens.len() == 1 {
+        if let Some(register) = tokens[0].keyword().and_then(|name| Register::from(&name)) {
+            operand.kind = OperandKind::Register(register);
+            return Ok(operand)
+        }
+    }
+
+    // far pointer seg:offset
+    if let Some(i) = tokens.iter().position(|token| token.is_op(":")) {
+        operand.kind = OperandKind::FarPointer(Expr::parse(&tokens[..i])?, Expr::parse(&tokens[i + 1..])?);
+        return Ok(operand)
+    }
+    operand.kind = OperandKind::Immediate(Expr::parse(tokens)?);
+    Ok(operand)
+}
+
+/**
+ * Parses what is inside the brackets of a memory operand.
+ */
+fn parse_memory(tokens: &[Token]) -> Result<Memory, String> {
+    let mut memory = Memory {
+        segment: None,
+        base: None,
+        index: None,
+        displacement: None,
+    };
+    let mut tokens = tokens;
+    // segment override inside the brackets: [es:bx]
+    if tokens.len() > 1 && tokens[1].is_op(":") {
+        match tokens[0].keyword().and_then(|name| Register::from(&name)) {
+            Some(Register::Segment(seg)) => memory.segment = Some(seg),
+            _ => return Err("invalid segment override".to_string()),
+        }
+        tokens = &tokens[2..];
+    }
+
+    // split the address into terms and take out the registers
+    let mut displacement: Vec<Token> = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for i in 0..=tokens.len() {
+        let end = i == tokens.len();
+        if !end {
+            if tokens[i].is_op("(") {
+                depth += 1;
+            } else if tokens[i].is_op(")") {
+                depth -= 1;
+            }
+        }
+        let split = end || (depth == 0 && i > start && (tokens[i].is_op("+") || tokens[i].is_op("-")));
+        if !split {
+            continue
+        }
+        let term = &tokens[start..i];
+        // the sign of the term, if any
+        let (sign, value) = match term.first() {
+            Some(token) if token.is_op("+") || token.is_op("-") => (Some(token), &term[1..]),
+            _ => (None, term),
+        };
+        let register = match value {
+            [token] => token.keyword().and_then(|name| Register::from(&name)),
+            _ => None,
+        };
+        match register {
+            Some(Register::Word(reg)) if sign.map_or(true, |s| s.is_op("+")) => {
+                match reg {
+                    3 | 5 if memory.base.is_none() => memory.base = Some(reg),
+                    6 | 7 if memory.index.is_none() => memory.index = Some(reg),
+                    _ => return Err("invalid effective address".to_string()),
+                }
+            },
+            Some(_) => return Err("invalid effective address".to_string()),
+            None => {
+                if displacement.is_empty() && sign.map_or(false, |s| s.is_op("+")) {
+                    displacement.extend_from_slice(value);
+                } else {
+                    displacement.extend_from_slice(term);
+                }
+            }
+        }
+        start = i;
+    }
+    if !displacement.is_empty() {
+        memory.displacement = Some(Expr::parse(&displacement)?);
+    }
+    if memory.base.is_none() && memory.index.is_none() && memory.displacement.is_none() {
+        return Err("invalid effective address".to_string())
+    }
+    Ok(memory)
+}
@@ -0,0 +1,480 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::program_loader::Diagnostic;
+
+// %include can't go deeper than this, it stops files including themselves
+const MAX_INCLUDE_DEPTH: usize = 32;
+// single line macros can't expand deeper than this
+const MAX_DEFINE_DEPTH: usize = 32;
+// macros can't call each other deeper than this
+const MAX_MACRO_DEPTH: usize = 64;
+
+/**
+ * A line after preprocessing, with where it came from.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: usize,
+    pub text: String,
+}
+
+/**
+ * A single line macro made by %define.
+ */
+#[derive(Clone, Debug)]
+struct Define {
+    params: Vec<String>,
+    body: String,
+}
+
+/**
+ * A multi line macro made by %macro.
+ */
+#[derive(Clone, Debug)]
+struct Macro {
+    params: usize,
+    lines: Vec<String>,
+}
+
+/**
+ * Splits a line into its first word and the rest.
+ */
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.?@$#~".contains(c)
+}
+
+/**
+ * Returns the end of the string starting at `start`, after its closing quote.
+ */
+fn string_end(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() && chars[i] != quote {
+        if quote == '`' && chars[i] == '\\' {
+            i += 1;
+        }
+        i += 1;
+    }
+    (i + 1).min(chars.len())
+}
+
+/**
+ * Splits the arguments of a macro call on the commas outside of strings,
+ * parentheses and braces. Braces are removed, they let an argument contain commas.
+ */
+fn split_args(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' | '"' | '`' => {
+                let end = string_end(&chars, i);
+                current.extend(&chars[i..end]);
+                i = end;
+                continue
+            },
+            ';' if depth == 0 => break,
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '{' if depth == 0 => {
+                let end = chars[i..].iter().position(|c| *c == '}').map_or(chars.len(), |end| i + end);
+                current.extend(&chars[i + 1..end]);
+                i = end + 1;
+                continue
+            },
+            ',' if depth == 0 => {
+                args.push(current.trim().to_string());
+                current.clear();
+                i += 1;
+                continue
+            },
+            _ => (),
+        }
+        current.push(c);
+        i += 1;
+    }
+    if !current.trim().is_empty() || !args.is_empty() {
+        args.push(current.trim().to_string());
+    }
+    args
+}
+
+/**
+ * Handles the lines starting with `%`: single and multi line macros and includes.
+ */
+pub struct Preprocessor {
+    defines: HashMap<String, Define>,
+    macros: HashMap<String, Macro>,
+    // counts the macro calls, to give each call its own %% labels
+    calls: usize,
+    diagnostics: Vec<Diagnostic>,
+    output: Vec<SourceLine>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self {
+            defines: HashMap::new(),
+            macros: HashMap::new(),
+            calls: 0,
+            diagnostics: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, file: &str, line: usize, message: String) {
+        self.diagnostics.push(Diagnostic {
+            file: file.to_string(),
+            line: line,
+            message: format!("error: {}", message),
+        });
+    }
+
+    /**
+     * Preprocesses a program.
+     * @param source: the text of the program.
+     * @param file_name: the path of the program, includes are relative to it.
+     * @return: the lines to assemble, or the errors found.
+     */
+    pub fn run(mut self, source: &str, file_name: &str) -> Result<Vec<SourceLine>, Vec<Diagnostic>> {
+        self.file(source, file_name, 0);
+        if self.diagnostics.is_empty() {
+            Ok(self.output)
+        } else {
+            Err(self.diagnostics)
+        }
+    }
+
+    /**
+     * Preprocesses the lines of a file.
+     * @param depth: how many includes deep the file is.
+     */
+    fn file(&mut self, source: &str, file_name: &str, depth: usize) {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut i = 0;
+        while i < lines.len() {
+            let number = i + 1;
+            let (word, rest) = split_word(lines[i]);
+            i += 1;
+            match word.to_lowercase().as_str() {
+                "%macro" | "%imacro" => {
+                    // the body ends at %endmacro
+                    let start = i;
+                    while i < lines.len() && !split_word(lines[i]).0.eq_ignore_ascii_case("%endmacro") {
+                        i += 1;
+                    }
+                    if i == lines.len() {
+                        self.error(file_name, number, "`%macro' without `%endmacro'".to_string());
+                        return
+                    }
+                    let body = lines[start..i].iter().map(|line| line.to_string()).collect();
+                    i += 1;
+                    self.define_macro(rest, body, file_name, number);
+                },
+                "%include" => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        self.error(file_name, number, "%include nested too deeply".to_string());
+                        continue
+                    }
+                    let name = rest.split(';').next().unwrap_or("").trim().trim_matches(|c| c == '"' || c == '\'' || c == '<' || c == '>');
+                    // relative to the including file, then to the working directory
+                    let relative = Path::new(file_name).parent().map(|dir| dir.join(name));
+                    let path = match relative {
+                        Some(path) if path.exists() => path,
+                        _ => Path::new(name).to_path_buf(),
+                    };
+                    match fs::read_to_string(&path) {
+                        Ok(text) => self.file(&text, &path.to_string_lossy(), depth + 1),
+                        Err(err) => self.error(file_name, number, format!("unable to open include file `{}': {}", name, err)),
+                    }
+                },
+                _ => self.line(lines[i - 1], file_name, number, 0),
+            }
+        }
+    }
+
+    /**
+     * Stores a macro made by %macro.
+     * @param header: what follows %macro, the name and the amount of parameters.
+     */
+    fn define_macro(&mut self, header: &str, lines: Vec<String>, file_name: &str, number: usize) {
+        let header = header.split(';').next().unwrap_or("");
+        let (name, params) = split_word(header);
+        if name.is_empty() {
+            return self.error(file_name, number, "`%macro' expects a macro name".to_string())
+        }
+        let params = match params {
+            "" => Ok(0),
+            params => params.parse::<usize>(),
+        };
+        match params {
+            Ok(params) => {
+                self.macros.insert(name.to_lowercase(), Macro {
+                    params: params,
+                    lines: lines,
+                });
+            },
+            Err(_) => self.error(file_name, number, "`%macro' expects a parameter count".to_string()),
+        }
+    }
+
+    /**
+     * Preprocesses a single line which isn't a part of a macro definition.
+     * @param depth: how many macro calls deep the line is.
+     */
+    fn line(&mut self, text: &str, file_name: &str, number: usize, depth: usize) {
+        let (word, rest) = split_word(text);
+        let directive = word.to_lowercase();
+        match directive.as_str() {
+            "%define" | "%xdefine" | "%idefine" => return self.define(rest, file_name, number),
+            "%undef" => {
+                self.defines.remove(split_word(rest).0);
+                return
+            },
+            "%endmacro" => return self.error(file_name, number, "`%endmacro' without `%macro'".to_string()),
+            _ if directive.starts_with('%') => {
+                return self.error(file_name, number, format!("unknown preprocessor directive `{}'", word))
+            },
+            _ => (),
+        }
+
+        let text = match self.expand(text, 0) {
+            Ok(text) => text,
+            Err(msg) => return self.error(file_name, number, msg),
+        };
+
+        // a macro call, maybe after a label
+        let (label, call) = match split_word(&text) {
+            (word, rest) if word.ends_with(':') => (Some(word.to_string()), rest.to_string()),
+            _ => (None, text.clone()),
+        };
+        let (name, args) = split_word(&call);
+        if let Some(mac) = self.macros.get(&name.to_lowercase()).cloned() {
+            let args = split_args(args);
+            if args.len() != mac.params {
+                return self.error(file_name, number,
+                    format!("macro `{}' expects {} parameters, got {}", name, mac.params, args.len()))
+            }
+            if depth >= MAX_MACRO_DEPTH {
+                return self.error(file_name, number, format!("macro `{}' nested too deeply", name))
+            }
+            if let Some(label) = label {
+                self.output.push(SourceLine {
+                    file: file_name.to_string(),
+                    line: number,
+                    text: label,
+                });
+            }
+            self.calls += 1;
+            let call = self.calls;
+            for line in &mac.lines {
+                let line = substitute_params(line, &args, call);
+                self.line(&line, file_name, number, depth + 1);
+            }
+            return
+        }
+        self.output.push(SourceLine {
+            file: file_name.to_string(),
+            line: number,
+            text: text,
+        });
+    }
+
+    /**
+     * Stores a single line macro: `%define name value` or `%define name(a, b) value`.
+     */
+    fn define(&mut self, text: &str, file_name: &str, number: usize) {
+        let chars: Vec<char> = text.chars().collect();
+        let end = chars.iter().position(|c| !is_ident_char(*c)).unwrap_or(chars.len());
+        let name: String = chars[..end].iter().collect();
+        if name.is_empty() {
+            return self.error(file_name, number, "`%define' expects a macro name".to_string())
+        }
+        let mut params = Vec::new();
+        let mut body_start = end;
+        if chars.get(end) == Some(&'(') {
+            let close = match chars[end..].iter().position(|c| *c == ')') {
+                Some(close) => end + close,
+                None => return self.error(file_name, number, "`)' expected in macro parameters".to_string()),
+            };
+            let list: String = chars[end + 1..close].iter().collect();
+            params = list.split(',').map(|param| param.trim().to_string()).filter(|p| !p.is_empty()).collect();
+            body_start = close + 1;
+        }
+        let body: String = chars[body_start..].iter().collect();
+        self.defines.insert(name, Define {
+            params: params,
+            body: body.trim().to_string(),
+        });
+    }
+
+    /**
+     * Replaces the single line macros in a line, outside of strings and comments.
+     */
+    fn expand(&self, text: &str, depth: usize) -> Result<String, String> {
+        if depth > MAX_DEFINE_DEPTH {
+            return Err("macro expansion nested too deeply".to_string())
+        }
+        if self.defines.is_empty() {
+            return Ok(text.to_string())
+        }
+        let chars: Vec<char> = text.chars().collect();
+        let mut out = String::new();
+        let mut changed = false;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c == ';' {
+                out.extend(&chars[i..]);
+                break
+            }
+            if c == '\'' || c == '"' || c == '`' {
+                let end = string_end(&chars, i);
+                out.extend(&chars[i..end]);
+                i = end;
+                continue
+            }
+            if !is_ident_char(c) {
+                out.push(c);
+                i += 1;
+                continue
+            }
+            let start = i;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            let define = match self.defines.get(&name) {
+                Some(define) => define,
+                None => {
+                    out += &name;
+                    continue
+                },
+            };
+            changed = true;
+            if define.params.is_empty() {
+                out += &define.body;
+                continue
+            }
+            // a macro with parameters needs its arguments in parentheses
+            if chars.get(i) != Some(&'(') {
+                return Err(format!("macro `{}' expects {} parameters", name, define.params.len()))
+            }
+            let mut level = 0;
+            let mut close = i;
+            while close < chars.len() {
+                match chars[close] {
+                    '(' => level += 1,
+                    ')' => {
+                        level -= 1;
+                        if level == 0 {
+                            break
+                        }
+                    },
+                    _ => (),
+                }
+                close += 1;
+            }
+            if close == chars.len() {
+                return Err(format!("`)' expected after the arguments of `{}'", name))
+            }
+            let args = split_args(&chars[i + 1..close].iter().collect::<String>());
+            if args.len() != define.params.len() {
+                return Err(format!("macro `{}' expects {} parameters, got {}", name, define.params.len(), args.len()))
+            }
+            out += &replace_idents(&define.body, &define.params, &args);
+            i = close + 1;
+        }
+        if changed {
+            // the replacement may use other macros
+            self.expand(&out, depth + 1)
+        } else {
+            Ok(out)
+        }
+    }
+}
+
+/**
+ * Replaces whole identifiers of a text.
+ */
+fn replace_idents(text: &str, names: &[String], values: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_ident_char(chars[i]) {
+            out.push(chars[i]);
+            i += 1;
+            continue
+        }
+        let start = i;
+        while i < chars.len() && is_ident_char(chars[i]) {
+            i += 1;
+        }
+        let name: String = chars[start..i].iter().collect();
+        match names.iter().position(|n| *n == name) {
+            Some(index) => out += &values[index],
+            None => out += &name,
+        }
+    }
+    out
+}
+
+/**
+ * Replaces the parameters of a macro line: %1.. with the arguments, %0 with
+ * their amount and %%label with a label unique to the call.
+ */
+fn substitute_params(line: &str, args: &[String], call: usize) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '%' || i + 1 == chars.len() {
+            out.push(chars[i]);
+            i += 1;
+            continue
+        }
+        let next = chars[i + 1];
+        if next == '%' && i + 2 < chars.len() && (chars[i + 2].is_ascii_alphabetic() || chars[i + 2] == '_' || chars[i + 2] == '.') {
+            out += &format!("..@{}.", call);
+            i += 2;
+            continue
+        }
+        // %{10} for parameters above 9
+        let (digits, end) = if next == '{' {
+            match chars[i + 2..].iter().position(|c| *c == '}') {
+                Some(close) => (chars[i + 2..i + 2 + close].iter().collect::<String>(), i + 3 + close),
+                None => (String::new(), i),
+            }
+        } else {
+            let end = chars[i + 1..].iter().position(|c| !c.is_ascii_digit()).map_or(chars.len(), |end| i + 1 + end);
+            (chars[i + 1..end].iter().collect::<String>(), end)
+        };
+        match digits.parse::<usize>() {
+            Ok(0) => out += &args.len().to_string(),
+            Ok(n) if n <= args.len() => out += &args[n - 1],
+            _ => {
+                out.push('%');
+                i += 1;
+                continue
+            },
+        }
+        i = end;
+    }
+    out
+}
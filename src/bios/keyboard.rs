@@ -0,0 +1,211 @@
+use crate::cpu::memory_unit::memory_segments::MemorySegments;
+use super::*;
+
+// the buffer holds 16 words, one is always free
+const BUFFER_SIZE: u16 = 0x20;
+
+// the rows of a US keyboard: their keys, with shift, and the scancode of the first one
+const ROWS: [(&[u8], &[u8], u8); 4] = [
+    (b"1234567890-=", b"!@#$%^&*()_+", 0x02),
+    (b"qwertyuiop[]", b"QWERTYUIOP{}", 0x10),
+    (b"asdfghjkl;'`", b"ASDFGHJKL:\"~", 0x1E),
+    (b"zxcvbnm,./", b"ZXCVBNM<>?", 0x2C),
+];
+const ESCAPE: u8 = 0x01;
+const BACKSPACE: u8 = 0x0E;
+const TAB: u8 = 0x0F;
+const ENTER: u8 = 0x1C;
+const BACKSLASH: u8 = 0x2B;
+const SPACE: u8 = 0x39;
+pub const LEFT_SHIFT: u8 = 0x2A;
+pub const RIGHT_SHIFT: u8 = 0x36;
+pub const CTRL: u8 = 0x1D;
+pub const ALT: u8 = 0x38;
+const CAPS_LOCK: u8 = 0x3A;
+const NUM_LOCK: u8 = 0x45;
+const SCROLL_LOCK: u8 = 0x46;
+const INSERT: u8 = 0x52;
+// the break code of a key is its make code with bit 7 set
+pub const BREAK: u8 = 0x80;
+
+// the bits of the keyboard flags in the BIOS Data Area
+const FLAG_RIGHT_SHIFT: u8 = 0x01;
+const FLAG_LEFT_SHIFT: u8 = 0x02;
+const FLAG_CTRL: u8 = 0x04;
+const FLAG_ALT: u8 = 0x08;
+const FLAG_SCROLL_LOCK: u8 = 0x10;
+const FLAG_NUM_LOCK: u8 = 0x20;
+const FLAG_CAPS_LOCK: u8 = 0x40;
+const FLAG_INSERT: u8 = 0x80;
+
+/**
+ * Makes the keyboard buffer of the BIOS Data Area empty.
+ */
+pub fn init_buffer(memory: &mut MemorySegments) {
+    set_bda_word(memory, BDA_KEYBOARD_START, BDA_KEYBOARD_BUFFER);
+    set_bda_word(memory, BDA_KEYBOARD_END, BDA_KEYBOARD_BUFFER + BUFFER_SIZE);
+    set_bda_word(memory, BDA_KEYBOARD_HEAD, BDA_KEYBOARD_BUFFER);
+    set_bda_word(memory, BDA_KEYBOARD_TAIL, BDA_KEYBOARD_BUFFER);
+}
+
+/**
+ * Returns the word after a position of the buffer, going back to its start at its end.
+ */
+fn next(memory: &MemorySegments, position: u16) -> u16 {
+    let position = position + 2;
+    if position >= bda_word(memory, BDA_KEYBOARD_END) {
+        bda_word(memory, BDA_KEYBOARD_START)
+    } else {
+        position
+    }
+}
+
+/**
+ * Adds a key to the end of the buffer.
+ * @param key: the scancode in the high byte and the character in the low byte.
+ * @return: false if the buffer is full.
+ */
+pub fn push_key(memory: &mut MemorySegments, key: u16) -> bool {
+    let tail = bda_word(memory, BDA_KEYBOARD_TAIL);
+    let next_tail = next(memory, tail);
+    if next_tail == bda_word(memory, BDA_KEYBOARD_HEAD) {
+        return false
+    }
+    set_bda_word(memory, tail, key);
+    set_bda_word(memory, BDA_KEYBOARD_TAIL, next_tail);
+    true
+}
+
+/**
+ * Returns the first key of the buffer, leaving it there.
+ */
+pub fn peek_key(memory: &MemorySegments) -> Option<u16> {
+    let head = bda_word(memory, BDA_KEYBOARD_HEAD);
+    if head == bda_word(memory, BDA_KEYBOARD_TAIL) {
+        return None
+    }
+    Some(bda_word(memory, head))
+}
+
+/**
+ * Takes the first key out of the buffer.
+ */
+pub fn pop_key(memory: &mut MemorySegments) -> Option<u16> {
+    let key = peek_key(memory)?;
+    let head = next(memory, bda_word(memory, BDA_KEYBOARD_HEAD));
+    set_bda_word(memory, BDA_KEYBOARD_HEAD, head);
+    Some(key)
+}
+
+/**
+ * Returns the scancode of the key that types a character on a US keyboard,
+ * in scancode set 1. Control characters are the keys of their letters.
+ */
+pub fn scancode(character: u8) -> u8 {
+    match character {
+        0x1B => return ESCAPE,
+        0x08 => return BACKSPACE,
+        b'\t' => return TAB,
+        b'\r' | b'\n' => return ENTER,
+        b' ' => return SPACE,
+        b'\\' | b'|' => return BACKSLASH,
+        // Ctrl-A to Ctrl-Z
+        0x01..=0x1A => return scancode(character - 1 + b'a'),
+        _ => (),
+    }
+    for (keys, shifted, first) in ROWS {
+        if let Some(i) = keys.iter().chain(shifted.iter()).position(|key| *key == character) {
+            return first + (i % keys.len()) as u8
+        }
+    }
+    0
+}
+
+/**
+ * Returns if shift is held down to type a character on a US keyboard.
+ */
+pub fn shifted(character: u8) -> bool {
+    character == b'|' || ROWS.iter().any(|(_, shifted, _)| shifted.contains(&character))
+}
+
+/**
+ * Returns the word INT 16h returns for a character: its scancode and the character.
+ */
+pub fn key_code(character: u8) -> u16 {
+    (scancode(character) as u16) << 8 | character as u16
+}
+
+/**
+ * Returns the character of a key on a US keyboard, 0 for the keys without one.
+ */
+fn character(scancode: u8, shift: bool) -> u8 {
+    match scancode {
+        ESCAPE => return 0x1B,
+        BACKSPACE => return 0x08,
+        TAB => return b'\t',
+        ENTER => return b'\r',
+        SPACE => return b' ',
+        BACKSLASH => return if shift { b'|' } else { b'\\' },
+        // the - and + of the keypad
+        0x4A => return b'-',
+        0x4E => return b'+',
+        _ => (),
+    }
+    for (keys, shifted, first) in ROWS {
+        if scancode >= first && scancode < first + keys.len() as u8 {
+            let i = (scancode - first) as usize;
+            return if shift { shifted[i] } else { keys[i] }
+        }
+    }
+    0
+}
+
+/**
+ * Translates a scancode the keyboard sent, like the INT 9 handler of the BIOS.
+ * The shift keys, ctrl, alt and the locks change the keyboard flags of the
+ * BIOS Data Area, the other keys give the word to add to the buffer: the
+ * scancode and the character, 0 for keys like the arrows and with alt.
+ * @return: the key, None for the break codes and the keys that only change the flags.
+ */
+pub fn translate(memory: &mut MemorySegments, scancode: u8) -> Option<u16> {
+    let flags = bda_byte(memory, BDA_KEYBOARD_FLAGS);
+    let key = scancode & !BREAK;
+    let held = match key {
+        RIGHT_SHIFT => FLAG_RIGHT_SHIFT,
+        LEFT_SHIFT => FLAG_LEFT_SHIFT,
+        CTRL => FLAG_CTRL,
+        ALT => FLAG_ALT,
+        _ => 0,
+    };
+    if held != 0 {
+        let flags = if scancode & BREAK != 0 { flags & !held } else { flags | held };
+        set_bda_byte(memory, BDA_KEYBOARD_FLAGS, flags);
+        return None
+    }
+    if scancode & BREAK != 0 {
+        return None
+    }
+    let toggled = match key {
+        CAPS_LOCK => FLAG_CAPS_LOCK,
+        NUM_LOCK => FLAG_NUM_LOCK,
+        SCROLL_LOCK => FLAG_SCROLL_LOCK,
+        INSERT => FLAG_INSERT,
+        _ => 0,
+    };
+    set_bda_byte(memory, BDA_KEYBOARD_FLAGS, flags ^ toggled);
+    if toggled != 0 && key != INSERT {
+        return None
+    }
+    let shift = flags & (FLAG_LEFT_SHIFT | FLAG_RIGHT_SHIFT) != 0;
+    let mut character = character(key, shift);
+    // caps lock shifts the letters only
+    if flags & FLAG_CAPS_LOCK != 0 && character.is_ascii_alphabetic() {
+        character ^= 0x20;
+    }
+    if flags & FLAG_ALT != 0 {
+        character = 0;
+    } else if flags & FLAG_CTRL != 0 && character.is_ascii_alphabetic() {
+        character &= 0x1F;
+    }
+    Some((key as u16) << 8 | character as u16)
+}
@@ -0,0 +1,722 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cpu::{PortBus, RESET_VECTOR};
+use crate::cpu::memory_unit::MemoryUnit;
+use crate::cpu::memory_unit::memory_segments::MemorySegments;
+use crate::cpu::services::{InterruptService, ServiceResult};
+use crate::dos::console::DosConsole;
+use crate::hardware::floppy::{Disk, DiskError, SECTOR_SIZE};
+
+pub mod keyboard;
+pub mod video;
+
+pub const BIOS_SEGMENT: u16 = 0xF000;
+pub const BDA_SEGMENT: u16 = 0x0040;
+
+// the fields of the BIOS Data Area, offsets in BDA_SEGMENT
+pub const BDA_SERIAL_PORTS: u16 = 0x00;
+pub const BDA_PRINTER_PORTS: u16 = 0x08;
+pub const BDA_EQUIPMENT: u16 = 0x10;
+pub const BDA_MEMORY_SIZE: u16 = 0x13;
+pub const BDA_KEYBOARD_FLAGS: u16 = 0x17;
+pub const BDA_KEYBOARD_HEAD: u16 = 0x1A;
+pub const BDA_KEYBOARD_TAIL: u16 = 0x1C;
+pub const BDA_KEYBOARD_BUFFER: u16 = 0x1E;
+pub const BDA_SEEK_STATUS: u16 = 0x3E;
+pub const BDA_DISKETTE_STATUS: u16 = 0x41;
+pub const BDA_VIDEO_MODE: u16 = 0x49;
+pub const BDA_COLUMNS: u16 = 0x4A;
+pub const BDA_PAGE_SIZE: u16 = 0x4C;
+pub const BDA_PAGE_OFFSET: u16 = 0x4E;
+pub const BDA_CURSOR_POSITIONS: u16 = 0x50;
+pub const BDA_CURSOR_SHAPE: u16 = 0x60;
+pub const BDA_ACTIVE_PAGE: u16 = 0x62;
+pub const BDA_CRTC_PORT: u16 = 0x63;
+pub const BDA_MODE_CONTROL: u16 = 0x65;
+pub const BDA_PALETTE: u16 = 0x66;
+pub const BDA_TICKS: u16 = 0x6C;
+pub const BDA_MIDNIGHT: u16 = 0x70;
+pub const BDA_KEYBOARD_START: u16 = 0x80;
+pub const BDA_KEYBOARD_END: u16 = 0x82;
+pub const BDA_ROWS: u16 = 0x84;
+pub const BDA_CHARACTER_HEIGHT: u16 = 0x85;
+
+const BDA_SIZE: usize = 0x100;
+// 640K of conventional memory
+const MEMORY_SIZE_KB: u16 = 640;
+// bits 4-5: the initial video mode is 80x25 color
+const EQUIPMENT: u16 = 0x0020;
+// bit 0: there are floppy drives, bits 6-7: their number less one,
+// bits 9-11: the number of serial ports, bits 14-15: the number of printers
+const EQUIPMENT_FLOPPY: u16 = 0x0001;
+const EQUIPMENT_FLOPPY_SHIFT: u16 = 6;
+const EQUIPMENT_SERIAL_SHIFT: u16 = 9;
+const EQUIPMENT_PRINTER_SHIFT: u16 = 14;
+// the timer ticks 1193182 / 65536 times a second, which is 0x1800B0 times a day
+pub const TICKS_PER_DAY: u32 = 0x1800B0;
+const TIMER_FREQUENCY: u64 = 1193182;
+
+// the entry points of the services in the BIOS of the IBM PC, each holds an IRET
+const ENTRY_POINTS: [(u8, u16); 12] = [
+    (0x08, 0xFEA5), (0x09, 0xE987), (0x0E, 0xEF57), (0x10, 0xF065), (0x11, 0xF84D),
+    (0x12, 0xF841), (0x13, 0xEC59), (0x16, 0xE82E), (0x17, 0xEFD2), (0x19, 0xE6F2),
+    (0x1A, 0xFE6E), (0x1C, 0xFF53),
+];
+const IRET: u8 = 0xCF;
+// the command port of the interrupt controller and its non-specific end of interrupt
+const PIC_COMMAND: u16 = 0x20;
+const PIC_EOI: u16 = 0x20;
+// the timer interrupt runs the timer tick of the programs
+const USER_TICK: u8 = 0x1C;
+// the keyboard controller has the scancode at this port
+const KEYBOARD_DATA: u16 = 0x60;
+// the printer ports are the data, the status and the control, the status has
+// ERROR and ACK inverted compared to the status INT 17h returns
+const PRINTER_NOT_BUSY: u8 = 0x80;
+const PRINTER_INVERTED: u8 = 0x48;
+// the control of a selected printer that isn't being reset, with STROBE or INIT
+const PRINTER_CONTROL: u8 = 0x0C;
+const PRINTER_STROBE: u8 = 0x01;
+const PRINTER_INIT: u8 = 0x04;
+// the status INT 17h returns for a printer that isn't there
+const PRINTER_TIMEOUT: u8 = 0x01;
+// the diskette parameter table INT 1Eh points to, for a 1.44M drive: the
+// SPECIFY bytes, the motor off delay, 512 byte sectors, 18 sectors a track,
+// the gaps, the format filler byte and the head and motor start times
+const DISKETTE_TABLE_VECTOR: u8 = 0x1E;
+const DISKETTE_TABLE: u16 = 0xEFC7;
+const DISKETTE_PARAMETERS: [u8; 11] = [0xDF, 0x02, 0x25, 0x02, 0x12, 0x1B, 0xFF, 0x6C, 0xF6, 0x0F, 0x08];
+const FORMAT_FILLER: u8 = 0xF6;
+// the status of INT 13h
+const DISK_OK: u8 = 0x00;
+const DISK_BAD_COMMAND: u8 = 0x01;
+const DISK_WRITE_PROTECTED: u8 = 0x03;
+const DISK_NO_SECTOR: u8 = 0x04;
+const DISK_DMA_BOUNDARY: u8 = 0x09;
+const DISK_TIMEOUT: u8 = 0x80;
+// the write fault of the fixed disks, for an image that couldn't be written
+const DISK_WRITE_FAULT: u8 = 0xCC;
+// the diskette interrupt sets bit 7 of the seek status
+const SEEK_INTERRUPT: u8 = 0x80;
+// where the bootstrap loader loads the boot sector and starts it
+pub const BOOT_ADDRESS: (u16, u16) = (0x0000, 0x7C00);
+
+// option ROMs start on a 2K boundary between C800:0000 and F000:0000
+const OPTION_ROMS_START: u32 = 0xC8000;
+const OPTION_ROMS_END: u32 = 0xF0000;
+const OPTION_ROM_ALIGNMENT: u32 = 0x800;
+const OPTION_ROM_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+// the init entry point of an option ROM, after its signature and size
+const OPTION_ROM_INIT: u16 = 3;
+// where the power on self test of the IBM PC starts, the reset code calls the option ROMs from there
+const POST_ENTRY: u16 = 0xE05B;
+// PUSH AX, CX, DX, BX, BP, SI, DI, DS, ES and the POPs in reverse order
+const SAVE_REGISTERS: [u8; 9] = [0x50, 0x51, 0x52, 0x53, 0x55, 0x56, 0x57, 0x1E, 0x06];
+const RESTORE_REGISTERS: [u8; 9] = [0x07, 0x1F, 0x5F, 0x5E, 0x5D, 0x5B, 0x5A, 0x59, 0x58];
+
+/**
+ * Reads a byte of the BIOS Data Area.
+ */
+pub fn bda_byte(memory: &MemorySegments, offset: u16) -> u8 {
+    memory[(BDA_SEGMENT, offset)]
+}
+
+/**
+ * Reads a word of the BIOS Data Area.
+ */
+pub fn bda_word(memory: &MemorySegments, offset: u16) -> u16 {
+    let bytes = memory.read((BDA_SEGMENT, offset), 2);
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+pub fn set_bda_byte(memory: &mut MemorySegments, offset: u16, value: u8) {
+    memory[(BDA_SEGMENT, offset)] = value;
+}
+
+pub fn set_bda_word(memory: &mut MemorySegments, offset: u16, value: u16) {
+    memory.write(&value.to_le_bytes().to_vec(), (BDA_SEGMENT, offset));
+}
+
+/**
+ * Points the vectors of the BIOS services to their entry points and fills
+ * the BIOS Data Area the way the BIOS leaves it after the power on self test.
+ * @param memory: the memory of the cpu.
+ */
+pub fn install_bios(memory: &mut MemorySegments) {
+    for (vector, offset) in ENTRY_POINTS {
+        memory.write(&vec![IRET], (BIOS_SEGMENT, offset));
+        memory.set_interrupt_vector(vector, (BIOS_SEGMENT, offset));
+    }
+    memory.write(&vec![0; BDA_SIZE], (BDA_SEGMENT, 0));
+    set_bda_word(memory, BDA_EQUIPMENT, EQUIPMENT);
+    set_bda_word(memory, BDA_MEMORY_SIZE, MEMORY_SIZE_KB);
+    keyboard::init_buffer(memory);
+    memory.write(&DISKETTE_PARAMETERS.to_vec(), (BIOS_SEGMENT, DISKETTE_TABLE));
+    memory.set_interrupt_vector(DISKETTE_TABLE_VECTOR, (BIOS_SEGMENT, DISKETTE_TABLE));
+    // mode 3 is one of the supported modes
+    let _ = video::set_mode(memory, 3);
+}
+
+/**
+ * Tells the programs about the serial ports, in the BIOS Data Area and the equipment word.
+ * @param ports: the base ports of the serial ports, COM1 first.
+ */
+pub fn set_serial_ports(memory: &mut MemorySegments, ports: &[u16]) {
+    for (i, port) in ports.iter().enumerate() {
+        set_bda_word(memory, BDA_SERIAL_PORTS + 2 * i as u16, *port);
+    }
+    let equipment = bda_word(memory, BDA_EQUIPMENT) & !(7 << EQUIPMENT_SERIAL_SHIFT);
+    set_bda_word(memory, BDA_EQUIPMENT, equipment | (ports.len() as u16) << EQUIPMENT_SERIAL_SHIFT);
+}
+
+/**
+ * Tells the programs about the parallel ports, in the BIOS Data Area and the equipment word.
+ * @param ports: the base ports of the parallel ports, LPT1 first.
+ */
+pub fn set_printer_ports(memory: &mut MemorySegments, ports: &[u16]) {
+    for (i, port) in ports.iter().enumerate() {
+        set_bda_word(memory, BDA_PRINTER_PORTS + 2 * i as u16, *port);
+    }
+    let equipment = bda_word(memory, BDA_EQUIPMENT) & !(3 << EQUIPMENT_PRINTER_SHIFT);
+    set_bda_word(memory, BDA_EQUIPMENT, equipment | (ports.len() as u16) << EQUIPMENT_PRINTER_SHIFT);
+}
+
+/**
+ * Tells the programs about the floppy drives, in the equipment word.
+ * @param drives: the number of drives, up to 4.
+ */
+pub fn set_floppy_drives(memory: &mut MemorySegments, drives: u16) {
+    let mut equipment = bda_word(memory, BDA_EQUIPMENT) & !(EQUIPMENT_FLOPPY | 3 << EQUIPMENT_FLOPPY_SHIFT);
+    if drives > 0 {
+        equipment |= EQUIPMENT_FLOPPY | (drives - 1) << EQUIPMENT_FLOPPY_SHIFT;
+    }
+    set_bda_word(memory, BDA_EQUIPMENT, equipment);
+}
+
+/**
+ * Finds the option ROMs: a 55AAh signature on a 2K boundary between C800h
+ * and F000h, followed by the size of the ROM in 512 byte blocks. The bytes
+ * of a ROM add up to 0, ROMs with a bad checksum are skipped.
+ * @return: the segments of the option ROMs.
+ */
+pub fn option_roms(memory: &MemorySegments) -> Vec<u16> {
+    let mut roms = Vec::new();
+    let mut address = OPTION_ROMS_START;
+    while address < OPTION_ROMS_END {
+        let segment = (address >> 4) as u16;
+        let header = memory.read((segment, 0), 3);
+        let size = header[2] as u32 * 512;
+        if header[..2] == OPTION_ROM_SIGNATURE && size > 0 {
+            let checksum = memory.read((segment, 0), size as usize).iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            if checksum == 0 {
+                roms.push(segment);
+                // the next ROM starts after this one
+                address += (size + OPTION_ROM_ALIGNMENT - 1) / OPTION_ROM_ALIGNMENT * OPTION_ROM_ALIGNMENT;
+                continue
+            }
+        }
+        address += OPTION_ROM_ALIGNMENT;
+    }
+    roms
+}
+
+/**
+ * Writes the code the cpu runs on reset at F000:FFF0. It far calls the init
+ * entry point of each option ROM, keeping the registers the program starts
+ * with, then jumps to the program. Without option ROMs it's just the jump.
+ * A reset vector elsewhere only starts the cpu there, its code can jump here.
+ * @param start: the segment:offset the program starts at.
+ * @param roms: the segments of the option ROMs.
+ */
+pub fn write_reset(memory: &mut MemorySegments, start: (u16, u16), roms: &[u16]) {
+    let far = |opcode: u8, (segment, offset): (u16, u16)| {
+        let mut bytes = vec![opcode];
+        bytes.extend(&offset.to_le_bytes());
+        bytes.extend(&segment.to_le_bytes());
+        bytes
+    };
+    if roms.is_empty() {
+        memory.write(&far(0xEA, start), RESET_VECTOR);
+        return
+    }
+    let mut code = SAVE_REGISTERS.to_vec();
+    for segment in roms {
+        code.extend(far(0x9A, (*segment, OPTION_ROM_INIT)));
+    }
+    code.extend(&RESTORE_REGISTERS);
+    code.extend(far(0xEA, start));
+    memory.write(&code, (BIOS_SEGMENT, POST_ENTRY));
+    memory.write(&far(0xEA, (BIOS_SEGMENT, POST_ENTRY)), RESET_VECTOR);
+}
+
+/**
+ * The BIOS services implemented by the emulator: the timer, keyboard and diskette interrupts,
+ * video, equipment, memory size, diskette, keyboard, printer, bootstrap and time of day. A service is only used
+ * while its vector points to its entry point, programs and devices may replace it.
+ */
+pub struct Bios {
+    console: Box<dyn DosConsole>,
+    // the tick count when the emulator started, and when that was
+    start_ticks: u32,
+    started: Instant,
+    // the days passed since the start that INT 1Ah reported
+    days: u64,
+    // the timer interrupt counts the ticks, instead of the clock of the host
+    timer: bool,
+    // the keyboard interrupt fills the keyboard buffer, instead of the console
+    pub keyboard: bool,
+    // the disks in the floppy drives A: and B:
+    pub floppies: [Option<Disk>; 2],
+}
+
+impl Bios {
+    /**
+     * Creates the BIOS services. The clock starts at the time of the host.
+     * @param console: where teletype output is copied and keys are read from.
+     */
+    pub fn new(console: Box<dyn DosConsole>) -> Self {
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        Self {
+            console: console,
+            start_ticks: ((seconds % 86400) * TIMER_FREQUENCY / 65536) as u32,
+            started: Instant::now(),
+            days: 0,
+            timer: false,
+            keyboard: false,
+            floppies: [None, None],
+        }
+    }
+
+    /**
+     * The timer interrupt, IRQ0. Counts the ticks of the day in the BIOS Data
+     * Area, ends the interrupt at the interrupt controller and runs INT 1Ch.
+     */
+    fn int08(&mut self, cpu: &mut MemoryUnit, ports: &mut PortBus) -> Result<ServiceResult, String> {
+        let memory = &mut cpu.memory;
+        let mut ticks = ((bda_word(memory, BDA_TICKS + 2) as u32) << 16 | bda_word(memory, BDA_TICKS) as u32) + 1;
+        if ticks >= TICKS_PER_DAY {
+            ticks = 0;
+            set_bda_byte(memory, BDA_MIDNIGHT, 1);
+        }
+        set_bda_word(memory, BDA_TICKS, ticks as u16);
+        set_bda_word(memory, BDA_TICKS + 2, (ticks >> 16) as u16);
+        self.timer = true;
+        ports.output(PIC_COMMAND, PIC_EOI, false);
+        Ok(ServiceResult::Chain(USER_TICK))
+    }
+
+    /**
+     * The keyboard interrupt, IRQ1. Reads the scancode from the keyboard
+     * controller, adds the key to the buffer of the BIOS Data Area, or
+     * changes the keyboard flags, and ends the interrupt.
+     */
+    fn int09(&mut self, cpu: &mut MemoryUnit, ports: &mut PortBus) -> Result<ServiceResult, String> {
+        let scancode = ports.input(KEYBOARD_DATA, false) as u8;
+        if let Some(key) = keyboard::translate(&mut cpu.memory, scancode) {
+            // a full buffer drops the key, the BIOS would beep
+            keyboard::push_key(&mut cpu.memory, key);
+        }
+        ports.output(PIC_COMMAND, PIC_EOI, false);
+        Ok(ServiceResult::Handled)
+    }
+
+    /**
+     * The diskette interrupt, IRQ6. Tells the program waiting for the disk
+     * controller that it interrupted, in bit 7 of the seek status of the BIOS
+     * Data Area, and ends the interrupt.
+     */
+    fn int0e(&mut self, cpu: &mut MemoryUnit, ports: &mut PortBus) -> Result<ServiceResult, String> {
+        let status = bda_byte(&cpu.memory, BDA_SEEK_STATUS);
+        set_bda_byte(&mut cpu.memory, BDA_SEEK_STATUS, status | SEEK_INTERRUPT);
+        ports.output(PIC_COMMAND, PIC_EOI, false);
+        Ok(ServiceResult::Handled)
+    }
+
+    /**
+     * Video services, INT 10h. The function number is in AH.
+     */
+    fn int10(&mut self, cpu: &mut MemoryUnit, ports: &mut PortBus) -> Result<ServiceResult, String> {
+        let function = (cpu.ax >> 8) as u8;
+        let al = cpu.ax as u8;
+        let bh = (cpu.bx >> 8) as u8;
+        let bl = cpu.bx as u8;
+        let memory = &mut cpu.memory;
+        match function {
+            0x00 => video::set_mode(memory, al)?,
+            // the cursor shape is CH:CL, its start and end scan lines
+            0x01 => set_bda_word(memory, BDA_CURSOR_SHAPE, cpu.cx),
+            0x02 => video::set_cursor(memory, bh, (cpu.dx >> 8) as u8, cpu.dx as u8),
+            0x03 => {
+                let (row, column) = video::cursor(memory, bh);
+                cpu.dx = (row as u16) << 8 | column as u16;
+                cpu.cx = bda_word(memory, BDA_CURSOR_SHAPE);
+            },
+            0x05 => video::set_active_page(memory, al),
+            // scroll the window CH,CL to DH,DL by AL lines, filling with the attribute BH
+            0x06 | 0x07 => video::scroll(memory, function == 0x06, al, bh,
+                ((cpu.cx >> 8) as u8, cpu.cx as u8), ((cpu.dx >> 8) as u8, cpu.dx as u8)),
+            0x08 => {
+                let (character, attribute) = video::read_char(memory, bh);
+                cpu.ax = (attribute as u16) << 8 | character as u16;
+            },
+            0x09 => video::write_char(memory, bh, al, Some(bl), cpu.cx),
+            0x0A => video::write_char(memory, bh, al, None, cpu.cx),
+            0x0B => video::set_palette(memory, bh, bl),
+            0x0C => video::write_pixel(memory, cpu.cx, cpu.dx, al),
+            0x0D => {
+                let color = video::read_pixel(memory, cpu.cx, cpu.dx);
+                cpu.ax = cpu.ax & 0xFF00 | color as u16;
+            },
+            0x0E => {
+                let page = bda_byte(memory, BDA_ACTIVE_PAGE);
+                video::teletype(memory, page, al, None);
+                self.console.write(al);
+            },
+            0x0F => {
+                cpu.ax = (bda_word(memory, BDA_COLUMNS) << 8) | bda_byte(memory, BDA_VIDEO_MODE) as u16;
+                cpu.bx = (bda_byte(memory, BDA_ACTIVE_PAGE) as u16) << 8 | bl as u16;
+            },
+            0x10 => Self::dac(cpu, ports)?,
+            0x13 => self.write_string(cpu),
+            _ => return Err(format!("INT 10h function {:02X}h isn't supported", function)),
+        }
+        // the functions that change what the adapter shows program it
+        match function {
+            0x00 => {
+                video::program_adapter(&cpu.memory, ports, true);
+                video::write_dac(ports, 0, &video::default_dac());
+            },
+            0x0B => video::program_adapter(&cpu.memory, ports, true),
+            0x01 | 0x02 | 0x05 | 0x0E | 0x13 => video::program_adapter(&cpu.memory, ports, false),
+            _ => (),
+        }
+        Ok(ServiceResult::Handled)
+    }
+
+    /**
+     * The DAC functions of the VGA, AH=10h. AL=10h sets the entry BX to the
+     * red DH, the green CH and the blue CL, AL=15h reads it back. AL=12h sets
+     * CX entries from BX on to the colors at ES:DX, 3 bytes each, AL=17h reads them.
+     */
+    fn dac(cpu: &mut MemoryUnit, ports: &mut PortBus) -> Result<(), String> {
+        let first = cpu.bx as u8;
+        let table = (cpu.memory.extra_segment, cpu.dx);
+        let count = (cpu.cx as usize).min(video::DAC_ENTRIES - first as usize);
+        match cpu.ax as u8 {
+            0x10 => video::write_dac(ports, first, &[[(cpu.dx >> 8) as u8, (cpu.cx >> 8) as u8, cpu.cx as u8]]),
+            0x12 => {
+                let colors = cpu.memory.read(table, count * 3);
+                let colors: Vec<[u8; 3]> = colors.chunks(3).map(|color| [color[0], color[1], color[2]]).collect();
+                video::write_dac(ports, first, &colors);
+            },
+            0x15 => {
+                let [red, green, blue] = video::read_dac(ports, first, 1)[0];
+                cpu.dx = (red as u16) << 8 | cpu.dx & 0x00FF;
+                cpu.cx = (green as u16) << 8 | blue as u16;
+            },
+            0x17 => {
+                let colors = video::read_dac(ports, first, count).concat();
+                cpu.memory.write(&colors, table);
+            },
+            function => return Err(format!("INT 10h function 10h/{:02X}h isn't supported", function)),
+        }
+        Ok(())
+    }
+
+    /**
+     * Writes the string at ES:BP, AH=13h. CX is its length and DH,DL where it's
+     * written on page BH. Bit 1 of AL says the characters are followed by their
+     * attribute, otherwise BL is used. Bit 0 says the cursor moves.
+     */
+    fn write_string(&mut self, cpu: &mut MemoryUnit) {
+        let mode = cpu.ax as u8;
+        let page = (cpu.bx >> 8) as u8;
+        let with_attributes = mode & 0x02 != 0;
+        let length = cpu.cx as usize * if with_attributes { 2 } else { 1 };
+        let string = cpu.memory.read((cpu.memory.extra_segment, cpu.bp), length);
+        let memory = &mut cpu.memory;
+        let cursor = video::cursor(memory, page);
+        video::set_cursor(memory, page, (cpu.dx >> 8) as u8, cpu.dx as u8);
+        for i in 0..cpu.cx as usize {
+            let (character, attribute) = if with_attributes {
+                (string[i * 2], string[i * 2 + 1])
+            } else {
+                (string[i], cpu.bx as u8)
+            };
+            video::teletype(memory, page, character, Some(attribute));
+            self.console.write(character);
+        }
+        if mode & 0x01 == 0 {
+            video::set_cursor(memory, page, cursor.0, cursor.1);
+        }
+    }
+
+    /**
+     * Diskette services, INT 13h, on the disks of the drives. DL is the
+     * drive, DH the head, CH the cylinder and CL the sector, from 1; bits 6-7
+     * of CL are bits 8-9 of the cylinder. AH returns the status, which is kept
+     * in the BIOS Data Area for function 01h, and CF is set on an error. A
+     * drive without a disk times out. Like the BIOS of the IBM PC, unknown
+     * functions are bad commands, as programs look for the ones of newer BIOSes.
+     */
+    fn int13(&mut self, cpu: &mut MemoryUnit) -> Result<ServiceResult, String> {
+        let function = (cpu.ax >> 8) as u8;
+        let disk = self.floppies.get(cpu.dx as u8 as usize).and_then(Option::as_ref).cloned();
+        let status = match (function, disk) {
+            (0x00, _) => DISK_OK,
+            (0x01, _) => bda_byte(&cpu.memory, BDA_DISKETTE_STATUS),
+            (0x02..=0x05, Some(disk)) => Self::transfer(cpu, function, &disk),
+            (0x02..=0x05, None) => {
+                cpu.ax &= 0xFF00;
+                DISK_TIMEOUT
+            },
+            // the drive parameters and the diskette parameter table in ES:DI
+            (0x08, Some(disk)) => {
+                let geometry = disk.geometry();
+                let last_cylinder = geometry.cylinders - 1;
+                cpu.ax = 0;
+                cpu.bx = (cpu.bx & 0xFF00) | disk.drive_type() as u16;
+                cpu.cx = (last_cylinder & 0xFF) << 8 | (last_cylinder >> 8) << 6 | geometry.sectors as u16;
+                cpu.dx = ((geometry.heads - 1) as u16) << 8 | self.floppies.iter().filter(|disk| disk.is_some()).count() as u16;
+                cpu.memory.extra_segment = BIOS_SEGMENT;
+                cpu.di = DISKETTE_TABLE;
+                DISK_OK
+            },
+            // the type of the drive, AH is 1 for a diskette drive, 0 without one
+            (0x15, disk) => {
+                cpu.ax = if disk.is_some() { 0x0100 } else { 0 };
+                cpu.flags.carry = false;
+                return Ok(ServiceResult::Handled)
+            },
+            // the disks aren't changed, and setting the type of the media is done
+            (0x16, Some(_)) | (0x17, Some(_)) => DISK_OK,
+            (0x16, None) | (0x17, None) => DISK_TIMEOUT,
+            _ => DISK_BAD_COMMAND,
+        };
+        set_bda_byte(&mut cpu.memory, BDA_DISKETTE_STATUS, status);
+        cpu.ax = (cpu.ax & 0x00FF) | (status as u16) << 8;
+        cpu.flags.carry = status != DISK_OK;
+        Ok(ServiceResult::Handled)
+    }
+
+    /**
+     * Reads, writes, verifies or formats the sectors of INT 13h at ES:BX:
+     * AL sectors from the one of CL, going on to head 1 after the last sector
+     * of head 0. A format fills the sectors of the track with the IDs at ES:BX,
+     * 4 bytes a sector. The buffer can't cross a 64K boundary, the DMA of the PC can't.
+     * @return: the status, AL is the sectors that were done.
+     */
+    fn transfer(cpu: &mut MemoryUnit, function: u8, disk: &Disk) -> u8 {
+        let geometry = disk.geometry();
+        let count = cpu.ax as u8;
+        let cylinder = (cpu.cx >> 8) | (cpu.cx & 0xC0) << 2;
+        let mut head = (cpu.dx >> 8) as u8;
+        let mut sector = cpu.cx as u8 & 0x3F;
+        let buffer = (cpu.memory.extra_segment as u32) * 16 + cpu.bx as u32;
+        let length = if function == 0x05 { 4 * count as u32 } else { count as u32 * SECTOR_SIZE as u32 };
+        cpu.ax &= 0xFF00;
+        if function != 0x04 && (buffer & 0xFFFF) + length > 0x10000 {
+            return DISK_DMA_BOUNDARY
+        }
+        let location = |offset: u32| {
+            let address = buffer + offset;
+            ((address >> 4) as u16, (address & 0xF) as u16)
+        };
+        let error = |err: DiskError| match err {
+            DiskError::NoSector => DISK_NO_SECTOR,
+            DiskError::WriteProtected => DISK_WRITE_PROTECTED,
+            DiskError::WriteFault => DISK_WRITE_FAULT,
+        };
+        for done in 0..count as u32 {
+            let result = match function {
+                0x02 => disk.read_sector(cylinder, head, sector)
+                    .map(|bytes| cpu.memory.write(&bytes, location(done * SECTOR_SIZE as u32)))
+                    .ok_or(DiskError::NoSector),
+                0x03 => disk.write_sector(cylinder, head, sector, &cpu.memory.read(location(done * SECTOR_SIZE as u32), SECTOR_SIZE)),
+                0x04 => disk.read_sector(cylinder, head, sector).map(|_| ()).ok_or(DiskError::NoSector),
+                // the ID of a sector is its cylinder, head, sector and size
+                _ => {
+                    let id = cpu.memory.read(location(4 * done), 4);
+                    disk.write_sector(cylinder, head, id[2], &vec![FORMAT_FILLER; SECTOR_SIZE])
+                },
+            };
+            if let Err(err) = result {
+                return error(err)
+            }
+            cpu.ax = (cpu.ax & 0xFF00) | (done + 1) as u16;
+            if function != 0x05 {
+                if sector == geometry.sectors && head == 0 && geometry.heads > 1 {
+                    head = 1;
+                    sector = 1;
+                } else {
+                    sector = sector.wrapping_add(1);
+                }
+            }
+        }
+        DISK_OK
+    }
+
+    /**
+     * Keyboard services, INT 16h. The keyboard interrupt fills the buffer of
+     * the BIOS Data Area, without the keyboard keys typed in the console are
+     * added to it when it's empty. While it waits for a key the hardware can
+     * interrupt it.
+     */
+    fn int16(&mut self, cpu: &mut MemoryUnit) -> Result<ServiceResult, String> {
+        let function = (cpu.ax >> 8) as u8;
+        let memory = &mut cpu.memory;
+        if !self.keyboard && keyboard::peek_key(memory).is_none() {
+            if let Some(byte) = self.console.read() {
+                keyboard::push_key(memory, keyboard::key_code(byte));
+            }
+        }
+        match function {
+            // wait for a key and read it
+            0x00 | 0x10 => match keyboard::pop_key(memory) {
+                Some(key) => cpu.ax = key,
+                None => return Ok(ServiceResult::Wait),
+            },
+            // check for a key, ZF is set if there's none
+            0x01 | 0x11 => match keyboard::peek_key(memory) {
+                Some(key) => {
+                    cpu.ax = key;
+                    cpu.flags.zero = false;
+                },
+                None => cpu.flags.zero = true,
+            },
+            0x02 | 0x12 => cpu.ax = (cpu.ax & 0xFF00) | bda_byte(memory, BDA_KEYBOARD_FLAGS) as u16,
+            // store the key CX in the buffer, AL is 1 if it's full
+            0x05 => {
+                let stored = keyboard::push_key(memory, cpu.cx);
+                cpu.ax = (cpu.ax & 0xFF00) | if stored { 0 } else { 1 };
+            },
+            _ => return Err(format!("INT 16h function {:02X}h isn't supported", function)),
+        }
+        Ok(ServiceResult::Handled)
+    }
+
+    /**
+     * Printer services, INT 17h. DX is the printer, its port is in the BIOS
+     * Data Area. AH returns the status of the printer: bit 7 not busy, 6
+     * acknowledge, 5 out of paper, 4 selected, 3 I/O error and 0 time out,
+     * which is what a printer that isn't there returns. Printing waits
+     * until the printer isn't busy, and the hardware can interrupt it meanwhile.
+     */
+    fn int17(&mut self, cpu: &mut MemoryUnit, ports: &mut PortBus) -> Result<ServiceResult, String> {
+        let function = (cpu.ax >> 8) as u8;
+        let base = if cpu.dx < 3 { bda_word(&cpu.memory, BDA_PRINTER_PORTS + 2 * cpu.dx) } else { 0 };
+        // there is no such printer
+        if base == 0 {
+            cpu.ax = (cpu.ax & 0x00FF) | (PRINTER_TIMEOUT as u16) << 8;
+            return Ok(ServiceResult::Handled)
+        }
+        match function {
+            // print AL, pulsing STROBE once the printer isn't busy
+            0x00 => {
+                if ports.input(base + 1, false) as u8 & PRINTER_NOT_BUSY == 0 {
+                    return Ok(ServiceResult::Wait)
+                }
+                ports.output(base, cpu.ax & 0xFF, false);
+                ports.output(base + 2, (PRINTER_CONTROL | PRINTER_STROBE) as u16, false);
+                ports.output(base + 2, PRINTER_CONTROL as u16, false);
+            },
+            // initialize the printer, pulsing INIT low
+            0x01 => {
+                ports.output(base + 2, (PRINTER_CONTROL & !PRINTER_INIT) as u16, false);
+                ports.output(base + 2, PRINTER_CONTROL as u16, false);
+            },
+            0x02 => (),
+            _ => return Err(format!("INT 17h function {:02X}h isn't supported", function)),
+        }
+        let status = (ports.input(base + 1, false) as u8 ^ PRINTER_INVERTED) & 0xF8;
+        cpu.ax = (cpu.ax & 0x00FF) | (status as u16) << 8;
+        Ok(ServiceResult::Handled)
+    }
+
+    /**
+     * The bootstrap loader, INT 19h. Loads the boot sector of the disk in A:
+     * at 0000:7C00 and starts it, with the drive in DL.
+     */
+    fn int19(&mut self, cpu: &mut MemoryUnit) -> Result<ServiceResult, String> {
+        let disk = self.floppies[0].as_ref().ok_or("there is no disk in A: to boot from")?;
+        let sector = disk.read_sector(0, 0, 1).ok_or("the disk in A: has no boot sector")?;
+        cpu.memory.write(&sector, BOOT_ADDRESS);
+        cpu.memory.code_segment = BOOT_ADDRESS.0;
+        cpu.ip = BOOT_ADDRESS.1;
+        cpu.dx = 0;
+        Ok(ServiceResult::Handled)
+    }
+
+    /**
+     * Time of day services, INT 1Ah. The tick count follows the clock of the host.
+     */
+    fn int1a(&mut self, cpu: &mut MemoryUnit) -> Result<ServiceResult, String> {
+        let function = (cpu.ax >> 8) as u8;
+        match function {
+            // once the timer interrupt runs the ticks are in the BIOS Data Area
+            0x00 if self.timer => {
+                cpu.cx = bda_word(&cpu.memory, BDA_TICKS + 2);
+                cpu.dx = bda_word(&cpu.memory, BDA_TICKS);
+                cpu.ax = (cpu.ax & 0xFF00) | bda_byte(&cpu.memory, BDA_MIDNIGHT) as u16;
+                set_bda_byte(&mut cpu.memory, BDA_MIDNIGHT, 0);
+            },
+            // the tick count in CX:DX, AL is set if midnight passed since the last read
+            0x00 => {
+                let elapsed = self.started.elapsed().as_micros() as u64 * TIMER_FREQUENCY / 65536 / 1_000_000;
+                let ticks = self.start_ticks as u64 + elapsed;
+                let days = ticks / TICKS_PER_DAY as u64;
+                let ticks = (ticks % TICKS_PER_DAY as u64) as u32;
+                let midnight = days > self.days;
+                self.days = days;
+                set_bda_word(&mut cpu.memory, BDA_TICKS, ticks as u16);
+                set_bda_word(&mut cpu.memory, BDA_TICKS + 2, (ticks >> 16) as u16);
+                set_bda_byte(&mut cpu.memory, BDA_MIDNIGHT, 0);
+                cpu.cx = (ticks >> 16) as u16;
+                cpu.dx = ticks as u16;
+                cpu.ax = (cpu.ax & 0xFF00) | midnight as u16;
+            },
+            0x01 => {
+                self.start_ticks = ((cpu.cx as u32) << 16 | cpu.dx as u32) % TICKS_PER_DAY;
+                self.started = Instant::now();
+                self.days = 0;
+                set_bda_word(&mut cpu.memory, BDA_TICKS, cpu.dx);
+                set_bda_word(&mut cpu.memory, BDA_TICKS + 2, cpu.cx);
+            },
+            _ => return Err(format!("INT 1Ah function {:02X}h isn't supported", function)),
+        }
+        Ok(ServiceResult::Handled)
+    }
+}
+
+impl InterruptService for Bios {
+    fn interrupt(&mut self, vector: u8, cpu: &mut MemoryUnit, ports: &mut PortBus) -> Result<ServiceResult, String> {
+        let installed = ENTRY_POINTS.iter()
+            .any(|(entry_vector, offset)| *entry_vector == vector
+                && cpu.memory.get_interrupt_vector(vector) == (BIOS_SEGMENT, *offset));
+        if !installed {
+            return Ok(ServiceResult::Unhandled)
+        }
+        match vector {
+            0x08 => self.int08(cpu, ports),
+            0x09 => self.int09(cpu, ports),
+            0x0E => self.int0e(cpu, ports),
+            0x10 => self.int10(cpu, ports),
+            0x11 => {
+                cpu.ax = bda_word(&cpu.memory, BDA_EQUIPMENT);
+                Ok(ServiceResult::Handled)
+            },
+            0x12 => {
+                cpu.ax = bda_word(&cpu.memory, BDA_MEMORY_SIZE);
+                Ok(ServiceResult::Handled)
+            },
+            0x13 => self.int13(cpu),
+            0x16 => self.int16(cpu),
+            0x17 => self.int17(cpu, ports),
+            0x19 => self.int19(cpu),
+            0x1A => self.int1a(cpu),
+            // nothing is hooked on the timer tick
+            USER_TICK => Ok(ServiceResult::Handled),
+            _ => Ok(ServiceResult::Unhandled),
+        }
+    }
+}
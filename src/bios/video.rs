@@ -0,0 +1,446 @@
+use crate::cpu::PortBus;
+use crate::cpu::memory_unit::memory_segments::MemorySegments;
+use super::*;
+
+// where the screen of the color and the monochrome adapters is
+pub const COLOR_SEGMENT: u16 = 0xB800;
+pub const MONO_SEGMENT: u16 = 0xB000;
+// the 320x200 screen of mode 13h of the VGA, a byte a pixel
+pub const VGA_SEGMENT: u16 = 0xA000;
+// the memory of each adapter
+const COLOR_MEMORY: usize = 0x4000;
+const MONO_MEMORY: usize = 0x1000;
+const VGA_MEMORY: usize = 320 * 200;
+
+const ROWS: u8 = 25;
+const BLANK: u8 = b' ';
+// light gray on black
+pub const DEFAULT_ATTRIBUTE: u8 = 0x07;
+// the cursor is on the last two scan lines of the 8 of a character
+const CURSOR_SHAPE: u16 = 0x0607;
+// the mode control register of each mode: 80 columns, graphics, black and white, enable, 640 dots, blink
+const MODE_CONTROL: [u8; 8] = [0x2C, 0x28, 0x2D, 0x29, 0x2A, 0x2E, 0x1E, 0x29];
+// the VGA ignores the mode control register in mode 13h, it's graphics and enabled
+const MODE_13_CONTROL: u8 = 0x0A;
+// the color select register: the border and background is black, mode 6 draws in white
+const PALETTE: u8 = 0x30;
+const MODE_6_PALETTE: u8 = 0x3F;
+// the CRTC registers of the cursor shape, the start address and the cursor address
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CRTC_START_ADDRESS: u8 = 0x0C;
+const CRTC_CURSOR_ADDRESS: u8 = 0x0E;
+// the attribute controller of the VGA, its index and data share a port
+const ATTRIBUTE_PORT: u16 = 0x3C0;
+const ATTRIBUTE_MODE_CONTROL: u8 = 0x10;
+// the index of the attribute controller with the bit that gives the screen back to the CRTC
+const ATTRIBUTE_ENABLE: u8 = 0x20;
+// the DAC of the VGA: the index to read, the index to write and the data, 3 colors an entry
+const DAC_READ_INDEX: u16 = 0x3C7;
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+const DAC_DATA: u16 = 0x3C9;
+pub const DAC_ENTRIES: usize = 256;
+
+/**
+ * Returns the columns of a mode and if it's a text mode, None if it isn't supported.
+ */
+fn mode_info(mode: u8) -> Option<(u16, bool)> {
+    match mode {
+        0 | 1 => Some((40, true)),
+        2 | 3 | 7 => Some((80, true)),
+        4 | 5 => Some((40, false)),
+        6 => Some((80, false)),
+        0x13 => Some((40, false)),
+        _ => None,
+    }
+}
+
+/**
+ * Returns the segment of the screen in a mode.
+ */
+pub fn screen_segment(mode: u8) -> u16 {
+    match mode {
+        7 => MONO_SEGMENT,
+        0x13 => VGA_SEGMENT,
+        _ => COLOR_SEGMENT,
+    }
+}
+
+/**
+ * Returns the size of the screen memory of a mode.
+ */
+fn screen_size(mode: u8) -> usize {
+    match mode {
+        7 => MONO_MEMORY,
+        0x13 => VGA_MEMORY,
+        _ => COLOR_MEMORY,
+    }
+}
+
+/**
+ * Returns the mode control register of the attribute controller of the VGA in
+ * a mode: 01h for graphics, 0Ch for text, 0Eh for monochrome text and 41h for
+ * mode 13h, where bit 6 puts the bits of 2 dots together into a color of the DAC.
+ */
+fn attribute_mode(mode: u8) -> u8 {
+    match (mode, mode_info(mode)) {
+        (0x13, _) => 0x41,
+        (7, _) => 0x0E,
+        (_, Some((_, true))) => 0x0C,
+        _ => 0x01,
+    }
+}
+
+/**
+ * Returns the palette the VGA BIOS loads into the DAC when it sets a mode, in
+ * 6 bits a color: the 16 colors of the CGA, 16 grays, then 24 hues in 3
+ * saturations in 3 intensities, and 8 black entries.
+ */
+pub fn default_dac() -> Vec<[u8; 3]> {
+    let mut dac = Vec::with_capacity(DAC_ENTRIES);
+    for i in 0..16u8 {
+        let intense = if i & 0x08 != 0 { 0x15 } else { 0 };
+        let level = |bit: u8| if i & bit != 0 { 0x2A } else { 0 } + intense;
+        // brown has half of the green
+        let green = if i == 6 { 0x15 } else { level(0x02) };
+        dac.push([level(0x04), green, level(0x01)]);
+    }
+    let grays = [0x00, 0x05, 0x08, 0x0B, 0x0E, 0x11, 0x14, 0x18, 0x1C, 0x20, 0x24, 0x28, 0x2D, 0x32, 0x38, 0x3F];
+    dac.extend(grays.iter().map(|&gray| [gray, gray, gray]));
+    // the high and low levels of each saturation and the 3 steps between them
+    let levels: [(u8, u8, [u8; 3]); 9] = [
+        (0x3F, 0x00, [0x10, 0x1F, 0x2F]), (0x3F, 0x1F, [0x27, 0x2F, 0x37]), (0x3F, 0x2D, [0x31, 0x36, 0x3A]),
+        (0x1C, 0x00, [0x07, 0x0E, 0x15]), (0x1C, 0x0E, [0x11, 0x15, 0x18]), (0x1C, 0x15, [0x17, 0x19, 0x1A]),
+        (0x10, 0x00, [0x04, 0x08, 0x0C]), (0x10, 0x08, [0x0A, 0x0C, 0x0E]), (0x10, 0x0B, [0x0C, 0x0D, 0x0F]),
+    ];
+    for (high, low, steps) in levels {
+        let up = [low, steps[0], steps[1], steps[2]];
+        let down = [high, steps[2], steps[1], steps[0]];
+        // blue to magenta to red to yellow to green to cyan and back to blue
+        for i in 0..4 { dac.push([up[i], low, high]); }
+        for i in 0..4 { dac.push([high, low, down[i]]); }
+        for i in 0..4 { dac.push([high, up[i], low]); }
+        for i in 0..4 { dac.push([down[i], high, low]); }
+        for i in 0..4 { dac.push([low, high, up[i]]); }
+        for i in 0..4 { dac.push([low, down[i], high]); }
+    }
+    dac.resize(DAC_ENTRIES, [0; 3]);
+    dac
+}
+
+/**
+ * Sets the video mode, AH=00h. Bit 7 of the mode keeps the screen.
+ * @param mode: the mode, 0-7 are the modes of the CGA and the MDA.
+ * @return: error if the mode isn't supported.
+ */
+pub fn set_mode(memory: &mut MemorySegments, mode: u8) -> Result<(), String> {
+    let clear = mode & 0x80 == 0;
+    let mode = mode & 0x7F;
+    let (columns, text) = mode_info(mode).ok_or(format!("video mode {:02X}h isn't supported", mode))?;
+    let page_size = match (text, columns) {
+        (true, 40) => 0x800,
+        (true, _) => 0x1000,
+        (false, _) => screen_size(mode) as u16,
+    };
+    set_bda_byte(memory, BDA_VIDEO_MODE, mode);
+    set_bda_word(memory, BDA_COLUMNS, columns);
+    set_bda_word(memory, BDA_PAGE_SIZE, page_size);
+    set_bda_word(memory, BDA_PAGE_OFFSET, 0);
+    memory.write(&vec![0; 16], (BDA_SEGMENT, BDA_CURSOR_POSITIONS));
+    set_bda_word(memory, BDA_CURSOR_SHAPE, CURSOR_SHAPE);
+    set_bda_byte(memory, BDA_ACTIVE_PAGE, 0);
+    set_bda_word(memory, BDA_CRTC_PORT, if mode == 7 { 0x3B4 } else { 0x3D4 });
+    set_bda_byte(memory, BDA_MODE_CONTROL, *MODE_CONTROL.get(mode as usize).unwrap_or(&MODE_13_CONTROL));
+    set_bda_byte(memory, BDA_PALETTE, if mode == 6 { MODE_6_PALETTE } else { PALETTE });
+    set_bda_byte(memory, BDA_ROWS, ROWS - 1);
+    set_bda_byte(memory, BDA_CHARACTER_HEIGHT, 8);
+    if clear {
+        let size = screen_size(mode);
+        let fill = if text {
+            [BLANK, DEFAULT_ATTRIBUTE].repeat(size / 2)
+        } else {
+            vec![0; size]
+        };
+        memory.write(&fill, (screen_segment(mode), 0));
+    }
+    Ok(())
+}
+
+fn is_text(memory: &MemorySegments) -> bool {
+    mode_info(bda_byte(memory, BDA_VIDEO_MODE)).map_or(false, |(_, text)| text)
+}
+
+fn columns(memory: &MemorySegments) -> u8 {
+    bda_word(memory, BDA_COLUMNS) as u8
+}
+
+fn rows(memory: &MemorySegments) -> u8 {
+    bda_byte(memory, BDA_ROWS) + 1
+}
+
+/**
+ * Returns where a character of a page is in memory.
+ */
+fn cell(memory: &MemorySegments, page: u8, row: u8, column: u8) -> (u16, u16) {
+    let offset = (page as u16).wrapping_mul(bda_word(memory, BDA_PAGE_SIZE))
+        .wrapping_add((row as u16 * columns(memory) as u16 + column as u16) * 2);
+    (screen_segment(bda_byte(memory, BDA_VIDEO_MODE)), offset)
+}
+
+/**
+ * Returns the row and the column of the cursor of a page.
+ */
+pub fn cursor(memory: &MemorySegments, page: u8) -> (u8, u8) {
+    let position = BDA_CURSOR_POSITIONS + (page & 7) as u16 * 2;
+    (bda_byte(memory, position + 1), bda_byte(memory, position))
+}
+
+pub fn set_cursor(memory: &mut MemorySegments, page: u8, row: u8, column: u8) {
+    let position = BDA_CURSOR_POSITIONS + (page & 7) as u16 * 2;
+    set_bda_byte(memory, position, column);
+    set_bda_byte(memory, position + 1, row);
+}
+
+/**
+ * Shows another page, AH=05h.
+ */
+pub fn set_active_page(memory: &mut MemorySegments, page: u8) {
+    let page = page & 7;
+    let offset = (page as u16).wrapping_mul(bda_word(memory, BDA_PAGE_SIZE));
+    set_bda_byte(memory, BDA_ACTIVE_PAGE, page);
+    set_bda_word(memory, BDA_PAGE_OFFSET, offset);
+}
+
+/**
+ * Scrolls a window of the active page, AH=06h and AH=07h.
+ * @param up: scroll up, otherwise down.
+ * @param lines: the lines to scroll, 0 clears the window.
+ * @param attribute: the attribute of the new blank lines.
+ * @param top_left: the row and the column of the top left corner.
+ * @param bottom_right: the row and the column of the bottom right corner.
+ */
+pub fn scroll(memory: &mut MemorySegments, up: bool, lines: u8, attribute: u8, top_left: (u8, u8), bottom_right: (u8, u8)) {
+    if !is_text(memory) {
+        return
+    }
+    let page = bda_byte(memory, BDA_ACTIVE_PAGE);
+    let (top, left) = top_left;
+    let bottom = bottom_right.0.min(rows(memory) - 1);
+    let right = bottom_right.1.min(columns(memory) - 1);
+    if top > bottom || left > right {
+        return
+    }
+    let height = bottom - top + 1;
+    let lines = if lines == 0 || lines > height { height } else { lines };
+    let width = (right - left + 1) as usize * 2;
+    let blank = [BLANK, attribute].repeat(width / 2);
+    for i in 0..height {
+        // scrolling down fills the window from the bottom
+        let row = if up { top + i } else { bottom - i };
+        let line = if i + lines < height {
+            let source = if up { row + lines } else { row - lines };
+            memory.read(cell(memory, page, source, left), width)
+        } else {
+            blank.clone()
+        };
+        memory.write(&line, cell(memory, page, row, left));
+    }
+}
+
+/**
+ * Returns the character and the attribute at the cursor of a page, AH=08h.
+ */
+pub fn read_char(memory: &MemorySegments, page: u8) -> (u8, u8) {
+    if !is_text(memory) {
+        return (0, 0)
+    }
+    let (row, column) = cursor(memory, page);
+    let bytes = memory.read(cell(memory, page, row, column), 2);
+    (bytes[0], bytes[1])
+}
+
+/**
+ * Writes a character a number of times from the cursor, without moving it, AH=09h and AH=0Ah.
+ * @param attribute: the attribute of the characters, None keeps the attributes on the screen.
+ * @param count: how many times the character is written, up to the end of the page.
+ */
+pub fn write_char(memory: &mut MemorySegments, page: u8, character: u8, attribute: Option<u8>, count: u16) {
+    if !is_text(memory) {
+        return
+    }
+    let (row, column) = cursor(memory, page);
+    let cells = rows(memory) as u16 * columns(memory) as u16;
+    let start = row as u16 * columns(memory) as u16 + column as u16;
+    let (segment, offset) = cell(memory, page, row, column);
+    for i in 0..count.min(cells.saturating_sub(start)) {
+        let offset = offset.wrapping_add(i * 2);
+        memory[(segment, offset)] = character;
+        if let Some(attribute) = attribute {
+            memory[(segment, offset.wrapping_add(1))] = attribute;
+        }
+    }
+}
+
+/**
+ * Writes a character like a terminal, AH=0Eh. Bell, backspace, line feed and
+ * carriage return move the cursor, other characters are written at the cursor
+ * and move it on, the page scrolls up when the cursor passes its bottom.
+ * @param attribute: the attribute of the character, None keeps the one on the screen.
+ */
+pub fn teletype(memory: &mut MemorySegments, page: u8, character: u8, attribute: Option<u8>) {
+    let (mut row, mut column) = cursor(memory, page);
+    match character {
+        0x07 => (),
+        0x08 => column = column.saturating_sub(1),
+        b'\n' => row += 1,
+        b'\r' => column = 0,
+        _ => {
+            write_char(memory, page, character, attribute, 1);
+            column += 1;
+        },
+    }
+    if column >= columns(memory) {
+        column = 0;
+        row += 1;
+    }
+    let bottom = rows(memory) - 1;
+    if row > bottom {
+        row = bottom;
+        // the new line gets the attribute of the line the cursor was on
+        let (segment, offset) = cell(memory, page, bottom, 0);
+        let line_attribute = memory[(segment, offset.wrapping_add(1))];
+        if page == bda_byte(memory, BDA_ACTIVE_PAGE) {
+            scroll(memory, true, 1, line_attribute, (0, 0), (bottom, columns(memory) - 1));
+        }
+    }
+    set_cursor(memory, page, row, column);
+}
+
+/**
+ * Returns where the byte of a pixel is and the shift and the mask of its bits.
+ * In the graphics modes of the CGA the even lines are in the first 8K of the
+ * screen, the odd ones in the second, 80 bytes a line. In mode 13h each pixel
+ * is a byte, 320 a line.
+ */
+fn pixel(memory: &MemorySegments, column: u16, row: u16) -> Option<((u16, u16), u8, u8)> {
+    let mode = bda_byte(memory, BDA_VIDEO_MODE);
+    let (bits, width) = match mode {
+        4 | 5 => (2, 320),
+        6 => (1, 640),
+        0x13 => (8, 320),
+        _ => return None,
+    };
+    if column >= width || row >= 200 {
+        return None
+    }
+    if bits == 8 {
+        return Some(((VGA_SEGMENT, row * width + column), 0, 0xFF))
+    }
+    let pixels_per_byte = 8 / bits;
+    let offset = (row & 1) * 0x2000 + (row >> 1) * 80 + column / pixels_per_byte;
+    let shift = (pixels_per_byte - 1 - column % pixels_per_byte) * bits;
+    Some(((COLOR_SEGMENT, offset), shift as u8, ((1 << bits) - 1) as u8))
+}
+
+/**
+ * Writes a pixel in a graphics mode, AH=0Ch. Bit 7 of the color XORs it with
+ * the pixel, except in mode 13h where it's part of the color.
+ */
+pub fn write_pixel(memory: &mut MemorySegments, column: u16, row: u16, color: u8) {
+    if let Some((address, shift, mask)) = pixel(memory, column, row) {
+        let value = (color & mask) << shift;
+        memory[address] = if color & 0x80 != 0 && mask != 0xFF {
+            memory[address] ^ value
+        } else {
+            memory[address] & !(mask << shift) | value
+        };
+    }
+}
+
+/**
+ * Returns the color of a pixel in a graphics mode, AH=0Dh.
+ */
+pub fn read_pixel(memory: &MemorySegments, column: u16, row: u16) -> u8 {
+    match pixel(memory, column, row) {
+        Some((address, shift, mask)) => (memory[address] >> shift) & mask,
+        None => 0,
+    }
+}
+
+/**
+ * Sets the color select register, AH=0Bh. With BH=0 the value is the background
+ * color (the foreground in 640x200) and its intensity, with BH=1 it selects the palette.
+ */
+pub fn set_palette(memory: &mut MemorySegments, id: u8, value: u8) {
+    let palette = bda_byte(memory, BDA_PALETTE);
+    let palette = match id {
+        0 => palette & 0xE0 | value & 0x1F,
+        _ => palette & 0xDF | (value & 0x01) << 5,
+    };
+    set_bda_byte(memory, BDA_PALETTE, palette);
+}
+
+/**
+ * Programs the adapter with the state in the BIOS Data Area, like the BIOS
+ * does in its video services: the CRTC registers of the cursor shape, the
+ * start of the active page and the cursor of the active page.
+ * @param mode: also set the mode control, the color select and the attribute
+ * mode control registers.
+ */
+pub fn program_adapter(memory: &MemorySegments, ports: &mut PortBus, mode: bool) {
+    let crtc = bda_word(memory, BDA_CRTC_PORT);
+    if mode {
+        // the mode control register is 4 ports after the CRTC, the color select register of the CGA 5
+        ports.output(crtc + 4, bda_byte(memory, BDA_MODE_CONTROL) as u16, false);
+        if crtc == 0x3D4 {
+            ports.output(crtc + 5, bda_byte(memory, BDA_PALETTE) as u16, false);
+        }
+        // reading the status register makes the next write to the attribute controller an index
+        ports.input(crtc + 6, false);
+        ports.output(ATTRIBUTE_PORT, ATTRIBUTE_MODE_CONTROL as u16, false);
+        ports.output(ATTRIBUTE_PORT, attribute_mode(bda_byte(memory, BDA_VIDEO_MODE)) as u16, false);
+        ports.output(ATTRIBUTE_PORT, ATTRIBUTE_ENABLE as u16, false);
+    }
+    let page = bda_byte(memory, BDA_ACTIVE_PAGE);
+    let start = bda_word(memory, BDA_PAGE_OFFSET) / 2;
+    let (row, column) = cursor(memory, page);
+    let [cursor_end, cursor_start] = bda_word(memory, BDA_CURSOR_SHAPE).to_le_bytes();
+    let address = start.wrapping_add(row as u16 * columns(memory) as u16 + column as u16);
+    // each register is written with its index in AL and its value in AH
+    let registers = [
+        (CRTC_CURSOR_START, cursor_start),
+        (CRTC_CURSOR_START + 1, cursor_end),
+        (CRTC_START_ADDRESS, (start >> 8) as u8),
+        (CRTC_START_ADDRESS + 1, start as u8),
+        (CRTC_CURSOR_ADDRESS, (address >> 8) as u8),
+        (CRTC_CURSOR_ADDRESS + 1, address as u8),
+    ];
+    for (index, value) in registers {
+        ports.output(crtc, (value as u16) << 8 | index as u16, true);
+    }
+}
+
+/**
+ * Writes colors to the DAC of the VGA, from an entry on.
+ */
+pub fn write_dac(ports: &mut PortBus, first: u8, colors: &[[u8; 3]]) {
+    ports.output(DAC_WRITE_INDEX, first as u16, false);
+    for color in colors {
+        for value in color {
+            ports.output(DAC_DATA, (value & 0x3F) as u16, false);
+        }
+    }
+}
+
+/**
+ * Returns colors of the DAC of the VGA, from an entry on.
+ */
+pub fn read_dac(ports: &mut PortBus, first: u8, count: usize) -> Vec<[u8; 3]> {
+    ports.output(DAC_READ_INDEX, first as u16, false);
+    (0..count).map(|_| {
+        let mut color = [0; 3];
+        for value in color.iter_mut() {
+            *value = ports.input(DAC_DATA, false) as u8;
+        }
+        color
+    }).collect()
+}
@@ -1,6 +1,16 @@
-use crate::cpu::CPU;
-use crate::program_loader::get_program;
-use crate::hardware::{Hardware, lightbulb::Lightbulb, keyboard::Keyboard};
+use crate::cpu::{CPU, PortAccess, PROGRAM_SEGMENT, PROGRAM_OFFSET, RESET_VECTOR, PORTS};
+use crate::bios::{Bios, install_bios, option_roms, set_serial_ports, set_printer_ports, set_floppy_drives, write_reset, BOOT_ADDRESS};
+use crate::debug_info::DebugInfo;
+use crate::dos::{Dos, console::DosConsole};
+use crate::program_loader::{load_program, LoadOptions, LoadError, Program, ProgramFormat};
+use crate::program_loader::dos::{install_dos, load_raw, load_com, load_exe, COM_OFFSET};
+use crate::program_loader::image::{write_blocks, load_bios_rom, Block, Records};
+use crate::hardware::{Hardware, IrqLines, lightbulb::Lightbulb, keyboard::Keyboard, console::Console, video::Video, pit::Pit, pic::Pic};
+use crate::hardware::speaker::Speaker;
+use crate::hardware::serial::{Uart, COM1, COM2, host::{self, SerialHost}};
+use crate::hardware::printer::{Printer, LPT1};
+use crate::hardware::dma::Dma;
+use crate::hardware::floppy::{Disk, Fdc};
 use std::sync::mpsc::TryRecvError;
 use std::thread;
 use std::sync::{Arc, Mutex, mpsc::channel};
@@ -12,6 +22,11 @@ use std::sync::{Arc, Mutex, mpsc::channel};
 pub struct HardwareList {
     pub lightbulb: bool,
     pub keyboard: bool,
+    pub console: bool,
+    pub video: bool,
+    pub timer: bool,
+    pub pic: bool,
+    pub speaker: bool,
 }
 
 impl HardwareList {
@@ -19,17 +34,39 @@ impl HardwareList {
         Self {
             lightbulb: false,
             keyboard: false,
+            console: false,
+            video: false,
+            timer: false,
+            pic: false,
+            speaker: false,
         }
     }
 
-    pub fn get_hardwares_vector(&self) -> Vec<Box<dyn Hardware>> {
+    /**
+     * Creates the connected hardware. The speaker is moved by the timer, it
+     * connects the timer too.
+     * @param irq: the interrupt request lines the hardware raises.
+     * @param speaker: the speaker, if connected.
+     */
+    pub fn get_hardwares_vector(&self, irq: &IrqLines, speaker: Option<&Speaker>) -> Vec<Box<dyn Hardware>> {
         let mut hardware_vec:Vec<Box<dyn Hardware>> = Vec::new();
         
         if self.lightbulb {
             hardware_vec.push(Box::new(Lightbulb::new()));
         }
-        if self.keyboard {
-            hardware_vec.push(Box::new(Keyboard::new()));
+        if self.console {
+            hardware_vec.push(Box::new(Console::new()));
+        }
+        if self.video {
+            hardware_vec.push(Box::new(Video::new()));
+        }
+        if self.timer || speaker.is_some() {
+            let mut pit = Pit::new(irq.clone());
+            pit.speaker = speaker.cloned();
+            hardware_vec.push(Box::new(pit));
+        }
+        if let Some(speaker) = speaker {
+            hardware_vec.push(Box::new(speaker.clone()));
         }
         hardware_vec
     }
@@ -38,6 +75,14 @@ impl HardwareList {
 pub struct Computer {
     pub cpu: CPU,
     pub connected_hardware: Arc<Mutex<Vec<Box<dyn Hardware>>>>,
+    // listing and labels of the program, if it was assembled
+    pub debug_info: Option<DebugInfo>,
+    // the interrupt request lines of the connected hardware
+    pub irq: IrqLines,
+    // the keyboard, if connected, to type on it
+    pub keyboard: Option<Keyboard>,
+    // the speaker, if connected, to save what it played
+    pub speaker: Option<Speaker>,
     ports: Arc<Mutex<Vec<u8>>>,
 }
 
@@ -45,26 +90,200 @@ impl Computer {
     /**
      * Creates a new Computer object and returns it.
      * @param program_path: the path to the program to load.
+     * @param hardware: the hardware to connect.
+     * @param options: the options for loading the program.
+     * @param console: the console of the DOS services.
+     * @return: the computer, or why the program couldn't be loaded.
+     */
+    pub fn new(program_path: &str, hardware: HardwareList, options: &LoadOptions, console: Box<dyn DosConsole>) -> Result<Self, LoadError> {
+        Self::with_serial_hosts(program_path, hardware, options, console, [None, None])
+    }
+
+    /**
+     * Creates two computers with their COM1 connected by a null-modem cable.
+     * Both load their program with the same hardware and options, but the host
+     * side of COM2, the printer and the floppy disks are opened once, for the
+     * first computer; the second always runs its program.
+     * @param programs: the paths to the programs of the two computers.
+     * @param consoles: the consoles of their DOS services.
+     * @return: the computers, or why a program couldn't be loaded.
+     */
+    pub fn linked(programs: [&str; 2], hardware: HardwareList, options: &LoadOptions,
+        consoles: [Box<dyn DosConsole>; 2]) -> Result<(Self, Self), LoadError> {
+        let (first_end, second_end) = host::null_modem();
+        let [first_console, second_console] = consoles;
+        let second_options = LoadOptions {
+            serial: [None, None],
+            printer: None,
+            floppies: [None, None],
+            boot: false,
+            ..options.clone()
+        };
+        let first = Self::with_serial_hosts(programs[0], hardware, options, first_console, [Some(Box::new(first_end)), None])?;
+        let second = Self::with_serial_hosts(programs[1], hardware, &second_options, second_console, [Some(Box::new(second_end)), None])?;
+        Ok((first, second))
+    }
+
+    /**
+     * Creates a new Computer object with serial ports connected to hosts
+     * that were already opened.
+     * @param hosts: the host side of COM1 and COM2, None to open the one of the options.
+     * @see new
      */
-    pub fn new(program_path: &str, hardware: HardwareList) -> Self {
+    pub fn with_serial_hosts(program_path: &str, hardware: HardwareList, options: &LoadOptions,
+        console: Box<dyn DosConsole>, hosts: [Option<Box<dyn SerialHost>>; 2]) -> Result<Self, LoadError> {
         use std::ops::DerefMut;
-        let ports = Arc::new(Mutex::new(vec![0; 0xFFFF]));
-        let hardware = Arc::new(Mutex::new(hardware.get_hardwares_vector()));
-        let (cpu_sender, handler_receiver) = channel::<(u16, bool)>();
+        // the disks of the floppy drives, the controller and the BIOS share them
+        let mut floppies = [None, None];
+        for (disk, path) in floppies.iter_mut().zip(options.floppies.iter()) {
+            if let Some(path) = path {
+                *disk = Some(Disk::open(path).map_err(|err| LoadError::Io(err))?);
+            }
+        }
+        let program = if options.boot {
+            boot_program(floppies[0].as_ref())?
+        } else {
+            load_program(program_path, options)?
+        };
+        let ports = Arc::new(Mutex::new(vec![0; PORTS]));
+        let irq = IrqLines::new();
+        let speaker = if hardware.speaker { Some(Speaker::new()) } else { None };
+        let mut hardware_vec = hardware.get_hardwares_vector(&irq, speaker.as_ref());
+        // the BIOS reads the keyboard through IRQ1, the keyboard connects the interrupt controller too
+        let pic = if hardware.pic || hardware.keyboard { Some(Pic::new(irq.clone())) } else { None };
+        if let Some(pic) = &pic {
+            hardware_vec.push(Box::new(pic.clone()));
+        }
+        let keyboard = if hardware.keyboard { Some(Keyboard::new(irq.clone())) } else { None };
+        if let Some(keyboard) = &keyboard {
+            hardware_vec.push(Box::new(keyboard.clone()));
+        }
+        // the serial ports that are connected to the host
+        let mut serial_ports = Vec::new();
+        for ((host, spec), port) in IntoIterator::into_iter(hosts).zip(options.serial.iter()).zip([COM1, COM2]) {
+            let host = match (host, spec) {
+                (Some(host), _) => host,
+                (None, Some(spec)) => host::open(spec).map_err(|err| LoadError::Io(err))?,
+                (None, None) => continue,
+            };
+            let mut uart = Uart::new(port, irq.clone(), host);
+            uart.paced = options.serial_pacing;
+            hardware_vec.push(Box::new(uart));
+            serial_ports.push(port.0);
+        }
+        // the printer, capturing to its file
+        let mut printer_ports = Vec::new();
+        if let Some(path) = &options.printer {
+            hardware_vec.push(Box::new(Printer::new(irq.clone(), path).map_err(|err| LoadError::Io(err))?));
+            printer_ports.push(LPT1);
+        }
+        // the floppy disk controller does its transfers on DMA
+        let dma = if floppies.iter().any(Option::is_some) { Some(Dma::new()) } else { None };
+        if let Some(dma) = &dma {
+            hardware_vec.push(Box::new(dma.clone()));
+            hardware_vec.push(Box::new(Fdc::new(irq.clone(), dma.clone(), floppies.clone())));
+        }
+        let hardware = Arc::new(Mutex::new(hardware_vec));
+        let (cpu_sender, handler_receiver) = channel::<PortAccess>();
         let (handler_sender, cpu_receiver) = channel::<u16>();
+        let format = program.format;
+        // the reset code is written once the program and the images are in memory
+        let mut cpu = CPU::init(&Vec::new(), ports.clone(), (cpu_sender, cpu_receiver), Some(&Vec::new()), None);
+        // the cpu shares the registers of the interrupt controller with the hardware
+        if let Some(pic) = pic {
+            cpu.interrupt_controller = Some(Box::new(pic));
+        }
+        if let Some(dma) = dma {
+            cpu.dma_controller = Some(Box::new(dma));
+        }
+        cpu.halt_policy = options.halt_policy;
+        let reset = options.reset_vector.unwrap_or(RESET_VECTOR);
+        cpu.set_reset_vector(reset);
+        install_dos(&mut cpu.memory_unit.memory);
+        match &options.bios {
+            Some(path) => load_bios_rom(&mut cpu.memory_unit.memory, path)?,
+            None => {
+                install_bios(&mut cpu.memory_unit.memory);
+                set_serial_ports(&mut cpu.memory_unit.memory, &serial_ports);
+                set_printer_ports(&mut cpu.memory_unit.memory, &printer_ports);
+                // B: is the second drive even when A: is empty
+                let drives = floppies.iter().rposition(Option::is_some).map_or(0, |drive| drive + 1);
+                set_floppy_drives(&mut cpu.memory_unit.memory, drives as u16);
+            },
+        }
+        let (segment, offset) = match format {
+            ProgramFormat::Raw => {
+                let segment = load_raw(&mut cpu.memory_unit, &program.bytes, options.load_segment, PROGRAM_OFFSET)?;
+                set_data_segments(&mut cpu, segment);
+                (segment, PROGRAM_OFFSET)
+            },
+            // the bytes of record files have their own addresses
+            ProgramFormat::Records => {
+                let records = program.records.as_ref().unwrap();
+                let start = records.start.unwrap_or((options.load_segment.unwrap_or(PROGRAM_SEGMENT), PROGRAM_OFFSET));
+                set_data_segments(&mut cpu, start.0);
+                write_blocks(&mut cpu.memory_unit.memory, &records.blocks, None);
+                start
+            },
+            ProgramFormat::Com => {
+                let segment = load_com(&mut cpu.memory_unit, &program.bytes, options.load_segment, &options.arguments)?;
+                (segment, COM_OFFSET)
+            },
+            ProgramFormat::Exe => {
+                let segment = load_exe(&mut cpu.memory_unit, &program.bytes, options.load_segment, &options.arguments)?;
+                (segment, 0)
+            },
+        };
+        // the loaders of DOS programs set CS:IP themselves
+        let dos_program = format == ProgramFormat::Com || format == ProgramFormat::Exe;
+        let start = if dos_program {
+            (cpu.memory_unit.memory.code_segment, cpu.memory_unit.ip)
+        } else {
+            (segment, offset)
+        };
         let mut computer = Self {
-            cpu: CPU::init(&get_program(&program_path),
-            ports.clone(),
-            (cpu_sender, cpu_receiver),
-            None,
-            None),
+            cpu: cpu,
             connected_hardware: hardware.clone(),
+            // the listing of an executable counts its header, which isn't loaded
+            debug_info: program.debug_info.filter(|_| format != ProgramFormat::Exe).map(|mut info| {
+                info.segment = segment;
+                info.offset = offset;
+                info
+            }),
+            irq: irq,
+            keyboard: keyboard,
+            speaker: speaker,
             ports: ports.clone()
         };
+        // DOS and the BIOS share the console
+        let console = Arc::new(Mutex::new(console));
+        computer.cpu.services.push(Box::new(Dos::new(Box::new(console.clone()), options.drive_root(program_path), segment)));
+        // a BIOS ROM has its own services
+        if options.bios.is_none() {
+            let mut bios = Bios::new(Box::new(console));
+            bios.keyboard = computer.keyboard.is_some();
+            bios.floppies = floppies;
+            computer.cpu.services.push(Box::new(bios));
+        }
+        // the images go over the program, before anything runs
+        for image in &options.images {
+            image.load(&mut computer.cpu.memory_unit.memory, options)?;
+        }
+        // the reset code of the built-in BIOS runs the option ROMs the images placed
+        // and jumps to the program, a BIOS ROM has its own
+        let roms = if options.bios.is_none() { option_roms(&computer.cpu.memory_unit.memory) } else { Vec::new() };
+        if options.bios.is_none() {
+            write_reset(&mut computer.cpu.memory_unit.memory, start, &roms);
+        }
+        // the loaders of DOS programs point CS:IP to them, which is where the reset
+        // code would jump, the cpu starts from the reset vector otherwise
+        if options.bios.is_some() || !dos_program || !roms.is_empty() || options.reset_vector.is_some() {
+            computer.cpu.set_reset_vector(reset);
+        }
         // init each hardware on memory
         let mut mhardware = hardware.lock().unwrap();
         for i in 0..mhardware.len() {
-            mhardware[i].deref_mut().init(&mut computer.cpu.memory_unit.memory);
+            mhardware[i].deref_mut().init(&mut computer.cpu.memory_unit.memory)?;
         }
         drop(mhardware);
 
@@ -75,11 +294,32 @@ impl Computer {
                         TryRecvError::Empty => (),
                         TryRecvError::Disconnected => break,
                     },
-                    Ok(port) =>  {
+                    // the clock goes on for all the hardware, the cpu waits for it
+                    // so the interrupts come after the same instructions on every run
+                    Ok(PortAccess::Clock(cycles)) => {
                         let mut hardwares = hardware.lock().unwrap();
                         for i in 0..hardwares.len() {
-                            if hardwares[i].is_port_related(&port.0) {
-                                hardwares[i].run_hardware(ports.lock().unwrap().deref_mut());
+                            hardwares[i].tick(cycles, ports.lock().unwrap().deref_mut());
+                        }
+                        handler_sender.send(10u16);
+                    },
+                    Ok(access) =>  {
+                        let mut hardwares = hardware.lock().unwrap();
+                        // a word access is an access to the port and the next one
+                        let (port, word) = match access {
+                            PortAccess::Out(port, word) | PortAccess::In(port, word) => (port, word),
+                            PortAccess::Clock(_) => unreachable!(),
+                        };
+                        let accessed = if word { vec![port, port.wrapping_add(1)] } else { vec![port] };
+                        for port in accessed {
+                            for i in 0..hardwares.len() {
+                                if !hardwares[i].is_port_related(&port) {
+                                    continue
+                                }
+                                match access {
+                                    PortAccess::Out(..) => hardwares[i].write_hardware(port, ports.lock().unwrap().deref_mut()),
+                                    _ => hardwares[i].read_hardware(port, ports.lock().unwrap().deref_mut()),
+                                }
                             }
                         }
                         handler_sender.send(10u16);
@@ -87,7 +327,7 @@ impl Computer {
                 }
             }
         });
-        computer
+        Ok(computer)
     }
 
     pub fn run(&mut self) {
@@ -97,4 +337,40 @@ impl Computer {
             assert!(true);
         });
     }
-}
\ No newline at end of file
+}
+
+/**
+ * Makes the boot sector of the disk in A: the program, loaded at 0000:7C00
+ * like the bootstrap loader of the BIOS does. DL is 0, the drive it came from.
+ * @param disk: the disk in A:.
+ */
+fn boot_program(disk: Option<&Disk>) -> Result<Program, LoadError> {
+    let disk = disk.ok_or(LoadError::Format("there is no disk in A: to boot from".to_string()))?;
+    let sector = disk.read_sector(0, 0, 1)
+        .ok_or(LoadError::Format("the disk in A: has no boot sector".to_string()))?;
+    let (segment, offset) = BOOT_ADDRESS;
+    Ok(Program {
+        bytes: Vec::new(),
+        debug_info: None,
+        format: ProgramFormat::Records,
+        records: Some(Records {
+            blocks: vec![Block {
+                address: (segment as u32) * 16 + offset as u32,
+                bytes: sector,
+            }],
+            start: Some(BOOT_ADDRESS),
+        }),
+    })
+}
+
+/**
+ * Puts the data and the stack of a program that isn't a DOS program
+ * in the segment it starts in.
+ * @param segment: the segment the program starts in.
+ */
+fn set_data_segments(cpu: &mut CPU, segment: u16) {
+    let memory = &mut cpu.memory_unit.memory;
+    memory.data_segment = segment;
+    memory.extra_segment = segment;
+    memory.stack_segment = segment;
+}
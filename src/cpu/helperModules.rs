@@ -137,15 +137,37 @@ pub enum ModrmRegField {
 
 /**
  * This function converts a given string to number from any base.
+ * The base is given with a prefix (0x, 0h, 0o, 0q, 0b, 0y, 0d, 0t)
+ * or a suffix (h, o, q, b, y, d, t), the way nasm accepts it.
  * @param string: the string to parse
  * @return: the resulted number.
  */
 pub fn string_to_number(string: &str) -> Result<u32, String> {
+    let string = string.replace('_', "").to_lowercase();
     // decimal
     match string.parse::<u32>() {
         Ok(number) => return Ok(number), 
         Err(_) => ()
     }
+    if string.len() < 2 {
+        return Err("bad number format".to_string())
+    }
+    // base prefix
+    if string.len() > 2 && string.starts_with('0') {
+        let radix = match &string[1..2] {
+            "x" | "h" => 16,
+            "o" | "q" => 8,
+            "b" | "y" => 2,
+            "d" | "t" => 10,
+            _ => 0,
+        };
+        if radix != 0 {
+            match u32::from_str_radix(&string[2..], radix) {
+                Ok(number) => return Ok(number), 
+                Err(_) => ()
+            }
+        }
+    }
     let numstring = &string[0..string.len() -1];
     // hex base
     if string.ends_with("h") {
@@ -155,18 +177,25 @@ pub fn string_to_number(string: &str) -> Result<u32, String> {
         }
     }
     // octal base
-    else if string.ends_with("o") {
-        match u32::from_str_radix(numstring, 2) {
+    else if string.ends_with("o") || string.ends_with("q") {
+        match u32::from_str_radix(numstring, 8) {
             Ok(number) => return Ok(number), 
             Err(_) => return Err("bad octal number format".to_string())
         }
     }
     // binary base
-    else if string.ends_with("b") {
+    else if string.ends_with("b") || string.ends_with("y") {
         match u32::from_str_radix(numstring, 2) {
             Ok(number) => return Ok(number), 
             Err(_) => return Err("bad binary number format".to_string())
         }
     }
+    // decimal base
+    else if string.ends_with("d") || string.ends_with("t") {
+        match numstring.parse::<u32>() {
+            Ok(number) => return Ok(number), 
+            Err(_) => return Err("bad decimal number format".to_string())
+        }
+    }
     return Err("bad number format".to_string())
 }
\ No newline at end of file
@@ -6,7 +6,7 @@ use super::*;
  * @param code: a 3 bits code representing the instruction.
  * @param inst: mutable reference to the InstructionFormat.
  */
-pub fn group1(code: u8, inst: &mut InstructionFormat){
+pub fn group1(code: u8, inst: &mut InstructionFormat) -> Result<(), String> {
     inst.execute = match code {
         // ADD
         0 => Some(Box::new(|dst, src, flags| dst.wrapping_add(src))),
@@ -41,10 +41,10 @@ pub fn group1(code: u8, inst: &mut InstructionFormat){
                 flags.sign = res >= 0x8000;
                 flags.overflow = overflow;
                 0 })),
-        _ => panic!("error tranlsating bonus bytes to instruciton")
+        _ => return Err("no instruction for this code".to_string())
     };
     inst.write = if code == 7 {false} else {true};
-
+    Ok(())
 }
 
 /**
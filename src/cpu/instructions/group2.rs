@@ -7,7 +7,7 @@ use super::*;
  * @param code: a 3 bits code representing the instruction.
  * @param inst: mutable reference to the InstructionFormat.
  */
-pub fn group2(code: u8, inst: &mut InstructionFormat){
+pub fn group2(code: u8, inst: &mut InstructionFormat) -> Result<(), String> {
     inst.execute = match code {
         // ROL
         0 => Some(Box::new(|dst, src, flags| dst.rotate_left(src as u32))),
@@ -35,8 +35,9 @@ pub fn group2(code: u8, inst: &mut InstructionFormat){
         
         // SAR
         7 => Some(Box::new(|dst, src, flags| (dst as i32 >> src) as u32)),
-        _ => panic!("error tranlsating bonus bytes to instruciton")
+        _ => return Err("no instruction for this code".to_string())
     };
+    Ok(())
 }
 
 /**
@@ -7,7 +7,7 @@ use super::*;
  * @param code: a 3 bits code representing the instruction.
  * @param inst: mutable reference to the InstructionFormat.
  */
-pub fn group3(code: u8, inst: &mut InstructionFormat){
+pub fn group3(code: u8, inst: &mut InstructionFormat) -> Result<(), String> {
     inst.execute = match code {
         // TEST
         0 => Some(Box::new(|dst, src, flags| dst & src)),
@@ -49,7 +49,7 @@ pub fn group3(code: u8, inst: &mut InstructionFormat){
                 (div + (((dst as i16 % src as i16) as i32 + 1) << 8)) as u32
             }
         })),
-        _ => panic!("error tranlsating bonus bytes to instruciton")
+        _ => return Err("no instruction for this code".to_string())
     };
 
     // some setting chagnes
@@ -69,6 +69,7 @@ pub fn group3(code: u8, inst: &mut InstructionFormat){
         inst.instrution_type = InstructionType::Math;
         inst.direction = true;
     };
+    Ok(())
 }
 
 /**
@@ -7,7 +7,7 @@ use super::*;
  * @param code: a 3 bits code representing the instruction.
  * @param inst: mutable reference to the InstructionFormat.
  */
-pub fn group4(code: u8, inst: &mut InstructionFormat){
+pub fn group4(code: u8, inst: &mut InstructionFormat) -> Result<(), String> {
     inst.execute = match code {
         // INC
         0 => Some(Box::new(|dst, src, flags| dst.wrapping_add(1))),
@@ -15,8 +15,9 @@ pub fn group4(code: u8, inst: &mut InstructionFormat){
         // DEC
         1 => Some(Box::new(|dst, src, flags| dst.wrapping_sub(1))),
         
-        _ => panic!("error tranlsating bonus bytes to instruciton")
+        _ => return Err("no instruction for this code".to_string())
     };
+    Ok(())
 }
 
 /**
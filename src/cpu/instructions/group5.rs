@@ -7,7 +7,7 @@ use super::*;
  * @param code: a 3 bits code representing the instruction.
  * @param inst: mutable reference to the InstructionFormat.
  */
-pub fn group5(code: u8, inst: &mut InstructionFormat){
+pub fn group5(code: u8, inst: &mut InstructionFormat) -> Result<(), String> {
     inst.execute = match code {
         // INC
         0 => Some(Box::new(|dst, src, flags| dst.wrapping_add(1))),
@@ -19,7 +19,7 @@ pub fn group5(code: u8, inst: &mut InstructionFormat){
         2 => Some(Box::new(|dst, src, flags| dst.wrapping_add(src))),
 
         // CALL Mp
-        3 => todo!("Group5 code 3 `CALL Mp`"),
+        3 => return Err("`CALL Mp` is not implemented".to_string()),
 
         // JMP
         4 => {
@@ -39,10 +39,10 @@ pub fn group5(code: u8, inst: &mut InstructionFormat){
             Some(Box::new(|dst, src, flags| src))
         },
         
-        _ => panic!("error tranlsating bonus bytes to instruciton")
+        _ => return Err("no instruction for this code".to_string())
     };
     inst.direction = if code == 6 {true} else {false};
-    
+    Ok(())
 }
 
 /**
@@ -20,6 +20,8 @@ pub enum InstructionType {
     ProgramFlow(bool),
     Prefix,
     Procedure,
+    // CALL far and RETF
+    FarProcedure,
     Math,
     Interrupt,
     SegmentOverride,
@@ -42,8 +44,9 @@ pub struct InstructionFormat {
  * @param group_number: number of the group.
  * @param group_code: the bonus 3 bit opcode.
  * @param inst: mutable reference to the InstructionFormat.
+ * Returns Err when the code is not an instruction of the group.
  */
-pub fn group_decode(group_number: u8, group_code: u8, inst: &mut InstructionFormat) {
+pub fn group_decode(group_number: u8, group_code: u8, inst: &mut InstructionFormat) -> Result<(), String> {
     match group_number {
         1 => group1::group1(group_code, inst),
         2 => group2::group2(group_code, inst),
@@ -55,6 +58,30 @@ pub fn group_decode(group_number: u8, group_code: u8, inst: &mut InstructionForm
     }
 }
 
+/**
+ * Returns about how many clock cycles of the 8088 an instruction takes, the
+ * time the emulated clock of the hardware counts with.
+ * @param inst: the InstructionFormat of the instruction that ran.
+ */
+pub fn instruction_cycles(inst: &InstructionFormat) -> u64 {
+    match inst.instrution_type {
+        // MUL and DIV
+        InstructionType::Math => 100,
+        InstructionType::Interrupt => 51,
+        InstructionType::FarProcedure => 32,
+        InstructionType::Procedure => 20,
+        InstructionType::ProgramFlow(_) => 15,
+        InstructionType::Stack => 12,
+        InstructionType::IO => 10,
+        InstructionType::Prefix | InstructionType::SegmentOverride => 2,
+        // the operands in memory take the time of their effective address
+        _ => match inst.operand_type {
+            OperandType::ModRM(..) => 12,
+            _ => 4,
+        },
+    }
+}
+
 /**
  * This function creates the InstruionFormat vector, which will then be turne into a map. 
  * @return: vecotr of tuples (u8, InstructionForamt)
@@ -203,6 +230,16 @@ pub fn instructions_decode() -> Vec<(u8, InstructionFormat)> {
             execute: Some(Box::new(|dst, src, flags| dst | src)),
             decode: None,
         }),
+        //MARK: 0x0E    PUSH CS
+        (0x0E, InstructionFormat{
+            operand_type: OperandType::Registers(Registers::RCS, None),
+            instrution_type: InstructionType::Stack,
+            word: true,
+            direction: true,
+            write: true,
+            execute: None,
+            decode: None,
+        }),
         //MARK: 0x10    ADC Eb Gb
         (0x10, InstructionFormat{
             operand_type: OperandType::ModRM(ModrmRegField::Reg, false),
@@ -275,6 +312,26 @@ pub fn instructions_decode() -> Vec<(u8, InstructionFormat)> {
                     flags.carry as u32)))),
             decode: None,
         }),
+        //MARK: 0x16    PUSH SS
+        (0x16, InstructionFormat{
+            operand_type: OperandType::Registers(Registers::RSS, None),
+            instrution_type: InstructionType::Stack,
+            word: true,
+            direction: true,
+            write: true,
+            execute: None,
+            decode: None,
+        }),
+        //MARK: 0x17    POP SS
+        (0x17, InstructionFormat{
+            operand_type: OperandType::Registers(Registers::RSS, None),
+            instrution_type: InstructionType::Stack,
+            word: true,
+            direction: false,
+            write: true,
+            execute: None,
+            decode: None,
+        }),
         //MARK: 0x18    SBB Eb Gb
         (0x018, InstructionFormat{
             operand_type: OperandType::ModRM(ModrmRegField::Reg, false),
@@ -347,6 +404,26 @@ pub fn instructions_decode() -> Vec<(u8, InstructionFormat)> {
             ))),
             decode: None,
         }),
+        //MARK: 0x1E    PUSH DS
+        (0x1E, InstructionFormat{
+            operand_type: OperandType::Registers(Registers::RDS, None),
+            instrution_type: InstructionType::Stack,
+            word: true,
+            direction: true,
+            write: true,
+            execute: None,
+            decode: None,
+        }),
+        //MARK: 0x1F    POP DS
+        (0x1F, InstructionFormat{
+            operand_type: OperandType::Registers(Registers::RDS, None),
+            instrution_type: InstructionType::Stack,
+            word: true,
+            direction: false,
+            write: true,
+            execute: None,
+            decode: None,
+        }),
         //MARK: 0x20    AND Eb Gb
         (0x20, InstructionFormat{
             operand_type: OperandType::ModRM(ModrmRegField::Reg, false),
@@ -1287,6 +1364,16 @@ pub fn instructions_decode() -> Vec<(u8, InstructionFormat)> {
             execute: None,
             decode: None,
         }),
+        //MARK: 0x9A    CALL far
+        (0x9A, InstructionFormat{
+            operand_type: OperandType::Data(0),
+            instrution_type: InstructionType::FarProcedure,
+            word: true,
+            direction: true,
+            write: true,
+            execute: Some(Box::new(|dst, src, flags| src)),
+            decode: None,
+        }),
         //MARK: 0x9C    PUSHFֵ
         (0x9C, InstructionFormat{
             operand_type: OperandType::None,
@@ -1387,9 +1474,9 @@ pub fn instructions_decode() -> Vec<(u8, InstructionFormat)> {
             execute: None,
             decode: None,
         }),
-        //MARK: 0xB2    MOV BL, Ib
+        //MARK: 0xB2    MOV DL, Ib
         (0xB2, InstructionFormat{
-            operand_type: OperandType::Data(Registers::BL),
+            operand_type: OperandType::Data(Registers::DL),
             instrution_type: InstructionType::General,
             word: false,
             direction: false,
@@ -1397,9 +1484,9 @@ pub fn instructions_decode() -> Vec<(u8, InstructionFormat)> {
             execute: None,
             decode: None,
         }),
-        //MARK: 0xB3    MOV DL, Ib
+        //MARK: 0xB3    MOV BL, Ib
         (0xB3, InstructionFormat{
-            operand_type: OperandType::Data(Registers::DL),
+            operand_type: OperandType::Data(Registers::BL),
             instrution_type: InstructionType::General,
             word: false,
             direction: false,
@@ -1567,6 +1654,26 @@ pub fn instructions_decode() -> Vec<(u8, InstructionFormat)> {
             execute: None,
             decode: None,
         }),
+        //MARK: 0XCA    RETF Iw
+        (0xCA, InstructionFormat{
+            operand_type: OperandType::Data(0),
+            instrution_type: InstructionType::FarProcedure,
+            word: true,
+            direction: false,
+            write: true,
+            execute: Some(Box::new(|dst, src, flags| src)),
+            decode: None,
+        }),
+        //MARK: 0XCB    RETF
+        (0xCB, InstructionFormat{
+            operand_type: OperandType::None,
+            instrution_type: InstructionType::FarProcedure,
+            word: true,
+            direction: false,
+            write: true,
+            execute: Some(Box::new(|dst, src, flags| src)),
+            decode: None,
+        }),
         //MARK: 0XCD    INT Ib
         (0xCD, InstructionFormat{
             operand_type: OperandType::Data(0),
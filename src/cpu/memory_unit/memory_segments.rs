@@ -1,14 +1,17 @@
 #![allow(dead_code)]
 
-use std::{
-    ops::{Index, IndexMut},
-    ptr::copy,
-};
+use std::ops::{Index, IndexMut};
 
 use crate::cpu::helperModules::Registers;
 
 const SEGMENT_SIZE: u32 = 0xFFFF;
 const MEMORY_SIZE: usize = 0x100000;
+// physical addresses wrap around at 1MB, like on the 8086
+const ADDRESS_MASK: u32 = 0xFFFFF;
+// room after the end of memory for a word or double word read through a pointer
+const MEMORY_SLACK: usize = 4;
+// the ROM of a BIOS can take the memory from C000:0000 to the end
+const MAX_ROM_SIZE: usize = 0x40000;
 /**
  * This enum helps access the needed segment of the memory.
  */
@@ -58,6 +61,8 @@ impl Segment {
  */
 pub struct MemoryPointer {
     len: u8,
+    // the bytes that can be written, the rest are in ROM
+    writable: u8,
     ptr: *mut u8,
 }
 impl MemoryPointer {
@@ -65,6 +70,7 @@ impl MemoryPointer {
     pub fn new(len: u8, ptr: *mut u8) -> Self {
         Self {
             len: len,
+            writable: len,
             ptr: ptr,
         }
     }
@@ -72,7 +78,7 @@ impl MemoryPointer {
     //Setter
     pub fn set(&mut self, val:u32) {
         let mut val = val;
-        for i in 0..self.len as usize {
+        for i in 0..self.writable as usize {
             unsafe { *self.ptr.add(i) = val as u8;}
             val >>= 8;
         }
@@ -95,6 +101,8 @@ pub struct MemorySegments {
      * but the `[]` operator is overloaded and can be accessed with it.
      */
     memory: Vec<u8>,
+    // the physical address the read-only memory starts at, MEMORY_SIZE if there is none
+    rom_start: u32,
 
     // Segment registers
     pub code_segment: u16,
@@ -106,7 +114,8 @@ impl MemorySegments {
     //Con
     pub fn new() -> Self {
         Self {
-            memory: vec![0; MEMORY_SIZE],
+            memory: vec![0; MEMORY_SIZE + MEMORY_SLACK],
+            rom_start: MEMORY_SIZE as u32,
             code_segment: 0x0000,
             data_segment: 0x0000,
             stack_segment: 0x0000,
@@ -116,18 +125,77 @@ impl MemorySegments {
 
     /**
      * This function gets a vector reference conatining data to write into
-     * segment:address pair.
+     * segment:address pair. Bytes that fall in ROM are left as they are.
      * @param data: a vector reference conatining a data to write into the memory.
      * @param add: a pair of segment:address. basically the location of memory
      *             to write into.
      */
     pub fn write(&mut self, data: &Vec<u8>, add: (u16, u16)) {
-        let address = get_physical_address(add) as usize;
-        unsafe {
-            copy(data.as_ptr(), self.memory.as_mut_ptr().add(address), data.len());
+        let address = get_physical_address(add);
+        for (i, byte) in data.iter().enumerate() {
+            let address = (address + i as u32) & ADDRESS_MASK;
+            if !self.is_read_only(address) {
+                self.memory[address as usize] = *byte;
+            }
         }
     }
 
+    /**
+     * This function places a ROM image at the top of the address space and makes it read-only.
+     * @param rom: the bytes of the ROM.
+     * @return: the physical address the ROM starts at, or an error if it's too big.
+     */
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<u32, String> {
+        if rom.is_empty() || rom.len() > MAX_ROM_SIZE {
+            return Err(format!("a ROM image must have 1 to {} bytes, not {}", MAX_ROM_SIZE, rom.len()))
+        }
+        let start = MEMORY_SIZE - rom.len();
+        self.memory[start..MEMORY_SIZE].copy_from_slice(rom);
+        self.rom_start = self.rom_start.min(start as u32);
+        Ok(start as u32)
+    }
+
+    /**
+     * This function returns true if a physical address is in ROM.
+     */
+    pub fn is_read_only(&self, address: u32) -> bool {
+        address >= self.rom_start
+    }
+
+    /**
+     * This function reads `len` bytes starting from segment:address.
+     * @param add: a pair of segment:address to read from.
+     * @param len: the number of bytes to read.
+     * @return: the bytes read.
+     */
+    pub fn read(&self, add: (u16, u16), len: usize) -> Vec<u8> {
+        let address = get_physical_address(add);
+        (0..len as u32).map(|i| self.memory[((address + i) & ADDRESS_MASK) as usize]).collect()
+    }
+
+    /**
+     * This function sets an entry of the interrupt vector table.
+     * Each entry is the offset of the handler followed by its segment.
+     * @param vector: the interrupt number.
+     * @param handler: a pair of segment:address of the interrupt handler.
+     */
+    pub fn set_interrupt_vector(&mut self, vector: u8, handler: (u16, u16)) {
+        let (segment, address) = handler;
+        let mut entry = address.to_le_bytes().to_vec();
+        entry.extend(&segment.to_le_bytes());
+        self.write(&entry, (0, vector as u16 * 4));
+    }
+
+    /**
+     * This function returns an entry of the interrupt vector table.
+     * @param vector: the interrupt number.
+     * @return: a pair of segment:address of the interrupt handler.
+     */
+    pub fn get_interrupt_vector(&self, vector: u8) -> (u16, u16) {
+        let entry = self.read((0, vector as u16 * 4), 4);
+        (u16::from_le_bytes([entry[2], entry[3]]), u16::from_le_bytes([entry[0], entry[1]]))
+    }
+
     /**
      * This function returns a pointer to a location in the memory.
      * @param seg: the segment from which to take the pointer.
@@ -138,6 +206,20 @@ impl MemorySegments {
         let p:*mut u8 = &mut self.memory[address];
         return p
     }
+
+    /**
+     * This function returns a MemoryPointer to a location in the memory,
+     * which doesn't write the bytes that are in ROM.
+     * @param len: the number of bytes to read/write.
+     * @param seg: the segment from which to take the pointer.
+     * @param add: the address to take.
+     */
+    pub fn pointer(&mut self, len: u8, seg: &Segment, add: u16) -> MemoryPointer {
+        let address = get_physical_address((seg.get_segment(self), add));
+        let mut pointer = MemoryPointer::new(len, self.get_memory_pointer(seg, add));
+        pointer.writable = self.rom_start.saturating_sub(address).min(len as u32) as u8;
+        pointer
+    }
 }
 
 //# `[]` operator overloading for easier memory access
@@ -162,5 +244,5 @@ impl IndexMut<(u16, u16)> for MemorySegments {
  */
 fn get_physical_address(addresses: (u16, u16)) -> u32 {
     let (segment, address) = addresses;
-    segment as u32 * 10 + address as u32
+    ((segment as u32) << 4).wrapping_add(address as u32) & ADDRESS_MASK
 }
\ No newline at end of file
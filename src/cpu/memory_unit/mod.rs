@@ -48,7 +48,7 @@ pub enum Operand {
                 if level {
                     return (*reg >> 8) as u32;
                 }
-                *reg as u32},
+                (*reg & 0xFF) as u32},
             Self::Memory(mem_pointer) => mem_pointer.get(),
             &Self::Data(data) => data as u32,
             Self::None => 0,
@@ -79,6 +79,8 @@ pub struct MemoryUnit {
 
     pub inst_bus: Buffer<u8>,
     pub segment: Segment,
+    // the segment of the instruction was given with a prefix
+    pub segment_override: bool,
     pub opcodes: HashMap<u8, InstructionFormat>,
 
     pub dst_operand: Operand,
@@ -102,7 +104,8 @@ impl MemoryUnit {
             ref_is_reg: true,
             memory: MemorySegments::new(),
             inst_bus: Buffer::new(6),
-            segment: Segment::CS,
+            segment: Segment::DS,
+            segment_override: false,
             opcodes: HashMap::new(),
             dst_operand: Operand::None,
             src_operand: Operand::None
@@ -178,7 +181,7 @@ impl MemoryUnit {
                 0 => 0,
                 // 8 bit diss
                 1 => {
-                    self.get_data(1).get() as u16
+                    self.get_data(1).get() as u8 as i8 as u16
                 }
                 // 16 bit diss
                 2 => {
@@ -189,14 +192,14 @@ impl MemoryUnit {
 
             // gets the address from a general register
             let address = match rm  {
-                0b000 => self.bx + self.si,
-                0b001 => self.bx + self.di,
-                0b010 => self.bp + self.si,
-                0b011 => self.bp + self.di,
+                0b000 => self.bx.wrapping_add(self.si),
+                0b001 => self.bx.wrapping_add(self.di),
+                0b010 => self.bp.wrapping_add(self.si),
+                0b011 => self.bp.wrapping_add(self.di),
                 0b100 => self.si,
                 0b101 => self.di,
                 0b110 => {
-                    if dis == 0 {
+                    if modf == 0 {
                         self.get_data(2).get() as u16
                     } else {
                         self.bp
@@ -205,12 +208,16 @@ impl MemoryUnit {
                 0b111 => self.bx,
                 _ => panic!("illegal R/M field"),
             };
-            let address = address + dis;
+            let address = address.wrapping_add(dis);
+
+            // addresses based on BP are in the stack segment
+            if !self.segment_override && (rm == 0b010 || rm == 0b011 || (rm == 0b110 && modf != 0)) {
+                self.segment = Segment::SS;
+            }
             
             // create the MemoryPointer
             let len: usize = if word{2} else {1};
-            let pointer =  MemoryPointer::new(len as u8,
-            self.memory.get_memory_pointer(&self.segment, address));
+            let pointer = self.memory.pointer(len as u8, &self.segment, address);
             
             Operand::Memory(pointer)
         }
@@ -243,7 +250,7 @@ impl MemoryUnit {
                     if inst.word {2} else {1}
                 );
                 match inst.instrution_type {
-                    InstructionType::Procedure =>(dst, src),
+                    InstructionType::Procedure | InstructionType::FarProcedure =>(dst, src),
                     _ => {
                         if inst.direction { (src, dst)}
                         else { (dst, src)}
@@ -302,7 +309,8 @@ impl MemoryUnit {
             &OperandType::Registers(reg1, reg2) => {
                 let dst = self.get_reg(reg1,ModrmRegField::Reg, inst.word);
                 let src = match reg2 {
-                    Some(reg) =>self.get_reg(reg,ModrmRegField::Reg, inst.word),
+                    // the port in DX is a word even when the data is a byte
+                    Some(reg) =>self.get_reg(reg,ModrmRegField::Reg, true),
                     None => Operand::None,
                 };
                 if inst.direction { (src, dst)}
@@ -325,9 +333,7 @@ impl MemoryUnit {
             InstructionType::Stack => {
                 // PUSH
                 if inst.direction {
-                    let stack_ref = Operand::Memory(MemoryPointer::new(
-                        2,
-                        self.memory.get_memory_pointer(&Segment::SS, self.sp -2)));
+                    let stack_ref = Operand::Memory(self.memory.pointer(2, &Segment::SS, self.sp.wrapping_sub(2)));
                     dst = stack_ref; 
                 }
                 // POP
@@ -343,13 +349,27 @@ impl MemoryUnit {
                 // RET
                 if !inst.direction {
                     // some cases pop out a number of words
-                    self.sp += src.get() as u16 * 2;
+                    self.sp = self.sp.wrapping_add(src.get() as u16 * 2);
                     let stack_ref = Operand::Memory(MemoryPointer::new(
                         2,
                         self.memory.get_memory_pointer(&Segment::SS, self.sp)));
                     src = stack_ref; 
                 }
             },
+            InstructionType::FarProcedure => {
+                dst = Operand::Register(&mut self.ip);
+                // CALL, the segment follows the offset
+                if inst.direction {
+                    let val = (self.get_data(2).get() << 16) + src.get();
+                    src = Operand::Data(val);
+                }
+                // RETF, the words to release are under the return address
+                else {
+                    let release = src.get() as u16;
+                    src = Operand::Memory(self.memory.pointer(4, &Segment::SS, self.sp));
+                    self.sp = self.sp.wrapping_add(release);
+                }
+            },
             InstructionType::Math => {
                 dst = Operand::Data(((self.dx as u32) << 16 ) + self.ax as u32);
                 self.flags.res1 = inst.word;
@@ -4,7 +4,8 @@
 use helperModules::*;
 use instructions::*;
 use memory_unit::{MemoryUnit};
-use memory_unit::memory_segments::{MemoryPointer, Segment};
+use services::{InterruptService, ServiceResult};
+use memory_unit::memory_segments::{MemoryPointer, MemorySegments, Segment};
 
 use std::{collections::HashMap};
 use std::sync::{Arc, Mutex, mpsc::{Sender, Receiver}};
@@ -12,28 +13,205 @@ use queues::*;
 
 pub mod helperModules;
 pub mod memory_unit;
+pub mod services;
 mod instructions;
 
+// where the program is loaded in memory
+pub const PROGRAM_SEGMENT: u16 = 0xDEEF;
+pub const PROGRAM_OFFSET: u16 = 0x100;
+// where the cpu starts after reset
+pub const RESET_VECTOR: (u16, u16) = (0xF000, 0xFFF0);
+// the size of the I/O address space, the ports vector holds a byte for each port
+pub const PORTS: usize = 0x10000;
+// the clock of the hardware is told about the cycles the cpu ran at least this often
+const CLOCK_STEP: u64 = 1000;
+pub const HLT: u8 = 0xF4;
+// the cycles that pass on each step HLT waits
+const HALT_CYCLES: u64 = 100;
+
+/**
+ * What HLT does.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HaltPolicy {
+    // HLT waits for an interrupt of the hardware, it ends the program only if
+    // none can come: IF is clear or there is no interrupt controller
+    Wait,
+    // HLT always ends the program, for exercises that end with it
+    Stop,
+}
+
+/**
+ * What a step of the cpu did.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Step {
+    // an instruction ran, HLT once the program ended
+    Ran(u8),
+    // HLT waits for an interrupt, the step only let the time go on
+    Waiting,
+}
+
+/**
+ * Message sent to the hardware thread whenever the cpu accesses a port.
+ * Holds the port number and if the access is word long.
+ * Clock holds the cycles the cpu ran since the last message. Each message is
+ * answered once the hardware handled it, so the hardware follows the cpu.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PortAccess {
+    In(u16, bool),
+    Out(u16, bool),
+    Clock(u64),
+}
+
+/**
+ * The ports of the cpu and the channel to the hardware thread, which lets
+ * the hardware react to every access. Used by IN and OUT and by the
+ * interrupt services that program hardware.
+ */
+pub struct PortBus {
+    ports: Arc<Mutex<Vec<u8>>>,
+    update_port: (Sender<PortAccess>, Receiver<u16>),
+    // the cycles the hardware wasn't told about yet
+    cycles: u64,
+}
+
+impl PortBus {
+    pub fn new(ports: Arc<Mutex<Vec<u8>>>, update_port: (Sender<PortAccess>, Receiver<u16>)) -> Self {
+        Self {
+            ports: ports,
+            update_port: update_port,
+            cycles: 0,
+        }
+    }
+
+    /**
+     * Advances the clock of the hardware by the cycles the cpu ran. The
+     * cycles are sent once there are enough of them, or before the next access.
+     */
+    pub fn advance(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        if self.cycles >= CLOCK_STEP {
+            self.send_clock();
+        }
+    }
+
+    /**
+     * Sends the cycles the hardware wasn't told about and waits for it to
+     * tick, so it's up to date when the cpu accesses it or looks for an interrupt.
+     */
+    fn send_clock(&mut self) {
+        if self.cycles != 0 {
+            self.update_port.0.send(PortAccess::Clock(self.cycles));
+            self.update_port.1.recv();
+            self.cycles = 0;
+        }
+    }
+
+    /**
+     * Writes a port and waits for the hardware to handle it.
+     * @param port: the port to write.
+     * @param value: the value, the high byte goes to the next port if word,
+     *      port 0 after FFFFh.
+     * @param word: if the access is word long.
+     */
+    pub fn output(&mut self, port: u16, value: u16, word: bool) {
+        self.send_clock();
+        let mut ports = self.ports.lock().unwrap();
+        ports[port as usize] = value as u8;
+        // assign next if word
+        if word {
+            ports[port.wrapping_add(1) as usize] = (value >> 8) as u8;
+        }
+        drop(ports);
+        self.update_port.0.send(PortAccess::Out(port, word));
+        self.update_port.1.recv();
+    }
+
+    /**
+     * Reads a port, after the hardware updated it.
+     * @param port: the port to read.
+     * @param word: if the access is word long, the next port is the high byte.
+     */
+    pub fn input(&mut self, port: u16, word: bool) -> u16 {
+        self.send_clock();
+        self.update_port.0.send(PortAccess::In(port, word));
+        self.update_port.1.recv();
+        let ports = self.ports.lock().unwrap();
+        let mut val = ports[port as usize] as u16;
+        if word {
+            val += (ports[port.wrapping_add(1) as usize] as u16) << 8;
+        }
+        val
+    }
+}
+
+/**
+ * The interrupt controller the cpu takes the interrupts of the hardware from.
+ */
+pub trait InterruptController: Send {
+    /**
+     * Acknowledges the interrupt request the cpu should take.
+     * @return: its vector, None if there is no request.
+     */
+    fn acknowledge(&mut self) -> Option<u8>;
+}
+
+/**
+ * The DMA controller that moves bytes between the hardware and the memory,
+ * the cpu lets it use the memory between its instructions.
+ */
+pub trait DmaController: Send {
+    /**
+     * Runs the transfers the hardware asked for.
+     * @param memory: the memory of the cpu.
+     */
+    fn transfer(&mut self, memory: &mut MemorySegments);
+}
+
 /**
  * cpu sturct
  */
 pub struct CPU {
     pub memory_unit: MemoryUnit,
     pub opcodes: HashMap<u8, InstructionFormat>,
-    ports: Arc<Mutex<Vec<u8>>>,
-    update_port: (Sender<(u16, bool)>, Receiver<u16>),
-    update_group: (bool, u8)
+    pub port_bus: PortBus,
+    update_group: (bool, u8),
+    // interrupts handled by the emulator
+    pub services: Vec<Box<dyn InterruptService>>,
+    // the return code of the program, once it ended through a service
+    pub exit_code: Option<u8>,
+    // the clock cycles the cpu ran
+    pub cycles: u64,
+    // where the interrupts of the hardware come from, while IF is set
+    pub interrupt_controller: Option<Box<dyn InterruptController>>,
+    // the DMA controller, it transfers between the instructions
+    pub dma_controller: Option<Box<dyn DmaController>>,
+    pub halt_policy: HaltPolicy,
+    // HLT waits for an interrupt
+    pub halted: bool,
+    // a native service waits for input, the hardware may interrupt it like
+    // the BIOS lets it with STI while it waits
+    service_waiting: bool,
 }
 
 impl CPU {
     // contructor
-    pub fn new(ports: Arc<Mutex<Vec<u8>>>, update_port: (Sender<(u16, bool)>, Receiver<u16>)) -> Self {
+    pub fn new(ports: Arc<Mutex<Vec<u8>>>, update_port: (Sender<PortAccess>, Receiver<u16>)) -> Self {
         let mut cpu = Self {
             memory_unit: MemoryUnit::new(),
             opcodes: HashMap::new(),
-            ports: ports,
-            update_port: update_port,
+            port_bus: PortBus::new(ports, update_port),
             update_group: (false, 0),
+            services: Vec::new(),
+            exit_code: None,
+            cycles: 0,
+            interrupt_controller: None,
+            dma_controller: None,
+            halt_policy: HaltPolicy::Wait,
+            halted: false,
+            service_waiting: false,
         };
         // initialize memory components
         cpu.memory_unit.flags.set(2);
@@ -42,11 +220,20 @@ impl CPU {
         // and CS is initialized with 0xF000. The physical address is 0xFFFF0.
         // The upper 16 bytes contain an initalization code for the cpu.
         // usaully contaning a JMP instruction to an actual initalization program.
-        cpu.memory_unit.ip = 0xFFF0;
-        cpu.memory_unit.memory.code_segment = 0xF000;
+        cpu.set_reset_vector(RESET_VECTOR);
         cpu
     }
 
+    /**
+     * Makes the cpu start at another address than F000:FFF0, like after a reset.
+     * @param vector: the segment:offset to start at.
+     */
+    pub fn set_reset_vector(&mut self, vector: (u16, u16)) {
+        let (segment, offset) = vector;
+        self.memory_unit.memory.code_segment = segment;
+        self.memory_unit.ip = offset;
+    }
+
     /**
      * This function creates a new CPU and initalizes it.
      * @param program: reference to a vector containing the program.
@@ -55,7 +242,7 @@ impl CPU {
      */
     pub fn init(program: &Vec<u8>,
         ports: Arc<Mutex<Vec<u8>>>,
-        update_port: (Sender<(u16, bool)>, Receiver<u16>), 
+        update_port: (Sender<PortAccess>, Receiver<u16>), 
         init_program: Option<&Vec<u8>>, 
         location: Option<&Vec<u8>>,) -> Self {
         let mut cpu = Self::new(ports, update_port);
@@ -80,7 +267,7 @@ impl CPU {
             None => &ini
         }, 
         (cpu.memory_unit.memory.code_segment, cpu.memory_unit.ip));
-        cpu.memory_unit.memory.write(program, (0xdeef, 0x100));
+        cpu.memory_unit.memory.write(program, (PROGRAM_SEGMENT, PROGRAM_OFFSET));
 
         cpu
         
@@ -93,7 +280,7 @@ impl CPU {
         loop {
             let res = self.run_next_instruction();
             // Halted
-            if res == Ok(0xF4 as u8)  {
+            if res == Ok(Step::Ran(HLT))  {
                 return Ok(())
             }
             // if Error
@@ -105,10 +292,41 @@ impl CPU {
 
     /**
      * Runs the next intruction in the code.
+     * @return: the opcode that ran, HLT once the program ended, or Waiting
+     *      while HLT waits for an interrupt.
      */
-    pub fn run_next_instruction(&mut self) -> Result<u8, String>{
+    pub fn run_next_instruction(&mut self) -> Result<Step, String>{
+        if let Some(controller) = self.dma_controller.as_mut() {
+            controller.transfer(&mut self.memory_unit.memory);
+        }
+        // the hardware interrupts come between instructions, and wake HLT; the
+        // hardware already ticked for the cycles sent at the end of the last one,
+        // so they come after the same instructions on every run
+        if self.memory_unit.flags.interrupt || self.service_waiting {
+            if let Some(vector) = self.interrupt_controller.as_mut().and_then(|controller| controller.acknowledge()) {
+                self.halted = false;
+                if let ServiceResult::Exit(code) = self.interrupt(vector)? {
+                    self.exit_code = Some(code);
+                    return Ok(Step::Ran(HLT))
+                }
+            }
+        }
+        self.service_waiting = false;
+        // the time goes on while HLT waits, the hardware ticks with it
+        if self.halted {
+            self.cycles += HALT_CYCLES;
+            self.port_bus.advance(HALT_CYCLES);
+            return Ok(Step::Waiting)
+        }
         // load and get instruction
-        self.load_instruction();
+        let instruction_ip = self.memory_unit.ip;
+        // the program ended through a service, reported like HLT
+        let mut ended = false;
+        // the fault is reported at the start of the instruction
+        if let Err(err) = self.load_instruction() {
+            self.memory_unit.ip = instruction_ip;
+            return Err(err)
+        }
         
         // get instructionFormat
         let opcode = self.memory_unit.inst_bus.remove().unwrap();
@@ -134,25 +352,38 @@ impl CPU {
                 InstructionType::Stack => {
                     dst.set(res as u32);
                     // PUSH
-                    if inst.direction { self.memory_unit.sp -= 2; }
+                    if inst.direction { self.memory_unit.sp = self.memory_unit.sp.wrapping_sub(2); }
                     // POP
-                    else { self.memory_unit.sp += 2; }
+                    else { self.memory_unit.sp = self.memory_unit.sp.wrapping_add(2); }
                 },
                 InstructionType::Procedure => {
                     // CALL
                     if inst.direction {
                         // push IP
-                        self.memory_unit.sp -=2;
-                        let mut stack_ref = MemoryPointer::new(
-                            2,
-                            self.memory_unit.memory.get_memory_pointer(&Segment::SS, self.memory_unit.sp));
+                        self.memory_unit.sp = self.memory_unit.sp.wrapping_sub(2);
+                        let mut stack_ref = self.memory_unit.memory.pointer(2, &Segment::SS, self.memory_unit.sp);
                         // sets the ip    
                         stack_ref.set(dst.get());
                     }
                     // RET
                     else {
-                        self.memory_unit.sp +=2;
+                        self.memory_unit.sp = self.memory_unit.sp.wrapping_add(2);
+                    }
+                    dst.set(res);
+                },
+                InstructionType::FarProcedure => {
+                    // CALL
+                    if inst.direction {
+                        // push CS:IP
+                        self.memory_unit.sp = self.memory_unit.sp.wrapping_sub(4);
+                        let mut stack_ref = self.memory_unit.memory.pointer(4, &Segment::SS, self.memory_unit.sp);
+                        stack_ref.set(((self.memory_unit.memory.code_segment as u32) << 16) + dst.get());
+                    }
+                    // RETF
+                    else {
+                        self.memory_unit.sp = self.memory_unit.sp.wrapping_add(4);
                     }
+                    self.memory_unit.memory.code_segment = (res >> 16) as u16;
                     dst.set(res);
                 },
                 InstructionType::Math => {
@@ -171,7 +402,7 @@ impl CPU {
                         let cs_ip = stack_ref.get();
                         self.memory_unit.ip = cs_ip as u16;
                         self.memory_unit.memory.code_segment = (cs_ip >> 16) as u16;
-                        self.memory_unit.sp += 4;
+                        self.memory_unit.sp = self.memory_unit.sp.wrapping_add(4);
                         
                         // pop freg
                         let stack_ref = MemoryPointer::new(
@@ -179,39 +410,24 @@ impl CPU {
                             self.memory_unit.memory.get_memory_pointer(&Segment::SS, self.memory_unit.sp));
                         let freg = stack_ref.get();
                         self.memory_unit.flags.set(freg as u16);
-                        self.memory_unit.sp += 2;
+                        self.memory_unit.sp = self.memory_unit.sp.wrapping_add(2);
+                        flags = self.memory_unit.flags;
                     }
                     // INT
                     else {
-                         // pushes freg   
-                        self.memory_unit.sp -=2;
-                        let mut stack_ref = MemoryPointer::new(
-                            2,
-                            self.memory_unit.memory.get_memory_pointer(&Segment::SS, self.memory_unit.sp));
-                        stack_ref.set(self.memory_unit.flags.as_word() as u32);
-                        
-                        // pushes cs:ip
-                        self.memory_unit.sp -=4;
-                        let mut stack_ref = MemoryPointer::new(
-                            4,
-                            self.memory_unit.memory.get_memory_pointer(&Segment::SS, self.memory_unit.sp));
-                        let mut cs_ip = self.memory_unit.memory.code_segment as u32;
-                        cs_ip = (cs_ip << 16) + self.memory_unit.ip as u32;
-                        stack_ref.set(cs_ip);
-
-                        // clear IF and TF
-                        self.memory_unit.flags.interrupt =false;
-                        self.memory_unit.flags.trap =false;
-                        
-                        // get address from IVT
-                        let interrupt_vector = MemoryPointer::new(
-                            4,
-                            self.memory_unit.memory.get_memory_pointer(&Segment::IVT, src.get() as u16 * 4));
-                        let interrupt_address = interrupt_vector.get();
-
-                        // set new cs:ip
-                        self.memory_unit.memory.code_segment = interrupt_address as u16;
-                        self.memory_unit.ip = (interrupt_address >> 16) as u16;
+                        match self.interrupt(src.get() as u8)? {
+                            // run the INT again on the next step
+                            ServiceResult::Wait => {
+                                self.memory_unit.ip = instruction_ip;
+                                self.service_waiting = true;
+                            },
+                            ServiceResult::Exit(code) => {
+                                self.exit_code = Some(code);
+                                ended = true;
+                            },
+                            _ => (),
+                        }
+                        flags = self.memory_unit.flags;
                     }
                 }
                 &InstructionType::ProgramFlow(far) => {
@@ -223,25 +439,14 @@ impl CPU {
                 }
                 // assign to port
                 &InstructionType::IO => {
-                    let mut ports = self.ports.lock().unwrap();
                     // OUT
                     if inst.direction {
-                        ports[dst.get() as usize] = src.get() as u8;
-                        // assign next if word
-                        if inst.word {
-                            ports[(dst.get()+1) as usize] = (src.get() >> 8) as u8;
-                        }
-                        self.update_port.0.send((dst.get() as u16, inst.word));
-                        drop(ports);
-                        self.update_port.1.recv();
+                        self.port_bus.output(dst.get() as u16, src.get() as u16, inst.word);
                     }
                     // IN
                     else {
-                        let mut val:u32 = ports[src.get() as usize] as u32;
-                        if inst.word {
-                            val += (ports[(src.get()+1) as usize] as u32) << 8;
-                        }
-                        dst.set(val);
+                        let val = self.port_bus.input(src.get() as u16, inst.word);
+                        dst.set(val as u32);
                     }
                 }
                 _ => dst.set(res)
@@ -250,6 +455,11 @@ impl CPU {
         // assign new flags value
         self.memory_unit.flags = flags;
 
+        // the time the instruction took goes to the clock of the hardware
+        let cycles = instruction_cycles(self.opcodes.get(&opcode).unwrap());
+        self.cycles += cycles;
+        self.port_bus.advance(cycles);
+
         // the instruction might be an updated Group instruction.
         // should update it back to being GroupInstruction.
         if self.update_group != (false, 0) {
@@ -258,49 +468,118 @@ impl CPU {
             self.update_group = (false, 0);
             inst.instrution_type = InstructionType::Group(group_number);
         }
-        Ok(opcode)
+        if ended {
+            return Ok(Step::Ran(HLT))
+        }
+        // HLT waits for an interrupt if one can come
+        if opcode == HLT && self.halt_policy == HaltPolicy::Wait
+            && self.memory_unit.flags.interrupt && self.interrupt_controller.is_some() {
+            self.halted = true;
+            return Ok(Step::Waiting)
+        }
+        Ok(Step::Ran(opcode))
+    }
+
+    /**
+     * Runs an interrupt, of an INT or of the hardware. The native services
+     * are asked first, otherwise the cpu pushes the flags and CS:IP, clears
+     * IF and TF and jumps through the IVT.
+     * @param vector: the interrupt number.
+     * @return: what the service did, Unhandled if the cpu jumped.
+     */
+    fn interrupt(&mut self, vector: u8) -> Result<ServiceResult, String> {
+        let mut result = ServiceResult::Unhandled;
+        for service in self.services.iter_mut() {
+            result = service.interrupt(vector, &mut self.memory_unit, &mut self.port_bus)?;
+            if result != ServiceResult::Unhandled {
+                break
+            }
+        }
+        match result {
+            ServiceResult::Unhandled => {
+                // pushes freg
+                self.memory_unit.sp = self.memory_unit.sp.wrapping_sub(2);
+                let mut stack_ref = self.memory_unit.memory.pointer(2, &Segment::SS, self.memory_unit.sp);
+                stack_ref.set(self.memory_unit.flags.as_word() as u32);
+
+                // pushes cs:ip
+                self.memory_unit.sp = self.memory_unit.sp.wrapping_sub(4);
+                let mut stack_ref = self.memory_unit.memory.pointer(4, &Segment::SS, self.memory_unit.sp);
+                let mut cs_ip = self.memory_unit.memory.code_segment as u32;
+                cs_ip = (cs_ip << 16) + self.memory_unit.ip as u32;
+                stack_ref.set(cs_ip);
+
+                // clear IF and TF
+                self.memory_unit.flags.interrupt = false;
+                self.memory_unit.flags.trap = false;
+
+                // get address from IVT and set new cs:ip
+                let (segment, address) = self.memory_unit.memory.get_interrupt_vector(vector);
+                self.memory_unit.memory.code_segment = segment;
+                self.memory_unit.ip = address;
+            },
+            // the service runs another interrupt as its last instruction
+            ServiceResult::Chain(next) => return self.interrupt(next),
+            _ => (),
+        }
+        Ok(result)
     }
 
     /**
      * This function loads the instruction into the inst_bus of memory_unit.
+     * Returns Err when the bytes after the prefixes are not an instruction the
+     * cpu knows.
      */
-    pub fn load_instruction(&mut self) {
+    pub fn load_instruction(&mut self) -> Result<(), String> {
         // make sure inst_bus is clear
         while self.memory_unit.inst_bus.size() > 0 {
             self.memory_unit.inst_bus.remove();
         }
-        let code_pointer = self.memory_unit.memory.get_memory_pointer(
-            &memory_unit::memory_segments::Segment::CS, self.memory_unit.ip);
+        // memory operands are in the data segment unless a prefix says otherwise
+        self.memory_unit.segment = Segment::DS;
+        self.memory_unit.segment_override = false;
+        let code_segment = self.memory_unit.memory.code_segment;
+        let ip = self.memory_unit.ip;
 
         // read instruction opcode
         self.memory_unit.read_code_segment(1);
 
-        let opcode = self.memory_unit.inst_bus.peek().unwrap();
-        let mut inst = self.opcodes.get_mut(&opcode).unwrap();
+        let mut opcode = self.memory_unit.inst_bus.peek().unwrap();
+        let mut inst = self.opcodes.get_mut(&opcode)
+            .ok_or(format!("unknown opcode {:02X} at {:04X}:{:04X}", opcode, code_segment, ip))?;
+
+        // the segment overrides come before the actual instruction
+        while let InstructionType::SegmentOverride = inst.instrution_type {
+            // overrdie the segment
+            let segment = match inst.operand_type {
+                OperandType::Registers(dst, _) => dst,
+                _ => panic!("error doing segment override")
+            };
+            self.memory_unit.segment = Segment::from_code(segment).unwrap();
+            self.memory_unit.segment_override = true;
+
+            // load the actual instruction
+            self.memory_unit.inst_bus.remove();
+            self.memory_unit.read_code_segment(1);
+            opcode = self.memory_unit.inst_bus.peek().unwrap();
+            inst = self.opcodes.get_mut(&opcode)
+                .ok_or(format!("unknown opcode {:02X} at {:04X}:{:04X}", opcode, code_segment, ip))?;
+        }
 
         // check for prefix or Group
         match inst.instrution_type {
-            InstructionType::Prefix => todo!("implement prefixes"),
+            InstructionType::Prefix => return Err(format!("unsupported prefix {:02X} at {:04X}:{:04X}",
+                opcode, code_segment, ip)),
             // Group
             InstructionType::Group(group_number) => {
-                let group_code = (unsafe{*code_pointer.add(1)}& 0b00111000 )>> 3;
-                instructions::group_decode(group_number % 10, group_code, inst);
+                // the reg field of the modRM byte that comes after the opcode
+                let modrm = self.memory_unit.memory[(code_segment, self.memory_unit.ip)];
+                let group_code = (modrm & 0b00111000) >> 3;
+                instructions::group_decode(group_number % 10, group_code, inst)
+                    .map_err(|err| format!("opcode {:02X} /{} at {:04X}:{:04X}: {}",
+                        opcode, group_code, code_segment, ip, err))?;
                 self.update_group = (true, group_number);
             },
-            InstructionType::SegmentOverride => {
-                // overrdie the segment
-                let segment = match inst.operand_type {
-                    OperandType::Registers(dst, _) => dst,
-                    _ => panic!("error doing segment override")
-                };
-                self.memory_unit.segment = Segment::from_code(segment).unwrap();
-
-                // load the actual instruction
-                self.memory_unit.inst_bus.remove();
-                self.memory_unit.read_code_segment(1);
-                let opcode = self.memory_unit.inst_bus.peek().unwrap();
-                inst = self.opcodes.get_mut(&opcode).unwrap();
-            }
             _ => (),
         }
 
@@ -334,8 +613,10 @@ impl CPU {
             OperandType::Registers(_,_) => (),
         }
 
-        if inst.instrution_type == InstructionType::ProgramFlow(true) {
+        if inst.instrution_type == InstructionType::ProgramFlow(true)
+            || (inst.instrution_type == InstructionType::FarProcedure && inst.direction) {
             self.memory_unit.read_code_segment(2);
         }
+        Ok(())
     }
 }
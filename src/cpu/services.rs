@@ -0,0 +1,34 @@
+use super::memory_unit::MemoryUnit;
+use super::PortBus;
+
+/**
+ * What a native interrupt handler did with an interrupt.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ServiceResult {
+    // the interrupt isn't handled natively, the cpu jumps through the IVT
+    Unhandled,
+    // the interrupt was handled, the program continues after the INT
+    Handled,
+    // the handler waits for input, the INT runs again on the next step
+    Wait,
+    // the program ended with a return code
+    Exit(u8),
+    // the interrupt was handled, then the cpu runs another one like an INT at its end
+    Chain(u8),
+}
+
+/**
+ * A software interrupt implemented by the emulator instead of code in memory,
+ * like the services of DOS. Handlers are asked in the order they were added.
+ */
+pub trait InterruptService: Send {
+    /**
+     * Handles an INT instruction.
+     * @param vector: the interrupt number.
+     * @param cpu: the registers and memory of the cpu, IP points after the INT.
+     * @param ports: the ports, for services that program the hardware.
+     * @return: what was done, or an error if the service isn't supported.
+     */
+    fn interrupt(&mut self, vector: u8, cpu: &mut MemoryUnit, ports: &mut PortBus) -> Result<ServiceResult, String>;
+}
@@ -0,0 +1,284 @@
+use crate::cpu::helperModules::string_to_number;
+
+// sections following .text are aligned to this, like the built-in assembler and nasm do
+const SECTION_ALIGN: usize = 4;
+
+/**
+ * A line of the listing: where it was assembled, its bytes and its source.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListingLine {
+    // the address the line was assembled at, including `org`
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub file: String,
+    pub line: usize,
+    pub source: String,
+}
+
+/**
+ * A label of the program.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u16,
+}
+
+/**
+ * What the assembler knows about the program, kept to debug it at the source level.
+ * Addresses are the ones the program was assembled with, `segment:offset` is where
+ * the first byte of the program was loaded.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct DebugInfo {
+    // the `org` of the program
+    pub origin: u16,
+    pub segment: u16,
+    pub offset: u16,
+    pub listing: Vec<ListingLine>,
+    pub symbols: Vec<Symbol>,
+}
+
+impl DebugInfo {
+    pub fn new(origin: u16, listing: Vec<ListingLine>, mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|symbol| symbol.address);
+        Self {
+            origin: origin,
+            segment: 0,
+            offset: 0,
+            listing: listing,
+            symbols: symbols,
+        }
+    }
+
+    /**
+     * Turns a location in memory into an address of the program.
+     * @return: the address, or None if the location isn't in the program's segment.
+     */
+    pub fn address(&self, segment: u16, offset: u16) -> Option<u16> {
+        if segment != self.segment {
+            return None
+        }
+        Some(offset.wrapping_sub(self.offset).wrapping_add(self.origin))
+    }
+
+    /**
+     * Returns the listing line which assembled the byte at `address`.
+     */
+    pub fn line_at(&self, address: u16) -> Option<&ListingLine> {
+        self.listing.iter().find(|line| {
+            let start = line.address as usize;
+            !line.bytes.is_empty() && (start..start + line.bytes.len()).contains(&(address as usize))
+        })
+    }
+
+    /**
+     * Returns the listing line of the instruction at `segment:offset`.
+     */
+    pub fn line_at_location(&self, segment: u16, offset: u16) -> Option<&ListingLine> {
+        self.address(segment, offset).and_then(|address| self.line_at(address))
+    }
+
+    /**
+     * Returns the address of a label.
+     */
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols.iter().find(|symbol| symbol.name == name).map(|symbol| symbol.address)
+    }
+
+    /**
+     * Returns where a label is in memory, the way `address` turns it back.
+     * @return: the `segment:offset` of the label, None if there's no such label.
+     */
+    pub fn symbol_location(&self, name: &str) -> Option<(u16, u16)> {
+        self.symbol(name).map(|address| (self.segment, address.wrapping_sub(self.origin).wrapping_add(self.offset)))
+    }
+
+    /**
+     * Returns the name of the label at an address, labels that aren't local first.
+     */
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        let mut labels = self.symbols.iter().filter(|symbol| symbol.address == address);
+        let first = labels.clone().next();
+        labels.find(|symbol| !symbol.name.contains('.'))
+            .or(first)
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    /**
+     * Parses the listing nasm writes with `-l`.
+     * Each line is `number address bytes source`; lines without bytes have no address.
+     * Addresses in the listing start at 0 for each section, so the sections
+     * are placed after .text the way `-f bin` does.
+     * @param text: the listing.
+     * @param file: the assembled file, nasm doesn't say which file included lines come from.
+     */
+    pub fn from_nasm_listing(text: &str, file: &str) -> Self {
+        let mut origin = 0;
+        let mut section = ".text".to_string();
+        let mut sections = vec![section.clone()];
+        // listing lines and labels with their section and offset in it
+        let mut lines: Vec<(String, ListingLine)> = Vec::new();
+        let mut labels: Vec<(String, String, Option<usize>)> = Vec::new();
+        let mut global = String::new();
+        let mut continues = false;
+
+        for raw in text.lines() {
+            if raw.len() < 7 {
+                continue
+            }
+            let number = match raw[..6].trim().parse::<usize>() {
+                Ok(number) => number,
+                Err(_) => continue,
+            };
+            let rest = &raw[7..];
+            let (address, data, source) = match rest.get(..8).map(|hex| usize::from_str_radix(hex, 16)) {
+                Some(Ok(address)) if rest.as_bytes().get(8) == Some(&b' ') => {
+                    let data_end = rest[9..].find("  ").map_or(rest.len(), |end| end + 9);
+                    (Some(address), rest[9..data_end].trim(), rest[data_end..].trim())
+                },
+                _ => (None, "", rest.trim()),
+            };
+            // the macro level, `<1>`, comes before the source
+            let source = match source.find('>') {
+                Some(end) if source.starts_with('<') => source[end + 1..].trim(),
+                _ => source,
+            };
+            let bytes = parse_listing_bytes(data);
+
+            // the bytes of a long line continue on the next lines
+            if continues {
+                if let Some((_, last)) = lines.last_mut() {
+                    last.bytes.extend(&bytes);
+                }
+                continues = data.ends_with('-');
+                continue
+            }
+            continues = data.ends_with('-');
+
+            let words: Vec<String> = source.trim_start_matches('[').split_whitespace()
+                .map(|word| word.trim_end_matches(']').to_string()).collect();
+            let first = words.get(0).map(|word| word.to_lowercase()).unwrap_or_default();
+            let second = words.get(1).map(|word| word.to_lowercase()).unwrap_or_default();
+            if (first == "section" || first == "segment") && words.len() > 1 {
+                section = words[1].clone();
+                if !sections.contains(&section) {
+                    sections.push(section.clone());
+                }
+            }
+            if first == "org" {
+                if let Some(Ok(val)) = words.get(1).map(|word| string_to_number(word)) {
+                    origin = val as usize;
+                }
+            }
+            // labels: `name:` or `name db ...`
+            let label = if first.ends_with(':') {
+                Some(words[0].trim_end_matches(':').to_string())
+            } else if ["db", "dw", "dd", "resb", "resw", "resd", "times"].contains(&second.as_str()) {
+                Some(words[0].clone())
+            } else {
+                None
+            };
+            if let Some(label) = label {
+                let name = if label.starts_with('.') {
+                    format!("{}{}", global, label)
+                } else {
+                    global = label.clone();
+                    label
+                };
+                labels.push((section.clone(), name, address));
+            }
+            // labels alone on their line get the address of the next line with bytes
+            if let Some(address) = address {
+                for label in labels.iter_mut().filter(|label| label.0 == section && label.2.is_none()) {
+                    label.2 = Some(address);
+                }
+            }
+            lines.push((section.clone(), ListingLine {
+                address: address.unwrap_or(0) as u16,
+                bytes: bytes,
+                file: file.to_string(),
+                line: number,
+                source: source.to_string(),
+            }));
+        }
+
+        // place the sections like `-f bin`: .text first, .bss last
+        let end_of = |name: &str| lines.iter()
+            .filter(|(section, _)| section == name)
+            .map(|(_, line)| line.address as usize + line.bytes.len())
+            .max()
+            .unwrap_or(0);
+        let (mut order, nobits): (Vec<String>, Vec<String>) = sections.into_iter().partition(|name| name != ".bss");
+        order.extend(nobits);
+        // start and length of each section
+        let mut places = Vec::new();
+        let mut address = origin;
+        for (i, name) in order.iter().enumerate() {
+            if i > 0 {
+                address = (address + SECTION_ALIGN - 1) / SECTION_ALIGN * SECTION_ALIGN;
+            }
+            places.push((name.clone(), address, end_of(name)));
+            address += end_of(name);
+        }
+        let place_of = |name: &str| places.iter()
+            .find(|(n, _, _)| n == name)
+            .map_or((origin, 0), |(_, start, len)| (*start, *len));
+
+        let symbols = labels.into_iter().map(|(section, name, address)| {
+            let (start, len) = place_of(&section);
+            Symbol {
+                address: (start + address.unwrap_or(len)) as u16,
+                name: name,
+            }
+        }).collect();
+        let listing = lines.into_iter().map(|(section, mut line)| {
+            line.address = (line.address as usize + place_of(&section).0) as u16;
+            line
+        }).collect();
+        Self::new(origin as u16, listing, symbols)
+    }
+}
+
+/**
+ * Parses the bytes column of a nasm listing. Relocated values are written
+ * in brackets or parentheses and repeated bytes as `00<rep 10h>`.
+ */
+fn parse_listing_bytes(data: &str) -> Vec<u8> {
+    let (data, repeat) = match data.find("<rep ") {
+        Some(i) => {
+            let count = data[i + 5..].trim_end_matches('>').trim();
+            (&data[..i], string_to_number(count).unwrap_or(1))
+        },
+        None => (data, 1),
+    };
+    // reserved space has no bytes
+    if data.starts_with("<res") {
+        return Vec::new()
+    }
+    let digits: Vec<char> = data.chars().filter(|c| c.is_ascii_hexdigit()).collect();
+    let bytes: Vec<u8> = digits.chunks(2)
+        .filter_map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).ok())
+        .collect();
+    bytes.repeat(repeat as usize)
+}
+
+/**
+ * Returns the target of a relative jump or call.
+ * @param bytes: the bytes of the instruction.
+ * @param offset: the offset of the instruction in its segment.
+ * @return: the offset the instruction jumps to, None if it isn't a relative jump.
+ */
+pub fn jump_target(bytes: &[u8], offset: u16) -> Option<u16> {
+    let (len, relative) = match bytes {
+        // jcc, jmp short, loop, jcxz
+        [0x70..=0x7F, rel, ..] | [0xEB, rel, ..] | [0xE0..=0xE3, rel, ..] => (2, *rel as i8 as i16 as u16),
+        // call and jmp near
+        [0xE8, low, high, ..] | [0xE9, low, high, ..] => (3, u16::from_le_bytes([*low, *high])),
+        // jcc near on the 386
+        [0x0F, 0x80..=0x8F, low, high, ..] => (4, u16::from_le_bytes([*low, *high])),
+        _ => return None,
+    };
+    Some(offset.wrapping_add(len).wrapping_add(relative))
+}
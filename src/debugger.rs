@@ -4,8 +4,9 @@ use console::Term;
 use queues::*;
 
 use crate::cpu::{memory_unit::memory_segments::Segment, helperModules};
+use crate::debug_info::{DebugInfo, jump_target};
 
-use super::cpu::CPU;
+use super::cpu::{CPU, Step, HLT};
 
 /**
  * The Debugger is the struct which runs the program on his own, which allows
@@ -16,6 +17,8 @@ pub struct Debugger {
     print_instruction: bool,
     //if true, this means that the program is halted.
     code_finished: bool,
+    // listing and labels of the program, if it was assembled.
+    debug_info: Option<DebugInfo>,
 }
 
 impl Debugger {
@@ -28,9 +31,28 @@ impl Debugger {
         Self{
             print_instruction: false,
             code_finished: false,
+            debug_info: None,
         }
     }
 
+    /**
+     * Sets the listing and labels used to show the source of the program.
+     * @param debug_info: the debug information of the loaded program.
+     */
+    pub fn set_debug_info(&mut self, debug_info: Option<DebugInfo>) {
+        self.debug_info = debug_info;
+    }
+
+    /**
+     * Returns the source line of the next instruction, as `file:line: source`.
+     * @param cpu: a reference to the cpu.
+     */
+    fn source_line(&self, cpu: &CPU) -> Option<String> {
+        let info = self.debug_info.as_ref()?;
+        let line = info.line_at_location(cpu.memory_unit.memory.code_segment, cpu.memory_unit.ip)?;
+        Some(format!("{}:{}: {}", line.file, line.line, line.source))
+    }
+
     /**
      * This is the main function for running the program in debug mode.
      * @param cpu: a mutable refernce to the cpu.
@@ -51,8 +73,16 @@ impl Debugger {
         loop {
             term.write(b">>> ");
 
-            // get the input and split to parameters
-            let input = term.read_line().expect("error");
+            // get the input and split to parameters, piped commands end with their input
+            let input = if term.is_term() {
+                term.read_line().expect("error")
+            } else {
+                let mut line = String::new();
+                if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                    break
+                }
+                line.trim_end().to_string()
+            };
             let split = input.split(' ').collect::<Vec<&str>>();
             let command = split[0];
             
@@ -62,6 +92,7 @@ impl Debugger {
                 _ if command == "run" => self.run(cpu, split),
                 _ if command == "sop" => self.showop(split),
                 _ if command == "echo" => self.echo(cpu, split),
+                _ if command == "line" => self.source_line(cpu).ok_or("no source for this address".to_string()),
                 _ if command == "restart" => unimplemented!(),
                 _ if command == "exit" => break,
                 _ if command == "" => Ok(format!("")),
@@ -108,17 +139,30 @@ impl Debugger {
             // print instruction
             if self.print_instruction {
                 let ip = cpu.memory_unit.ip;
-                cpu.load_instruction();
+                let _ = cpu.load_instruction();
+                let mut bytes = Vec::new();
                 while cpu.memory_unit.inst_bus.size() > 0 {
-                    print!{"0x{:02X?}\t", cpu.memory_unit.inst_bus.remove().unwrap()}
+                    bytes.push(cpu.memory_unit.inst_bus.remove().unwrap());
+                    print!{"0x{:02X?}\t", bytes[bytes.len() - 1]}
                 }
-                println!();
                 cpu.memory_unit.ip = ip;
+                // name the target of jumps and calls
+                if let (Some(info), Some(target)) = (&self.debug_info, jump_target(&bytes, ip)) {
+                    let label = info.address(cpu.memory_unit.memory.code_segment, target)
+                        .and_then(|address| info.label_at(address));
+                    if let Some(label) = label {
+                        print!("<{}>", label);
+                    }
+                }
+                println!();
+                if let Some(line) = self.source_line(cpu) {
+                    println!("{}", line);
+                }
             }
 
             let res = cpu.run_next_instruction();
             // Halted
-            if res == Ok(0xF4 as u8)  {
+            if res == Ok(Step::Ran(HLT))  {
                 self.code_finished = true;
                 return Ok(format!("program halted"))
             }
@@ -128,6 +172,9 @@ impl Debugger {
 
             count -= 1;
         }
+        if let Some(line) = self.source_line(cpu) {
+            return Ok(format!("{} lines runned succesfuly\n{}", c, line));
+        }
         return Ok(format!("{} lines runned succesfuly", c));
     }
 
@@ -135,7 +182,7 @@ impl Debugger {
      *# echo reg
      * Print value of a registers.
      *# echo [seg]:[add] -l [len]
-     * prints memory location.
+     * prints memory location, a label as the address is in the segment of the program.
      * @param cpu: a mutable reference to the cpu.
      * @param parameters: Vec containing the parameters of the command.
      */
@@ -179,8 +226,8 @@ impl Debugger {
                 }
                 // check segemnt
                 let segment = args[0].to_lowercase();
-                let segment = match Segment::from(&segment) {
-                    Result::Ok(val) => val,
+                let mut segment = match Segment::from(&segment) {
+                    Result::Ok(val) => val.get_segment(&cpu.memory_unit.memory),
                     Result::Err(_) => return Err(format!("bad `segmet` argument '{}'", segment))
                 };
 
@@ -188,10 +235,15 @@ impl Debugger {
                 let address = match helperModules::string_to_number(args[1]) {
                     Result::Ok(val) => val as u16,
                     Result::Err(_) => {
-                        // accept ip as address
+                        // accept ip and labels of the program as address
+                        let label = self.debug_info.as_ref().and_then(|info| info.symbol_location(args[1]));
                         if args[1].eq("ip") {
                             cpu.memory_unit.ip
                         }
+                        else if let Some((label_segment, offset)) = label {
+                            segment = label_segment;
+                            offset
+                        }
                         else{
                             return Err(format!("bad `address` argument '{}'", args[1]))
                         }
@@ -202,29 +254,29 @@ impl Debugger {
                 if parameters.len() == 4 && parameters[2] == "-l" {
                     let len_res = parameters[3].parse::<u16>();
                     if len_res.is_err() {
-                        return Err(format!("bad `length` argument '{}'", args[3]))
+                        return Err(format!("bad `length` argument '{}'", parameters[3]))
                     }
                     len = len_res.unwrap();
                     if len > 0x100 {
                         return Err(format!("length can't be more then {}", 0x100))
                     }
                 }
-                // print memory column
-                print!("\t");
+                // memory column
+                let mut out = "\t".to_string();
                 let columns = if len > 16 {16} else {len};
                 for i in 0..columns {
-                    print!("{:02X?}\t", i);
+                    out += &format!("{:02X?}\t", i);
                 }
-                // print memory
+                // memory
                 for i in 0..len {
-                    // print memory row
+                    // memory row
                     if i % 16 == 0 {
-                        print!("\n{:02X?}\t", i);
+                        out += &format!("\n{:02X?}\t", i);
                     }
-                    let data = unsafe{*cpu.memory_unit.memory.get_memory_pointer(&segment, address + i)};
-                    print!("0x{:02X?}\t", data);
+                    let data = cpu.memory_unit.memory[(segment, address.wrapping_add(i))];
+                    out += &format!("0x{:02X?}\t", data);
                 }
-                return Ok(format!(""))                
+                return Ok(out)
             }
             _ => return Err(format!("bad argument '{}'", parameters[1]))
         };
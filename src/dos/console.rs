@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+/**
+ * Where the DOS console services read and write characters.
+ */
+pub trait DosConsole: Send {
+    /**
+     * Writes a character to the screen.
+     */
+    fn write(&mut self, byte: u8);
+
+    /**
+     * Reads the next key. Consoles that can't block return None when no key
+     * was pressed, services that wait for a key try again later.
+     * @return: the key, or None if no key was pressed.
+     */
+    fn read(&mut self) -> Option<u8>;
+}
+
+/**
+ * A console used by more than one service, like DOS and the BIOS.
+ */
+impl DosConsole for Arc<Mutex<Box<dyn DosConsole>>> {
+    fn write(&mut self, byte: u8) {
+        self.lock().unwrap().write(byte);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.lock().unwrap().read()
+    }
+}
+
+/**
+ * Console on the stdin and stdout of the emulator, used in headless mode.
+ * Enter is read as a carriage return, like the keyboard gives it, and the
+ * end of the input as Ctrl-Z.
+ */
+pub struct StdConsole {
+    input_ended: bool,
+}
+
+impl StdConsole {
+    pub fn new() -> Self {
+        Self {
+            input_ended: false,
+        }
+    }
+}
+
+impl DosConsole for StdConsole {
+    fn write(&mut self, byte: u8) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(&[byte]);
+        let _ = stdout.flush();
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        let mut byte = [0u8];
+        if self.input_ended {
+            return Some(0x1A)
+        }
+        match std::io::stdin().read(&mut byte) {
+            Ok(1) if byte[0] == b'\n' => Some(b'\r'),
+            Ok(1) => Some(byte[0]),
+            _ => {
+                self.input_ended = true;
+                Some(0x1A)
+            },
+        }
+    }
+}
+
+/**
+ * Console shown in a window of the gui. The window and the DOS services
+ * share it, the window adds the typed keys and shows the output.
+ */
+#[derive(Clone)]
+pub struct SharedConsole {
+    output: Arc<Mutex<String>>,
+    input: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl SharedConsole {
+    pub fn new() -> Self {
+        Self {
+            output: Arc::new(Mutex::new(String::new())),
+            input: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /**
+     * Returns the text written to the console.
+     */
+    pub fn output(&self) -> String {
+        self.output.lock().unwrap().clone()
+    }
+
+    /**
+     * Adds typed text to the input, a new line is sent as Enter.
+     */
+    pub fn type_text(&self, text: &str) {
+        let mut input = self.input.lock().unwrap();
+        for byte in text.bytes() {
+            input.push_back(if byte == b'\n' { b'\r' } else { byte });
+        }
+    }
+}
+
+impl DosConsole for SharedConsole {
+    fn write(&mut self, byte: u8) {
+        let mut output = self.output.lock().unwrap();
+        match byte {
+            // carriage returns come before new lines
+            b'\r' => (),
+            // backspace
+            0x08 => {
+                output.pop();
+            },
+            // bell
+            0x07 => (),
+            _ => output.push(byte as char),
+        }
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.input.lock().unwrap().pop_front()
+    }
+}
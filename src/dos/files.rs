@@ -0,0 +1,369 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// DOS error codes, returned in AX with the carry flag set
+pub const ERROR_INVALID_FUNCTION: u16 = 0x01;
+pub const ERROR_FILE_NOT_FOUND: u16 = 0x02;
+pub const ERROR_PATH_NOT_FOUND: u16 = 0x03;
+pub const ERROR_TOO_MANY_FILES: u16 = 0x04;
+pub const ERROR_ACCESS_DENIED: u16 = 0x05;
+pub const ERROR_INVALID_HANDLE: u16 = 0x06;
+pub const ERROR_INVALID_ACCESS: u16 = 0x0C;
+pub const ERROR_NO_MORE_FILES: u16 = 0x12;
+
+// handles 0 to 4 are stdin, stdout, stderr, stdaux and stdprn
+pub const FIRST_FILE_HANDLE: u16 = 5;
+// the size of the job file table in the PSP
+const MAX_HANDLES: usize = 20;
+
+pub const ATTRIBUTE_DIRECTORY: u8 = 0x10;
+pub const ATTRIBUTE_ARCHIVE: u8 = 0x20;
+
+/**
+ * A file found by find first/next, with what DOS writes to the DTA about it.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct FoundFile {
+    pub name: String,
+    pub attribute: u8,
+    pub time: u16,
+    pub date: u16,
+    pub size: u32,
+}
+
+/**
+ * The files of the DOS services. A host directory is drive C:, paths
+ * are resolved in it and can't get out of it.
+ */
+pub struct FileSystem {
+    root: PathBuf,
+    // open files by handle, the standard handles are never here
+    handles: Vec<Option<File>>,
+    // what's left to find of the last find first
+    found: Vec<FoundFile>,
+}
+
+impl FileSystem {
+    /**
+     * Creates the file system.
+     * @param root: the host directory used as drive C:.
+     */
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root: root,
+            handles: (0..MAX_HANDLES).map(|_| None).collect(),
+            found: Vec::new(),
+        }
+    }
+
+    /**
+     * Turns a DOS path into a path of the host, inside the root directory.
+     * The drive is optional and must be C:, both '\' and '/' separate
+     * directories and names are matched without case, like DOS does.
+     * @param dos_path: the path given by the program.
+     * @return: the host path, or the DOS error if the path is invalid or leaves the root.
+     */
+    pub fn resolve(&self, dos_path: &str) -> Result<PathBuf, u16> {
+        let mut path = dos_path.trim();
+        if path.len() >= 2 && path.as_bytes()[1] == b':' {
+            if !path[..1].eq_ignore_ascii_case("c") {
+                return Err(ERROR_PATH_NOT_FOUND)
+            }
+            path = &path[2..];
+        }
+        let mut parts: Vec<String> = Vec::new();
+        for part in path.split(|c| c == '\\' || c == '/') {
+            match part {
+                "" | "." => (),
+                ".." => if parts.pop().is_none() {
+                    return Err(ERROR_ACCESS_DENIED)
+                },
+                _ if part.contains(':') => return Err(ERROR_PATH_NOT_FOUND),
+                _ => parts.push(part.to_string()),
+            }
+        }
+        let mut host = self.root.clone();
+        for part in parts {
+            host = match find_entry(&host, &part) {
+                Some(name) => host.join(name),
+                None => host.join(part),
+            };
+        }
+        // nothing is known to be inside a root that can't be found
+        let root = fs::canonicalize(&self.root).map_err(|_| ERROR_ACCESS_DENIED)?;
+        let parent = host.parent().unwrap_or(&self.root);
+        if !parent.is_dir() {
+            return Err(ERROR_PATH_NOT_FOUND)
+        }
+        // links may point out of the root, so the real path is checked, a missing
+        // file through its directory, and a link to a missing file can't be checked
+        let real = match fs::canonicalize(&host) {
+            Ok(real) => real,
+            Err(_) if fs::symlink_metadata(&host).is_ok() => return Err(ERROR_ACCESS_DENIED),
+            Err(_) => fs::canonicalize(parent).map_err(|_| ERROR_PATH_NOT_FOUND)?,
+        };
+        if !real.starts_with(&root) {
+            return Err(ERROR_ACCESS_DENIED)
+        }
+        Ok(host)
+    }
+
+    /**
+     * Takes a free handle for a file.
+     */
+    fn add_handle(&mut self, file: File) -> Result<u16, u16> {
+        let free = (FIRST_FILE_HANDLE as usize..MAX_HANDLES).find(|handle| self.handles[*handle].is_none())
+            .ok_or(ERROR_TOO_MANY_FILES)?;
+        self.handles[free] = Some(file);
+        Ok(free as u16)
+    }
+
+    fn file(&mut self, handle: u16) -> Result<&mut File, u16> {
+        self.handles.get_mut(handle as usize)
+            .and_then(|file| file.as_mut())
+            .ok_or(ERROR_INVALID_HANDLE)
+    }
+
+    /**
+     * Creates a file or truncates it, AH=3Ch.
+     * @return: the handle of the file.
+     */
+    pub fn create(&mut self, dos_path: &str) -> Result<u16, u16> {
+        let path = self.resolve(dos_path)?;
+        if path.is_dir() {
+            return Err(ERROR_ACCESS_DENIED)
+        }
+        let file = File::create(&path).map_err(|err| io_error(&err))?;
+        self.add_handle(file)
+    }
+
+    /**
+     * Opens a file, AH=3Dh.
+     * @param mode: 0 to read, 1 to write, 2 for both.
+     * @return: the handle of the file.
+     */
+    pub fn open(&mut self, dos_path: &str, mode: u8) -> Result<u16, u16> {
+        let path = self.resolve(dos_path)?;
+        let mut options = OpenOptions::new();
+        match mode & 0x07 {
+            0 => options.read(true),
+            1 => options.write(true),
+            2 => options.read(true).write(true),
+            _ => return Err(ERROR_INVALID_ACCESS),
+        };
+        if path.is_dir() {
+            return Err(ERROR_ACCESS_DENIED)
+        }
+        let file = options.open(&path).map_err(|err| io_error(&err))?;
+        self.add_handle(file)
+    }
+
+    /**
+     * Closes a file, AH=3Eh.
+     */
+    pub fn close(&mut self, handle: u16) -> Result<(), u16> {
+        self.file(handle)?;
+        self.handles[handle as usize] = None;
+        Ok(())
+    }
+
+    /**
+     * Reads from a file, AH=3Fh.
+     * @return: the bytes read, fewer than `count` at the end of the file.
+     */
+    pub fn read(&mut self, handle: u16, count: usize) -> Result<Vec<u8>, u16> {
+        let file = self.file(handle)?;
+        let mut data = vec![0; count];
+        let mut read = 0;
+        while read < count {
+            match file.read(&mut data[read..]) {
+                Ok(0) => break,
+                Ok(len) => read += len,
+                Err(err) => return Err(io_error(&err)),
+            }
+        }
+        data.truncate(read);
+        Ok(data)
+    }
+
+    /**
+     * Writes to a file, AH=40h. Writing nothing truncates the file at the current position.
+     * @return: the number of bytes written.
+     */
+    pub fn write(&mut self, handle: u16, data: &[u8]) -> Result<u16, u16> {
+        let file = self.file(handle)?;
+        if data.is_empty() {
+            let position = file.stream_position().map_err(|err| io_error(&err))?;
+            file.set_len(position).map_err(|err| io_error(&err))?;
+            return Ok(0)
+        }
+        file.write_all(data).map_err(|err| io_error(&err))?;
+        Ok(data.len() as u16)
+    }
+
+    /**
+     * Moves the position in a file, AH=42h.
+     * @param origin: 0 from the start, 1 from the current position, 2 from the end.
+     * @param offset: the signed distance to move.
+     * @return: the new position.
+     */
+    pub fn seek(&mut self, handle: u16, origin: u8, offset: i32) -> Result<u32, u16> {
+        let file = self.file(handle)?;
+        let from = match origin {
+            0 => SeekFrom::Start(offset as u32 as u64),
+            1 => SeekFrom::Current(offset as i64),
+            2 => SeekFrom::End(offset as i64),
+            _ => return Err(ERROR_INVALID_FUNCTION),
+        };
+        file.seek(from).map(|position| position as u32).map_err(|err| io_error(&err))
+    }
+
+    /**
+     * Deletes a file, AH=41h.
+     */
+    pub fn delete(&mut self, dos_path: &str) -> Result<(), u16> {
+        let path = self.resolve(dos_path)?;
+        if path.is_dir() {
+            return Err(ERROR_ACCESS_DENIED)
+        }
+        fs::remove_file(&path).map_err(|err| io_error(&err))
+    }
+
+    /**
+     * Starts a search, AH=4Eh. The name may have the wildcards '*' and '?'.
+     * Only names that fit in 8.3 are found, directories only if the attributes ask for them.
+     * @param attributes: the attributes of the search, from CX.
+     * @return: the first file found.
+     */
+    pub fn find_first(&mut self, dos_path: &str, attributes: u8) -> Result<FoundFile, u16> {
+        let (directory, pattern) = match dos_path.rfind(|c| c == '\\' || c == '/' || c == ':') {
+            Some(i) => (&dos_path[..i + 1], &dos_path[i + 1..]),
+            None => ("", dos_path),
+        };
+        let directory = self.resolve(directory)?;
+        let entries = fs::read_dir(&directory).map_err(|_| ERROR_PATH_NOT_FOUND)?;
+        let pattern = short_name_template(pattern).ok_or(ERROR_FILE_NOT_FOUND)?;
+        let mut found = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_uppercase();
+            let template = match short_name_template(&name) {
+                Some(template) if !name.contains(|c| c == '*' || c == '?') => template,
+                _ => continue,
+            };
+            if !template.iter().zip(pattern.iter()).all(|(c, p)| *p == b'?' || c == p) {
+                continue
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let attribute = if metadata.is_dir() { ATTRIBUTE_DIRECTORY } else { ATTRIBUTE_ARCHIVE };
+            if metadata.is_dir() && attributes & ATTRIBUTE_DIRECTORY == 0 {
+                continue
+            }
+            let seconds = metadata.modified().ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_secs());
+            let (date, time) = dos_date_time(seconds);
+            found.push(FoundFile {
+                name: name,
+                attribute: attribute,
+                time: time,
+                date: date,
+                size: metadata.len().min(u32::MAX as u64) as u32,
+            });
+        }
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        found.reverse();
+        self.found = found;
+        self.find_next()
+    }
+
+    /**
+     * Continues the last search, AH=4Fh.
+     * @return: the next file found.
+     */
+    pub fn find_next(&mut self) -> Result<FoundFile, u16> {
+        self.found.pop().ok_or(ERROR_NO_MORE_FILES)
+    }
+}
+
+/**
+ * Finds an entry of a directory by name, without case.
+ */
+fn find_entry(directory: &Path, name: &str) -> Option<String> {
+    let entries = fs::read_dir(directory).ok()?;
+    entries.flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .find(|entry| entry.eq_ignore_ascii_case(name))
+}
+
+/**
+ * Turns a name into the 11 characters of an 8.3 directory entry, a '*' fills
+ * its part with '?'. Returns None if the name doesn't fit.
+ */
+fn short_name_template(name: &str) -> Option<[u8; 11]> {
+    let name = name.to_uppercase();
+    if name == "." || name == ".." {
+        return None
+    }
+    let (base, extension) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name.as_str(), ""),
+    };
+    let mut template = [b' '; 11];
+    for (part, start, len) in [(base, 0, 8), (extension, 8, 3)] {
+        let mut i = 0;
+        for c in part.bytes() {
+            if c == b'*' {
+                for slot in &mut template[start + i..start + len] {
+                    *slot = b'?';
+                }
+                break
+            }
+            if i == len || c == b'.' || c == b' ' {
+                return None
+            }
+            template[start + i] = c;
+            i += 1;
+        }
+    }
+    Some(template)
+}
+
+/**
+ * Turns seconds since 1970 into the packed date and time of DOS.
+ * @return: (year-1980 << 9 | month << 5 | day, hours << 11 | minutes << 5 | seconds / 2)
+ */
+fn dos_date_time(seconds: u64) -> (u16, u16) {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    // days to a civil date
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    let year = (year - 1980).clamp(0, 127);
+    let date = (year << 9 | month << 5 | day) as u16;
+    let time = ((time / 3600) << 11 | (time / 60 % 60) << 5 | (time % 60) / 2) as u16;
+    (date, time)
+}
+
+/**
+ * Turns an error of the host into a DOS error code.
+ */
+fn io_error(err: &std::io::Error) -> u16 {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::NotFound => ERROR_FILE_NOT_FOUND,
+        ErrorKind::PermissionDenied => ERROR_ACCESS_DENIED,
+        _ => ERROR_ACCESS_DENIED,
+    }
+}
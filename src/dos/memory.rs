@@ -0,0 +1,261 @@
+use crate::cpu::memory_unit::memory_segments::MemorySegments;
+
+// DOS error codes of the memory functions
+pub const ERROR_ARENA_TRASHED: u16 = 0x07;
+pub const ERROR_NOT_ENOUGH_MEMORY: u16 = 0x08;
+pub const ERROR_INVALID_BLOCK: u16 = 0x09;
+
+// the first memory control block, the memory below it belongs to DOS
+pub const FIRST_MCB: u16 = 0x0FFF;
+// the owner of a free block
+const FREE: u16 = 0;
+// every block but the last is marked 'M', the last one 'Z'
+const MIDDLE: u8 = b'M';
+const LAST: u8 = b'Z';
+// a chain longer than this can only be a loop
+const MAX_BLOCKS: usize = 0x10000;
+
+/**
+ * A memory control block, the paragraph before each block of memory.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mcb {
+    // the segment of the control block, the block starts in the next paragraph
+    pub segment: u16,
+    pub last: bool,
+    // the PSP of the program owning the block, 0 if it's free
+    pub owner: u16,
+    // the size of the block in paragraphs, without the control block
+    pub size: u16,
+}
+
+impl Mcb {
+    /**
+     * Reads the control block at a segment.
+     * @return: the control block, or ERROR_ARENA_TRASHED if there isn't one.
+     */
+    fn read(memory: &MemorySegments, segment: u16) -> Result<Self, u16> {
+        let bytes = memory.read((segment, 0), 5);
+        let last = match bytes[0] {
+            MIDDLE => false,
+            LAST => true,
+            _ => return Err(ERROR_ARENA_TRASHED),
+        };
+        Ok(Self {
+            segment: segment,
+            last: last,
+            owner: u16::from_le_bytes([bytes[1], bytes[2]]),
+            size: u16::from_le_bytes([bytes[3], bytes[4]]),
+        })
+    }
+
+    fn write(&self, memory: &mut MemorySegments) {
+        let [owner_low, owner_high] = self.owner.to_le_bytes();
+        let [size_low, size_high] = self.size.to_le_bytes();
+        let kind = if self.last { LAST } else { MIDDLE };
+        memory.write(&vec![kind, owner_low, owner_high, size_low, size_high], (self.segment, 0));
+    }
+
+    /**
+     * Returns the segment of the block.
+     */
+    pub fn block(&self) -> u16 {
+        self.segment + 1
+    }
+
+    /**
+     * Returns the segment after the block, where the next control block is.
+     */
+    fn end(&self) -> u32 {
+        self.segment as u32 + 1 + self.size as u32
+    }
+
+    /**
+     * Cuts the block after `size` paragraphs, the rest becomes a free block.
+     */
+    fn split(&mut self, memory: &mut MemorySegments, size: u16) {
+        if size < self.size {
+            Self {
+                segment: self.segment + 1 + size,
+                last: self.last,
+                owner: FREE,
+                size: self.size - size - 1,
+            }.write(memory);
+            self.last = false;
+            self.size = size;
+        }
+        self.write(memory);
+    }
+}
+
+/**
+ * Creates the chain with one free block, from FIRST_MCB to the top of memory.
+ * @param top: the first segment after conventional memory.
+ */
+pub fn init_arena(memory: &mut MemorySegments, top: u16) {
+    Mcb {
+        segment: FIRST_MCB,
+        last: true,
+        owner: FREE,
+        size: top - FIRST_MCB - 1,
+    }.write(memory);
+}
+
+/**
+ * Walks the chain of control blocks.
+ * @return: the control blocks, or ERROR_ARENA_TRASHED if the chain is broken.
+ */
+pub fn blocks(memory: &MemorySegments) -> Result<Vec<Mcb>, u16> {
+    let mut blocks = Vec::new();
+    let mut segment = FIRST_MCB as u32;
+    while blocks.len() < MAX_BLOCKS && segment <= 0xFFFF {
+        let mcb = Mcb::read(memory, segment as u16)?;
+        blocks.push(mcb);
+        if mcb.last {
+            return Ok(blocks)
+        }
+        segment = mcb.end();
+    }
+    Err(ERROR_ARENA_TRASHED)
+}
+
+/**
+ * Joins free blocks that follow each other.
+ */
+fn merge_free(memory: &mut MemorySegments) -> Result<Vec<Mcb>, u16> {
+    let mut merged: Vec<Mcb> = Vec::new();
+    for mcb in blocks(memory)? {
+        match merged.last_mut() {
+            Some(previous) if previous.owner == FREE && mcb.owner == FREE => {
+                previous.size += mcb.size + 1;
+                previous.last = mcb.last;
+                previous.write(memory);
+            },
+            _ => merged.push(mcb),
+        }
+    }
+    Ok(merged)
+}
+
+/**
+ * Returns the size of the largest free block.
+ */
+pub fn largest_free(memory: &MemorySegments) -> u16 {
+    blocks(memory).unwrap_or_default().iter()
+        .filter(|mcb| mcb.owner == FREE)
+        .map(|mcb| mcb.size)
+        .max()
+        .unwrap_or(0)
+}
+
+/**
+ * Allocates a block in the first free block big enough, AH=48h.
+ * @param owner: the PSP of the program the block is for.
+ * @param min: the paragraphs needed.
+ * @param max: the paragraphs wanted, the block gets as many as fit.
+ * @return: the segment and the size of the block, or the error and the largest free block.
+ */
+pub fn allocate(memory: &mut MemorySegments, owner: u16, min: u16, max: u16) -> Result<(u16, u16), (u16, u16)> {
+    let blocks = merge_free(memory).map_err(|err| (err, 0))?;
+    let mut mcb = blocks.into_iter()
+        .find(|mcb| mcb.owner == FREE && mcb.size >= min)
+        .ok_or((ERROR_NOT_ENOUGH_MEMORY, largest_free(memory)))?;
+    mcb.owner = owner;
+    mcb.split(memory, mcb.size.min(max));
+    Ok((mcb.block(), mcb.size))
+}
+
+/**
+ * Allocates a block starting at a given segment, which must be free.
+ * @param segment: the segment of the block.
+ * @return: the size of the block, or the error and the paragraphs free at the segment.
+ */
+pub fn allocate_at(memory: &mut MemorySegments, owner: u16, segment: u16, min: u16, max: u16) -> Result<u16, (u16, u16)> {
+    let blocks = merge_free(memory).map_err(|err| (err, 0))?;
+    let mut mcb = blocks.into_iter()
+        .find(|mcb| mcb.owner == FREE && mcb.block() <= segment && (segment as u32) < mcb.end())
+        .ok_or((ERROR_NOT_ENOUGH_MEMORY, 0))?;
+    // the free paragraphs before the segment stay a free block
+    if segment > mcb.block() {
+        mcb.split(memory, segment - mcb.block() - 1);
+        mcb = Mcb::read(memory, segment - 1).map_err(|err| (err, 0))?;
+    }
+    if mcb.size < min {
+        return Err((ERROR_NOT_ENOUGH_MEMORY, mcb.size))
+    }
+    mcb.owner = owner;
+    mcb.split(memory, mcb.size.min(max));
+    Ok(mcb.size)
+}
+
+/**
+ * Finds the control block of an allocated block.
+ * @param segment: the segment of the block, as returned by allocate.
+ */
+fn find(memory: &MemorySegments, segment: u16) -> Result<Mcb, u16> {
+    blocks(memory)?.into_iter()
+        .find(|mcb| mcb.block() == segment && mcb.owner != FREE)
+        .ok_or(ERROR_INVALID_BLOCK)
+}
+
+/**
+ * Gives a block to another program.
+ * @param segment: the segment of the block.
+ * @param owner: the PSP of the program.
+ */
+pub fn set_owner(memory: &mut MemorySegments, segment: u16, owner: u16) -> Result<(), u16> {
+    let mut mcb = find(memory, segment)?;
+    mcb.owner = owner;
+    mcb.write(memory);
+    Ok(())
+}
+
+/**
+ * Frees a block, AH=49h.
+ * @param segment: the segment of the block.
+ */
+pub fn free(memory: &mut MemorySegments, segment: u16) -> Result<(), u16> {
+    let mut mcb = find(memory, segment)?;
+    mcb.owner = FREE;
+    mcb.write(memory);
+    merge_free(memory)?;
+    Ok(())
+}
+
+/**
+ * Frees the blocks of a program when it ends.
+ * @param owner: the PSP of the program.
+ */
+pub fn free_owned(memory: &mut MemorySegments, owner: u16) -> Result<(), u16> {
+    for mut mcb in blocks(memory)? {
+        if mcb.owner == owner {
+            mcb.owner = FREE;
+            mcb.write(memory);
+        }
+    }
+    merge_free(memory)?;
+    Ok(())
+}
+
+/**
+ * Changes the size of a block, AH=4Ah. A block grows into the free block after it.
+ * @param segment: the segment of the block.
+ * @param size: the new size in paragraphs.
+ * @return: the error and the largest size the block can have, if it can't have this one.
+ */
+pub fn resize(memory: &mut MemorySegments, segment: u16, size: u16) -> Result<(), (u16, u16)> {
+    merge_free(memory).map_err(|err| (err, 0))?;
+    let mut mcb = find(memory, segment).map_err(|err| (err, 0))?;
+    if size > mcb.size && !mcb.last {
+        let next = Mcb::read(memory, mcb.end() as u16).map_err(|err| (err, 0))?;
+        if next.owner == FREE {
+            mcb.size += next.size + 1;
+            mcb.last = next.last;
+        }
+    }
+    if size > mcb.size {
+        return Err((ERROR_NOT_ENOUGH_MEMORY, mcb.size))
+    }
+    mcb.split(memory, size);
+    Ok(())
+}
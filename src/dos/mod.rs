@@ -0,0 +1,472 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::cpu::PortBus;
+use crate::cpu::memory_unit::MemoryUnit;
+use crate::cpu::services::{InterruptService, ServiceResult};
+use crate::program_loader::LoadError;
+use crate::program_loader::dos::{load_com, load_exe, ExeHeader, DOS_SEGMENT, DOS_ENTRY_POINTS};
+
+pub mod console;
+pub mod files;
+pub mod memory;
+
+use console::DosConsole;
+use files::{FileSystem, FoundFile, FIRST_FILE_HANDLE, ERROR_INVALID_FUNCTION, ERROR_FILE_NOT_FOUND, ERROR_INVALID_HANDLE};
+
+// the longest string AH=09h prints, so a missing '$' doesn't print all of memory
+const MAX_STRING: usize = 0x10000;
+// the longest path DOS accepts
+const MAX_PATH: usize = 128;
+// the disk transfer area is in the PSP until the program moves it
+const DEFAULT_DTA: u16 = 0x80;
+// EXEC was given a file that isn't a program
+const ERROR_BAD_FORMAT: u16 = 0x0B;
+// where the PSP keeps the address the program returns to and its parent
+const PSP_TERMINATE_ADDRESS: u16 = 0x0A;
+const PSP_PARENT: u16 = 0x16;
+
+/**
+ * A program that started another one with EXEC, with the registers of its
+ * INT 21h to continue with when the child ends.
+ */
+struct Parent {
+    psp: u16,
+    dta: (u16, u16),
+    cs: u16,
+    ip: u16,
+    ss: u16,
+    sp: u16,
+    ds: u16,
+    es: u16,
+}
+
+/**
+ * The DOS services implemented by the emulator: INT 20h and the console,
+ * file, memory and process functions of INT 21h.
+ */
+pub struct Dos {
+    console: Box<dyn DosConsole>,
+    files: FileSystem,
+    // the line being read from the console, kept while it waits for keys
+    line: Option<Vec<u8>>,
+    // the segment of the PSP of the running program
+    pub psp: u16,
+    // the disk transfer area, where find first/next write what they found
+    pub dta: (u16, u16),
+    // the programs waiting for the programs they started to end
+    parents: Vec<Parent>,
+    // the return code of the last child, for AH=4Dh
+    return_code: u16,
+}
+
+impl Dos {
+    /**
+     * Creates the DOS services.
+     * @param console: where the console functions read and write.
+     * @param drive: the host directory used as drive C:.
+     * @param psp: the segment of the PSP of the program.
+     */
+    pub fn new(console: Box<dyn DosConsole>, drive: PathBuf, psp: u16) -> Self {
+        Self {
+            console: console,
+            files: FileSystem::new(drive),
+            line: None,
+            psp: psp,
+            dta: (psp, DEFAULT_DTA),
+            parents: Vec::new(),
+            return_code: 0,
+        }
+    }
+
+    /**
+     * Handles the functions of INT 21h, the function number is in AH.
+     */
+    fn int21(&mut self, cpu: &mut MemoryUnit) -> Result<ServiceResult, String> {
+        let function = (cpu.ax >> 8) as u8;
+        let dl = cpu.dx as u8;
+        match function {
+            // terminate
+            0x00 => return self.terminate(cpu, 0),
+            // read a character with echo
+            0x01 => match self.console.read() {
+                Some(key) => {
+                    self.console.write(key);
+                    set_al(cpu, key);
+                },
+                None => return Ok(ServiceResult::Wait),
+            },
+            // write a character
+            0x02 => {
+                self.console.write(dl);
+                set_al(cpu, dl);
+            },
+            // direct console input when DL is FFh, ZF tells if there was a key, output otherwise
+            0x06 if dl == 0xFF => match self.console.read() {
+                Some(key) => {
+                    set_al(cpu, key);
+                    cpu.flags.zero = false;
+                },
+                None => {
+                    set_al(cpu, 0);
+                    cpu.flags.zero = true;
+                },
+            },
+            0x06 => {
+                self.console.write(dl);
+                set_al(cpu, dl);
+            },
+            // write a string ending with '$' from DS:DX
+            0x09 => {
+                let segment = cpu.memory.data_segment;
+                for i in 0..MAX_STRING {
+                    let byte = cpu.memory[(segment, cpu.dx.wrapping_add(i as u16))];
+                    if byte == b'$' {
+                        break
+                    }
+                    self.console.write(byte);
+                }
+                set_al(cpu, b'$');
+            },
+            0x0A => return Ok(self.read_line(cpu)),
+            // set and get the disk transfer area
+            0x1A => self.dta = (cpu.memory.data_segment, cpu.dx),
+            0x2F => {
+                cpu.memory.extra_segment = self.dta.0;
+                cpu.bx = self.dta.1;
+            },
+            0x3C => {
+                let path = read_path(cpu);
+                let result = self.files.create(&path);
+                finish(cpu, result);
+            },
+            0x3D => {
+                let path = read_path(cpu);
+                let result = self.files.open(&path, cpu.ax as u8);
+                finish(cpu, result);
+            },
+            0x3E => {
+                let result = match cpu.bx {
+                    handle if handle < FIRST_FILE_HANDLE => Ok(0),
+                    handle => self.files.close(handle).map(|_| 0),
+                };
+                finish(cpu, result);
+            },
+            0x3F => return Ok(self.read_handle(cpu)),
+            0x40 => {
+                let data = cpu.memory.read((cpu.memory.data_segment, cpu.dx), cpu.cx as usize);
+                let result = match cpu.bx {
+                    1 | 2 => {
+                        for byte in &data {
+                            self.console.write(*byte);
+                        }
+                        Ok(data.len() as u16)
+                    },
+                    0 => Err(ERROR_INVALID_HANDLE),
+                    // nothing is connected to stdaux and stdprn
+                    3 | 4 => Ok(data.len() as u16),
+                    handle => self.files.write(handle, &data),
+                };
+                finish(cpu, result);
+            },
+            0x41 => {
+                let path = read_path(cpu);
+                let result = self.files.delete(&path).map(|_| 0);
+                finish(cpu, result);
+            },
+            // the offset is in CX:DX and the new position is returned in DX:AX
+            0x42 => {
+                let offset = ((cpu.cx as u32) << 16 | cpu.dx as u32) as i32;
+                match self.files.seek(cpu.bx, cpu.ax as u8, offset) {
+                    Ok(position) => {
+                        cpu.dx = (position >> 16) as u16;
+                        finish(cpu, Ok(position as u16));
+                    },
+                    Err(err) => finish(cpu, Err(err)),
+                }
+            },
+            0x4E => {
+                let path = read_path(cpu);
+                let result = self.files.find_first(&path, cpu.cx as u8);
+                self.found(cpu, result);
+            },
+            0x4F => {
+                let result = self.files.find_next();
+                self.found(cpu, result);
+            },
+            // allocate BX paragraphs, BX is the largest free block if they don't fit
+            0x48 => match memory::allocate(&mut cpu.memory, self.psp, cpu.bx, cpu.bx) {
+                Ok((segment, _)) => finish(cpu, Ok(segment)),
+                Err((err, largest)) => {
+                    cpu.bx = largest;
+                    finish(cpu, Err(err));
+                },
+            },
+            // free the block at ES
+            0x49 => {
+                let segment = cpu.memory.extra_segment;
+                let result = memory::free(&mut cpu.memory, segment);
+                finish(cpu, result.map(|_| segment));
+            },
+            // resize the block at ES to BX paragraphs, BX is the largest size it can have if it can't
+            0x4A => {
+                let segment = cpu.memory.extra_segment;
+                match memory::resize(&mut cpu.memory, segment, cpu.bx) {
+                    Ok(_) => finish(cpu, Ok(segment)),
+                    Err((err, largest)) => {
+                        cpu.bx = largest;
+                        finish(cpu, Err(err));
+                    },
+                }
+            },
+            0x4B => if let Err(err) = self.exec(cpu) {
+                finish(cpu, Err(err));
+            },
+            // terminate with the return code in AL
+            0x4C => return self.terminate(cpu, cpu.ax as u8),
+            // the return code of the last child, it's only returned once
+            0x4D => {
+                cpu.ax = self.return_code;
+                self.return_code = 0;
+                cpu.flags.carry = false;
+            },
+            _ => return Err(format!("INT 21h function {:02X}h isn't supported", function)),
+        }
+        Ok(ServiceResult::Handled)
+    }
+
+    /**
+     * Buffered input, AH=0Ah. The buffer at DS:DX holds the maximum number of
+     * characters including the carriage return, the number read is written
+     * after it followed by the characters and the carriage return.
+     */
+    fn read_line(&mut self, cpu: &mut MemoryUnit) -> ServiceResult {
+        let buffer = (cpu.memory.data_segment, cpu.dx);
+        let max = cpu.memory[buffer] as usize;
+        if max == 0 {
+            return ServiceResult::Handled
+        }
+        let line = match self.edit_line(max) {
+            Some(line) => line,
+            None => return ServiceResult::Wait,
+        };
+        let (segment, offset) = buffer;
+        let mut data = vec![line.len() as u8];
+        data.extend(&line);
+        data.push(b'\r');
+        cpu.memory.write(&data, (segment, offset.wrapping_add(1)));
+        ServiceResult::Handled
+    }
+
+    /**
+     * Reads from a handle, AH=3Fh. Reading stdin reads a line from the console,
+     * which ends with a carriage return and a line feed if they fit.
+     */
+    fn read_handle(&mut self, cpu: &mut MemoryUnit) -> ServiceResult {
+        let count = cpu.cx as usize;
+        let result = match cpu.bx {
+            0 => {
+                let mut line = match self.edit_line(count.max(2) - 1) {
+                    Some(line) => line,
+                    None => return ServiceResult::Wait,
+                };
+                self.console.write(b'\n');
+                line.extend(b"\r\n");
+                line.truncate(count);
+                Ok(line)
+            },
+            1..=4 => Ok(Vec::new()),
+            handle => self.files.read(handle, count),
+        };
+        match result {
+            Ok(data) => {
+                cpu.memory.write(&data, (cpu.memory.data_segment, cpu.dx));
+                finish(cpu, Ok(data.len() as u16));
+            },
+            Err(err) => finish(cpu, Err(err)),
+        }
+        ServiceResult::Handled
+    }
+
+    /**
+     * Reads a line from the console with echo, until Enter. Backspace erases
+     * the last character and the bell rings when the line is full.
+     * @param max: the length of the line including the carriage return.
+     * @return: the line without the carriage return, None while waiting for keys.
+     */
+    fn edit_line(&mut self, max: usize) -> Option<Vec<u8>> {
+        let mut line = self.line.take().unwrap_or_default();
+        loop {
+            let key = match self.console.read() {
+                Some(key) => key,
+                None => {
+                    self.line = Some(line);
+                    return None
+                },
+            };
+            match key {
+                b'\r' => {
+                    self.console.write(b'\r');
+                    break
+                },
+                // backspace erases the last character
+                0x08 => if line.pop().is_some() {
+                    for byte in [0x08, b' ', 0x08] {
+                        self.console.write(byte);
+                    }
+                },
+                // the buffer is full, only Enter fits
+                _ if line.len() + 1 >= max => self.console.write(0x07),
+                _ => {
+                    self.console.write(key);
+                    line.push(key);
+                },
+            }
+        }
+        Some(line)
+    }
+
+    /**
+     * Loads and runs a program, AH=4Bh with AL=00h. The path of the program is
+     * at DS:DX and the parameter block at ES:BX points to its command tail.
+     * The child runs right away and its parent continues when it ends.
+     * @return: the DOS error if the program couldn't be loaded.
+     */
+    fn exec(&mut self, cpu: &mut MemoryUnit) -> Result<(), u16> {
+        if cpu.ax as u8 != 0 {
+            return Err(ERROR_INVALID_FUNCTION)
+        }
+        let path = self.files.resolve(&read_path(cpu))?;
+        let program = fs::read(&path).map_err(|_| ERROR_FILE_NOT_FOUND)?;
+        let block = cpu.memory.read((cpu.memory.extra_segment, cpu.bx), 6);
+        let tail_address = (u16::from_le_bytes([block[4], block[5]]), u16::from_le_bytes([block[2], block[3]]));
+        let tail = cpu.memory.read(tail_address, 128);
+        let length = (tail[0] as usize).min(tail.len() - 1);
+        // the tail starts with the space after the program name, the loader adds it back
+        let arguments = String::from_utf8_lossy(&tail[1..1 + length]).to_string();
+        let arguments = arguments.strip_prefix(' ').unwrap_or(&arguments).to_string();
+
+        let parent = Parent {
+            psp: self.psp,
+            dta: self.dta,
+            cs: cpu.memory.code_segment,
+            ip: cpu.ip,
+            ss: cpu.memory.stack_segment,
+            sp: cpu.sp,
+            ds: cpu.memory.data_segment,
+            es: cpu.memory.extra_segment,
+        };
+        let loaded = if ExeHeader::parse(&program).is_some() {
+            load_exe(cpu, &program, None, &arguments)
+        } else {
+            load_com(cpu, &program, None, &arguments)
+        };
+        let psp = loaded.map_err(|err| match err {
+            LoadError::Memory(_) => memory::ERROR_NOT_ENOUGH_MEMORY,
+            _ => ERROR_BAD_FORMAT,
+        })?;
+        let [ip_low, ip_high] = parent.ip.to_le_bytes();
+        let [cs_low, cs_high] = parent.cs.to_le_bytes();
+        cpu.memory.write(&vec![ip_low, ip_high, cs_low, cs_high], (psp, PSP_TERMINATE_ADDRESS));
+        cpu.memory.write(&parent.psp.to_le_bytes().to_vec(), (psp, PSP_PARENT));
+        self.parents.push(parent);
+        self.psp = psp;
+        self.dta = (psp, DEFAULT_DTA);
+        Ok(())
+    }
+
+    /**
+     * Ends the running program. A child frees its memory and its parent
+     * continues after its EXEC, the first program ends the emulation.
+     * @param code: the return code of the program.
+     * @return: an error if the memory of a child can't be freed, DOS halts then.
+     */
+    fn terminate(&mut self, cpu: &mut MemoryUnit, code: u8) -> Result<ServiceResult, String> {
+        let parent = match self.parents.pop() {
+            Some(parent) => parent,
+            None => return Ok(ServiceResult::Exit(code)),
+        };
+        memory::free_owned(&mut cpu.memory, self.psp).map_err(|_| format!(
+            "the memory control blocks are destroyed, the memory of the program at {:04X} can't be freed", self.psp))?;
+        self.psp = parent.psp;
+        self.dta = parent.dta;
+        self.return_code = code as u16;
+        cpu.memory.code_segment = parent.cs;
+        cpu.ip = parent.ip;
+        cpu.memory.stack_segment = parent.ss;
+        cpu.sp = parent.sp;
+        cpu.memory.data_segment = parent.ds;
+        cpu.memory.extra_segment = parent.es;
+        cpu.flags.carry = false;
+        Ok(ServiceResult::Handled)
+    }
+
+    /**
+     * Writes what find first/next found to the DTA.
+     */
+    fn found(&mut self, cpu: &mut MemoryUnit, result: Result<FoundFile, u16>) {
+        match result {
+            Ok(file) => {
+                let mut entry = vec![0u8; 43];
+                entry[0x15] = file.attribute;
+                entry[0x16..0x18].copy_from_slice(&file.time.to_le_bytes());
+                entry[0x18..0x1A].copy_from_slice(&file.date.to_le_bytes());
+                entry[0x1A..0x1E].copy_from_slice(&file.size.to_le_bytes());
+                entry[0x1E..0x1E + file.name.len()].copy_from_slice(file.name.as_bytes());
+                cpu.memory.write(&entry, self.dta);
+                finish(cpu, Ok(0));
+            },
+            Err(err) => finish(cpu, Err(err)),
+        }
+    }
+}
+
+impl InterruptService for Dos {
+    fn interrupt(&mut self, vector: u8, cpu: &mut MemoryUnit, _ports: &mut PortBus) -> Result<ServiceResult, String> {
+        // a program that hooked the interrupt gets it, its handler may chain to DOS
+        let installed = DOS_ENTRY_POINTS.iter()
+            .any(|(entry_vector, offset)| *entry_vector == vector
+                && cpu.memory.get_interrupt_vector(vector) == (DOS_SEGMENT, *offset));
+        if !installed {
+            return Ok(ServiceResult::Unhandled)
+        }
+        match vector {
+            0x20 => self.terminate(cpu, 0),
+            0x21 => self.int21(cpu),
+            _ => Ok(ServiceResult::Unhandled),
+        }
+    }
+}
+
+/**
+ * Reads the ASCIIZ path at DS:DX.
+ */
+fn read_path(cpu: &MemoryUnit) -> String {
+    let path = cpu.memory.read((cpu.memory.data_segment, cpu.dx), MAX_PATH);
+    let end = path.iter().position(|byte| *byte == 0).unwrap_or(path.len());
+    String::from_utf8_lossy(&path[..end]).to_string()
+}
+
+/**
+ * Returns the result of a function the way DOS does: the carry flag is clear
+ * and AX holds the value, or the carry flag is set and AX holds the error code.
+ */
+fn finish(cpu: &mut MemoryUnit, result: Result<u16, u16>) {
+    match result {
+        Ok(value) => {
+            cpu.ax = value;
+            cpu.flags.carry = false;
+        },
+        Err(err) => {
+            cpu.ax = err;
+            cpu.flags.carry = true;
+        },
+    }
+}
+
+/**
+ * Sets AL, keeping AH.
+ */
+fn set_al(cpu: &mut MemoryUnit, value: u8) {
+    cpu.ax = (cpu.ax & 0xFF00) | value as u16;
+}
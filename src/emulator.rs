@@ -1,12 +1,18 @@
 use crate::computer::{Computer, HardwareList};
+use crate::cpu::helperModules::string_to_number;
+use crate::debug_info::jump_target;
 use crate::hardware::{Hardware, lightbulb::Lightbulb, keyboard::Keyboard};
+use crate::program_loader::{LoadOptions, LoadError};
+use crate::cpu::{HaltPolicy, Step, HLT};
+use crate::program_loader::image::{Image, parse_location};
+use crate::dos::console::SharedConsole;
 
 use eframe::egui::Widget;
 use eframe::{epi::App, egui, 
     egui::{CentralPanel, Window, Vec2, TopBottomPanel, CtxRef, Ui}};
 use queues::IsQueue;
 
-use std::{sync::{Arc, Mutex}, path::PathBuf};
+use std::{sync::{Arc, Mutex}, path::{Path, PathBuf}};
 use std::thread;
 
 enum State {
@@ -33,14 +39,17 @@ enum RegInfo {
     ES,
 }
 
-pub struct Emulator{
-    pub computer: Option<Computer>,
-    program_path: PathBuf,
-    open_err: bool,
-    state: State,
-
+/**
+ * A computer and the state of its debugger.
+ */
+struct Machine {
+    computer: Computer,
+    // the file name of its program, to tell linked computers apart
+    name: String,
+    // the console of the DOS services and the line being typed in it
+    console: SharedConsole,
+    console_input: String,
     halted: bool,
-    connected_hardware: HardwareList,
 
     // Debugging options
     running: bool,
@@ -53,88 +62,56 @@ pub struct Emulator{
     inst_len: u16,
 }
 
-impl Emulator{
-    pub fn new() -> Self {
+pub struct Emulator{
+    // the computer being debugged, two when they are linked
+    machines: Vec<Machine>,
+    program_path: PathBuf,
+    program_path_txt: String,
+    open_err: bool,
+    load_options: LoadOptions,
+    load_segment_txt: String,
+    images_txt: String,
+    drive_txt: String,
+    bios_txt: String,
+    reset_txt: String,
+    serial_txt: [String; 2],
+    printer_txt: String,
+    floppy_txt: [String; 2],
+    // the program of a second computer, linked to the first through COM1
+    link_path_txt: String,
+    load_error: Option<LoadError>,
+    state: State,
+
+    connected_hardware: HardwareList,
+    // the textures of the pictures of the hardware, made again every frame
+    hardware_textures: Vec<egui::TextureId>,
+}
+
+impl Machine {
+    /**
+     * Creates the debugger of a computer, showing the memory at CS:IP.
+     */
+    fn new(computer: Computer, program_path: &str, console: SharedConsole) -> Self {
+        let cpu = &computer.cpu;
+        let seg = cpu.memory_unit.memory.code_segment;
+        let add = cpu.memory_unit.ip;
         Self {
-            computer: None,
-            program_path: PathBuf::new(),
-            open_err: false,
-            state: State::ProgramLoader,
+            name: Path::new(program_path).file_name().map_or(program_path.to_string(), |name| name.to_string_lossy().to_string()),
+            computer: computer,
+            console: console,
+            console_input: String::new(),
             halted: false,
-            connected_hardware: HardwareList::new(),
             running: false,
             follow_add: false,
             reg_info: RegInfo::NONE,
-            add: 0,
-            seg: 0,
-            add_txt: String::with_capacity(4),
-            seg_txt: String::with_capacity(4),
+            add: add,
+            seg: seg,
+            add_txt: format!("{:04X}", add),
+            seg_txt: format!("{:04X}", seg),
             inst_len: 0,
         }
     }
 
-    /**
-     * Program Loader Menu.
-     * This is the menu where the user can choose the program to run and as well
-     * initialize the Computer with hardware and more.
-     */
-    fn program_loader_menu(&mut self, ui: &mut Ui) {
-        ui.vertical(|ui| {
-            // get program path from explorer.
-            ui.horizontal(|ui| {
-                if ui.button("open program").clicked() {
-                    #[cfg(windows)]
-                    match wfd::open_dialog(Default::default()) {
-                        Ok(dialog_result) => {
-                            self.program_path = dialog_result.selected_file_path;
-                            self.open_err = false;
-                        },
-                        Err(__) => (),
-                    }
-                }
-                ui.label( self.program_path.to_str().unwrap());
-            });
-            if self.open_err {
-                ui.label("file doesn't exist");
-            }
-            // if ui.button("run program").clicked() {
-            //     self.computer = Some(Computer::new(
-            //         self.program_path.to_str().unwrap()
-            //     ));
-            //     self.state = State::Running;
-            // }
-            if ui.button("run with debugger").clicked() {
-                if self.program_path.exists() {
-                    self.computer = Some(Computer::new(
-                        self.program_path.to_str().unwrap(),
-                        self.connected_hardware,
-                    ));
-                    let cpu = &mut self.computer.as_mut().unwrap().cpu;
-                    self.add = cpu.memory_unit.ip;
-                    self.seg = cpu.memory_unit.memory.code_segment;
-                    self.seg_txt = format!("{:04X}", self.seg);
-                    self.add = cpu.memory_unit.ip;
-                    self.add_txt = format!("{:04X}", self.add);
-                    self.state = State::Debugger;
-                }
-                else {
-                    self.open_err = true;
-                }
-            }
-            ui.checkbox(&mut self.connected_hardware.lightbulb, "Lightbulb");
-            // ui.checkbox(&mut self.connected_hardware.keyboard, "Keyboard");
-        });
-    }
-
-    /**
-     * Menu for a program running without a debugger
-     */
-    fn running(&mut self, ui: &mut Ui) {
-        ui.label("program running");
-        let computer = &mut self.computer.as_mut().unwrap();
-        computer.run();
-    }
-
     /**
      * Menu for a program running with a Debugger
      */
@@ -162,28 +139,57 @@ impl Emulator{
                 self.running = false;
             }
             if !enabled {
-                ui.label("program halted");
+                match self.computer.cpu.exit_code {
+                    Some(code) => ui.label(format!("program ended with return code {}", code)),
+                    None => ui.label("program halted"),
+                };
                 self.running = false;
                 run = false;
+            } else if self.computer.cpu.halted {
+                ui.label("waiting for an interrupt");
             }
         });
         // get ref to CPU
-        let cpu = &mut self.computer.as_mut().unwrap().cpu;
+        let cpu = &mut self.computer.cpu;
         // run the cpu virtualy
         if run {
             let res = cpu.run_next_instruction();
-            if res == Ok(0xF4 as u8) {
+            if res == Ok(Step::Ran(HLT)) {
                 self.halted = true;
             }
             // get length of the instruction (used later for color it in memory view)
             inst_len = 0;
             let ip = cpu.memory_unit.ip;
-            cpu.load_instruction();
+            let _ = cpu.load_instruction();
             inst_len = cpu.memory_unit.ip - ip;
             cpu.memory_unit.inst_bus = queues::Buffer::new(6);
             cpu.memory_unit.ip = ip;
         }
-        let cpu = &self.computer.as_ref().unwrap().cpu;
+        let cpu = &self.computer.cpu;
+        let debug_info = self.computer.debug_info.as_ref();
+        //# SOURCE LINE
+        if let Some(info) = debug_info {
+            let code_segment = cpu.memory_unit.memory.code_segment;
+            let ip = cpu.memory_unit.ip;
+            match info.line_at_location(code_segment, ip) {
+                Some(line) => {
+                    // name the target of jumps and calls
+                    let bytes = (0..inst_len).map(|i| cpu.memory_unit.memory[(code_segment, ip.wrapping_add(i))])
+                        .collect::<Vec<u8>>();
+                    let target = jump_target(&bytes, ip)
+                        .and_then(|target| info.address(code_segment, target))
+                        .and_then(|address| info.label_at(address));
+                    let mut text = format!("{}:{}: {}", line.file, line.line, line.source);
+                    if let Some(label) = target {
+                        text += &format!("  -> {}", label);
+                    }
+                    ui.label(egui::RichText::new(text).monospace());
+                },
+                None => {
+                    ui.label("no source for this address");
+                },
+            }
+        }
         ui.separator();
         ui.horizontal(|ui| {
             //# REGISTORS DATA
@@ -295,7 +301,16 @@ impl Emulator{
                        update_seg = check_number_is_hex(&mut seg);
                     }
                     if add_editor.lost_focus() {
-                        update_add = check_number_is_hex(&mut add);
+                        // the address can be a label of the program
+                        match debug_info.and_then(|info| info.symbol_location(add.trim())) {
+                            Some((segment, offset)) => {
+                                seg = format!("{:04X}", segment);
+                                update_seg = true;
+                                add = format!("{:04X}", offset);
+                                update_add = true;
+                            },
+                            None => update_add = check_number_is_hex(&mut add),
+                        }
                     }
                     if follow_add {
                         update_seg = true;
@@ -314,7 +329,14 @@ impl Emulator{
                         Err(_) => "",
                     };
                     // print the memory
-                    let mut mem = egui::RichText::new(format!("{:04X} : {:04X}\t{:02X}  {2:03}  {3}",print_seg, print_add, val, asci));
+                    let mut text = format!("{:04X} : {:04X}\t{:02X}  {2:03}  {3}",print_seg, print_add, val, asci);
+                    // show the labels of the program
+                    let label = debug_info.and_then(|info| info.address(print_seg, print_add))
+                        .and_then(|address| debug_info.unwrap().label_at(address));
+                    if let Some(label) = label {
+                        text += &format!("  {}:", label);
+                    }
+                    let mut mem = egui::RichText::new(text);
                     
                     // color the current instruction
                     if (print_seg == cpu.memory_unit.memory.code_segment &&
@@ -369,17 +391,281 @@ impl Emulator{
         }
     }
 
-    fn show_hardware(&mut self, ctx: &CtxRef) {
-        match self.computer.as_mut() {
-            None => (),
-            Some(computer) => {
-                let hardware = computer.connected_hardware.lock().unwrap();
-                for i in 0..hardware.len() {
-                    Window::new(hardware[i].name()).show(ctx, |ui| {
-                        hardware[i].ui(ui);
-                    });
+    /**
+     * Returns the title of a window of the computer, which says which
+     * computer it is when they are linked.
+     */
+    fn title(&self, name: &str, linked: bool) -> String {
+        if linked { format!("{} ({})", name, self.name) } else { name.to_string() }
+    }
+}
+
+impl Emulator{
+    pub fn new() -> Self {
+        Self {
+            machines: Vec::new(),
+            program_path: PathBuf::new(),
+            program_path_txt: String::new(),
+            open_err: false,
+            load_options: LoadOptions::new(),
+            load_segment_txt: String::new(),
+            images_txt: String::new(),
+            drive_txt: String::new(),
+            bios_txt: String::new(),
+            reset_txt: String::new(),
+            serial_txt: [String::new(), String::new()],
+            printer_txt: String::new(),
+            floppy_txt: [String::new(), String::new()],
+            link_path_txt: String::new(),
+            load_error: None,
+            state: State::ProgramLoader,
+            connected_hardware: HardwareList::new(),
+            hardware_textures: Vec::new(),
+        }
+    }
+
+    /**
+     * Program Loader Menu.
+     * This is the menu where the user can choose the program to run and as well
+     * initialize the Computer with hardware and more.
+     */
+    fn program_loader_menu(&mut self, ui: &mut Ui) {
+        ui.vertical(|ui| {
+            // get program path from explorer.
+            ui.horizontal(|ui| {
+                if ui.button("open program").clicked() {
+                    #[cfg(windows)]
+                    match wfd::open_dialog(Default::default()) {
+                        Ok(dialog_result) => {
+                            self.program_path = dialog_result.selected_file_path;
+                            self.program_path_txt = self.program_path.to_string_lossy().to_string();
+                            self.open_err = false;
+                        },
+                        Err(__) => (),
+                    }
+                }
+                // the path can be typed as well, there is no open dialog outside of windows
+                if ui.text_edit_singleline(&mut self.program_path_txt).changed() {
+                    self.program_path = PathBuf::from(&self.program_path_txt);
+                    self.open_err = false;
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("assembler");
+                ui.text_edit_singleline(&mut self.load_options.assembler);
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.load_options.com, "load as .COM");
+                ui.label("segment");
+                if ui.text_edit_singleline(&mut self.load_segment_txt).changed() {
+                    self.load_options.load_segment = match string_to_number(&self.load_segment_txt) {
+                        Ok(val) if val <= 0xFFFF => Some(val as u16),
+                        _ => None,
+                    };
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("arguments");
+                ui.text_edit_singleline(&mut self.load_options.arguments);
+            });
+            // images are separated with ';', like `table.bin@2000:0; rom.hex`
+            ui.horizontal(|ui| {
+                ui.label("images");
+                ui.text_edit_singleline(&mut self.images_txt);
+            });
+            // the directory of the program is drive C: if none is given
+            ui.horizontal(|ui| {
+                ui.label("drive C:");
+                if ui.text_edit_singleline(&mut self.drive_txt).changed() {
+                    let drive = self.drive_txt.trim();
+                    self.load_options.drive = if drive.is_empty() { None } else { Some(drive.to_string()) };
+                }
+            });
+            // the built-in BIOS is used without a ROM image, the cpu starts at F000:FFF0 without a reset vector
+            ui.horizontal(|ui| {
+                ui.label("BIOS ROM");
+                if ui.text_edit_singleline(&mut self.bios_txt).changed() {
+                    let bios = self.bios_txt.trim();
+                    self.load_options.bios = if bios.is_empty() { None } else { Some(bios.to_string()) };
+                }
+                ui.label("reset");
+                if ui.text_edit_singleline(&mut self.reset_txt).changed() {
+                    self.load_options.reset_vector = parse_location(self.reset_txt.trim()).ok();
+                }
+            });
+            // the serial ports without a host aren't connected
+            ui.horizontal(|ui| {
+                for (i, name) in ["COM1", "COM2"].iter().enumerate() {
+                    ui.label(*name);
+                    if ui.text_edit_singleline(&mut self.serial_txt[i]).changed() {
+                        let host = self.serial_txt[i].trim();
+                        self.load_options.serial[i] = if host.is_empty() { None } else { Some(host.to_string()) };
+                    }
+                }
+            });
+            // the printer captures to the file, without one it isn't connected
+            ui.horizontal(|ui| {
+                ui.label("LPT1");
+                if ui.text_edit_singleline(&mut self.printer_txt).changed() {
+                    let path = self.printer_txt.trim();
+                    self.load_options.printer = if path.is_empty() { None } else { Some(path.to_string()) };
+                }
+            });
+            // the disk images of the floppy drives, the drives without one are empty
+            ui.horizontal(|ui| {
+                for (i, name) in ["A:", "B:"].iter().enumerate() {
+                    ui.label(*name);
+                    if ui.text_edit_singleline(&mut self.floppy_txt[i]).changed() {
+                        let path = self.floppy_txt[i].trim();
+                        self.load_options.floppies[i] = if path.is_empty() { None } else { Some(path.to_string()) };
+                    }
+                }
+                ui.checkbox(&mut self.load_options.boot, "boot from A:");
+            });
+            // a second computer runs the program of the link, COM1 of both connected by a null-modem cable
+            ui.horizontal(|ui| {
+                ui.label("link");
+                ui.text_edit_singleline(&mut self.link_path_txt);
+                ui.checkbox(&mut self.load_options.serial_pacing, "paced at the baud rate");
+            });
+            // HLT waits for an interrupt unless it ends the program
+            let mut halt_stops = self.load_options.halt_policy == HaltPolicy::Stop;
+            if ui.checkbox(&mut halt_stops, "HLT ends the program").changed() {
+                self.load_options.halt_policy = if halt_stops { HaltPolicy::Stop } else { HaltPolicy::Wait };
+            }
+            if self.open_err {
+                ui.label("file doesn't exist");
+            }
+            // show why the program couldn't be loaded
+            if let Some(err) = &self.load_error {
+                match err {
+                    LoadError::Diagnostics(diagnostics) => for diagnostic in diagnostics {
+                        ui.colored_label(egui::Color32::RED, diagnostic.to_string());
+                    },
+                    _ => {
+                        ui.colored_label(egui::Color32::RED, err.to_string());
+                    },
                 }
             }
+            // if ui.button("run program").clicked() {
+            //     self.computer = Some(Computer::new(
+            //         self.program_path.to_str().unwrap()
+            //     ));
+            //     self.state = State::Running;
+            // }
+            if ui.button("run with debugger").clicked() {
+                // booting needs the disk in A: instead of a program
+                if self.program_path.exists() || self.load_options.boot {
+                    let images = self.images_txt.split(';')
+                        .map(|image| image.trim())
+                        .filter(|image| !image.is_empty())
+                        .map(|image| Image::from(image))
+                        .collect::<Result<Vec<Image>, String>>();
+                    match images {
+                        Ok(images) => self.load_options.images = images,
+                        Err(err) => {
+                            self.load_error = Some(LoadError::Format(err));
+                            return
+                        },
+                    }
+                    let program = match (&self.load_options.floppies[0], self.load_options.boot) {
+                        (Some(disk), true) => disk.as_str(),
+                        _ => self.program_path.to_str().unwrap(),
+                    };
+                    let link = self.link_path_txt.trim();
+                    let consoles = [SharedConsole::new(), SharedConsole::new()];
+                    let created = if link.is_empty() {
+                        Computer::new(program, self.connected_hardware, &self.load_options, Box::new(consoles[0].clone()))
+                            .map(|computer| vec![Machine::new(computer, program, consoles[0].clone())])
+                    } else {
+                        Computer::linked([program, link], self.connected_hardware, &self.load_options,
+                            [Box::new(consoles[0].clone()), Box::new(consoles[1].clone())])
+                            .map(|(first, second)| vec![
+                                Machine::new(first, program, consoles[0].clone()),
+                                Machine::new(second, link, consoles[1].clone()),
+                            ])
+                    };
+                    match created {
+                        Ok(machines) => {
+                            self.machines = machines;
+                            self.load_error = None;
+                        },
+                        Err(err) => {
+                            self.load_error = Some(err);
+                            return
+                        },
+                    }
+                    self.state = State::Debugger;
+                }
+                else {
+                    self.open_err = true;
+                }
+            }
+            ui.checkbox(&mut self.connected_hardware.lightbulb, "Lightbulb");
+            ui.checkbox(&mut self.connected_hardware.video, "Video");
+            ui.checkbox(&mut self.connected_hardware.timer, "Timer");
+            ui.checkbox(&mut self.connected_hardware.pic, "Interrupt controller");
+            ui.checkbox(&mut self.connected_hardware.speaker, "Speaker");
+            ui.checkbox(&mut self.connected_hardware.keyboard, "Keyboard");
+        });
+    }
+
+    /**
+     * Menu for a program running without a debugger
+     */
+    fn running(&mut self, ui: &mut Ui) {
+        ui.label("program running");
+        for machine in self.machines.iter_mut() {
+            machine.computer.run();
+        }
+    }
+
+    /**
+     * Shows the console of the DOS services. Typed lines are sent with Enter.
+     */
+    fn show_console(&mut self, ctx: &CtxRef) {
+        let linked = self.machines.len() > 1;
+        for machine in self.machines.iter_mut() {
+            let console = machine.console.clone();
+            let title = machine.title("DOS console", linked);
+            let input = &mut machine.console_input;
+            Window::new(title).show(ctx, |ui| {
+                egui::ScrollArea::vertical().max_height(300.0).stick_to_bottom().show(ui, |ui| {
+                    ui.monospace(console.output());
+                });
+                ui.horizontal(|ui| {
+                    let response = ui.text_edit_singleline(input);
+                    let entered = response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+                    if entered || ui.button("send").clicked() {
+                        console.type_text(&format!("{}\n", input));
+                        input.clear();
+                        response.request_focus();
+                    }
+                });
+            });
+        }
+    }
+
+    fn show_hardware(&mut self, ctx: &CtxRef, frame: &eframe::epi::Frame) {
+        for texture in self.hardware_textures.drain(..) {
+            frame.free_texture(texture);
+        }
+        let textures = &mut self.hardware_textures;
+        let linked = self.machines.len() > 1;
+        for machine in self.machines.iter() {
+            let computer = &machine.computer;
+            let hardware = computer.connected_hardware.lock().unwrap();
+            let memory = &computer.cpu.memory_unit.memory;
+            for i in 0..hardware.len() {
+                Window::new(machine.title(hardware[i].name(), linked)).show(ctx, |ui| {
+                    hardware[i].ui(ui, memory);
+                    if let Some(picture) = hardware[i].picture(memory) {
+                        let texture = frame.alloc_texture(picture.image);
+                        textures.push(texture);
+                        ui.image(texture, picture.size);
+                    }
+                });
+            }
         }
     }
 
@@ -439,10 +725,22 @@ impl App for Emulator {
             match &self.state {
                 State::ProgramLoader => self.program_loader_menu(ui),
                 State::Running => self.running(ui),
-                State::Debugger => self.debugger(ui, ctx),
+                // linked computers are debugged side by side
+                State::Debugger => {
+                    let linked = self.machines.len() > 1;
+                    ui.columns(self.machines.len(), |columns| {
+                        for (machine, ui) in self.machines.iter_mut().zip(columns.iter_mut()) {
+                            if linked {
+                                ui.heading(&machine.name);
+                            }
+                            machine.debugger(ui, ctx);
+                        }
+                    });
+                },
             }
         });
-        self.show_hardware(ctx);
+        self.show_hardware(ctx, frame);
+        self.show_console(ctx);
     }
 
     fn name(&self) -> &str {
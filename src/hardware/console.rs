@@ -0,0 +1,68 @@
+use super::*;
+use std::io::{Read, Write};
+
+/**
+ * The port of the console. The same port is used by bochs and qemu
+ * as a debug console, so programs written for them work as well.
+ */
+pub const CONSOLE_PORT: u16 = 0xE9;
+
+/**
+ * Console connected to the stdin and stdout of the emulator.
+ * Writing a byte to the port prints it, reading the port returns the next
+ * byte of the input, or 0 when the input has ended.
+ */
+pub struct Console {
+    written: usize,
+    input_ended: bool,
+}
+
+impl Console {
+    // Creates a new instance of Console
+    pub fn new() -> Self {
+        Self {
+            written: 0,
+            input_ended: false,
+        }
+    }
+}
+
+impl Hardware for Console {
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, ports: &mut Vec<u8>) -> () {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(&[ports[CONSOLE_PORT as usize]]);
+        let _ = stdout.flush();
+        self.written += 1;
+    }
+
+    fn read_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let mut byte = [0u8];
+        if !self.input_ended {
+            match std::io::stdin().read(&mut byte) {
+                Ok(1) => (),
+                _ => {
+                    self.input_ended = true;
+                    byte[0] = 0;
+                }
+            }
+        }
+        ports[port as usize] = byte[0];
+    }
+
+    fn is_port_related(&self, port: &u16) -> bool {
+        *port == CONSOLE_PORT
+    }
+
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
+        ui.label(format!("stdin/stdout on port {:02X}h", CONSOLE_PORT));
+        ui.label(format!("{} bytes written", self.written));
+    }
+
+    fn name(&self) -> &str {
+        "Console"
+    }
+}
@@ -0,0 +1,323 @@
+use super::*;
+use crate::cpu::DmaController;
+
+// the address and the count of channels 0-3 are ports 00h-07h, two ports a channel
+const LAST_CHANNEL_PORT: u16 = 0x07;
+const STATUS_COMMAND: u16 = 0x08;
+const REQUEST: u16 = 0x09;
+const SINGLE_MASK: u16 = 0x0A;
+const MODE: u16 = 0x0B;
+const CLEAR_FLIP_FLOP: u16 = 0x0C;
+const MASTER_CLEAR: u16 = 0x0D;
+const CLEAR_MASK: u16 = 0x0E;
+const WRITE_MASK: u16 = 0x0F;
+// the page registers of channels 0-3, bits 16-19 of the address
+const PAGE_PORTS: [u16; 4] = [0x87, 0x83, 0x81, 0x82];
+// bits 2-3 of the mode are the transfer: verify, write to the memory or read from it
+const MODE_TRANSFER: u8 = 0x0C;
+const TRANSFER_WRITE: u8 = 0x04;
+const TRANSFER_READ: u8 = 0x08;
+const MODE_AUTO_INIT: u8 = 0x10;
+const MODE_DECREMENT: u8 = 0x20;
+// bit 2 of the command disables the controller
+const COMMAND_DISABLE: u8 = 0x04;
+
+/**
+ * What a device asked a channel to transfer: the bytes it gives the
+ * memory, or how many it wants from it.
+ */
+struct Request {
+    bytes: Vec<u8>,
+    length: usize,
+}
+
+/**
+ * A transfer the controller finished for a device.
+ */
+pub struct DmaResult {
+    // the bytes that were transferred
+    pub moved: usize,
+    // the bytes read from the memory, for a device that wants them
+    pub bytes: Vec<u8>,
+    // the count ran out, the device sees the terminal count
+    pub terminal: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    base_address: u16,
+    address: u16,
+    base_count: u16,
+    count: u16,
+    page: u8,
+    mode: u8,
+    masked: bool,
+}
+
+/**
+ * The registers of the 8237A and the transfers the devices asked for.
+ */
+struct DmaState {
+    channels: [Channel; 4],
+    command: u8,
+    // the terminal counts reached since the status was read, a bit each channel
+    terminal: u8,
+    // the next write to an address or a count is the high byte
+    high_byte: bool,
+    requests: [Option<Request>; 4],
+    results: [Option<DmaResult>; 4],
+}
+
+impl DmaState {
+    fn write_channel_port(&mut self, port: u16, value: u8) {
+        let high_byte = self.high_byte;
+        self.high_byte = !self.high_byte;
+        let channel = &mut self.channels[port as usize / 2];
+        let register = if port % 2 == 0 { &mut channel.base_address } else { &mut channel.base_count };
+        *register = if high_byte {
+            (*register & 0x00FF) | (value as u16) << 8
+        } else {
+            (*register & 0xFF00) | value as u16
+        };
+        // writing the base writes the current register too
+        channel.address = channel.base_address;
+        channel.count = channel.base_count;
+    }
+
+    fn read_channel_port(&mut self, port: u16) -> u8 {
+        let high_byte = self.high_byte;
+        self.high_byte = !self.high_byte;
+        let channel = &self.channels[port as usize / 2];
+        let value = if port % 2 == 0 { channel.address } else { channel.count };
+        if high_byte { (value >> 8) as u8 } else { value as u8 }
+    }
+
+    fn status(&mut self) -> u8 {
+        let requests = (0..4).filter(|channel| self.requests[*channel].is_some())
+            .fold(0, |bits, channel| bits | 1 << channel);
+        let status = self.terminal | requests << 4;
+        self.terminal = 0;
+        status
+    }
+
+    fn master_clear(&mut self) {
+        for channel in self.channels.iter_mut() {
+            channel.masked = true;
+        }
+        self.command = 0;
+        self.terminal = 0;
+        self.high_byte = false;
+    }
+
+    /**
+     * Runs the transfer a device asked a channel for, up to the end of the
+     * count. The address wraps inside its 64K page, the page register doesn't
+     * count.
+     */
+    fn run(&mut self, number: usize, memory: &mut MemorySegments) {
+        let request = self.requests[number].take().unwrap();
+        let channel = &mut self.channels[number];
+        let left = channel.count as usize + 1;
+        let moved = request.length.min(left);
+        let mut bytes = Vec::new();
+        for i in 0..moved {
+            let physical = (channel.page as u32) << 16 | channel.address as u32;
+            let address = ((physical >> 4) as u16, (physical & 0xF) as u16);
+            match channel.mode & MODE_TRANSFER {
+                TRANSFER_WRITE => memory.write(&vec![request.bytes.get(i).copied().unwrap_or(0)], address),
+                TRANSFER_READ => bytes.push(memory[address]),
+                // a verify moves nothing, the device gets zeros
+                _ => bytes.push(0),
+            }
+            channel.address = if channel.mode & MODE_DECREMENT != 0 {
+                channel.address.wrapping_sub(1)
+            } else {
+                channel.address.wrapping_add(1)
+            };
+        }
+        channel.count = channel.count.wrapping_sub(moved as u16);
+        let terminal = moved == left;
+        if terminal {
+            self.terminal |= 1 << number;
+            if channel.mode & MODE_AUTO_INIT != 0 {
+                channel.address = channel.base_address;
+                channel.count = channel.base_count;
+            } else {
+                channel.masked = true;
+            }
+        }
+        self.results[number] = Some(DmaResult {
+            moved: moved,
+            bytes: bytes,
+            terminal: terminal,
+        });
+    }
+}
+
+/**
+ * An 8237A DMA controller at ports 00h-0Fh, with the page registers of the
+ * PC at 81h-87h. A device asks a channel for a transfer, and the controller
+ * moves the bytes between the device and the memory the way the program
+ * set the channel up: its address, its count, the direction of its mode,
+ * and once it isn't masked. The cpu has a clone of it and lets it use the
+ * memory between its instructions.
+ */
+#[derive(Clone)]
+pub struct Dma {
+    state: Arc<Mutex<DmaState>>,
+}
+
+impl Dma {
+    /**
+     * Creates a controller with all the channels masked.
+     */
+    pub fn new() -> Self {
+        let channel = Channel {
+            base_address: 0,
+            address: 0,
+            base_count: 0,
+            count: 0,
+            page: 0,
+            mode: 0,
+            masked: true,
+        };
+        Self {
+            state: Arc::new(Mutex::new(DmaState {
+                channels: [channel; 4],
+                command: 0,
+                terminal: 0,
+                high_byte: false,
+                requests: [None, None, None, None],
+                results: [None, None, None, None],
+            })),
+        }
+    }
+
+    /**
+     * Asks a channel for a transfer, replacing the one it didn't run yet.
+     * @param channel: the channel, 0-3.
+     * @param bytes: the bytes the device gives the memory, empty if it wants bytes.
+     * @param length: the bytes the device transfers.
+     */
+    pub fn request(&self, channel: usize, bytes: Vec<u8>, length: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.results[channel] = None;
+        state.requests[channel] = Some(Request {
+            bytes: bytes,
+            length: length,
+        });
+    }
+
+    /**
+     * Cancels the transfer of a channel, like a device that stops asking.
+     */
+    pub fn cancel(&self, channel: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.requests[channel] = None;
+        state.results[channel] = None;
+    }
+
+    /**
+     * Takes the transfer a channel finished.
+     * @return: the transfer, None while the channel didn't run it.
+     */
+    pub fn take_result(&self, channel: usize) -> Option<DmaResult> {
+        self.state.lock().unwrap().results[channel].take()
+    }
+}
+
+impl DmaController for Dma {
+    fn transfer(&mut self, memory: &mut MemorySegments) {
+        let mut state = self.state.lock().unwrap();
+        if state.command & COMMAND_DISABLE != 0 {
+            return
+        }
+        for channel in 0..4 {
+            if state.requests[channel].is_some() && !state.channels[channel].masked {
+                state.run(channel, memory);
+            }
+        }
+    }
+}
+
+impl Hardware for Dma {
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    fn write_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let value = ports[port as usize];
+        let mut state = self.state.lock().unwrap();
+        if let Some(channel) = PAGE_PORTS.iter().position(|page_port| *page_port == port) {
+            state.channels[channel].page = value & 0x0F;
+            return
+        }
+        let channel = (value & 3) as usize;
+        match port {
+            0..=LAST_CHANNEL_PORT => state.write_channel_port(port, value),
+            STATUS_COMMAND => state.command = value,
+            // the requests of the software aren't supported, no device answers them
+            REQUEST => (),
+            SINGLE_MASK => state.channels[channel].masked = value & 0x04 != 0,
+            MODE => state.channels[channel].mode = value,
+            CLEAR_FLIP_FLOP => state.high_byte = false,
+            MASTER_CLEAR => state.master_clear(),
+            CLEAR_MASK => for channel in state.channels.iter_mut() {
+                channel.masked = false;
+            },
+            _ => for (i, channel) in state.channels.iter_mut().enumerate() {
+                channel.masked = value & (1 << i) != 0;
+            },
+        }
+    }
+
+    fn read_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let mut state = self.state.lock().unwrap();
+        ports[port as usize] = if let Some(channel) = PAGE_PORTS.iter().position(|page_port| *page_port == port) {
+            state.channels[channel].page
+        } else {
+            match port {
+                0..=LAST_CHANNEL_PORT => state.read_channel_port(port),
+                STATUS_COMMAND => state.status(),
+                // the mask register reads back on later controllers, the unused bits high
+                WRITE_MASK => state.channels.iter().enumerate()
+                    .fold(0xF0, |mask, (i, channel)| mask | (channel.masked as u8) << i),
+                _ => 0xFF,
+            }
+        };
+    }
+
+    fn is_port_related(&self, port: &u16) -> bool {
+        *port <= WRITE_MASK || PAGE_PORTS.contains(port)
+    }
+
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
+        let state = self.state.lock().unwrap();
+        egui::Grid::new("dma").show(ui, |ui| {
+            ui.label("channel");
+            ui.label("address");
+            ui.label("count");
+            ui.label("mode");
+            ui.end_row();
+            for (i, channel) in state.channels.iter().enumerate() {
+                ui.label(format!("{}{}", i, if channel.masked { " masked" } else { "" }));
+                ui.monospace(format!("{:X}{:04X}", channel.page, channel.address));
+                ui.monospace(format!("{:04X}", channel.count));
+                ui.monospace(format!("{:02X}", channel.mode));
+                ui.end_row();
+            }
+        });
+        if state.command & COMMAND_DISABLE != 0 {
+            ui.label("disabled");
+        }
+        ui.ctx().request_repaint();
+    }
+
+    fn name(&self) -> &str {
+        "DMA controller"
+    }
+}
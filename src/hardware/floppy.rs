@@ -0,0 +1,635 @@
+use super::*;
+use super::dma::Dma;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+
+// the ports of the controller
+const DIGITAL_OUTPUT: u16 = 0x3F2;
+const MAIN_STATUS: u16 = 0x3F4;
+const DATA: u16 = 0x3F5;
+const DIGITAL_INPUT: u16 = 0x3F7;
+const FIRST_PORT: u16 = 0x3F0;
+const IRQ_LINE: u8 = 6;
+const DMA_CHANNEL: usize = 2;
+pub const SECTOR_SIZE: usize = 512;
+// the sector size code of the commands for 512 bytes
+const SIZE_CODE: u8 = 2;
+
+// the DOR: bits 0-1 select a drive, bit 2 is low while the controller is reset,
+// bit 3 lets it interrupt and use DMA, bits 4-7 turn the motors of the drives on
+const DOR_DRIVE: u8 = 0x03;
+const DOR_NOT_RESET: u8 = 0x04;
+const DOR_IRQ_DMA: u8 = 0x08;
+const DOR_MOTOR: u8 = 0x10;
+// the main status: the controller takes or gives a byte, which way, and it's busy
+// with a command; bits 0-3 are the drives that are seeking
+const MSR_READY: u8 = 0x80;
+const MSR_TO_CPU: u8 = 0x40;
+const MSR_BUSY: u8 = 0x10;
+// the disk of the selected drive was changed, on the DIR
+const DIR_CHANGED: u8 = 0x80;
+
+// the status registers of the results
+const ST0_INVALID: u8 = 0x80;
+const ST0_ABNORMAL: u8 = 0x40;
+const ST0_READY_CHANGED: u8 = 0xC0;
+const ST0_SEEK_END: u8 = 0x20;
+const ST0_EQUIPMENT_CHECK: u8 = 0x10;
+const ST0_NOT_READY: u8 = 0x08;
+const ST1_END_OF_CYLINDER: u8 = 0x80;
+const ST1_NO_DATA: u8 = 0x04;
+const ST1_NOT_WRITABLE: u8 = 0x02;
+const ST2_WRONG_CYLINDER: u8 = 0x10;
+const ST3_WRITE_PROTECTED: u8 = 0x40;
+const ST3_READY: u8 = 0x20;
+const ST3_TRACK_0: u8 = 0x10;
+const ST3_TWO_SIDED: u8 = 0x08;
+
+// the commands are the low 5 bits of their first byte, MT goes on to head 1
+const SPECIFY: u8 = 0x03;
+const SENSE_DRIVE_STATUS: u8 = 0x04;
+const WRITE_DATA: u8 = 0x05;
+const READ_DATA: u8 = 0x06;
+const RECALIBRATE: u8 = 0x07;
+const SENSE_INTERRUPT: u8 = 0x08;
+const READ_ID: u8 = 0x0A;
+const FORMAT_TRACK: u8 = 0x0D;
+const SEEK: u8 = 0x0F;
+const COMMAND_CODE: u8 = 0x1F;
+const MULTI_TRACK: u8 = 0x80;
+// a command takes 1 ms, a seek 3 ms a cylinder
+const COMMAND_CYCLES: u64 = 4773;
+const STEP_CYCLES: u64 = 14318;
+
+/**
+ * The cylinders, heads and sectors of a track of a disk.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Geometry {
+    pub cylinders: u16,
+    pub heads: u8,
+    pub sectors: u8,
+}
+
+// the images by their size, with the drive type INT 13h reports
+const FORMATS: [(usize, Geometry, u8); 3] = [
+    (368640, Geometry { cylinders: 40, heads: 2, sectors: 9 }, 1),
+    (737280, Geometry { cylinders: 80, heads: 2, sectors: 9 }, 3),
+    (1474560, Geometry { cylinders: 80, heads: 2, sectors: 18 }, 4),
+];
+
+/**
+ * Why a sector couldn't be read or written.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DiskError {
+    // the disk has no such sector
+    NoSector,
+    // the image can't be written
+    WriteProtected,
+    // writing the image failed, the disk is write protected from then on
+    WriteFault,
+}
+
+struct DiskImage {
+    bytes: Vec<u8>,
+    // the image the writes go to, None if it's read-only
+    file: Option<File>,
+    path: String,
+    geometry: Geometry,
+    drive_type: u8,
+    // why writing the image failed
+    fault: Option<String>,
+}
+
+/**
+ * A floppy disk in a drive, from a raw image of a 360K, 720K or 1.44M disk.
+ * The disk controller and the BIOS share it, each clone is the same disk.
+ * The sectors written go to the image file right away.
+ */
+#[derive(Clone)]
+pub struct Disk {
+    image: Arc<Mutex<DiskImage>>,
+}
+
+impl Disk {
+    /**
+     * Opens an image, its size says the geometry of the disk. An image that
+     * can't be written is a write protected disk.
+     * @param path: the image file.
+     * @return: the disk, or why the image can't be used.
+     */
+    pub fn open(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|err| format!("couldn't read {}: {}", path, err))?;
+        let (_, geometry, drive_type) = *FORMATS.iter().find(|(size, ..)| *size == bytes.len())
+            .ok_or(format!("{} has {} bytes, a disk image has 368640, 737280 or 1474560", path, bytes.len()))?;
+        Ok(Self {
+            image: Arc::new(Mutex::new(DiskImage {
+                bytes: bytes,
+                file: OpenOptions::new().write(true).open(path).ok(),
+                path: path.to_string(),
+                geometry: geometry,
+                drive_type: drive_type,
+                fault: None,
+            })),
+        })
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.image.lock().unwrap().geometry
+    }
+
+    /**
+     * Returns the type of drive the disk goes in: 1 for 360K, 3 for 720K and 4 for 1.44M.
+     */
+    pub fn drive_type(&self) -> u8 {
+        self.image.lock().unwrap().drive_type
+    }
+
+    pub fn write_protected(&self) -> bool {
+        self.image.lock().unwrap().file.is_none()
+    }
+
+    pub fn path(&self) -> String {
+        self.image.lock().unwrap().path.clone()
+    }
+
+    /**
+     * Returns why writing the image failed, None if it didn't.
+     */
+    pub fn fault(&self) -> Option<String> {
+        self.image.lock().unwrap().fault.clone()
+    }
+
+    /**
+     * Returns where a sector is in the image, None if the disk doesn't have it.
+     * @param sector: the sector in the track, from 1.
+     */
+    fn offset(geometry: Geometry, cylinder: u16, head: u8, sector: u8) -> Option<usize> {
+        if cylinder >= geometry.cylinders || head >= geometry.heads || sector == 0 || sector > geometry.sectors {
+            return None
+        }
+        let track = cylinder as usize * geometry.heads as usize + head as usize;
+        Some((track * geometry.sectors as usize + sector as usize - 1) * SECTOR_SIZE)
+    }
+
+    /**
+     * Reads a sector.
+     * @param sector: the sector in the track, from 1.
+     * @return: its bytes, None if the disk doesn't have it.
+     */
+    pub fn read_sector(&self, cylinder: u16, head: u8, sector: u8) -> Option<Vec<u8>> {
+        let image = self.image.lock().unwrap();
+        let offset = Self::offset(image.geometry, cylinder, head, sector)?;
+        Some(image.bytes[offset..offset + SECTOR_SIZE].to_vec())
+    }
+
+    /**
+     * Writes a sector, and the image file. If the file can't be written the
+     * sector keeps its bytes and the disk becomes write protected.
+     * @param bytes: the bytes of the sector, the ones missing are zeros.
+     */
+    pub fn write_sector(&self, cylinder: u16, head: u8, sector: u8, bytes: &[u8]) -> Result<(), DiskError> {
+        let mut image = self.image.lock().unwrap();
+        let offset = Self::offset(image.geometry, cylinder, head, sector).ok_or(DiskError::NoSector)?;
+        let mut data = bytes[..bytes.len().min(SECTOR_SIZE)].to_vec();
+        data.resize(SECTOR_SIZE, 0);
+        let file = image.file.as_mut().ok_or(DiskError::WriteProtected)?;
+        if let Err(err) = file.seek(SeekFrom::Start(offset as u64)).and_then(|_| file.write_all(&data)) {
+            image.fault = Some(format!("couldn't write {}: {}", image.path, err));
+            image.file = None;
+            return Err(DiskError::WriteFault)
+        }
+        image.bytes[offset..offset + SECTOR_SIZE].copy_from_slice(&data);
+        Ok(())
+    }
+}
+
+/**
+ * A read, write or format waiting for its DMA transfer.
+ */
+struct Transfer {
+    command: Vec<u8>,
+    // the sectors of a read or a write, and if the last one was missing
+    sectors: Vec<(u8, u8)>,
+    missing: bool,
+}
+
+/**
+ * An NEC 765 floppy disk controller at ports 3F0h-3F7h, with the digital
+ * output register of the PC for the motors and the drives. The cpu writes a
+ * command to the data port a byte at a time, reading the main status first;
+ * the controller executes it and the cpu reads its result. Reads, writes and
+ * formats move their bytes on DMA channel 2; seeks, transfers and a reset
+ * end with IRQ6, once the DOR enables it.
+ */
+pub struct Fdc {
+    irq: IrqLines,
+    dma: Dma,
+    drives: [Option<Disk>; 2],
+    dor: u8,
+    // the bytes of the command being written, and the result being read
+    command: Vec<u8>,
+    result: VecDeque<u8>,
+    // the command that waits to execute, for its cycles
+    executing: Option<(u64, Vec<u8>)>,
+    transfer: Option<Transfer>,
+    // the seeks of the drives: the cycles left and where the head goes
+    seeks: [Option<(u64, u8)>; 4],
+    // the cylinder the head of each drive is on
+    cylinders: [u8; 4],
+    // the status of the seeks and the reset, for SENSE INTERRUPT STATUS
+    senses: VecDeque<(u8, u8)>,
+    interrupt: bool,
+    // the disk was changed since the last step of the drive
+    changed: [bool; 4],
+    // the SPECIFY bytes: step rate and head times, and if DMA is off
+    specify: [u8; 2],
+    data_rate: u8,
+}
+
+impl Fdc {
+    /**
+     * Creates a controller that's reset, with the disks in its drives.
+     * @param irq: the lines it raises IRQ6 on.
+     * @param dma: the DMA controller that does its transfers.
+     * @param drives: the disks of drives A: and B:, None if empty.
+     */
+    pub fn new(irq: IrqLines, dma: Dma, drives: [Option<Disk>; 2]) -> Self {
+        Self {
+            irq: irq,
+            dma: dma,
+            drives: drives,
+            dor: 0,
+            command: Vec::new(),
+            result: VecDeque::new(),
+            executing: None,
+            transfer: None,
+            seeks: [None; 4],
+            cylinders: [0; 4],
+            senses: VecDeque::new(),
+            interrupt: false,
+            changed: [true; 4],
+            specify: [0, 0],
+            data_rate: 0,
+        }
+    }
+
+    fn disk(&self, drive: usize) -> Option<&Disk> {
+        self.drives.get(drive).and_then(Option::as_ref)
+    }
+
+    /**
+     * Says if a drive has a disk and its motor on.
+     */
+    fn ready(&self, drive: usize) -> bool {
+        self.disk(drive).is_some() && self.dor & (DOR_MOTOR << drive) != 0
+    }
+
+    fn update_irq(&self) {
+        self.irq.set(IRQ_LINE, self.interrupt && self.dor & DOR_IRQ_DMA != 0);
+    }
+
+    fn main_status(&self) -> u8 {
+        let seeking = (0..4).filter(|drive| self.seeks[*drive].is_some())
+            .fold(0, |bits, drive| bits | 1 << drive);
+        if self.dor & DOR_NOT_RESET == 0 {
+            0
+        } else if !self.result.is_empty() {
+            MSR_READY | MSR_TO_CPU | MSR_BUSY | seeking
+        } else if self.executing.is_some() || self.transfer.is_some() {
+            MSR_BUSY | seeking
+        } else if !self.command.is_empty() {
+            MSR_READY | MSR_BUSY | seeking
+        } else {
+            MSR_READY | seeking
+        }
+    }
+
+    fn write_dor(&mut self, value: u8) {
+        let old = self.dor;
+        self.dor = value;
+        if value & DOR_NOT_RESET == 0 {
+            self.command.clear();
+            self.result.clear();
+            self.executing = None;
+            self.transfer = None;
+            self.dma.cancel(DMA_CHANNEL);
+            self.seeks = [None; 4];
+            self.senses.clear();
+            self.interrupt = false;
+        } else if old & DOR_NOT_RESET == 0 {
+            // after a reset the drives report they became ready
+            self.senses = (0..4).map(|drive| (ST0_READY_CHANGED | drive as u8, self.cylinders[drive])).collect();
+            self.interrupt = true;
+        }
+        self.update_irq();
+    }
+
+    fn write_data(&mut self, value: u8) {
+        if self.main_status() & (MSR_READY | MSR_TO_CPU) != MSR_READY {
+            return
+        }
+        self.command.push(value);
+        let length = match self.command[0] & COMMAND_CODE {
+            READ_DATA | WRITE_DATA => 9,
+            FORMAT_TRACK => 6,
+            SPECIFY | SEEK => 3,
+            READ_ID | RECALIBRATE | SENSE_DRIVE_STATUS => 2,
+            _ => 1,
+        };
+        if self.command.len() == length {
+            let command = std::mem::take(&mut self.command);
+            self.run_command(command);
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        match self.result.pop_front() {
+            Some(byte) => {
+                // reading the result ends the interrupt of a transfer
+                if self.senses.is_empty() {
+                    self.interrupt = false;
+                    self.update_irq();
+                }
+                byte
+            },
+            None => 0xFF,
+        }
+    }
+
+    /**
+     * Runs the commands that don't wait, and starts the others.
+     */
+    fn run_command(&mut self, command: Vec<u8>) {
+        let drive = (command.get(1).copied().unwrap_or(0) & DOR_DRIVE) as usize;
+        match command[0] & COMMAND_CODE {
+            SPECIFY => self.specify = [command[1], command[2]],
+            SENSE_DRIVE_STATUS => {
+                let head = command[1] & 0x04;
+                let mut st3 = head | drive as u8 | ST3_TWO_SIDED;
+                if let Some(disk) = self.disk(drive) {
+                    st3 |= ST3_READY;
+                    if disk.write_protected() {
+                        st3 |= ST3_WRITE_PROTECTED;
+                    }
+                }
+                if self.cylinders[drive] == 0 {
+                    st3 |= ST3_TRACK_0;
+                }
+                self.result.push_back(st3);
+            },
+            SENSE_INTERRUPT => match self.senses.pop_front() {
+                Some((st0, cylinder)) => {
+                    self.result.extend([st0, cylinder]);
+                    self.interrupt = false;
+                    self.update_irq();
+                },
+                None => self.result.push_back(ST0_INVALID),
+            },
+            RECALIBRATE => self.start_seek(drive, 0),
+            SEEK => self.start_seek(drive, command[2]),
+            READ_DATA | WRITE_DATA | READ_ID | FORMAT_TRACK => self.executing = Some((COMMAND_CYCLES, command)),
+            _ => self.result.push_back(ST0_INVALID),
+        }
+    }
+
+    fn start_seek(&mut self, drive: usize, cylinder: u8) {
+        let steps = (self.cylinders[drive] as i16 - cylinder as i16).abs() as u64;
+        self.seeks[drive] = Some((COMMAND_CYCLES + steps * STEP_CYCLES, cylinder));
+    }
+
+    /**
+     * Moves the head of a drive at the end of its seek. A drive without a
+     * disk doesn't find cylinder 0.
+     */
+    fn end_seek(&mut self, drive: usize, cylinder: u8) {
+        let mut st0 = ST0_SEEK_END | drive as u8;
+        match self.disk(drive) {
+            Some(disk) => {
+                self.cylinders[drive] = cylinder.min((disk.geometry().cylinders - 1) as u8);
+                self.changed[drive] = false;
+            },
+            None => st0 |= ST0_ABNORMAL | ST0_EQUIPMENT_CHECK,
+        }
+        self.senses.push_back((st0, self.cylinders[drive]));
+        self.interrupt = true;
+        self.update_irq();
+    }
+
+    /**
+     * Ends a command with its result and an interrupt.
+     */
+    fn end_command(&mut self, result: Vec<u8>) {
+        self.result.extend(result);
+        self.interrupt = true;
+        self.update_irq();
+    }
+
+    /**
+     * Executes a read, write, format or read ID once its time passed: the
+     * transfers ask for DMA, the errors end the command.
+     */
+    fn execute(&mut self, command: Vec<u8>) {
+        let drive = (command[1] & DOR_DRIVE) as usize;
+        let head = (command[1] >> 2) & 1;
+        let unit = command[1] & 0x07;
+        let cylinder = self.cylinders[drive];
+        let code = command[0] & COMMAND_CODE;
+        // the ID of the result: the one of the command, or where the head is
+        let id = match code {
+            READ_DATA | WRITE_DATA => [command[2], command[3], command[4], command[5]],
+            _ => [cylinder, head, 1, SIZE_CODE],
+        };
+        let failed = |st0: u8, st1: u8, st2: u8| [vec![ST0_ABNORMAL | st0 | unit, st1, st2], id.to_vec()].concat();
+        if !self.ready(drive) {
+            return self.end_command(failed(ST0_NOT_READY, 0, 0))
+        }
+        let disk = self.disk(drive).unwrap().clone();
+        let geometry = disk.geometry();
+        if (code == WRITE_DATA || code == FORMAT_TRACK) && disk.write_protected() {
+            return self.end_command(failed(0, ST1_NOT_WRITABLE, 0))
+        }
+        match code {
+            READ_ID => self.end_command([vec![unit, 0, 0], id.to_vec()].concat()),
+            FORMAT_TRACK => {
+                let sectors = command[3] as usize;
+                self.dma.request(DMA_CHANNEL, Vec::new(), 4 * sectors);
+                self.transfer = Some(Transfer { command: command, sectors: Vec::new(), missing: false });
+            },
+            _ => {
+                // the head has to be on the cylinder of the sectors
+                if command[2] != cylinder || command[3] != head || command[5] != SIZE_CODE {
+                    let wrong = if command[2] != cylinder { ST2_WRONG_CYLINDER } else { 0 };
+                    return self.end_command(failed(0, ST1_NO_DATA, wrong))
+                }
+                // the sectors up to the end of the track, and of the cylinder for MT
+                let end = command[6];
+                let mut sectors: Vec<(u8, u8)> = (command[4]..=end).map(|sector| (head, sector)).collect();
+                if command[0] & MULTI_TRACK != 0 && head == 0 && geometry.heads > 1 {
+                    sectors.extend((1..=end).map(|sector| (1, sector)));
+                }
+                let found = sectors.iter().take_while(|(_, sector)| *sector >= 1 && *sector <= geometry.sectors).count();
+                let missing = found < sectors.len();
+                sectors.truncate(found);
+                if sectors.is_empty() {
+                    return self.end_command(failed(0, ST1_NO_DATA, 0))
+                }
+                if code == READ_DATA {
+                    let bytes: Vec<u8> = sectors.iter()
+                        .flat_map(|(head, sector)| disk.read_sector(cylinder as u16, *head, *sector).unwrap())
+                        .collect();
+                    let length = bytes.len();
+                    self.dma.request(DMA_CHANNEL, bytes, length);
+                } else {
+                    self.dma.request(DMA_CHANNEL, Vec::new(), sectors.len() * SECTOR_SIZE);
+                }
+                self.transfer = Some(Transfer { command: command, sectors: sectors, missing: missing });
+            },
+        }
+    }
+
+    /**
+     * Ends a transfer once DMA moved its bytes. The terminal count ends it
+     * normally; without it the controller goes on past the last sector, which
+     * ends it with an error, like the end of the cylinder.
+     */
+    fn end_transfer(&mut self, transfer: Transfer, moved: usize, bytes: Vec<u8>, terminal: bool) {
+        let command = transfer.command;
+        let drive = (command[1] & DOR_DRIVE) as usize;
+        let unit = command[1] & 0x07;
+        let cylinder = self.cylinders[drive];
+        let disk = self.disk(drive).unwrap().clone();
+        if command[0] & COMMAND_CODE == FORMAT_TRACK {
+            let head = (command[1] >> 2) & 1;
+            for id in bytes.chunks(4).filter(|id| id.len() == 4) {
+                let written = disk.write_sector(cylinder as u16, head, id[2], &vec![command[5]; SECTOR_SIZE]);
+                if written == Err(DiskError::WriteFault) {
+                    return self.end_command(vec![ST0_ABNORMAL | ST0_EQUIPMENT_CHECK | unit, 0, 0, cylinder, head, 1, command[2]])
+                }
+            }
+            return self.end_command(vec![unit, 0, 0, cylinder, head, 1, command[2]])
+        }
+        // the sectors the bytes got to, a sector that was started is finished
+        let done = ((moved + SECTOR_SIZE - 1) / SECTOR_SIZE).max(1).min(transfer.sectors.len());
+        if command[0] & COMMAND_CODE == WRITE_DATA {
+            for (i, (head, sector)) in transfer.sectors[..done].iter().enumerate() {
+                let start = (i * SECTOR_SIZE).min(bytes.len());
+                let end = (start + SECTOR_SIZE).min(bytes.len());
+                // the drive reports a fault on the sector the image couldn't take
+                if disk.write_sector(cylinder as u16, *head, *sector, &bytes[start..end]).is_err() {
+                    return self.end_command(vec![ST0_ABNORMAL | ST0_EQUIPMENT_CHECK | unit, 0, 0, cylinder, *head, *sector, command[5]])
+                }
+            }
+        }
+        // the ID after the last sector
+        let (head, sector) = transfer.sectors[done - 1];
+        let id = if sector < command[6] {
+            [cylinder, head, sector + 1]
+        } else if command[0] & MULTI_TRACK != 0 && head == 0 {
+            [cylinder, 1, 1]
+        } else {
+            [cylinder.wrapping_add(1), head, 1]
+        };
+        let (st0, st1) = if terminal {
+            (unit, 0)
+        } else if transfer.missing {
+            (ST0_ABNORMAL | unit, ST1_NO_DATA)
+        } else {
+            (ST0_ABNORMAL | unit, ST1_END_OF_CYLINDER)
+        };
+        self.end_command(vec![st0, st1, 0, id[0], id[1], id[2], command[5]]);
+    }
+}
+
+impl Hardware for Fdc {
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    fn write_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let value = ports[port as usize];
+        match port {
+            DIGITAL_OUTPUT => self.write_dor(value),
+            DATA => self.write_data(value),
+            // the configuration control register sets the data rate
+            DIGITAL_INPUT => self.data_rate = value & 3,
+            _ => (),
+        }
+    }
+
+    fn read_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        ports[port as usize] = match port {
+            DIGITAL_OUTPUT => self.dor,
+            MAIN_STATUS => self.main_status(),
+            DATA => self.read_data(),
+            DIGITAL_INPUT => if self.changed[(self.dor & DOR_DRIVE) as usize] { DIR_CHANGED } else { 0 },
+            _ => 0xFF,
+        };
+    }
+
+    /**
+     * Ends the seeks and the commands as the time passes, and the transfers once DMA did them.
+     */
+    fn tick(&mut self, cycles: u64, _ports: &mut Vec<u8>) -> () {
+        for drive in 0..4 {
+            if let Some((left, cylinder)) = self.seeks[drive] {
+                if left > cycles {
+                    self.seeks[drive] = Some((left - cycles, cylinder));
+                } else {
+                    self.seeks[drive] = None;
+                    self.end_seek(drive, cylinder);
+                }
+            }
+        }
+        if let Some((left, command)) = self.executing.take() {
+            if left > cycles {
+                self.executing = Some((left - cycles, command));
+            } else {
+                self.execute(command);
+            }
+        }
+        if self.transfer.is_some() {
+            if let Some(result) = self.dma.take_result(DMA_CHANNEL) {
+                let transfer = self.transfer.take().unwrap();
+                self.end_transfer(transfer, result.moved, result.bytes, result.terminal);
+            }
+        }
+    }
+
+    fn is_port_related(&self, port: &u16) -> bool {
+        *port >= FIRST_PORT && *port <= DIGITAL_INPUT
+    }
+
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
+        ui.label(format!("3F0h-3F7h IRQ{}, DMA channel {}", IRQ_LINE, DMA_CHANNEL));
+        ui.label(format!("step rate {:X}h, data rate {}{}", self.specify[0] >> 4, self.data_rate,
+            if self.specify[1] & 1 != 0 { ", no DMA" } else { "" }));
+        ui.monospace(format!("DOR {:02X}  MSR {:02X}  {}", self.dor, self.main_status(),
+            if self.transfer.is_some() { "transferring" } else if self.executing.is_some() { "executing" } else { "" }));
+        for (drive, letter) in ['A', 'B'].iter().enumerate() {
+            let motor = if self.dor & (DOR_MOTOR << drive) != 0 { "motor on" } else { "motor off" };
+            match self.disk(drive) {
+                Some(disk) => {
+                    ui.label(format!("{}: {}, cylinder {}, {}", letter, disk.path(), self.cylinders[drive], motor));
+                    if let Some(fault) = disk.fault() {
+                        ui.colored_label(egui::Color32::RED, fault);
+                    }
+                },
+                None => {
+                    ui.label(format!("{}: no disk", letter));
+                },
+            }
+        }
+        ui.ctx().request_repaint();
+    }
+
+    fn name(&self) -> &str {
+        "Floppy disk controller"
+    }
+}
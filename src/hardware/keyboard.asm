@@ -1,4 +0,0 @@
-mov dx, 0x1000
-mov al, 0
-out dx, al
-iret
\ No newline at end of file
@@ -1,48 +1,370 @@
 use super::*;
-use libc::getchar;
+use std::collections::VecDeque;
+use eframe::egui::{Event, Key, Modifiers, Sense};
+use crate::bios::keyboard::{scancode, shifted, LEFT_SHIFT, CTRL, ALT, BREAK};
 
+// the data of the keyboard at 60h, the status and the commands of the controller at 64h
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const KEYBOARD_IRQ: u8 = 1;
+// a scancode takes about a millisecond on the line of the keyboard
+const SCANCODE_CYCLES: u64 = 4770;
+
+// the bits of the status: a byte waits in the output buffer, the self test
+// passed, the last write was a command and the keyboard isn't locked
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_SYSTEM: u8 = 0x04;
+const STATUS_COMMAND: u8 = 0x08;
+const STATUS_UNLOCKED: u8 = 0x10;
+// the bits of the command byte: IRQ1 when a byte comes, the system flag and the keyboard disabled
+const COMMAND_INTERRUPT: u8 = 0x01;
+const COMMAND_SYSTEM: u8 = 0x04;
+const COMMAND_DISABLED: u8 = 0x10;
+// IRQ1 on, the self test passed and the scancodes translated to set 1, like the BIOS leaves it
+const DEFAULT_COMMAND: u8 = 0x45;
+
+// the commands of the controller
+const READ_COMMAND: u8 = 0x20;
+const WRITE_COMMAND: u8 = 0x60;
+const SELF_TEST: u8 = 0xAA;
+const INTERFACE_TEST: u8 = 0xAB;
+const DISABLE_KEYBOARD: u8 = 0xAD;
+const ENABLE_KEYBOARD: u8 = 0xAE;
+const SELF_TEST_PASSED: u8 = 0x55;
+
+// the commands of the keyboard and its replies
+const SET_LEDS: u8 = 0xED;
+const ECHO: u8 = 0xEE;
+const IDENTIFY: u8 = 0xF2;
+const ENABLE: u8 = 0xF4;
+const DISABLE: u8 = 0xF5;
+const RESET: u8 = 0xFF;
+const ACK: u8 = 0xFA;
+const RESET_PASSED: u8 = 0xAA;
+const KEYBOARD_ID: [u8; 2] = [0xAB, 0x83];
+
+/**
+ * Returns the scancode of a key of egui, in scancode set 1.
+ */
+fn key_scancode(key: Key) -> u8 {
+    match key {
+        Key::ArrowUp => 0x48,
+        Key::ArrowDown => 0x50,
+        Key::ArrowLeft => 0x4B,
+        Key::ArrowRight => 0x4D,
+        Key::Insert => 0x52,
+        Key::Delete => 0x53,
+        Key::Home => 0x47,
+        Key::End => 0x4F,
+        Key::PageUp => 0x49,
+        Key::PageDown => 0x51,
+        Key::Escape => scancode(0x1B),
+        Key::Tab => scancode(b'\t'),
+        Key::Backspace => scancode(0x08),
+        Key::Enter => scancode(b'\r'),
+        Key::Space => scancode(b' '),
+        Key::Num0 => scancode(b'0'),
+        Key::Num1 => scancode(b'1'),
+        Key::Num2 => scancode(b'2'),
+        Key::Num3 => scancode(b'3'),
+        Key::Num4 => scancode(b'4'),
+        Key::Num5 => scancode(b'5'),
+        Key::Num6 => scancode(b'6'),
+        Key::Num7 => scancode(b'7'),
+        Key::Num8 => scancode(b'8'),
+        Key::Num9 => scancode(b'9'),
+        // the letters follow each other in egui
+        letter => scancode(b'a' + (letter as u8 - Key::A as u8)),
+    }
+}
+
+/**
+ * The keyboard and the registers of its controller.
+ */
+struct KeyboardState {
+    irq: IrqLines,
+    // the scancodes and replies the keyboard didn't send yet
+    keys: VecDeque<u8>,
+    output: u8,
+    output_full: bool,
+    command_byte: u8,
+    last_command: bool,
+    // the command waiting for its byte on port 60h
+    pending: Option<u8>,
+    scanning: bool,
+    leds: u8,
+    cycles: u64,
+    // the modifiers the window saw last, their keys are sent as they change
+    modifiers: Modifiers,
+}
+
+impl KeyboardState {
+    /**
+     * Puts a byte in the output buffer, raising IRQ1 if the command byte lets it.
+     */
+    fn send(&mut self, byte: u8) {
+        self.output = byte;
+        self.output_full = true;
+        if self.command_byte & COMMAND_INTERRUPT != 0 {
+            self.irq.raise(KEYBOARD_IRQ);
+        }
+    }
+
+    /**
+     * Replies to a command of the keyboard, before the keys waiting.
+     */
+    fn reply(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter().rev() {
+            self.keys.push_front(*byte);
+        }
+    }
+
+    fn press(&mut self, scancode: u8, pressed: bool) {
+        if self.scanning {
+            self.keys.push_back(if pressed { scancode } else { scancode | BREAK });
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        self.output_full = false;
+        self.irq.lower(KEYBOARD_IRQ);
+        self.output
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = STATUS_UNLOCKED;
+        if self.output_full { status |= STATUS_OUTPUT_FULL }
+        if self.command_byte & COMMAND_SYSTEM != 0 { status |= STATUS_SYSTEM }
+        if self.last_command { status |= STATUS_COMMAND }
+        status
+    }
+
+    fn write_command(&mut self, value: u8) {
+        self.last_command = true;
+        self.pending = None;
+        match value {
+            READ_COMMAND => self.send(self.command_byte),
+            WRITE_COMMAND => self.pending = Some(WRITE_COMMAND),
+            SELF_TEST => self.send(SELF_TEST_PASSED),
+            INTERFACE_TEST => self.send(0),
+            DISABLE_KEYBOARD => self.command_byte |= COMMAND_DISABLED,
+            ENABLE_KEYBOARD => self.command_byte &= !COMMAND_DISABLED,
+            _ => (),
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        self.last_command = false;
+        match self.pending.take() {
+            Some(WRITE_COMMAND) => {
+                self.command_byte = value;
+                return
+            },
+            Some(SET_LEDS) => {
+                self.leds = value & 0x07;
+                self.reply(&[ACK]);
+                return
+            },
+            _ => (),
+        }
+        match value {
+            SET_LEDS => {
+                self.pending = Some(SET_LEDS);
+                self.reply(&[ACK]);
+            },
+            ECHO => self.reply(&[ECHO]),
+            IDENTIFY => {
+                self.reply(&KEYBOARD_ID);
+                self.reply(&[ACK]);
+            },
+            ENABLE => {
+                self.scanning = true;
+                self.reply(&[ACK]);
+            },
+            DISABLE | RESET => {
+                self.keys.clear();
+                self.scanning = value == RESET;
+                self.reply(if value == RESET { &[ACK, RESET_PASSED] } else { &[ACK] });
+            },
+            _ => self.reply(&[ACK]),
+        }
+    }
+
+    /**
+     * Sends the make and break codes of the keys typed in the window. The
+     * modifiers are sent as they change, the keys egui has no name for are
+     * taken from the text they type.
+     */
+    fn type_events(&mut self, events: &[Event], modifiers: Modifiers) {
+        for (held, was_held, scancode) in [
+            (modifiers.shift, self.modifiers.shift, LEFT_SHIFT),
+            (modifiers.ctrl, self.modifiers.ctrl, CTRL),
+            (modifiers.alt, self.modifiers.alt, ALT),
+        ] {
+            if held != was_held {
+                self.press(scancode, held);
+            }
+        }
+        self.modifiers = modifiers;
+        for event in events {
+            match event {
+                Event::Key { key, pressed, .. } => self.press(key_scancode(*key), *pressed),
+                Event::Text(text) => for character in text.bytes() {
+                    if !character.is_ascii_alphanumeric() && character != b' ' {
+                        let code = scancode(character);
+                        if code != 0 {
+                            self.press(code, true);
+                            self.press(code, false);
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+/**
+ * A PC keyboard and its 8042 controller at ports 60h and 64h. The keys send
+ * the make and break codes of scancode set 1, one at a time through the
+ * output buffer at port 60h, raising IRQ1 for each. The status and the
+ * commands of the controller are at port 64h, the commands of the keyboard
+ * are written to port 60h. The window is a clone of it, they share the
+ * registers.
+ */
+#[derive(Clone)]
 pub struct Keyboard {
-    ports_range: Range<usize>,
-    buffer: i32,
+    state: Arc<Mutex<KeyboardState>>,
 }
 
 impl Keyboard {
-    // Creates a new instance of Lightbulb
-    pub fn new() -> Self {
+    /**
+     * Creates a keyboard the way the BIOS leaves it, scanning with IRQ1 on.
+     * @param irq: the lines it raises IRQ1 on.
+     */
+    pub fn new(irq: IrqLines) -> Self {
         Self {
-            ports_range: 0x1000..0x1002,
-            buffer: 0,
+            state: Arc::new(Mutex::new(KeyboardState {
+                irq: irq,
+                keys: VecDeque::new(),
+                output: 0,
+                output_full: false,
+                command_byte: DEFAULT_COMMAND,
+                last_command: false,
+                pending: None,
+                scanning: true,
+                leds: 0,
+                cycles: 0,
+                modifiers: Modifiers::default(),
+            })),
+        }
+    }
+
+    /**
+     * Presses or releases a key.
+     * @param scancode: the make code of the key, in scancode set 1.
+     */
+    pub fn press(&self, scancode: u8, pressed: bool) {
+        self.state.lock().unwrap().press(scancode, pressed);
+    }
+
+    /**
+     * Types text on a US keyboard, pressing shift for the characters that need it.
+     */
+    pub fn type_text(&self, text: &str) {
+        let mut state = self.state.lock().unwrap();
+        for character in text.bytes() {
+            let code = scancode(character);
+            if code == 0 {
+                continue
+            }
+            let shift = shifted(character) || character.is_ascii_uppercase();
+            if shift { state.press(LEFT_SHIFT, true) }
+            state.press(code, true);
+            state.press(code, false);
+            if shift { state.press(LEFT_SHIFT, false) }
         }
     }
 }
 
 impl Hardware for Keyboard {
-    fn init(&self, memory: &mut MemorySegments) -> () {
-        let interrupt_vector = vec![0x00, 0x16, 0x00, 0x00];
-        memory.write(&interrupt_vector, (0, 0x58));
-        memory.write(&get_program("src/hardware/keyboard.asm"), (0x1600, 0));
-    }  
-
-    fn run_hardware(&mut self, ports: &mut Vec<u8>) -> () {
-        // get keystroke
-        if ports[0x1000] == 0 {
-            unsafe {
-                self.buffer = getchar();
-            }
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    fn write_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let value = ports[port as usize];
+        let mut state = self.state.lock().unwrap();
+        match port {
+            STATUS_PORT => state.write_command(value),
+            _ => state.write_data(value),
+        }
+    }
+
+    fn read_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let mut state = self.state.lock().unwrap();
+        ports[port as usize] = match port {
+            STATUS_PORT => state.status(),
+            _ => state.read_data(),
+        };
+    }
+
+    /**
+     * Sends the next scancode once the output buffer is read.
+     */
+    fn tick(&mut self, cycles: u64, _ports: &mut Vec<u8>) -> () {
+        let mut state = self.state.lock().unwrap();
+        state.cycles += cycles;
+        if state.cycles < SCANCODE_CYCLES {
+            return
+        }
+        state.cycles = 0;
+        if state.output_full || state.command_byte & COMMAND_DISABLED != 0 {
+            return
+        }
+        if let Some(byte) = state.keys.pop_front() {
+            state.send(byte);
         }
     }
 
     fn is_port_related(&self, port: &u16) -> bool {
-        let p = *port as usize;
-        self.ports_range.contains(&p)
+        *port == DATA_PORT || *port == STATUS_PORT
     }
 
-    fn ui(&self, ui: &mut Ui) {
-        ui.label("THIS IS DEBBUGING WINDOWS");
-        ui.label(format!("{}", self.buffer));
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
+        let mut state = self.state.lock().unwrap();
+        // the keys are typed while the area has the focus, tab doesn't move it
+        let (rect, response) = ui.allocate_exact_size(Vec2::new(240.0, 40.0), Sense::click());
+        if response.clicked() {
+            response.request_focus();
+        }
+        let focused = response.has_focus();
+        ui.memory().lock_focus(response.id, focused);
+        let text = if focused { "typing on the keyboard" } else { "click here to type" };
+        let visuals = ui.style().interact(&response);
+        ui.painter().rect_stroke(rect, 2.0, visuals.fg_stroke);
+        ui.painter().text(rect.center(), egui::Align2::CENTER_CENTER, text, egui::TextStyle::Body, visuals.text_color());
+        if focused {
+            let input = ui.input();
+            state.type_events(&input.events, input.modifiers);
+        } else {
+            // the modifiers held are released when the focus goes
+            state.type_events(&[], Modifiers::default());
+        }
+        ui.label(format!("output buffer {}", if state.output_full { format!("{:02X}", state.output) } else { "empty".to_string() }));
+        ui.label(format!("{} scancodes waiting, command byte {:02X}", state.keys.len(), state.command_byte));
+        ui.label(format!("LEDs: {}{}{}",
+            if state.leds & 0x04 != 0 { "caps " } else { "" },
+            if state.leds & 0x02 != 0 { "num " } else { "" },
+            if state.leds & 0x01 != 0 { "scroll" } else { "" }));
+        ui.ctx().request_repaint();
     }
 
     fn name(&self) -> &str {
         "Keyboard"
     }
-}
\ No newline at end of file
+}
@@ -18,10 +18,10 @@ impl Lightbulb {
 }
 
 impl Hardware for Lightbulb {
-    fn init(&self, memory: &mut MemorySegments) -> () {
-        let interrupt_vector = vec![0x00, 0x33, 0x00, 0x00];
-        memory.write(&interrupt_vector, (0, 0xCC));
-        memory.write(&get_program("src/hardware/lightbulb.asm"), (0x3300, 0));
+    fn init(&self, memory: &mut MemorySegments) -> Result<(), LoadError> {
+        memory.set_interrupt_vector(0x33, (0x3300, 0));
+        memory.write(&get_program("src/hardware/lightbulb.asm")?, (0x3300, 0));
+        Ok(())
     }  
 
     fn run_hardware(&mut self, ports: &mut Vec<u8>) -> () {
@@ -43,7 +43,7 @@ impl Hardware for Lightbulb {
         self.ports_range.contains(&p)
     }
 
-    fn ui(&self, ui: &mut Ui) {
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
         if self.power {
             ui.add(egui::Label::new(egui::RichText::new("    ")
                 .background_color(egui::Rgba::from_rgb(self.color, self.color, self.color))));
@@ -1,15 +1,86 @@
 use std::ops::Range;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU16, Ordering};
 use std::thread;
 
-use eframe::egui;
-use eframe::egui::Ui;
+use eframe::{egui, epi};
+use eframe::egui::{Ui, Vec2};
 
 use crate::cpu::memory_unit::memory_segments::MemorySegments;
-use crate::program_loader::get_program;
+use crate::program_loader::{get_program, LoadError};
 
 pub mod lightbulb;
 pub mod keyboard;
+pub mod console;
+pub mod video;
+pub mod pit;
+pub mod pic;
+pub mod speaker;
+pub mod serial;
+pub mod printer;
+pub mod dma;
+pub mod floppy;
+
+/**
+ * The interrupt request lines IRQ0-15 the hardware raises and lowers. The
+ * lines are shared, each clone is connected to the same lines. A line that
+ * goes up is remembered until it's taken, so a short pulse isn't lost.
+ */
+#[derive(Clone)]
+pub struct IrqLines {
+    levels: Arc<AtomicU16>,
+    edges: Arc<AtomicU16>,
+}
+
+impl IrqLines {
+    pub fn new() -> Self {
+        Self {
+            levels: Arc::new(AtomicU16::new(0)),
+            edges: Arc::new(AtomicU16::new(0)),
+        }
+    }
+
+    pub fn raise(&self, irq: u8) {
+        let bit = 1 << irq;
+        if self.levels.fetch_or(bit, Ordering::SeqCst) & bit == 0 {
+            self.edges.fetch_or(bit, Ordering::SeqCst);
+        }
+    }
+
+    pub fn lower(&self, irq: u8) {
+        self.levels.fetch_and(!(1 << irq), Ordering::SeqCst);
+    }
+
+    /**
+     * Raises or lowers a line.
+     */
+    pub fn set(&self, irq: u8, level: bool) {
+        if level { self.raise(irq) } else { self.lower(irq) }
+    }
+
+    /**
+     * Returns the lines that are up, a bit each.
+     */
+    pub fn levels(&self) -> u16 {
+        self.levels.load(Ordering::SeqCst)
+    }
+
+    /**
+     * Returns the lines that went up since the last call, a bit each.
+     */
+    pub fn take_edges(&self) -> u16 {
+        self.edges.swap(0, Ordering::SeqCst)
+    }
+}
+
+/**
+ * A picture hardware shows in its window, like the screen of a graphics mode.
+ */
+pub struct Picture {
+    pub image: epi::Image,
+    // the size it's shown at, the pixels of a screen aren't always square
+    pub size: Vec2,
+}
 
 pub trait Hardware: Send {
 
@@ -18,8 +89,9 @@ pub trait Hardware: Send {
      * code and program.
      * @param memory: a reference to the MemorySegments struct
      *      of the cpu, letting the hardware to write it's program.
+     * @return: error if the program of the hardware couldn't be loaded.
      */
-    fn init(&self, memory: &mut MemorySegments) -> ();
+    fn init(&self, memory: &mut MemorySegments) -> Result<(), LoadError>;
 
     /**
      * Runs the hardware. That is, the way to simulate a 'hardware' in action.
@@ -30,17 +102,59 @@ pub trait Hardware: Send {
      */
     fn run_hardware(&mut self, ports: &mut Vec<u8>) -> ();
 
+    /**
+     * Called after the cpu writes one of the hardware's ports.
+     * If not implamented, the hardware is run.
+     * @param port: the port the cpu wrote.
+     * @param ports: the ports vector.
+     */
+    fn write_hardware(&mut self, _port: u16, ports: &mut Vec<u8>) -> () {
+        self.run_hardware(ports)
+    }
+
+    /**
+     * Called before the cpu reads one of the hardware's ports, letting the
+     * hardware put the value to read in the ports vector.
+     * If not implamented, the cpu reads the last value written to the port.
+     * @param port: the port the cpu reads.
+     * @param ports: the ports vector.
+     */
+    fn read_hardware(&mut self, _port: u16, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    /**
+     * Called as the emulated clock goes on, letting the hardware count time
+     * even when the cpu doesn't access it.
+     * If not implamented, nothing happens.
+     * @param cycles: the cpu clock cycles since the last call.
+     * @param ports: the ports vector.
+     */
+    fn tick(&mut self, _cycles: u64, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
     fn is_port_related(&self, port: &u16) -> bool;
 
     /**
      * Adds a ui content on to a new `Window`.
      * If not implamented, nothing happens.
      * @param ui: refernece to the Ui
+     * @param memory: the memory of the cpu, for hardware that shows it.
      */
-    fn ui(&self, ui: &mut Ui) {
+    fn ui(&self, _ui: &mut Ui, _memory: &MemorySegments) {
         ()
     }
 
+    /**
+     * Returns a picture to show in the window of the hardware, under its ui content.
+     * If not implamented, there is none.
+     * @param memory: the memory of the cpu.
+     */
+    fn picture(&self, _memory: &MemorySegments) -> Option<Picture> {
+        None
+    }
+
     /**
      * Returns the name of the hardware.
      */
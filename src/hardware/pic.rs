@@ -0,0 +1,291 @@
+use super::*;
+use crate::cpu::InterruptController;
+
+const COMMAND_PORT: u16 = 0x20;
+const DATA_PORT: u16 = 0x21;
+
+// ICW1 has bit 4 set, OCW3 bit 3, OCW2 neither
+const ICW1: u8 = 0x10;
+const ICW1_NEEDS_ICW4: u8 = 0x01;
+const ICW1_SINGLE: u8 = 0x02;
+const ICW1_LEVEL: u8 = 0x08;
+const OCW3: u8 = 0x08;
+const OCW3_POLL: u8 = 0x04;
+const OCW3_READ: u8 = 0x02;
+const OCW3_READ_ISR: u8 = 0x01;
+const OCW3_SET_SPECIAL_MASK: u8 = 0x40;
+const OCW3_SPECIAL_MASK: u8 = 0x20;
+const ICW4_AUTO_EOI: u8 = 0x02;
+
+/**
+ * The registers of the 8259A.
+ */
+struct PicState {
+    irq: IrqLines,
+    // the requests, the ones in service and the masked ones, a bit each line
+    irr: u8,
+    isr: u8,
+    imr: u8,
+    // the vector of IRQ0, the others follow it
+    vector_base: u8,
+    // the ICW the next write to port 21h is, while initializing
+    next_icw: Option<u8>,
+    single: bool,
+    needs_icw4: bool,
+    level_triggered: bool,
+    auto_eoi: bool,
+    rotate_on_auto_eoi: bool,
+    special_mask: bool,
+    read_isr: bool,
+    poll: bool,
+    // the line with the lowest priority, the one after it has the highest
+    lowest: u8,
+}
+
+impl PicState {
+    /**
+     * Takes the requests of the lines: the lines that went up, or the lines
+     * that are up when triggered by level.
+     */
+    fn sample(&mut self) {
+        let edges = self.irq.take_edges() as u8;
+        if self.level_triggered {
+            self.irr = self.irq.levels() as u8;
+        } else {
+            self.irr |= edges;
+        }
+    }
+
+    /**
+     * Returns the lines from the highest priority to the lowest.
+     */
+    fn priorities(&self) -> impl Iterator<Item = u8> {
+        let lowest = self.lowest;
+        (1..=8).map(move |i| (lowest + i) & 7)
+    }
+
+    /**
+     * Returns the request with the highest priority the cpu should get. A
+     * line in service holds back its own and the lower priority requests,
+     * unless the special mask mode lets them through.
+     */
+    fn pending(&self) -> Option<u8> {
+        for line in self.priorities() {
+            let bit = 1 << line;
+            if self.isr & bit != 0 && !self.special_mask {
+                return None
+            }
+            if self.irr & !self.imr & bit != 0 {
+                return Some(line)
+            }
+        }
+        None
+    }
+
+    /**
+     * Moves the request with the highest priority to in service.
+     * @return: the line, None if there is no request.
+     */
+    fn acknowledge(&mut self) -> Option<u8> {
+        self.sample();
+        let line = self.pending()?;
+        let bit = 1 << line;
+        self.irr &= !bit;
+        if !self.auto_eoi {
+            self.isr |= bit;
+        } else if self.rotate_on_auto_eoi {
+            self.lowest = line;
+        }
+        Some(line)
+    }
+
+    /**
+     * Ends the interrupt in service with the highest priority, the non-specific EOI.
+     */
+    fn end_highest(&mut self) -> Option<u8> {
+        let line = self.priorities().find(|line| self.isr & (1 << line) != 0)?;
+        self.isr &= !(1 << line);
+        Some(line)
+    }
+
+    fn write_command(&mut self, value: u8) {
+        if value & ICW1 != 0 {
+            // the lines that are up now aren't requests
+            self.irq.take_edges();
+            self.imr = 0;
+            self.isr = 0;
+            self.irr = 0;
+            self.lowest = 7;
+            self.single = value & ICW1_SINGLE != 0;
+            self.needs_icw4 = value & ICW1_NEEDS_ICW4 != 0;
+            self.level_triggered = value & ICW1_LEVEL != 0;
+            self.auto_eoi = false;
+            self.rotate_on_auto_eoi = false;
+            self.special_mask = false;
+            self.read_isr = false;
+            self.poll = false;
+            self.next_icw = Some(2);
+        } else if value & OCW3 != 0 {
+            if value & OCW3_SET_SPECIAL_MASK != 0 {
+                self.special_mask = value & OCW3_SPECIAL_MASK != 0;
+            }
+            if value & OCW3_READ != 0 {
+                self.read_isr = value & OCW3_READ_ISR != 0;
+            }
+            self.poll = value & OCW3_POLL != 0;
+        } else {
+            // OCW2: bits 5-7 say what to do, bits 0-2 are the line
+            let line = value & 7;
+            match value >> 5 {
+                // non-specific EOI, and with rotation
+                0b001 => { self.end_highest(); },
+                0b101 => if let Some(line) = self.end_highest() {
+                    self.lowest = line;
+                },
+                // specific EOI, and with rotation
+                0b011 => self.isr &= !(1 << line),
+                0b111 => {
+                    self.isr &= !(1 << line);
+                    self.lowest = line;
+                },
+                0b100 => self.rotate_on_auto_eoi = true,
+                0b000 => self.rotate_on_auto_eoi = false,
+                0b110 => self.lowest = line,
+                _ => (),
+            }
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        self.next_icw = match self.next_icw {
+            Some(2) => {
+                self.vector_base = value & 0xF8;
+                if !self.single { Some(3) } else if self.needs_icw4 { Some(4) } else { None }
+            },
+            // ICW3 says how the controllers are cascaded, there is only one
+            Some(3) => if self.needs_icw4 { Some(4) } else { None },
+            Some(_) => {
+                self.auto_eoi = value & ICW4_AUTO_EOI != 0;
+                None
+            },
+            // OCW1 is the mask
+            None => {
+                self.imr = value;
+                None
+            },
+        };
+    }
+
+    fn read_command(&mut self) -> u8 {
+        self.sample();
+        if self.poll {
+            // the poll command acknowledges the request, bit 7 says there is one
+            self.poll = false;
+            return self.acknowledge().map_or(0, |line| 0x80 | line)
+        }
+        if self.read_isr { self.isr } else { self.irr }
+    }
+}
+
+/**
+ * An 8259A programmable interrupt controller at ports 20h and 21h. It takes
+ * the requests of the IRQ lines of the hardware, by their edge or their
+ * level, and gives the cpu the vector of the one with the highest priority
+ * that isn't masked or held back by one in service, until it gets an EOI.
+ * The cpu has a clone of it, they share the registers.
+ */
+#[derive(Clone)]
+pub struct Pic {
+    state: Arc<Mutex<PicState>>,
+}
+
+impl Pic {
+    /**
+     * Creates a controller the way the BIOS leaves it: edge triggered, IRQ0
+     * at vector 08h, nothing masked and IRQ0 with the highest priority. The
+     * lines that are already up aren't requests.
+     * @param irq: the lines the hardware raises.
+     */
+    pub fn new(irq: IrqLines) -> Self {
+        irq.take_edges();
+        Self {
+            state: Arc::new(Mutex::new(PicState {
+                irq: irq,
+                irr: 0,
+                isr: 0,
+                imr: 0,
+                vector_base: 0x08,
+                next_icw: None,
+                single: true,
+                needs_icw4: true,
+                level_triggered: false,
+                auto_eoi: false,
+                rotate_on_auto_eoi: false,
+                special_mask: false,
+                read_isr: false,
+                poll: false,
+                lowest: 7,
+            })),
+        }
+    }
+}
+
+impl InterruptController for Pic {
+    fn acknowledge(&mut self) -> Option<u8> {
+        let mut state = self.state.lock().unwrap();
+        let line = state.acknowledge()?;
+        Some(state.vector_base + line)
+    }
+}
+
+impl Hardware for Pic {
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    fn write_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let value = ports[port as usize];
+        let mut state = self.state.lock().unwrap();
+        match port {
+            COMMAND_PORT => state.write_command(value),
+            _ => state.write_data(value),
+        }
+    }
+
+    fn read_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let mut state = self.state.lock().unwrap();
+        ports[port as usize] = match port {
+            COMMAND_PORT => state.read_command(),
+            _ => state.imr,
+        };
+    }
+
+    fn is_port_related(&self, port: &u16) -> bool {
+        *port == COMMAND_PORT || *port == DATA_PORT
+    }
+
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
+        let mut state = self.state.lock().unwrap();
+        state.sample();
+        egui::Grid::new("pic").show(ui, |ui| {
+            ui.label("IRQ");
+            ui.monospace("76543210");
+            ui.end_row();
+            for (name, value) in [("requests", state.irr), ("in service", state.isr), ("masked", state.imr)] {
+                ui.label(name);
+                ui.monospace(format!("{:08b}", value));
+                ui.end_row();
+            }
+        });
+        ui.label(format!("vectors {:02X}h-{:02X}h, IRQ{} first", state.vector_base, state.vector_base + 7, (state.lowest + 1) & 7));
+        ui.ctx().request_repaint();
+    }
+
+    fn name(&self) -> &str {
+        "Interrupt controller"
+    }
+}
@@ -0,0 +1,416 @@
+use super::*;
+use super::speaker::Speaker;
+
+// the counters at 40h-42h and the control word at 43h
+const PORTS: Range<usize> = 0x40..0x44;
+const COUNTER_PORT: u16 = 0x40;
+const CONTROL_PORT: u16 = 0x43;
+// bit 0 of port 61h is the gate of counter 2, bit 1 lets its output move
+// the speaker and bit 5 reads its output
+const SYSTEM_PORT: u16 = 0x61;
+const SYSTEM_GATE: u8 = 0x01;
+const SYSTEM_SPEAKER: u8 = 0x02;
+const SYSTEM_OUTPUT: u8 = 0x20;
+// the clock of the timer is 1.19 MHz, a quarter of the 4.77 MHz of the cpu
+const CYCLES_PER_CLOCK: u64 = 4;
+
+// the fields of the control word
+const SELECT_READ_BACK: u8 = 3;
+const ACCESS_LATCH: u8 = 0;
+const ACCESS_LOW: u8 = 1;
+const ACCESS_HIGH: u8 = 2;
+// the read-back command latches the count when bit 5 is clear, the status when bit 4 is
+const READ_BACK_COUNT: u8 = 0x20;
+const READ_BACK_STATUS: u8 = 0x10;
+// the status of a counter: its output and if the count written wasn't loaded yet
+const STATUS_OUTPUT: u8 = 0x80;
+const STATUS_NULL_COUNT: u8 = 0x40;
+
+const NAMES: [&str; 6] = [
+    "interrupt on terminal count",
+    "hardware retriggerable one-shot",
+    "rate generator",
+    "square wave",
+    "software triggered strobe",
+    "hardware triggered strobe",
+];
+
+/**
+ * Returns the number of a BCD count.
+ */
+fn from_bcd(value: u16) -> u32 {
+    (0..4).rev().fold(0, |number, digit| number * 10 + ((value >> (digit * 4)) & 0x0F).min(9) as u32)
+}
+
+/**
+ * Returns the BCD count of a number below 10000.
+ */
+fn to_bcd(number: u32) -> u16 {
+    (0..4).fold(0, |value, digit| value | (((number / 10u32.pow(digit)) % 10) as u16) << (digit * 4))
+}
+
+/**
+ * A counter of the timer. The count is kept as a number, 0 is the largest
+ * count: 65536, or 10000 when counting in BCD.
+ */
+struct Counter {
+    mode: u8,
+    access: u8,
+    bcd: bool,
+    // the count written by the cpu and the counting element
+    initial: u16,
+    count: u32,
+    output: bool,
+    gate: bool,
+    // the count is loaded on the next clock
+    load: bool,
+    counting: bool,
+    // reaching 0 changes the output only once in the one-shot modes
+    armed: bool,
+    null_count: bool,
+    // the first byte written or read of an access of both bytes
+    write_low: Option<u8>,
+    read_high: bool,
+    latch: Option<u16>,
+    status: Option<u8>,
+}
+
+impl Counter {
+    fn new() -> Self {
+        Self {
+            mode: 0,
+            access: ACCESS_LOW | ACCESS_HIGH,
+            bcd: false,
+            initial: 0,
+            count: 0,
+            output: false,
+            gate: true,
+            load: false,
+            counting: false,
+            armed: false,
+            null_count: true,
+            write_low: None,
+            read_high: false,
+            latch: None,
+            status: None,
+        }
+    }
+
+    fn modulus(&self) -> u32 {
+        if self.bcd { 10000 } else { 0x10000 }
+    }
+
+    /**
+     * Returns the count that the counting element is loaded with. In mode 3 it
+     * counts by 2, an odd count keeps the output high one clock longer.
+     */
+    fn reload_count(&self) -> u32 {
+        let count = match self.initial {
+            0 => self.modulus(),
+            initial if self.bcd => from_bcd(initial),
+            initial => initial as u32,
+        };
+        match (self.mode, count & 1 == 1) {
+            (3, true) if self.output => count + 1,
+            (3, true) => (count - 1).max(2),
+            _ => count,
+        }
+    }
+
+    fn control(&mut self, access: u8, mode: u8, bcd: bool) {
+        self.access = access;
+        // modes 6 and 7 are modes 2 and 3
+        self.mode = if mode > 5 { mode - 4 } else { mode };
+        self.bcd = bcd;
+        self.output = self.mode != 0;
+        self.load = false;
+        self.counting = false;
+        self.null_count = true;
+        self.write_low = None;
+        self.read_high = false;
+        self.latch = None;
+    }
+
+    fn latch_count(&mut self) {
+        if self.latch.is_none() {
+            self.latch = Some(self.read_count());
+        }
+    }
+
+    fn latch_status(&mut self) {
+        if self.status.is_none() {
+            let output = if self.output { STATUS_OUTPUT } else { 0 };
+            let null_count = if self.null_count { STATUS_NULL_COUNT } else { 0 };
+            self.status = Some(output | null_count | self.access << 4 | self.mode << 1 | self.bcd as u8);
+        }
+    }
+
+    /**
+     * Returns the count as the cpu reads it, in BCD if it counts in BCD.
+     */
+    fn read_count(&self) -> u16 {
+        let count = self.count % self.modulus();
+        if self.bcd { to_bcd(count) } else { count as u16 }
+    }
+
+    fn write(&mut self, value: u8) {
+        let count = match (self.access, self.write_low) {
+            (ACCESS_LOW, _) => value as u16,
+            (ACCESS_HIGH, _) => (value as u16) << 8,
+            (_, None) => {
+                self.write_low = Some(value);
+                // writing the first byte stops mode 0
+                if self.mode == 0 {
+                    self.counting = false;
+                    self.output = false;
+                }
+                return
+            },
+            (_, Some(low)) => (value as u16) << 8 | low as u16,
+        };
+        self.write_low = None;
+        self.initial = count;
+        self.null_count = true;
+        match self.mode {
+            // a new count starts the count again
+            0 | 4 => {
+                self.output = self.mode != 0;
+                self.load = true;
+            },
+            // the periodic modes take a new count at the end of the period
+            2 | 3 => if !self.counting {
+                self.load = true;
+            },
+            // the one-shot modes wait for the gate
+            _ => (),
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if let Some(status) = self.status.take() {
+            return status
+        }
+        let count = self.latch.unwrap_or_else(|| self.read_count());
+        let (value, done) = match self.access {
+            ACCESS_LOW => (count as u8, true),
+            ACCESS_HIGH => ((count >> 8) as u8, true),
+            _ if self.read_high => ((count >> 8) as u8, true),
+            _ => (count as u8, false),
+        };
+        self.read_high = !done;
+        if done {
+            self.latch = None;
+        }
+        value
+    }
+
+    /**
+     * Sets the gate. Going up triggers the one-shot modes and starts the
+     * periodic modes again, going down stops the periodic modes with their output high.
+     */
+    fn set_gate(&mut self, gate: bool) {
+        if gate && !self.gate {
+            if self.mode != 0 && self.mode != 4 {
+                self.load = true;
+            }
+        } else if !gate && (self.mode == 2 || self.mode == 3) {
+            self.output = true;
+        }
+        self.gate = gate;
+    }
+
+    /**
+     * Runs the counter for one clock.
+     */
+    fn clock(&mut self) {
+        if self.load {
+            self.load = false;
+            self.count = self.reload_count();
+            self.counting = true;
+            self.armed = true;
+            self.null_count = false;
+            match self.mode {
+                1 => self.output = false,
+                2 | 3 => self.output = true,
+                _ => (),
+            }
+            return
+        }
+        if !self.counting || (!self.gate && self.mode != 1 && self.mode != 5) {
+            return
+        }
+        match self.mode {
+            2 => {
+                if self.count <= 1 {
+                    self.count = self.reload_count();
+                    self.output = true;
+                    self.null_count = false;
+                } else {
+                    self.count -= 1;
+                    self.output = self.count != 1;
+                }
+            },
+            3 => {
+                self.count = self.count.saturating_sub(2);
+                if self.count == 0 {
+                    self.output = !self.output;
+                    self.count = self.reload_count();
+                    self.null_count = false;
+                }
+            },
+            _ => {
+                // 0 goes round to the largest count
+                self.count = (self.count + self.modulus() - 1) % self.modulus();
+                // the strobe modes put the output low for a clock
+                if (self.mode == 4 || self.mode == 5) && !self.output {
+                    self.output = true;
+                }
+                if self.count == 0 && self.armed {
+                    self.armed = false;
+                    self.output = self.mode < 4;
+                }
+            },
+        }
+    }
+}
+
+/**
+ * An 8253/8254 programmable interval timer with three counters, clocked by
+ * the cycles the cpu runs. The output of counter 0 is IRQ0, counter 1
+ * refreshes the memory and counter 2 is for the speaker, gated by port 61h.
+ * Port 43h selects a counter, how its count is accessed, its mode and if it
+ * counts in BCD, or latches a count, or reads back the status of counters.
+ */
+pub struct Pit {
+    counters: [Counter; 3],
+    irq: IrqLines,
+    // the cycles that didn't make a whole clock
+    cycles: u64,
+    pub speaker: Option<Speaker>,
+}
+
+impl Pit {
+    /**
+     * Creates a timer the way the BIOS leaves it: counter 0 in mode 3 with a
+     * count of 65536, 18.2 interrupts a second, and counter 1 in mode 2 with 18.
+     * @param irq: the lines the output of counter 0 raises IRQ0 on.
+     */
+    pub fn new(irq: IrqLines) -> Self {
+        let mut pit = Self {
+            counters: [Counter::new(), Counter::new(), Counter::new()],
+            irq: irq,
+            cycles: 0,
+            speaker: None,
+        };
+        for (counter, mode, count) in [(0, 3, 0u16), (1, 2, 18)] {
+            pit.control(counter << 6 | 0x30 | mode << 1);
+            pit.counters[counter as usize].write(count as u8);
+            pit.counters[counter as usize].write((count >> 8) as u8);
+        }
+        pit.irq.set(0, pit.counters[0].output);
+        pit
+    }
+
+    fn control(&mut self, value: u8) {
+        let select = value >> 6;
+        let access = (value >> 4) & 3;
+        if select == SELECT_READ_BACK {
+            for i in 0..3 {
+                if value & (2 << i) == 0 {
+                    continue
+                }
+                if value & READ_BACK_COUNT == 0 {
+                    self.counters[i].latch_count();
+                }
+                if value & READ_BACK_STATUS == 0 {
+                    self.counters[i].latch_status();
+                }
+            }
+        } else if access == ACCESS_LATCH {
+            self.counters[select as usize].latch_count();
+        } else {
+            self.counters[select as usize].control(access, (value >> 1) & 7, value & 1 != 0);
+        }
+    }
+
+    /**
+     * Runs the counters for one clock, IRQ0 follows the output of counter 0.
+     */
+    fn clock(&mut self) {
+        for counter in self.counters.iter_mut() {
+            counter.clock();
+        }
+        self.irq.set(0, self.counters[0].output);
+    }
+}
+
+impl Hardware for Pit {
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    fn write_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let value = ports[port as usize];
+        match port {
+            CONTROL_PORT => self.control(value),
+            SYSTEM_PORT => self.counters[2].set_gate(value & SYSTEM_GATE != 0),
+            _ => self.counters[(port - COUNTER_PORT) as usize].write(value),
+        }
+        self.irq.set(0, self.counters[0].output);
+    }
+
+    fn read_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        match port {
+            CONTROL_PORT => (),
+            SYSTEM_PORT => {
+                let output = if self.counters[2].output { SYSTEM_OUTPUT } else { 0 };
+                ports[port as usize] = ports[port as usize] & !SYSTEM_OUTPUT | output;
+            },
+            _ => ports[port as usize] = self.counters[(port - COUNTER_PORT) as usize].read(),
+        }
+    }
+
+    fn tick(&mut self, cycles: u64, ports: &mut Vec<u8>) -> () {
+        self.cycles += cycles;
+        let speaker_on = ports[SYSTEM_PORT as usize] & SYSTEM_SPEAKER != 0;
+        let mut levels = Vec::new();
+        while self.cycles >= CYCLES_PER_CLOCK {
+            self.cycles -= CYCLES_PER_CLOCK;
+            self.clock();
+            levels.push(speaker_on && self.counters[2].output);
+        }
+        if let Some(speaker) = &self.speaker {
+            speaker.record(&levels);
+        }
+    }
+
+    fn is_port_related(&self, port: &u16) -> bool {
+        PORTS.contains(&(*port as usize)) || *port == SYSTEM_PORT
+    }
+
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
+        egui::Grid::new("pit").striped(true).show(ui, |ui| {
+            ui.label("counter");
+            ui.label("mode");
+            ui.label("count");
+            ui.label("output");
+            ui.end_row();
+            for (i, counter) in self.counters.iter().enumerate() {
+                ui.label(format!("{}", i));
+                ui.label(format!("{} {}{}", counter.mode, NAMES[counter.mode as usize], if counter.bcd { ", BCD" } else { "" }));
+                ui.monospace(format!("{:04X} / {:04X}", counter.read_count(), counter.initial));
+                ui.label(if counter.output { "high" } else { "low" });
+                ui.end_row();
+            }
+        });
+        ui.ctx().request_repaint();
+    }
+
+    fn name(&self) -> &str {
+        "Timer"
+    }
+}
@@ -0,0 +1,205 @@
+use super::*;
+use std::fs::File;
+use std::io::Write;
+
+// the base port and the IRQ of the parallel port
+pub const LPT1: u16 = 0x378;
+const IRQ_LINE: u8 = 7;
+
+// the registers, offsets from the base port
+const DATA: u16 = 0;
+const STATUS: u16 = 1;
+const CONTROL: u16 = 2;
+
+// the status lines of the printer, ERROR, ACK and BUSY are inverted on the port
+const STATUS_NO_ERROR: u8 = 0x08;
+const STATUS_SELECTED: u8 = 0x10;
+const STATUS_NO_ACK: u8 = 0x40;
+const STATUS_NOT_BUSY: u8 = 0x80;
+// the control lines: STROBE takes the data, INIT resets the printer while it's low,
+// and the acknowledge raises IRQ7 when it's enabled
+const CONTROL_STROBE: u8 = 0x01;
+const CONTROL_INIT: u8 = 0x04;
+const CONTROL_SELECT: u8 = 0x08;
+const CONTROL_IRQ: u8 = 0x10;
+// the unused bits of the control port read high
+const CONTROL_UNUSED: u8 = 0xE0;
+// the printer is busy with a character for 100 us, then acknowledges it for 5 us
+const BUSY_CYCLES: u64 = 477;
+const ACK_CYCLES: u64 = 24;
+// the printed text the window shows
+const SHOWN_TEXT: usize = 4096;
+
+/**
+ * A printer on the parallel port LPT1. The cpu puts a byte on the data port
+ * and pulses STROBE of the control port; the printer takes it, is busy for a
+ * while and then acknowledges it, raising IRQ7 if the control port enables it.
+ * A strobe while the printer is busy is lost, like on a real printer. What
+ * it prints is captured to a file on the host; once the file can't be
+ * written the printer reports an error and prints no more.
+ */
+pub struct Printer {
+    irq: IrqLines,
+    data: u8,
+    control: u8,
+    // the cycles left of the character being printed, and of its acknowledge
+    busy_cycles: u64,
+    ack_cycles: u64,
+    file: File,
+    path: String,
+    printed: u64,
+    text: String,
+    // why the capture file couldn't be written
+    error: Option<String>,
+}
+
+impl Printer {
+    /**
+     * Creates a printer that is online, with its capture file.
+     * @param irq: the lines it raises IRQ7 on.
+     * @param path: the file the printed bytes are written to.
+     * @return: the printer, or why the file couldn't be created.
+     */
+    pub fn new(irq: IrqLines, path: &str) -> Result<Self, String> {
+        Ok(Self {
+            irq: irq,
+            data: 0,
+            control: CONTROL_INIT | CONTROL_SELECT,
+            busy_cycles: 0,
+            ack_cycles: 0,
+            file: File::create(path).map_err(|err| format!("couldn't create {}: {}", path, err))?,
+            path: path.to_string(),
+            printed: 0,
+            text: String::new(),
+            error: None,
+        })
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = STATUS_SELECTED;
+        if self.error.is_none() {
+            status |= STATUS_NO_ERROR;
+        }
+        if self.busy_cycles == 0 && self.ack_cycles == 0 {
+            status |= STATUS_NOT_BUSY;
+        }
+        if self.ack_cycles == 0 {
+            status |= STATUS_NO_ACK;
+        }
+        status
+    }
+
+    /**
+     * Prints a byte to the capture file and the text of the window, or
+     * keeps the error if the file can't be written.
+     */
+    fn print(&mut self, byte: u8) {
+        if self.error.is_some() {
+            return
+        }
+        if let Err(err) = self.file.write_all(&[byte]).and_then(|_| self.file.flush()) {
+            self.error = Some(format!("couldn't write {}: {}", self.path, err));
+            return
+        }
+        self.printed += 1;
+        match byte {
+            b'\r' => (),
+            // a form feed starts a new page
+            0x0C => self.text.push_str("\n----\n"),
+            b'\n' | b'\t' => self.text.push(byte as char),
+            byte if byte.is_ascii_graphic() || byte == b' ' => self.text.push(byte as char),
+            _ => self.text.push('.'),
+        }
+        if self.text.len() > SHOWN_TEXT {
+            let cut = self.text.len() - SHOWN_TEXT;
+            self.text.drain(..cut);
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        let old = self.control;
+        self.control = value & 0x1F;
+        if value & CONTROL_INIT == 0 {
+            // the printer is reset while INIT is low
+            self.busy_cycles = 0;
+            self.ack_cycles = 0;
+        } else if value & CONTROL_STROBE != 0 && old & CONTROL_STROBE == 0 && self.status() & STATUS_NOT_BUSY != 0 {
+            self.print(self.data);
+            self.busy_cycles = BUSY_CYCLES;
+        }
+        self.update_irq();
+    }
+
+    fn update_irq(&self) {
+        self.irq.set(IRQ_LINE, self.ack_cycles > 0 && self.control & CONTROL_IRQ != 0);
+    }
+}
+
+impl Hardware for Printer {
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    fn write_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let value = ports[port as usize];
+        match port - LPT1 {
+            DATA => self.data = value,
+            CONTROL => self.write_control(value),
+            _ => (),
+        }
+    }
+
+    fn read_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        ports[port as usize] = match port - LPT1 {
+            DATA => self.data,
+            STATUS => self.status(),
+            _ => self.control | CONTROL_UNUSED,
+        };
+    }
+
+    /**
+     * Prints the character and acknowledges it as the time passes.
+     */
+    fn tick(&mut self, cycles: u64, _ports: &mut Vec<u8>) -> () {
+        let mut left = cycles;
+        if self.busy_cycles > 0 {
+            if self.busy_cycles > left {
+                self.busy_cycles -= left;
+                return
+            }
+            left -= self.busy_cycles;
+            self.busy_cycles = 0;
+            self.ack_cycles = ACK_CYCLES;
+            // the line goes up even if the pulse ends in this tick
+            self.update_irq();
+        }
+        self.ack_cycles = self.ack_cycles.saturating_sub(left);
+        self.update_irq();
+    }
+
+    fn is_port_related(&self, port: &u16) -> bool {
+        *port >= LPT1 && *port <= LPT1 + CONTROL
+    }
+
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
+        let state = if self.busy_cycles > 0 { "busy" } else if self.ack_cycles > 0 { "acknowledging" } else { "ready" };
+        ui.label(format!("{:03X}h IRQ{}, printing to {}", LPT1, IRQ_LINE, self.path));
+        ui.monospace(format!("DATA {:02X}  STATUS {:02X}  CONTROL {:02X}  {}", self.data, self.status(), self.control, state));
+        ui.label(format!("{} bytes printed", self.printed));
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        egui::ScrollArea::vertical().max_height(300.0).stick_to_bottom().show(ui, |ui| {
+            ui.monospace(&self.text);
+        });
+        ui.ctx().request_repaint();
+    }
+
+    fn name(&self) -> &str {
+        "Printer"
+    }
+}
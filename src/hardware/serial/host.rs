@@ -0,0 +1,310 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, mpsc::{channel, Receiver, Sender}};
+use std::thread;
+
+/**
+ * The host side of a serial port, where the bytes the UART transmits go and
+ * the bytes it receives come from.
+ */
+pub trait SerialHost: Send {
+    /**
+     * Sends a byte the UART transmitted.
+     */
+    fn write(&mut self, byte: u8);
+
+    /**
+     * Returns the next byte for the UART to receive, None if none came yet.
+     */
+    fn read(&mut self) -> Option<u8>;
+
+    /**
+     * Returns if the other side is there, which raises DSR and DCD.
+     * If not implamented, it always is.
+     */
+    fn connected(&self) -> bool {
+        true
+    }
+
+    /**
+     * Returns if the other side is ready to receive, which raises CTS.
+     * If not implamented, it is while it's connected.
+     */
+    fn clear_to_send(&self) -> bool {
+        self.connected()
+    }
+
+    /**
+     * Tells the other side about DTR and RTS of the UART.
+     * If not implamented, the other side doesn't care.
+     */
+    fn set_outputs(&mut self, _dtr: bool, _rts: bool) {
+    }
+
+    /**
+     * Returns what the port is connected to, for the window of the UART.
+     */
+    fn describe(&self) -> String;
+}
+
+/**
+ * Opens the host side of a serial port.
+ * @param spec: `stdio`, `file:<output>[,<input>]`, `unix:<path>` or `tcp:<port>`.
+ *      The sockets listen for a client, on localhost for TCP.
+ * @return: the host, or why it couldn't be opened.
+ */
+pub fn open(spec: &str) -> Result<Box<dyn SerialHost>, String> {
+    let (kind, argument) = match spec.find(':') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => (spec, ""),
+    };
+    match kind {
+        "stdio" => Ok(Box::new(StdioHost::new())),
+        "file" => {
+            let (output, input) = match argument.find(',') {
+                Some(i) => (&argument[..i], Some(&argument[i + 1..])),
+                None => (argument, None),
+            };
+            Ok(Box::new(FileHost::new(output, input)?))
+        },
+        "tcp" => {
+            let port = argument.parse::<u16>().map_err(|_| format!("bad tcp port '{}'", argument))?;
+            let listener = TcpListener::bind(("127.0.0.1", port))
+                .map_err(|err| format!("couldn't listen on port {}: {}", port, err))?;
+            Ok(Box::new(StreamHost::serve(format!("localhost:{}", port), move || {
+                let (stream, _) = listener.accept()?;
+                let reader: Box<dyn Read + Send> = Box::new(stream.try_clone()?);
+                Ok((reader, Box::new(stream)))
+            })))
+        },
+        #[cfg(unix)]
+        "unix" => {
+            use std::os::unix::net::UnixListener;
+            // a socket left by an earlier run is replaced
+            let _ = std::fs::remove_file(argument);
+            let listener = UnixListener::bind(argument)
+                .map_err(|err| format!("couldn't listen on {}: {}", argument, err))?;
+            Ok(Box::new(StreamHost::serve(argument.to_string(), move || {
+                let (stream, _) = listener.accept()?;
+                let reader: Box<dyn Read + Send> = Box::new(stream.try_clone()?);
+                Ok((reader, Box::new(stream)))
+            })))
+        },
+        _ => Err(format!("unknown serial host '{}'", spec)),
+    }
+}
+
+/**
+ * Makes the two ends of a null-modem cable, which connects two serial ports:
+ * the bytes one end writes are read by the other, and the DTR and RTS of
+ * each end are the DSR, DCD and CTS of the other.
+ */
+pub fn null_modem() -> (NullModem, NullModem) {
+    let first = Arc::new(Mutex::new(CableEnd::new()));
+    let second = Arc::new(Mutex::new(CableEnd::new()));
+    (NullModem { own: first.clone(), other: second.clone() }, NullModem { own: second, other: first })
+}
+
+/**
+ * Sends the bytes of a reader to a channel from a new thread, until it ends.
+ */
+fn read_to_channel(mut reader: Box<dyn Read + Send>, sender: Sender<u8>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut byte = [0u8];
+        while let Ok(1) = reader.read(&mut byte) {
+            if sender.send(byte[0]).is_err() {
+                break
+            }
+        }
+    })
+}
+
+/**
+ * The stdin and stdout of the emulator.
+ */
+struct StdioHost {
+    received: Receiver<u8>,
+}
+
+impl StdioHost {
+    fn new() -> Self {
+        let (sender, receiver) = channel();
+        read_to_channel(Box::new(std::io::stdin()), sender);
+        Self {
+            received: receiver,
+        }
+    }
+}
+
+impl SerialHost for StdioHost {
+    fn write(&mut self, byte: u8) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(&[byte]);
+        let _ = stdout.flush();
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.received.try_recv().ok()
+    }
+
+    fn describe(&self) -> String {
+        String::from("stdin/stdout")
+    }
+}
+
+/**
+ * A file the transmitted bytes are written to, and a file the received bytes
+ * are read from, if there is one.
+ */
+struct FileHost {
+    output: File,
+    path: String,
+    input: VecDeque<u8>,
+}
+
+impl FileHost {
+    fn new(output: &str, input: Option<&str>) -> Result<Self, String> {
+        let input = match input {
+            Some(path) => std::fs::read(path).map_err(|err| format!("couldn't read {}: {}", path, err))?,
+            None => Vec::new(),
+        };
+        Ok(Self {
+            output: File::create(output).map_err(|err| format!("couldn't create {}: {}", output, err))?,
+            path: output.to_string(),
+            input: input.into(),
+        })
+    }
+}
+
+impl SerialHost for FileHost {
+    fn write(&mut self, byte: u8) {
+        let _ = self.output.write_all(&[byte]);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn describe(&self) -> String {
+        format!("file {}", self.path)
+    }
+}
+
+/**
+ * A socket that listens for a client, one at a time. While there is none the
+ * line is down and the transmitted bytes are lost.
+ */
+struct StreamHost {
+    name: String,
+    received: Receiver<u8>,
+    client: Arc<Mutex<Option<Box<dyn Write + Send>>>>,
+}
+
+impl StreamHost {
+    /**
+     * Accepts the clients in a new thread.
+     * @param name: where it listens.
+     * @param accept: waits for a client and returns the two ends of its stream.
+     */
+    fn serve<F>(name: String, mut accept: F) -> Self
+        where F: FnMut() -> std::io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> + Send + 'static {
+        let (sender, receiver) = channel();
+        let client = Arc::new(Mutex::new(None));
+        let connected = client.clone();
+        thread::spawn(move || {
+            while let Ok((reader, writer)) = accept() {
+                *connected.lock().unwrap() = Some(writer);
+                let _ = read_to_channel(reader, sender.clone()).join();
+                *connected.lock().unwrap() = None;
+            }
+        });
+        Self {
+            name: name,
+            received: receiver,
+            client: client,
+        }
+    }
+}
+
+impl SerialHost for StreamHost {
+    fn write(&mut self, byte: u8) {
+        let mut client = self.client.lock().unwrap();
+        if let Some(writer) = client.as_mut() {
+            if writer.write_all(&[byte]).is_err() {
+                *client = None;
+            }
+        }
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.received.try_recv().ok()
+    }
+
+    fn connected(&self) -> bool {
+        self.client.lock().unwrap().is_some()
+    }
+
+    fn describe(&self) -> String {
+        let state = if self.connected() { "connected" } else { "waiting for a client" };
+        format!("{}, {}", self.name, state)
+    }
+}
+
+/**
+ * The lines of one end of a null-modem cable.
+ */
+struct CableEnd {
+    // the bytes the other end wrote that weren't read yet
+    received: VecDeque<u8>,
+    dtr: bool,
+    rts: bool,
+}
+
+impl CableEnd {
+    fn new() -> Self {
+        Self {
+            received: VecDeque::new(),
+            dtr: false,
+            rts: false,
+        }
+    }
+}
+
+/**
+ * An end of a null-modem cable, see `null_modem`.
+ */
+pub struct NullModem {
+    own: Arc<Mutex<CableEnd>>,
+    other: Arc<Mutex<CableEnd>>,
+}
+
+impl SerialHost for NullModem {
+    fn write(&mut self, byte: u8) {
+        self.other.lock().unwrap().received.push_back(byte);
+    }
+
+    fn read(&mut self) -> Option<u8> {
+        self.own.lock().unwrap().received.pop_front()
+    }
+
+    fn connected(&self) -> bool {
+        self.other.lock().unwrap().dtr
+    }
+
+    fn clear_to_send(&self) -> bool {
+        self.other.lock().unwrap().rts
+    }
+
+    fn set_outputs(&mut self, dtr: bool, rts: bool) {
+        let mut own = self.own.lock().unwrap();
+        own.dtr = dtr;
+        own.rts = rts;
+    }
+
+    fn describe(&self) -> String {
+        let state = if self.connected() { "DTR up" } else { "DTR down" };
+        format!("null-modem cable, the other end has {}", state)
+    }
+}
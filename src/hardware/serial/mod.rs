@@ -0,0 +1,458 @@
+use super::*;
+use std::collections::VecDeque;
+
+pub mod host;
+
+use host::SerialHost;
+
+// the base port and the IRQ of the serial ports
+pub const COM1: (u16, u8) = (0x3F8, 4);
+pub const COM2: (u16, u8) = (0x2F8, 3);
+// the 1.8432 MHz clock of the UART divided by 16 is the baud rate of divisor 1
+const BAUD_CLOCK: u64 = 115200;
+const CPU_CLOCK: u64 = 4772727;
+const FIFO_SIZE: usize = 16;
+// the transmitted text the window shows
+const SHOWN_TEXT: usize = 256;
+
+// the registers, offsets from the base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const INTERRUPT_ID: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const MODEM_STATUS: u16 = 6;
+const SCRATCH: u16 = 7;
+
+// the interrupts: received data, transmitter empty, line status and modem status
+const IER_RECEIVED: u8 = 0x01;
+const IER_TRANSMIT: u8 = 0x02;
+const IER_LINE: u8 = 0x04;
+const IER_MODEM: u8 = 0x08;
+// the interrupt identification, bit 0 is set when there is none
+const IIR_NONE: u8 = 0x01;
+const IIR_MODEM: u8 = 0x00;
+const IIR_TRANSMIT: u8 = 0x02;
+const IIR_RECEIVED: u8 = 0x04;
+const IIR_LINE: u8 = 0x06;
+const IIR_TIMEOUT: u8 = 0x0C;
+const IIR_FIFOS: u8 = 0xC0;
+// the FIFO control: enable, clear the receiver and the transmitter, bits 6-7 the trigger level
+const FCR_ENABLE: u8 = 0x01;
+const FCR_CLEAR_RECEIVER: u8 = 0x02;
+const FCR_CLEAR_TRANSMITTER: u8 = 0x04;
+const TRIGGER_LEVELS: [usize; 4] = [1, 4, 8, 14];
+// bit 7 of the line control makes registers 0 and 1 the divisor latch
+const LCR_DLAB: u8 = 0x80;
+const LCR_STOP_BITS: u8 = 0x04;
+const LCR_PARITY: u8 = 0x08;
+// the modem control outputs, OUT2 lets the interrupt reach the IRQ line of a PC
+const MCR_DTR: u8 = 0x01;
+const MCR_RTS: u8 = 0x02;
+const MCR_OUT1: u8 = 0x04;
+const MCR_OUT2: u8 = 0x08;
+const MCR_LOOPBACK: u8 = 0x10;
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_OVERRUN: u8 = 0x02;
+const LSR_TRANSMIT_EMPTY: u8 = 0x20;
+const LSR_IDLE: u8 = 0x40;
+// the modem status inputs are the high bits, their changes the low bits
+const MSR_CTS: u8 = 0x10;
+const MSR_DSR: u8 = 0x20;
+const MSR_RI: u8 = 0x40;
+const MSR_DCD: u8 = 0x80;
+const MSR_TRAILING_RI: u8 = 0x04;
+
+/**
+ * An 8250/16550 UART, the serial port of a PC. The divisor latch sets the
+ * baud rate and the line control the format of the characters, which take
+ * their time on the line like on a real port. With the FIFOs enabled the
+ * receiver and the transmitter hold 16 bytes and the received data interrupt
+ * waits for the trigger level or a timeout, without them they hold one. The
+ * interrupt reaches its IRQ line when OUT2 is set. The bytes go to and come
+ * from the host side the port is connected to. Unless it's paced a character
+ * takes no time: the bytes are sent at once and received while there's room.
+ */
+pub struct Uart {
+    // if the characters take their time on the line, at the baud rate
+    pub paced: bool,
+    base: u16,
+    irq_line: u8,
+    irq: IrqLines,
+    host: Box<dyn SerialHost>,
+    name: String,
+    divisor: u16,
+    interrupt_enable: u8,
+    line_control: u8,
+    modem_control: u8,
+    modem_status: u8,
+    scratch: u8,
+    overrun: bool,
+    fifos: bool,
+    trigger: usize,
+    receiver: VecDeque<u8>,
+    transmitter: VecDeque<u8>,
+    // the byte being shifted out and the cycles it has left
+    shifting: Option<u8>,
+    transmit_cycles: u64,
+    // the cycles since the last character came, and since the receiver was read
+    receive_cycles: u64,
+    idle_cycles: u64,
+    // the transmitter empty interrupt is pending until IIR reports it or THR is written
+    transmit_interrupt: bool,
+    transmitted: u64,
+    received: u64,
+    text: VecDeque<u8>,
+}
+
+impl Uart {
+    /**
+     * Creates a paced UART with a divisor of 12, 9600 baud.
+     * @param (base, irq_line): the base port and the IRQ, COM1 or COM2.
+     * @param irq: the lines it raises its IRQ on.
+     * @param host: where the port is connected to.
+     */
+    pub fn new((base, irq_line): (u16, u8), irq: IrqLines, host: Box<dyn SerialHost>) -> Self {
+        let mut uart = Self {
+            paced: true,
+            base: base,
+            irq_line: irq_line,
+            irq: irq,
+            host: host,
+            name: String::from(if base == COM1.0 { "COM1" } else { "COM2" }),
+            divisor: 12,
+            interrupt_enable: 0,
+            line_control: 0x03,
+            modem_control: 0,
+            modem_status: 0,
+            scratch: 0,
+            overrun: false,
+            fifos: false,
+            trigger: 1,
+            receiver: VecDeque::new(),
+            transmitter: VecDeque::new(),
+            shifting: None,
+            transmit_cycles: 0,
+            receive_cycles: 0,
+            idle_cycles: 0,
+            transmit_interrupt: false,
+            transmitted: 0,
+            received: 0,
+            text: VecDeque::new(),
+        };
+        uart.modem_status = uart.modem_inputs();
+        uart
+    }
+
+    /**
+     * Returns the cycles of the cpu a character takes on the line: a start
+     * bit, 5-8 data bits, the parity bit and the stop bits.
+     */
+    fn character_cycles(&self) -> u64 {
+        let data_bits = 5 + (self.line_control & 3) as u64;
+        let parity = if self.line_control & LCR_PARITY != 0 { 1 } else { 0 };
+        let stop_bits = if self.line_control & LCR_STOP_BITS != 0 { 2 } else { 1 };
+        let divisor = if self.divisor == 0 { 0x10000 } else { self.divisor as u64 };
+        CPU_CLOCK * (1 + data_bits + parity + stop_bits) * divisor / BAUD_CLOCK
+    }
+
+    fn capacity(&self) -> usize {
+        if self.fifos { FIFO_SIZE } else { 1 }
+    }
+
+    /**
+     * Returns the modem status inputs. In loopback the outputs are connected
+     * to them, otherwise they follow the host.
+     */
+    fn modem_inputs(&self) -> u8 {
+        let control = self.modem_control;
+        if control & MCR_LOOPBACK != 0 {
+            let mut inputs = 0;
+            if control & MCR_RTS != 0 { inputs |= MSR_CTS }
+            if control & MCR_DTR != 0 { inputs |= MSR_DSR }
+            if control & MCR_OUT1 != 0 { inputs |= MSR_RI }
+            if control & MCR_OUT2 != 0 { inputs |= MSR_DCD }
+            inputs
+        } else {
+            let mut inputs = 0;
+            if self.host.connected() { inputs |= MSR_DSR | MSR_DCD }
+            if self.host.clear_to_send() { inputs |= MSR_CTS }
+            inputs
+        }
+    }
+
+    /**
+     * Updates the modem status inputs, setting the bits of their changes.
+     */
+    fn update_modem_status(&mut self) {
+        let inputs = self.modem_inputs();
+        let old = self.modem_status & 0xF0;
+        let changed = (old ^ inputs) >> 4;
+        // RI only reports its trailing edge
+        let changed = changed & !MSR_TRAILING_RI | if old & MSR_RI != 0 && inputs & MSR_RI == 0 { MSR_TRAILING_RI } else { 0 };
+        self.modem_status = inputs | (self.modem_status & 0x0F) | changed;
+    }
+
+    /**
+     * Returns the pending interrupt with the highest priority.
+     */
+    fn interrupt_id(&self) -> u8 {
+        let enabled = self.interrupt_enable;
+        let receiver_full = self.receiver.len() >= if self.fifos { self.trigger } else { 1 };
+        let timeout = self.fifos && !self.receiver.is_empty() && self.idle_cycles >= 4 * self.character_cycles();
+        if enabled & IER_LINE != 0 && self.overrun {
+            IIR_LINE
+        } else if enabled & IER_RECEIVED != 0 && receiver_full {
+            IIR_RECEIVED
+        } else if enabled & IER_RECEIVED != 0 && timeout {
+            IIR_TIMEOUT
+        } else if enabled & IER_TRANSMIT != 0 && self.transmit_interrupt {
+            IIR_TRANSMIT
+        } else if enabled & IER_MODEM != 0 && self.modem_status & 0x0F != 0 {
+            IIR_MODEM
+        } else {
+            IIR_NONE
+        }
+    }
+
+    fn update_irq(&self) {
+        let pending = self.interrupt_id() != IIR_NONE;
+        self.irq.set(self.irq_line, pending && self.modem_control & MCR_OUT2 != 0);
+    }
+
+    fn line_status(&self) -> u8 {
+        let mut status = 0;
+        if !self.receiver.is_empty() { status |= LSR_DATA_READY }
+        if self.overrun { status |= LSR_OVERRUN }
+        if self.transmitter.is_empty() { status |= LSR_TRANSMIT_EMPTY }
+        if self.transmitter.is_empty() && self.shifting.is_none() { status |= LSR_IDLE }
+        status
+    }
+
+    /**
+     * Adds a byte that came on the line to the receiver, it's lost if the receiver is full.
+     */
+    fn receive(&mut self, byte: u8) {
+        if self.receiver.len() >= self.capacity() {
+            self.overrun = true;
+        } else {
+            self.receiver.push_back(byte);
+            self.received += 1;
+        }
+        self.idle_cycles = 0;
+    }
+
+    /**
+     * Starts shifting out the next byte of the transmitter.
+     */
+    fn start_transmit(&mut self) {
+        if self.shifting.is_some() {
+            return
+        }
+        if let Some(byte) = self.transmitter.pop_front() {
+            self.shifting = Some(byte);
+            self.transmit_cycles = if self.paced { self.character_cycles() } else { 0 };
+            if self.transmitter.is_empty() {
+                self.transmit_interrupt = true;
+            }
+        }
+    }
+
+    /**
+     * Sends the byte that was shifted out, back to the receiver in loopback.
+     */
+    fn transmit(&mut self, byte: u8) {
+        if self.modem_control & MCR_LOOPBACK != 0 {
+            self.receive(byte);
+        } else {
+            self.host.write(byte);
+        }
+        self.transmitted += 1;
+        self.text.push_back(byte);
+        if self.text.len() > SHOWN_TEXT {
+            self.text.pop_front();
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        let dlab = self.line_control & LCR_DLAB != 0;
+        match register {
+            DATA if dlab => self.divisor = self.divisor & 0xFF00 | value as u16,
+            INTERRUPT_ENABLE if dlab => self.divisor = self.divisor & 0x00FF | (value as u16) << 8,
+            DATA => {
+                if self.transmitter.len() < self.capacity() {
+                    self.transmitter.push_back(value);
+                }
+                self.transmit_interrupt = false;
+                self.start_transmit();
+                // unpaced, the bytes are on the line at once
+                while let (false, Some(byte)) = (self.paced, self.shifting) {
+                    self.shifting = None;
+                    self.transmit(byte);
+                    self.start_transmit();
+                }
+            },
+            INTERRUPT_ENABLE => {
+                // enabling the interrupt with the transmitter empty makes it pending
+                if value & IER_TRANSMIT != 0 && self.interrupt_enable & IER_TRANSMIT == 0 && self.transmitter.is_empty() {
+                    self.transmit_interrupt = true;
+                }
+                self.interrupt_enable = value & 0x0F;
+            },
+            INTERRUPT_ID => {
+                self.fifos = value & FCR_ENABLE != 0;
+                if value & FCR_CLEAR_RECEIVER != 0 || !self.fifos {
+                    self.receiver.clear();
+                }
+                if value & FCR_CLEAR_TRANSMITTER != 0 || !self.fifos {
+                    self.transmitter.clear();
+                }
+                self.trigger = TRIGGER_LEVELS[(value >> 6) as usize];
+            },
+            LINE_CONTROL => self.line_control = value,
+            MODEM_CONTROL => {
+                self.modem_control = value & 0x1F;
+                // in loopback the outputs are disconnected from the line
+                let line = if value & MCR_LOOPBACK == 0 { value } else { 0 };
+                self.host.set_outputs(line & MCR_DTR != 0, line & MCR_RTS != 0);
+                self.update_modem_status();
+            },
+            SCRATCH => self.scratch = value,
+            _ => (),
+        }
+    }
+
+    fn read_register(&mut self, register: u16) -> u8 {
+        let dlab = self.line_control & LCR_DLAB != 0;
+        match register {
+            DATA if dlab => self.divisor as u8,
+            INTERRUPT_ENABLE if dlab => (self.divisor >> 8) as u8,
+            DATA => {
+                self.idle_cycles = 0;
+                self.receiver.pop_front().unwrap_or(0)
+            },
+            INTERRUPT_ENABLE => self.interrupt_enable,
+            INTERRUPT_ID => {
+                let id = self.interrupt_id();
+                // reading that the transmitter is empty is its acknowledge
+                if id == IIR_TRANSMIT {
+                    self.transmit_interrupt = false;
+                }
+                id | if self.fifos { IIR_FIFOS } else { 0 }
+            },
+            LINE_CONTROL => self.line_control,
+            MODEM_CONTROL => self.modem_control,
+            LINE_STATUS => {
+                let status = self.line_status();
+                self.overrun = false;
+                status
+            },
+            MODEM_STATUS => {
+                self.update_modem_status();
+                let status = self.modem_status;
+                self.modem_status &= 0xF0;
+                status
+            },
+            _ => self.scratch,
+        }
+    }
+}
+
+impl Hardware for Uart {
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    fn write_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        self.write_register(port - self.base, ports[port as usize]);
+        self.update_irq();
+    }
+
+    fn read_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        ports[port as usize] = self.read_register(port - self.base);
+        self.update_irq();
+    }
+
+    /**
+     * Shifts the bytes out and in as the time of a character passes.
+     */
+    fn tick(&mut self, cycles: u64, _ports: &mut Vec<u8>) -> () {
+        let character = self.character_cycles();
+        let mut left = cycles;
+        while let Some(byte) = self.shifting {
+            if self.transmit_cycles > left {
+                self.transmit_cycles -= left;
+                break
+            }
+            left -= self.transmit_cycles;
+            self.shifting = None;
+            self.transmit(byte);
+            self.start_transmit();
+        }
+        self.idle_cycles += cycles;
+        let loopback = self.modem_control & MCR_LOOPBACK != 0;
+        // unpaced, the bytes of the host wait there while the receiver is full
+        while !self.paced && !loopback && self.receiver.len() < self.capacity() {
+            match self.host.read() {
+                Some(byte) => self.receive(byte),
+                None => break,
+            }
+        }
+        // paced, a byte from the host comes once the last one had its time
+        self.receive_cycles += cycles;
+        while self.paced && self.receive_cycles >= character {
+            let byte = if !loopback { self.host.read() } else { None };
+            match byte {
+                Some(byte) => {
+                    self.receive(byte);
+                    self.receive_cycles -= character;
+                },
+                None => {
+                    self.receive_cycles = character;
+                    break
+                },
+            }
+        }
+        self.update_modem_status();
+        self.update_irq();
+    }
+
+    fn is_port_related(&self, port: &u16) -> bool {
+        *port >= self.base && *port < self.base + 8
+    }
+
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
+        let parity = match (self.line_control >> 3) & 7 {
+            1 => "O",
+            3 => "E",
+            5 => "M",
+            7 => "S",
+            _ => "N",
+        };
+        let divisor = if self.divisor == 0 { 0x10000 } else { self.divisor as u32 };
+        ui.label(format!("{:03X}h IRQ{}, {}", self.base, self.irq_line, self.host.describe()));
+        ui.label(format!("{} baud {}{}{}{}", BAUD_CLOCK as u32 / divisor, 5 + (self.line_control & 3), parity,
+            if self.line_control & LCR_STOP_BITS != 0 { 2 } else { 1 },
+            if self.fifos { format!(", FIFOs trigger at {}", self.trigger) } else { String::new() }));
+        if !self.paced {
+            ui.label("not paced");
+        }
+        ui.monospace(format!("IER {:02X}  LCR {:02X}  MCR {:02X}  LSR {:02X}  MSR {:02X}",
+            self.interrupt_enable, self.line_control, self.modem_control, self.line_status(), self.modem_status));
+        ui.label(format!("{} bytes transmitted, {} received, {} waiting", self.transmitted, self.received, self.receiver.len()));
+        let text = self.text.iter()
+            .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' || *byte == b'\n' { *byte as char } else { '.' })
+            .collect::<String>();
+        ui.monospace(text);
+        ui.ctx().request_repaint();
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -0,0 +1,156 @@
+use super::*;
+use std::collections::VecDeque;
+use eframe::egui::{Sense, Shape, pos2};
+
+pub const SAMPLE_RATE: u32 = 44100;
+// the clock of the timer, the speaker follows the output of its counter 2
+const TIMER_CLOCK: u32 = 1193182;
+// the samples of a speaker that's out and in, 8 bit PCM is unsigned
+const HIGH: u8 = 0xC0;
+const LOW: u8 = 0x40;
+// the last minute is kept
+const MAX_SAMPLES: usize = SAMPLE_RATE as usize * 60;
+// the samples the window draws, about 50 ms
+const WAVEFORM_SAMPLES: usize = 2205;
+const WAV_HEADER_SIZE: u32 = 44;
+
+/**
+ * Returns a WAV file of 8 bit mono samples.
+ */
+pub fn wav(samples: &[u8]) -> Vec<u8> {
+    let size = samples.len() as u32;
+    let mut bytes = Vec::with_capacity((WAV_HEADER_SIZE + size) as usize);
+    bytes.extend(b"RIFF");
+    bytes.extend(&(WAV_HEADER_SIZE - 8 + size).to_le_bytes());
+    bytes.extend(b"WAVEfmt ");
+    // the format chunk: PCM, 1 channel, the rate, the bytes a second, 1 byte a sample of 8 bits
+    bytes.extend(&16u32.to_le_bytes());
+    bytes.extend(&1u16.to_le_bytes());
+    bytes.extend(&1u16.to_le_bytes());
+    bytes.extend(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend(&1u16.to_le_bytes());
+    bytes.extend(&8u16.to_le_bytes());
+    bytes.extend(b"data");
+    bytes.extend(&size.to_le_bytes());
+    bytes.extend(samples);
+    bytes
+}
+
+struct SpeakerState {
+    samples: VecDeque<u8>,
+    // the clocks of the timer in the sample being made and how many were high
+    clocks: u32,
+    high: u32,
+    phase: u32,
+    // the file the window saves to and what happened the last time
+    path: String,
+    message: String,
+}
+
+/**
+ * The PC speaker, driven by counter 2 of the timer when bit 1 of port 61h
+ * is set. The square wave is rendered to 8 bit samples at 44.1 kHz, each
+ * the average of the clocks of the timer it covers, kept in memory to be
+ * saved as a WAV file. The timer has a clone of it, they share the samples.
+ */
+#[derive(Clone)]
+pub struct Speaker {
+    state: Arc<Mutex<SpeakerState>>,
+}
+
+impl Speaker {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SpeakerState {
+                samples: VecDeque::new(),
+                clocks: 0,
+                high: 0,
+                phase: 0,
+                path: String::from("speaker.wav"),
+                message: String::new(),
+            })),
+        }
+    }
+
+    /**
+     * Moves the speaker for clocks of the timer.
+     * @param levels: if the speaker is out on each clock.
+     */
+    pub fn record(&self, levels: &[bool]) {
+        let mut state = self.state.lock().unwrap();
+        for level in levels {
+            state.clocks += 1;
+            state.high += *level as u32;
+            state.phase += SAMPLE_RATE;
+            if state.phase < TIMER_CLOCK {
+                continue
+            }
+            state.phase -= TIMER_CLOCK;
+            let sample = LOW as u32 + (HIGH - LOW) as u32 * state.high / state.clocks;
+            state.samples.push_back(sample as u8);
+            if state.samples.len() > MAX_SAMPLES {
+                state.samples.pop_front();
+            }
+            state.clocks = 0;
+            state.high = 0;
+        }
+    }
+
+    pub fn samples(&self) -> Vec<u8> {
+        self.state.lock().unwrap().samples.iter().copied().collect()
+    }
+
+    /**
+     * Saves the samples as a WAV file.
+     */
+    pub fn save_wav(&self, path: &str) -> std::io::Result<()> {
+        std::fs::write(path, wav(&self.samples()))
+    }
+}
+
+impl Hardware for Speaker {
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    // port 61h belongs to the timer, which moves the speaker
+    fn is_port_related(&self, _port: &u16) -> bool {
+        false
+    }
+
+    fn ui(&self, ui: &mut Ui, _memory: &MemorySegments) {
+        let mut state = self.state.lock().unwrap();
+        let (rect, _) = ui.allocate_exact_size(Vec2::new(400.0, 80.0), Sense::hover());
+        let visuals = ui.style().noninteractive();
+        ui.painter().rect_stroke(rect, 0.0, visuals.bg_stroke);
+        let shown = state.samples.len().min(WAVEFORM_SAMPLES);
+        let points = state.samples.iter().skip(state.samples.len() - shown).enumerate()
+            .map(|(i, sample)| pos2(
+                rect.left() + rect.width() * i as f32 / WAVEFORM_SAMPLES as f32,
+                rect.bottom() - rect.height() * *sample as f32 / 255.0))
+            .collect::<Vec<_>>();
+        ui.painter().add(Shape::line(points, visuals.fg_stroke));
+        ui.label(format!("{:.1} s recorded", state.samples.len() as f32 / SAMPLE_RATE as f32));
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut state.path);
+            if ui.button("save WAV").clicked() {
+                let samples = state.samples.iter().copied().collect::<Vec<u8>>();
+                state.message = match std::fs::write(&state.path, wav(&samples)) {
+                    Ok(()) => format!("saved {}", state.path),
+                    Err(err) => format!("couldn't save {}: {}", state.path, err),
+                };
+            }
+        });
+        ui.label(&state.message);
+        ui.ctx().request_repaint();
+    }
+
+    fn name(&self) -> &str {
+        "Speaker"
+    }
+}
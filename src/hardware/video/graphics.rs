@@ -0,0 +1,87 @@
+use super::*;
+use super::text::{color, COLORS};
+
+pub const WIDTH: usize = 320;
+pub const HIGH_WIDTH: usize = 640;
+pub const HEIGHT: usize = 200;
+// the even lines are in the first 8K of the memory, the odd lines in the second
+const BANK_SIZE: u16 = 0x2000;
+const LINE_BYTES: u16 = 80;
+
+// the bits of the color select register
+const BACKGROUND: u8 = 0x0F;
+const INTENSE: u8 = 0x10;
+const PALETTE_1: u8 = 0x20;
+
+// the colors 1-3 of the palettes of the 320x200 mode
+const PALETTES: [[u8; 3]; 3] = [
+    // green, red, brown
+    [2, 4, 6],
+    // cyan, magenta, white
+    [3, 5, 7],
+    // cyan, red, white, with the black and white bit of the mode control register
+    [3, 4, 7],
+];
+
+impl Video {
+    /**
+     * Returns the 4 colors of the 320x200 mode. Color 0 is the background of
+     * the color select register, 1-3 come from the palette it selects.
+     */
+    fn palette(&self) -> [u32; 4] {
+        let palette = if self.mode_control & MODE_BLACK_AND_WHITE != 0 {
+            PALETTES[2]
+        } else if self.color_select & PALETTE_1 != 0 {
+            PALETTES[1]
+        } else {
+            PALETTES[0]
+        };
+        let intense = if self.color_select & INTENSE != 0 { 8 } else { 0 };
+        [
+            COLORS[(self.color_select & BACKGROUND) as usize],
+            COLORS[(palette[0] + intense) as usize],
+            COLORS[(palette[1] + intense) as usize],
+            COLORS[(palette[2] + intense) as usize],
+        ]
+    }
+
+    /**
+     * Draws the screen of the graphics modes: 320x200 with 2 bits a pixel, or
+     * 640x200 with 1 bit a pixel in the color of the color select register.
+     * Each byte holds the pixels from the left, in its high bits first.
+     */
+    pub fn graphics_picture(&self, memory: &MemorySegments) -> Picture {
+        let high = self.mode_control & MODE_640_DOTS != 0;
+        let (width, bits) = if high { (HIGH_WIDTH, 1) } else { (WIDTH, 2) };
+        let colors = if !self.enabled() {
+            [COLORS[0]; 4]
+        } else if high {
+            [COLORS[0], COLORS[(self.color_select & BACKGROUND) as usize], 0, 0]
+        } else {
+            self.palette()
+        };
+        let start = self.start_address().wrapping_mul(2);
+        let pixels_per_byte = 8 / bits;
+        let mut pixels = Vec::with_capacity(width * HEIGHT);
+        for y in 0..HEIGHT as u16 {
+            let bank = (y & 1) * BANK_SIZE;
+            let line = start.wrapping_add((y >> 1) * LINE_BYTES);
+            let bytes = (0..LINE_BYTES)
+                .map(|x| memory[(COLOR_SEGMENT, bank + (line.wrapping_add(x) & (BANK_SIZE - 1)))]);
+            for byte in bytes {
+                for i in (0..pixels_per_byte).rev() {
+                    let value = (byte >> (i * bits)) & ((1 << bits) - 1);
+                    pixels.push(color(colors[value as usize]));
+                }
+            }
+        }
+        Picture {
+            image: epi::Image {
+                size: [width, HEIGHT],
+                pixels: pixels,
+            },
+            // the screen is 4:3, so a dot is twice as high as wide in the 640x200 mode
+            size: Vec2::new(HIGH_WIDTH as f32, (HEIGHT * 2) as f32),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use super::*;
+use eframe::egui::{Align2, Color32, Rect, Sense, Stroke, TextStyle, Vec2};
+
+pub mod text;
+pub mod graphics;
+pub mod vga;
+
+use vga::{Vga, VGA_PORTS};
+
+// the ports of the MDA and the CGA
+const MONO_PORTS: Range<usize> = 0x3B0..0x3BC;
+const COLOR_PORTS: Range<usize> = 0x3D0..0x3DD;
+// offsets of the registers from the first port of an adapter
+const CRTC_INDEX: u16 = 0x4;
+const CRTC_DATA: u16 = 0x5;
+const MODE_CONTROL: u16 = 0x8;
+const COLOR_SELECT: u16 = 0x9;
+const STATUS: u16 = 0xA;
+
+const CRTC_REGISTERS: usize = 18;
+const CRTC_CURSOR_START: usize = 0x0A;
+const CRTC_CURSOR_END: usize = 0x0B;
+const CRTC_START_ADDRESS: usize = 0x0C;
+const CRTC_CURSOR_ADDRESS: usize = 0x0E;
+
+// the bits of the mode control register
+const MODE_80_COLUMNS: u8 = 0x01;
+const MODE_GRAPHICS: u8 = 0x02;
+const MODE_BLACK_AND_WHITE: u8 = 0x04;
+const MODE_ENABLE: u8 = 0x08;
+const MODE_640_DOTS: u8 = 0x10;
+const MODE_BLINK: u8 = 0x20;
+
+const ROWS: u16 = 25;
+const COLOR_SEGMENT: u16 = 0xB800;
+const MONO_SEGMENT: u16 = 0xB000;
+// the memory of each adapter
+const COLOR_MEMORY: u16 = 0x4000;
+const MONO_MEMORY: u16 = 0x1000;
+
+/**
+ * A CGA or MDA adapter. The screen is the memory at B800:0000, or B000:0000
+ * once the program writes the mode control register of the MDA. The 6845 CRTC
+ * at ports 3D4h/3D5h (3B4h/3B5h) says where the screen and the cursor start.
+ * The graphics modes of the CGA are shown as a picture, and so is mode 13h of
+ * the VGA once its attribute controller selects 256 colors.
+ */
+pub struct Video {
+    crtc_index: u8,
+    crtc: [u8; CRTC_REGISTERS],
+    mode_control: u8,
+    color_select: u8,
+    // the MDA is used instead of the CGA
+    mono: bool,
+    // the status register changes on every read, so programs waiting for a retrace go on
+    status_reads: u8,
+    vga: Vga,
+}
+
+impl Video {
+    /**
+     * Creates a CGA in 80x25 text mode, the way the BIOS leaves it.
+     */
+    pub fn new() -> Self {
+        let mut crtc = [0; CRTC_REGISTERS];
+        crtc[CRTC_CURSOR_START] = 6;
+        crtc[CRTC_CURSOR_END] = 7;
+        Self {
+            crtc_index: 0,
+            crtc: crtc,
+            mode_control: MODE_80_COLUMNS | MODE_ENABLE | MODE_BLINK,
+            color_select: 0x30,
+            mono: false,
+            status_reads: 0,
+            vga: Vga::new(),
+        }
+    }
+
+    /**
+     * Returns the first port of the adapter a port belongs to.
+     */
+    fn base(port: u16) -> u16 {
+        if MONO_PORTS.contains(&(port as usize)) { 0x3B0 } else { 0x3D0 }
+    }
+
+    fn columns(&self) -> u16 {
+        if self.mono || self.mode_control & MODE_80_COLUMNS != 0 { 80 } else { 40 }
+    }
+
+    fn graphics(&self) -> bool {
+        !self.mono && self.mode_control & MODE_GRAPHICS != 0
+    }
+
+    fn enabled(&self) -> bool {
+        self.mode_control & MODE_ENABLE != 0
+    }
+
+    fn blink(&self) -> bool {
+        self.mode_control & MODE_BLINK != 0
+    }
+
+    fn character_height(&self) -> u8 {
+        if self.mono { 14 } else { 8 }
+    }
+
+    /**
+     * Returns the segment of the screen and the mask of an offset in its memory.
+     */
+    fn screen(&self) -> (u16, u16) {
+        if self.mono { (MONO_SEGMENT, MONO_MEMORY - 1) } else { (COLOR_SEGMENT, COLOR_MEMORY - 1) }
+    }
+
+    fn crtc_word(&self, register: usize) -> u16 {
+        (self.crtc[register] as u16) << 8 | self.crtc[register + 1] as u16
+    }
+
+    /**
+     * Returns the character the screen starts at.
+     */
+    fn start_address(&self) -> u16 {
+        self.crtc_word(CRTC_START_ADDRESS)
+    }
+
+    /**
+     * Returns the row and the column of the cursor and its first and last scan
+     * lines, or None if it's off. Bits 5-6 of the cursor start register set to
+     * 01 turn it off.
+     */
+    fn cursor(&self) -> Option<(u16, u16, u8, u8)> {
+        let start = self.crtc[CRTC_CURSOR_START];
+        let first = start & 0x1F;
+        let last = (self.crtc[CRTC_CURSOR_END] & 0x1F).min(self.character_height() - 1);
+        if start & 0x60 == 0x20 || first > last {
+            return None
+        }
+        let position = self.crtc_word(CRTC_CURSOR_ADDRESS).wrapping_sub(self.start_address());
+        Some((position / self.columns(), position % self.columns(), first, last))
+    }
+}
+
+impl Hardware for Video {
+    fn init(&self, _memory: &mut MemorySegments) -> Result<(), LoadError> {
+        Ok(())
+    }
+
+    fn run_hardware(&mut self, _ports: &mut Vec<u8>) -> () {
+        ()
+    }
+
+    fn write_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        let value = ports[port as usize];
+        if VGA_PORTS.contains(&(port as usize)) {
+            return self.vga.write(port, value)
+        }
+        match port - Self::base(port) {
+            CRTC_INDEX => self.crtc_index = value & 0x1F,
+            CRTC_DATA => if (self.crtc_index as usize) < CRTC_REGISTERS {
+                self.crtc[self.crtc_index as usize] = value;
+            },
+            // the adapter whose mode is set is the one shown
+            MODE_CONTROL => {
+                self.mode_control = value;
+                self.mono = Self::base(port) == 0x3B0;
+            },
+            COLOR_SELECT => self.color_select = value,
+            _ => (),
+        }
+    }
+
+    fn read_hardware(&mut self, port: u16, ports: &mut Vec<u8>) -> () {
+        if VGA_PORTS.contains(&(port as usize)) {
+            if let Some(value) = self.vga.read(port) {
+                ports[port as usize] = value;
+            }
+            return
+        }
+        match port - Self::base(port) {
+            CRTC_DATA => ports[port as usize] = *self.crtc.get(self.crtc_index as usize).unwrap_or(&0),
+            // bit 0 is the horizontal and bit 3 the vertical retrace
+            STATUS => {
+                self.vga.reset_attribute();
+                self.status_reads = self.status_reads.wrapping_add(1);
+                ports[port as usize] = (self.status_reads & 0x01) | ((self.status_reads >> 1) & 0x08);
+            },
+            _ => (),
+        }
+    }
+
+    fn is_port_related(&self, port: &u16) -> bool {
+        let port = *port as usize;
+        MONO_PORTS.contains(&port) || COLOR_PORTS.contains(&port) || VGA_PORTS.contains(&port)
+    }
+
+    fn ui(&self, ui: &mut Ui, memory: &MemorySegments) {
+        if self.graphics() || self.vga.mode_13() {
+            ui.ctx().request_repaint();
+            return
+        }
+        self.text_ui(ui, memory);
+    }
+
+    fn picture(&self, memory: &MemorySegments) -> Option<Picture> {
+        if self.vga.mode_13() {
+            Some(self.vga.picture(memory))
+        } else if self.graphics() {
+            Some(self.graphics_picture(memory))
+        } else {
+            None
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Video"
+    }
+}
@@ -0,0 +1,147 @@
+use super::*;
+
+// code page 437, the characters of the IBM PC, in the order of their codes
+const CONTROL_CHARACTERS: &str = " ☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+const HIGH_CHARACTERS: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+    ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+    αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{A0}";
+
+// the 16 colors of the CGA, in RGB
+pub const COLORS: [u32; 16] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA,
+    0x555555, 0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF,
+];
+// the colors of the MDA: black, normal and intense
+const MONO_COLORS: [u32; 3] = [0x000000, 0xAAAAAA, 0xFFFFFF];
+
+// the characters blink every 16 frames and the cursor every 8, at 60 frames a second
+const CHARACTER_BLINK: f64 = 16.0 / 60.0;
+const CURSOR_BLINK: f64 = 8.0 / 60.0;
+
+/**
+ * Returns the character of a code of code page 437.
+ */
+pub fn cp437(code: u8) -> char {
+    match code {
+        0x00..=0x1F => CONTROL_CHARACTERS.chars().nth(code as usize).unwrap(),
+        0x7F => '⌂',
+        0x80..=0xFF => HIGH_CHARACTERS.chars().nth(code as usize - 0x80).unwrap(),
+        _ => code as char,
+    }
+}
+
+pub fn color(rgb: u32) -> Color32 {
+    Color32::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+}
+
+/**
+ * The colors of a character and if it's underlined.
+ */
+struct Cell {
+    foreground: Color32,
+    background: Color32,
+    underline: bool,
+}
+
+/**
+ * Returns how the CGA shows an attribute. With blinking enabled bit 7 blinks
+ * the character, otherwise it makes the background intense.
+ * @param blink_on: the blinking characters are shown now.
+ */
+fn color_cell(attribute: u8, blink: bool, blink_on: bool) -> Cell {
+    let (background, hidden) = if blink {
+        ((attribute >> 4) & 0x07, attribute & 0x80 != 0 && !blink_on)
+    } else {
+        (attribute >> 4, false)
+    };
+    Cell {
+        foreground: color(COLORS[if hidden { background } else { attribute & 0x0F } as usize]),
+        background: color(COLORS[background as usize]),
+        underline: false,
+    }
+}
+
+/**
+ * Returns how the MDA shows an attribute: 00h, 08h, 80h and 88h are hidden,
+ * 70h is reversed, a foreground of 1 is underlined and bit 3 is intense.
+ */
+fn mono_cell(attribute: u8, blink: bool, blink_on: bool) -> Cell {
+    let intense = if attribute & 0x08 != 0 { MONO_COLORS[2] } else { MONO_COLORS[1] };
+    let hidden = attribute & 0x77 == 0 || (blink && attribute & 0x80 != 0 && !blink_on);
+    let (foreground, background) = match attribute & 0x77 {
+        0x70 => (MONO_COLORS[0], MONO_COLORS[1]),
+        _ => (intense, MONO_COLORS[0]),
+    };
+    Cell {
+        foreground: color(if hidden { background } else { foreground }),
+        background: color(background),
+        underline: !hidden && attribute & 0x07 == 1,
+    }
+}
+
+impl Video {
+    /**
+     * Draws the text screen: the characters of the page the CRTC starts at,
+     * with their attributes, and the cursor.
+     */
+    pub fn text_ui(&self, ui: &mut Ui, memory: &MemorySegments) {
+        let columns = self.columns();
+        let style = TextStyle::Monospace;
+        let (width, height) = {
+            let fonts = ui.fonts();
+            (fonts.glyph_width(style, 'M'), fonts.row_height(style))
+        };
+        let size = Vec2::new(width * columns as f32, height * ROWS as f32);
+        let (response, painter) = ui.allocate_painter(size, Sense::hover());
+        let origin = response.rect.min;
+        painter.rect_filled(response.rect, 0.0, Color32::BLACK);
+        if !self.enabled() {
+            return
+        }
+        let time = ui.input().time;
+        let blink_on = (time / CHARACTER_BLINK) as u64 % 2 == 0;
+        let (segment, size_mask) = self.screen();
+        let start = self.start_address() * 2;
+        for row in 0..ROWS {
+            for column in 0..columns {
+                let offset = (start + (row * columns + column) * 2) & size_mask;
+                let bytes = memory.read((segment, offset), 2);
+                let (character, attribute) = (bytes[0], bytes[1]);
+                let cell = if self.mono {
+                    mono_cell(attribute, self.blink(), blink_on)
+                } else {
+                    color_cell(attribute, self.blink(), blink_on)
+                };
+                let min = origin + Vec2::new(column as f32 * width, row as f32 * height);
+                let rect = Rect::from_min_size(min, Vec2::new(width, height));
+                if cell.background != Color32::BLACK {
+                    painter.rect_filled(rect, 0.0, cell.background);
+                }
+                if ![0x00, b' ', 0xFF].contains(&character) && cell.foreground != cell.background {
+                    painter.text(min, Align2::LEFT_TOP, cp437(character), style, cell.foreground);
+                }
+                if cell.underline {
+                    painter.line_segment([rect.left_bottom(), rect.right_bottom()], Stroke::new(1.0, cell.foreground));
+                }
+            }
+        }
+        // the cursor takes the scan lines from its start to its end
+        if let Some((row, column, first, last)) = self.cursor() {
+            let cursor_on = (time / CURSOR_BLINK) as u64 % 2 == 0;
+            let scan_lines = self.character_height() as f32;
+            if row < ROWS && column < columns && cursor_on {
+                let min = origin + Vec2::new(column as f32 * width, (row as f32 + first as f32 / scan_lines) * height);
+                let max = origin + Vec2::new((column + 1) as f32 * width, (row as f32 + (last + 1) as f32 / scan_lines) * height);
+                let offset = (start + (row * columns + column) * 2 + 1) & size_mask;
+                let attribute = memory[(segment, offset)];
+                let foreground = if self.mono {
+                    mono_cell(attribute, false, true).foreground
+                } else {
+                    color_cell(attribute, false, true).foreground
+                };
+                painter.rect_filled(Rect::from_min_max(min, max), 0.0, foreground);
+            }
+        }
+        ui.ctx().request_repaint();
+    }
+}
@@ -0,0 +1,148 @@
+use super::*;
+use super::graphics::{WIDTH, HIGH_WIDTH, HEIGHT};
+use crate::bios::video::default_dac;
+
+// the ports of the VGA that aren't the ones of the CGA
+pub const VGA_PORTS: Range<usize> = 0x3C0..0x3D0;
+const ATTRIBUTE_PORT: u16 = 0x3C0;
+const ATTRIBUTE_READ: u16 = 0x3C1;
+const DAC_STATE: u16 = 0x3C7;
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+const DAC_DATA: u16 = 0x3C9;
+
+const ATTRIBUTE_REGISTERS: usize = 0x15;
+const ATTRIBUTE_MODE_CONTROL: usize = 0x10;
+// bit 6 of the attribute mode control register: a byte a dot, the 256 colors of mode 13h
+const ATTRIBUTE_256_COLORS: u8 = 0x40;
+// bit 5 of the attribute index gives the screen to the CRTC
+const ATTRIBUTE_ENABLE: u8 = 0x20;
+
+const VGA_SEGMENT: u16 = 0xA000;
+
+/**
+ * The registers of the VGA that the CGA doesn't have: the attribute
+ * controller, whose index and data alternate on port 3C0h, and the DAC with
+ * 256 colors of 6 bits. Port 3C7h sets the entry to read and 3C8h the one to
+ * write, then 3C9h reads or writes its red, green and blue in turn.
+ */
+pub struct Vga {
+    attribute_index: u8,
+    // the next write to port 3C0h is the data of the register
+    attribute_data: bool,
+    attributes: [u8; ATTRIBUTE_REGISTERS],
+    dac: Vec<[u8; 3]>,
+    dac_read: (u8, usize),
+    dac_write: (u8, usize),
+    // the last index set was the one to read
+    dac_reading: bool,
+}
+
+impl Vga {
+    /**
+     * Creates a VGA with the palette of the BIOS in its DAC.
+     */
+    pub fn new() -> Self {
+        Self {
+            attribute_index: ATTRIBUTE_ENABLE,
+            attribute_data: false,
+            attributes: [0; ATTRIBUTE_REGISTERS],
+            dac: default_dac(),
+            dac_read: (0, 0),
+            dac_write: (0, 0),
+            dac_reading: false,
+        }
+    }
+
+    /**
+     * Mode 13h: the screen is the 320x200 bytes at A000:0000.
+     */
+    pub fn mode_13(&self) -> bool {
+        self.attributes[ATTRIBUTE_MODE_CONTROL] & ATTRIBUTE_256_COLORS != 0
+    }
+
+    /**
+     * Reading the status register makes the next write to port 3C0h an index.
+     */
+    pub fn reset_attribute(&mut self) {
+        self.attribute_data = false;
+    }
+
+    /**
+     * Returns the color of an entry of the DAC, its 6 bits made 8.
+     */
+    fn color(&self, entry: u8) -> Color32 {
+        let [red, green, blue] = self.dac[entry as usize].map(|value| value << 2 | value >> 4);
+        Color32::from_rgb(red, green, blue)
+    }
+
+    /**
+     * Moves to the next color of an entry, and to the next entry after blue.
+     */
+    fn next((entry, component): (u8, usize)) -> (u8, usize) {
+        if component == 2 { (entry.wrapping_add(1), 0) } else { (entry, component + 1) }
+    }
+
+    pub fn write(&mut self, port: u16, value: u8) {
+        match port {
+            ATTRIBUTE_PORT => {
+                if self.attribute_data {
+                    if let Some(register) = self.attributes.get_mut((self.attribute_index & 0x1F) as usize) {
+                        *register = value;
+                    }
+                } else {
+                    self.attribute_index = value & 0x3F;
+                }
+                self.attribute_data = !self.attribute_data;
+            },
+            DAC_STATE => {
+                self.dac_read = (value, 0);
+                self.dac_reading = true;
+            },
+            DAC_WRITE_INDEX => {
+                self.dac_write = (value, 0);
+                self.dac_reading = false;
+            },
+            DAC_DATA => {
+                let (entry, component) = self.dac_write;
+                self.dac[entry as usize][component] = value & 0x3F;
+                self.dac_write = Self::next(self.dac_write);
+            },
+            _ => (),
+        }
+    }
+
+    pub fn read(&mut self, port: u16) -> Option<u8> {
+        match port {
+            ATTRIBUTE_PORT => Some(self.attribute_index),
+            ATTRIBUTE_READ => Some(*self.attributes.get((self.attribute_index & 0x1F) as usize).unwrap_or(&0)),
+            // 3 when the DAC is read, 0 when it's written
+            DAC_STATE => Some(if self.dac_reading { 3 } else { 0 }),
+            DAC_WRITE_INDEX => Some(self.dac_write.0),
+            DAC_DATA => {
+                let (entry, component) = self.dac_read;
+                self.dac_read = Self::next(self.dac_read);
+                Some(self.dac[entry as usize][component])
+            },
+            _ => None,
+        }
+    }
+
+    /**
+     * Draws the screen of mode 13h, each byte the entry of the DAC of its dot.
+     * Without bit 5 of the attribute index the screen is black.
+     */
+    pub fn picture(&self, memory: &MemorySegments) -> Picture {
+        let enabled = self.attribute_index & ATTRIBUTE_ENABLE != 0;
+        let screen = memory.read((VGA_SEGMENT, 0), WIDTH * HEIGHT);
+        let pixels = screen.iter()
+            .map(|&entry| if enabled { self.color(entry) } else { Color32::BLACK })
+            .collect();
+        Picture {
+            image: epi::Image {
+                size: [WIDTH, HEIGHT],
+                pixels: pixels,
+            },
+            size: Vec2::new(HIGH_WIDTH as f32, (HEIGHT * 2) as f32),
+        }
+    }
+}
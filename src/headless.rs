@@ -0,0 +1,605 @@
+use crate::computer::{Computer, HardwareList};
+use crate::cpu::{CPU, HaltPolicy, Step, HLT};
+use crate::cpu::helperModules::string_to_number;
+use crate::program_loader::LoadOptions;
+use crate::program_loader::image::{Image, parse_location};
+use crate::dos::console::StdConsole;
+use crate::debugger::Debugger;
+
+use std::panic;
+
+// exit status of the emulator in headless mode
+pub const EXIT_HALTED: i32 = 0;
+pub const EXIT_FAULT: i32 = 1;
+pub const EXIT_TIMEOUT: i32 = 2;
+pub const EXIT_LOAD: i32 = 3;
+pub const EXIT_USAGE: i32 = 64;
+
+const DEFAULT_LIMIT: u64 = 1_000_000;
+// the steps each of two linked computers runs in its turn
+const LINK_SLICE: u64 = 1000;
+
+pub const USAGE: &str = "usage: emulator [options] <program> [arguments...]
+       emulator [options] --fda <image> --boot
+Runs the program without the gui until it reaches HLT or ends through DOS.
+The DOS console services and the BIOS keyboard and teletype read stdin and write to stdout.
+The arguments after the program are passed to it in the command tail of its PSP.
+
+options:
+    --limit <count>         maximum number of instructions to run (default 1000000)
+    --hardware <list>       comma separated hardware to connect: lightbulb, keyboard (with the pic), console,
+                            video, timer, pic, speaker (with the timer)
+    --dump <seg:add[,len]>  print memory at seg:add when the program stops (can repeat)
+    --format <text|json>    format of the printed state (default text)
+    --assembler <path>      the nasm binary used for .asm programs, or builtin (default $NASM or builtin)
+    --com                   load the program as a DOS .COM program (default for .com files)
+    --segment <seg>         the segment to load the program at (default the first free segment, 0x1000)
+    --image <file[@seg:off]>
+                            place a file in memory before reset (can repeat), raw files and .asm programs
+                            at seg:off, Intel HEX and S-record files at the addresses of their records
+    --drive <dir>           the directory DOS programs see as drive C: (default the directory of the program)
+    --bios <file>           a BIOS ROM image to place read-only at the top of memory instead of the built-in BIOS
+    --reset <seg:off>       where the cpu starts after reset (default F000:FFF0)
+    --halt <wait|stop>      HLT waits for an interrupt while IF is set and the interrupt controller is connected,
+                            or always ends the program (default wait), waiting isn't counted as instructions
+                            but a wait of as many steps as the limit ends the run as the limit does
+    --keys <text>           type the text on the keyboard as the program starts, connecting the keyboard and
+                            the interrupt controller it needs for INT 09h to fill the BIOS buffer
+    --wav <file>            save what the speaker played as a WAV file when the program stops, connecting it
+    --com1 <host>           connect COM1 (3F8h, IRQ4) to stdio, file:<output>[,<input>], unix:<socket path>
+                            or tcp:<port>, the sockets listen for a client on localhost
+    --com2 <host>           connect COM2 (2F8h, IRQ3) the same way
+    --lpt1 <file>           connect a printer to LPT1 (378h, IRQ7), capturing what it prints to the file
+    --fda <image>           put a raw 360K, 720K or 1.44M disk image in floppy drive A:, connecting the floppy
+                            disk controller (3F0h, IRQ6, DMA channel 2), the sectors written go to the image
+    --fdb <image>           put a disk image in drive B: the same way
+    --boot                  load the boot sector of the disk in A: at 0000:7C00 and start it with DL 0,
+                            instead of a program
+    --link <program>        run a second computer with the program, the same hardware and options, its COM1
+                            connected to COM1 of the first by a null-modem cable, both run by turns and
+                            their states are printed one after the other (an array of the two with json);
+                            COM2, LPT1 and the disks are only connected to the first computer
+    --debug                 run the program in the console debugger, reading its commands from stdin,
+                            instead of running it to the end
+    --unpaced               the characters on the serial ports take no time, instead of their time at the
+                            baud rate, and the received bytes wait for room in the receiver
+    --help                  print this message
+
+exit status:
+    0   the program reached HLT or ended through DOS
+    1   the cpu faulted
+    2   the instruction limit was reached
+    3   the program couldn't be loaded";
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/**
+ * A memory range to print when the program stops.
+ * The segment is either a number or the name of a segment register,
+ * which is read only when the program stops.
+ */
+#[derive(PartialEq, Clone, Debug)]
+pub struct MemoryRange {
+    pub segment: String,
+    pub address: u16,
+    pub len: u16,
+}
+
+impl MemoryRange {
+    /**
+     * Parses a `seg:add[,len]` string, the way `echo` of the debugger does.
+     * @param string: the string to parse.
+     */
+    pub fn from(string: &str) -> Result<Self, String> {
+        let (location, len) = match string.find(',') {
+            Some(i) => (&string[..i], &string[i + 1..]),
+            None => (string, "1"),
+        };
+        let args = location.split(':').collect::<Vec<&str>>();
+        if args.len() != 2 {
+            return Err(format!("bad memory range '{}'", string))
+        }
+        let segment = args[0].to_lowercase();
+        if !["cs", "ds", "ss", "es"].contains(&segment.as_str()) && string_to_number(&segment).is_err() {
+            return Err(format!("bad `segment` argument '{}'", args[0]))
+        }
+        let address = match string_to_number(args[1]) {
+            Ok(val) if val <= 0xFFFF => val as u16,
+            _ => return Err(format!("bad `address` argument '{}'", args[1]))
+        };
+        let len = match string_to_number(len) {
+            Ok(val) if val > 0 && val <= 0x10000 => val as u16,
+            _ => return Err(format!("bad `length` argument '{}'", len))
+        };
+        Ok(Self {
+            segment: segment,
+            address: address,
+            len: len,
+        })
+    }
+
+    /**
+     * Returns the value of the segment of the range.
+     */
+    fn segment_value(&self, cpu: &CPU) -> u16 {
+        let memory = &cpu.memory_unit.memory;
+        match self.segment.as_str() {
+            "cs" => memory.code_segment,
+            "ds" => memory.data_segment,
+            "ss" => memory.stack_segment,
+            "es" => memory.extra_segment,
+            seg => string_to_number(seg).unwrap() as u16,
+        }
+    }
+
+    /**
+     * Reads the bytes of the range from the memory.
+     */
+    fn read(&self, cpu: &CPU) -> (u16, Vec<u8>) {
+        let segment = self.segment_value(cpu);
+        let mut bytes = Vec::with_capacity(self.len as usize);
+        for i in 0..self.len {
+            bytes.push(cpu.memory_unit.memory[(segment, self.address.wrapping_add(i))]);
+        }
+        (segment, bytes)
+    }
+}
+
+/**
+ * Options of the headless mode, parsed from the command line.
+ */
+#[derive(Clone)]
+pub struct HeadlessOptions {
+    pub program: String,
+    pub limit: u64,
+    pub hardware: HardwareList,
+    pub dumps: Vec<MemoryRange>,
+    pub format: OutputFormat,
+    pub load: LoadOptions,
+    // text typed on the keyboard as the program starts
+    pub keys: String,
+    // the WAV file the speaker is saved to when the program stops
+    pub wav: Option<String>,
+    // the program of a second computer, linked to the first through COM1
+    pub link: Option<String>,
+    // run the program in the console debugger
+    pub debug: bool,
+}
+
+impl HeadlessOptions {
+    /**
+     * Parses the command line arguments.
+     * @param args: the arguments, without the name of the executable.
+     * @return: the options, or a message explaining what's wrong.
+     */
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            program: String::new(),
+            limit: DEFAULT_LIMIT,
+            hardware: HardwareList::new(),
+            dumps: Vec::new(),
+            format: OutputFormat::Text,
+            load: LoadOptions::new(),
+            keys: String::new(),
+            wav: None,
+            link: None,
+            debug: false,
+        };
+        let mut i = 0;
+        while i < args.len() {
+            let arg = args[i].as_str();
+            // options that take a value
+            if ["--limit", "--hardware", "--dump", "--format", "--assembler", "--segment", "--image", "--drive",
+                "--bios", "--reset", "--halt", "--keys", "--wav", "--com1", "--com2", "--link", "--lpt1",
+                "--fda", "--fdb"].contains(&arg) {
+                if i + 1 >= args.len() {
+                    return Err(format!("missing value for '{}'", arg))
+                }
+                let value = args[i + 1].as_str();
+                match arg {
+                    "--limit" => options.limit = match value.parse::<u64>() {
+                        Ok(val) => val,
+                        Err(_) => return Err(format!("bad `limit` argument '{}'", value))
+                    },
+                    "--hardware" => for name in value.split(',') {
+                        match name {
+                            "lightbulb" => options.hardware.lightbulb = true,
+                            "keyboard" => options.hardware.keyboard = true,
+                            "console" => options.hardware.console = true,
+                            "video" => options.hardware.video = true,
+                            "timer" => options.hardware.timer = true,
+                            "pic" => options.hardware.pic = true,
+                            "speaker" => options.hardware.speaker = true,
+                            _ => return Err(format!("unknown hardware '{}'", name))
+                        }
+                    },
+                    "--dump" => options.dumps.push(MemoryRange::from(value)?),
+                    "--keys" => {
+                        options.keys = value.to_string();
+                        options.hardware.keyboard = true;
+                    },
+                    "--com1" => options.load.serial[0] = Some(value.to_string()),
+                    "--com2" => options.load.serial[1] = Some(value.to_string()),
+                    "--link" => options.link = Some(value.to_string()),
+                    "--lpt1" => options.load.printer = Some(value.to_string()),
+                    "--fda" => options.load.floppies[0] = Some(value.to_string()),
+                    "--fdb" => options.load.floppies[1] = Some(value.to_string()),
+                    "--wav" => {
+                        options.wav = Some(value.to_string());
+                        options.hardware.speaker = true;
+                    },
+                    "--assembler" => options.load.assembler = value.to_string(),
+                    "--image" => options.load.images.push(Image::from(value)?),
+                    "--drive" => options.load.drive = Some(value.to_string()),
+                    "--bios" => options.load.bios = Some(value.to_string()),
+                    "--reset" => options.load.reset_vector = Some(parse_location(value)?),
+                    "--halt" => options.load.halt_policy = match value {
+                        "wait" => HaltPolicy::Wait,
+                        "stop" => HaltPolicy::Stop,
+                        _ => return Err(format!("unknown halt policy '{}'", value))
+                    },
+                    "--segment" => options.load.load_segment = match string_to_number(value) {
+                        Ok(val) if val <= 0xFFFF => Some(val as u16),
+                        _ => return Err(format!("bad `segment` argument '{}'", value))
+                    },
+                    _ => options.format = match value {
+                        "text" => OutputFormat::Text,
+                        "json" => OutputFormat::Json,
+                        _ => return Err(format!("unknown format '{}'", value))
+                    },
+                }
+                i += 2;
+                continue;
+            }
+            if arg == "--com" {
+                options.load.com = true;
+                i += 1;
+                continue;
+            }
+            if arg == "--unpaced" {
+                options.load.serial_pacing = false;
+                i += 1;
+                continue;
+            }
+            if arg == "--boot" {
+                options.load.boot = true;
+                i += 1;
+                continue;
+            }
+            if arg == "--debug" {
+                options.debug = true;
+                i += 1;
+                continue;
+            }
+            if arg.starts_with("--") {
+                return Err(format!("unknown option '{}'", arg))
+            }
+            // everything after the program is passed to it
+            options.program = arg.to_string();
+            options.load.arguments = args[i + 1..].join(" ");
+            break;
+        }
+        if options.program.is_empty() && !options.load.boot {
+            return Err("no program given".to_string())
+        }
+        if !options.program.is_empty() && options.load.boot {
+            return Err("--boot starts the disk in A:, it doesn't take a program".to_string())
+        }
+        if options.debug && options.link.is_some() {
+            return Err("--debug runs a single computer, it can't be used with --link".to_string())
+        }
+        Ok(options)
+    }
+}
+
+/**
+ * How the run of the program ended.
+ */
+#[derive(PartialEq, Clone, Debug)]
+pub enum RunStatus {
+    Halted,
+    // the program ended through DOS with a return code
+    Exited(u8),
+    Fault(String),
+    Timeout,
+}
+
+impl RunStatus {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Halted | Self::Exited(_) => EXIT_HALTED,
+            Self::Fault(_) => EXIT_FAULT,
+            Self::Timeout => EXIT_TIMEOUT,
+        }
+    }
+
+    /**
+     * Returns the worse of two statuses, the one of linked computers:
+     * a fault, then a timeout, then an end.
+     */
+    pub fn worse(self, other: Self) -> Self {
+        let severity = |status: &Self| match status {
+            Self::Halted | Self::Exited(_) => 0,
+            Self::Timeout => 1,
+            Self::Fault(_) => 2,
+        };
+        if severity(&other) > severity(&self) { other } else { self }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::Halted => "halted",
+            Self::Exited(_) => "exited",
+            Self::Fault(_) => "fault",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+/**
+ * Runs the cpu until HLT, a fault or until `limit` instructions were executed.
+ * The steps HLT waits for an interrupt aren't instructions, but waiting for
+ * `limit` steps in a row is a timeout too, as the interrupt might never come.
+ * @return: the status and the amount of executed instructions.
+ */
+pub fn run_cpu(cpu: &mut CPU, limit: u64) -> (RunStatus, u64) {
+    let mut progress = (0, 0);
+    let status = run_steps(cpu, limit, u64::MAX, &mut progress);
+    (status, progress.0)
+}
+
+/**
+ * Runs the cpu for at most `steps` steps, until HLT, a fault, or until it
+ * executed `limit` instructions or waited `limit` steps in a row for an interrupt.
+ * The cpu panics on some malformed instructions, so a panic is reported as a fault,
+ * `run` silences the panic message of the cpu thread once for all the run.
+ * @param progress: the executed instructions and the steps waited in a row, kept between the calls.
+ * @return: the status, Timeout if the cpu can still run.
+ */
+fn run_steps(cpu: &mut CPU, limit: u64, steps: u64, progress: &mut (u64, u64)) -> RunStatus {
+    let (count, waited) = progress;
+    let mut status = RunStatus::Timeout;
+    let mut step = 0;
+    while *count < limit && *waited < limit && step < steps {
+        step += 1;
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| cpu.run_next_instruction()));
+        match res {
+            Ok(Ok(Step::Ran(HLT))) => {
+                *count += 1;
+                status = match cpu.exit_code {
+                    Some(code) => RunStatus::Exited(code),
+                    None => RunStatus::Halted,
+                };
+                break
+            },
+            Ok(Ok(Step::Ran(_))) => {
+                *count += 1;
+                *waited = 0;
+            },
+            Ok(Ok(Step::Waiting)) => {
+                // the first step is HLT itself
+                if *waited == 0 {
+                    *count += 1;
+                }
+                *waited += 1;
+            },
+            Ok(Err(msg)) => {
+                status = RunStatus::Fault(msg);
+                break
+            },
+            Err(payload) => {
+                let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+                    msg.to_string()
+                } else if let Some(msg) = payload.downcast_ref::<String>() {
+                    msg.clone()
+                } else {
+                    "cpu panicked".to_string()
+                };
+                status = RunStatus::Fault(msg);
+                break
+            },
+        }
+    }
+    status
+}
+
+/**
+ * Runs two cpus by turns, LINK_SLICE steps at a time, until each one
+ * stopped, executed `limit` instructions or waited `limit` steps in a row.
+ * @return: the status and the amount of executed instructions of each cpu.
+ */
+pub fn run_linked(first: &mut CPU, second: &mut CPU, limit: u64) -> [(RunStatus, u64); 2] {
+    let mut statuses = [RunStatus::Timeout, RunStatus::Timeout];
+    let mut progress = [(0, 0), (0, 0)];
+    let mut cpus = [first, second];
+    loop {
+        let mut running = false;
+        for ((cpu, status), progress) in cpus.iter_mut().zip(statuses.iter_mut()).zip(progress.iter_mut()) {
+            if *status != RunStatus::Timeout || progress.0 >= limit || progress.1 >= limit {
+                continue
+            }
+            running = true;
+            *status = run_steps(cpu, limit, LINK_SLICE, progress);
+        }
+        if !running {
+            break
+        }
+    }
+    let [first_status, second_status] = statuses;
+    [(first_status, progress[0].0), (second_status, progress[1].0)]
+}
+
+/**
+ * Returns the registers of the cpu as (name, value) pairs.
+ */
+fn registers(cpu: &CPU) -> Vec<(&'static str, u16)> {
+    let mem = &cpu.memory_unit;
+    vec![
+        ("ax", mem.ax), ("bx", mem.bx), ("cx", mem.cx), ("dx", mem.dx),
+        ("sp", mem.sp), ("bp", mem.bp), ("si", mem.si), ("di", mem.di),
+        ("ip", mem.ip),
+        ("cs", mem.memory.code_segment), ("ds", mem.memory.data_segment),
+        ("ss", mem.memory.stack_segment), ("es", mem.memory.extra_segment),
+        ("flags", mem.flags.as_word()),
+    ]
+}
+
+/**
+ * Formats the state of the cpu after the run.
+ * @param cpu: the cpu to print.
+ * @param status: how the run ended.
+ * @param count: amount of executed instructions.
+ * @param options: the options of the run.
+ */
+pub fn format_state(cpu: &CPU, status: &RunStatus, count: u64, options: &HeadlessOptions) -> String {
+    let mut out = String::new();
+    match options.format {
+        OutputFormat::Text => {
+            out += &format!("status: {}", status.name());
+            match status {
+                RunStatus::Fault(msg) => out += &format!(" ({})", msg),
+                RunStatus::Exited(code) => out += &format!(" (return code {})", code),
+                _ => (),
+            }
+            out += &format!("\ninstructions: {}\n", count);
+            let regs = registers(cpu);
+            for (i, (name, val)) in regs.iter().enumerate() {
+                out += &format!("{}: {:04X}", name.to_uppercase(), val);
+                out += if i % 4 == 3 || i == regs.len() - 1 {"\n"} else {"  "};
+            }
+            for range in &options.dumps {
+                let (segment, bytes) = range.read(cpu);
+                for (i, byte) in bytes.iter().enumerate() {
+                    if i % 16 == 0 {
+                        if i > 0 {
+                            out += "\n";
+                        }
+                        out += &format!("{:04X}:{:04X}", segment, range.address.wrapping_add(i as u16));
+                    }
+                    out += &format!(" {:02X}", byte);
+                }
+                out += "\n";
+            }
+        },
+        OutputFormat::Json => {
+            out += &format!("{{\"status\":\"{}\"", status.name());
+            match status {
+                RunStatus::Fault(msg) => out += &format!(",\"fault\":\"{}\"", json_escape(msg)),
+                RunStatus::Exited(code) => out += &format!(",\"return_code\":{}", code),
+                _ => (),
+            }
+            out += &format!(",\"instructions\":{},\"registers\":{{", count);
+            let regs = registers(cpu).iter()
+                .map(|(name, val)| format!("\"{}\":{}", name, val))
+                .collect::<Vec<String>>();
+            out += &regs.join(",");
+            out += "},\"memory\":[";
+            let dumps = options.dumps.iter().map(|range| {
+                let (segment, bytes) = range.read(cpu);
+                let bytes = bytes.iter().map(|b| b.to_string()).collect::<Vec<String>>();
+                format!("{{\"segment\":{},\"address\":{},\"bytes\":[{}]}}",
+                    segment, range.address, bytes.join(","))
+            }).collect::<Vec<String>>();
+            out += &dumps.join(",");
+            out += "]}\n";
+        },
+    }
+    out
+}
+
+/**
+ * Formats the states of two linked computers, one after the other with text
+ * and as an array of the two with json.
+ * @param results: the status and the amount of executed instructions of each cpu.
+ */
+pub fn format_linked(cpus: [&CPU; 2], results: &[(RunStatus, u64); 2], options: &HeadlessOptions) -> String {
+    let [first, second] = [0, 1].map(|i| format_state(cpus[i], &results[i].0, results[i].1, options));
+    match options.format {
+        OutputFormat::Text => format!("{}--- {} ---\n{}", first, options.link.as_deref().unwrap_or(""), second),
+        OutputFormat::Json => format!("[{},{}]\n", first.trim_end(), second.trim_end()),
+    }
+}
+
+fn json_escape(string: &str) -> String {
+    let mut out = String::new();
+    for c in string.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/**
+ * Runs the emulator in headless mode.
+ * @param args: the command line arguments, without the name of the executable.
+ * @return: the exit status of the emulator.
+ */
+pub fn run(args: &[String]) -> i32 {
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", USAGE);
+        return EXIT_HALTED
+    }
+    let options = match HeadlessOptions::from_args(args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            return EXIT_USAGE
+        }
+    };
+    let console = Box::new(StdConsole::new());
+    let created = match &options.link {
+        Some(second) => Computer::linked([&options.program, second], options.hardware, &options.load,
+            [console, Box::new(StdConsole::new())]).map(|(first, second)| (first, Some(second))),
+        None => Computer::new(&options.program, options.hardware, &options.load, console).map(|computer| (computer, None)),
+    };
+    let (mut computer, mut linked) = match created {
+        Ok(computers) => computers,
+        Err(err) => {
+            eprintln!("{}", err);
+            return EXIT_LOAD
+        }
+    };
+    if let Some(keyboard) = &computer.keyboard {
+        keyboard.type_text(&options.keys);
+    }
+    if options.debug {
+        let mut debugger = Debugger::new();
+        debugger.set_debug_info(computer.debug_info.clone());
+        debugger.run_emulator(&mut computer.cpu);
+        return EXIT_HALTED
+    }
+    // a panic of the cpu is reported as a fault in the state, the other
+    // threads keep the default panic message
+    let cpu_thread = std::thread::current().id();
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| if std::thread::current().id() != cpu_thread {
+        hook(info)
+    }));
+    let status = match linked.as_mut() {
+        Some(second) => {
+            let results = run_linked(&mut computer.cpu, &mut second.cpu, options.limit);
+            print!("{}", format_linked([&computer.cpu, &second.cpu], &results, &options));
+            let [(first_status, _), (second_status, _)] = results;
+            first_status.worse(second_status)
+        },
+        None => {
+            let (status, count) = run_cpu(&mut computer.cpu, options.limit);
+            print!("{}", format_state(&computer.cpu, &status, count, &options));
+            status
+        },
+    };
+    if let (Some(path), Some(speaker)) = (&options.wav, &computer.speaker) {
+        if let Err(err) = speaker.save_wav(path) {
+            eprintln!("couldn't save {}: {}", path, err);
+        }
+    }
+    status.exit_code()
+}
@@ -4,13 +4,49 @@ use crate::cpu::helperModules::ModrmRegField;
 use crate::cpu::memory_unit::Operand;
 use crate::cpu::memory_unit::memory_segments::*;
 
+mod assembler;
+mod bios;
+mod computer;
 mod cpu;
+mod debug_info;
+mod debugger;
+mod dos;
 mod hardware;
+mod headless;
 mod program_loader;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::{Arc, Mutex, mpsc::channel};
+    use hardware::Hardware;
+
+    /**
+     * A cpu without a program, its ports aren't connected to any hardware.
+     */
+    fn test_cpu() -> CPU {
+        let (cpu_sender, _) = channel();
+        let (_, cpu_receiver) = channel();
+        CPU::init(&Vec::new(), Arc::new(Mutex::new(vec![0; PORTS])), (cpu_sender, cpu_receiver), None, None)
+    }
+
+    /**
+     * Writes bytes to a port of a device one after the other, the way the cpu does.
+     */
+    fn port_out(device: &mut dyn Hardware, ports: &mut Vec<u8>, port: u16, values: &[u8]) {
+        for value in values {
+            ports[port as usize] = *value;
+            device.write_hardware(port, ports);
+        }
+    }
+
+    /**
+     * Reads a port of a device, the way the cpu does.
+     */
+    fn port_in(device: &mut dyn Hardware, ports: &mut Vec<u8>, port: u16) -> u8 {
+        device.read_hardware(port, ports);
+        ports[port as usize]
+    }
 
     #[test]
     fn mempointer() {
@@ -32,4 +68,1214 @@ mod tests {
         assert!(mem.ax == 20);
         assert!(mem.cx == 5);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn low_byte_registers() {
+        let mut cpu = test_cpu();
+        // mov ax, 1200h; cmp al, 0
+        cpu.memory_unit.memory.write(&vec![0xB8, 0x00, 0x12, 0x3C, 0x00], (0x1000, 0));
+        cpu.memory_unit.memory.code_segment = 0x1000;
+        cpu.memory_unit.ip = 0;
+        for _ in 0..2 {
+            cpu.run_next_instruction().unwrap();
+        }
+        // AH isn't part of AL
+        assert!(cpu.memory_unit.flags.zero);
+    }
+
+    #[test]
+    fn mov_immediate_and_iret() {
+        let mut cpu = test_cpu();
+        let memory = &mut cpu.memory_unit.memory;
+        // the handler sets the carry, IRET gives back the flags of the caller
+        memory.set_interrupt_vector(0x60, (0x2000, 0));
+        memory.write(&vec![0xF9, 0xCF], (0x2000, 0));
+        // mov dl, 12h; mov bl, 34h; int 60h
+        memory.write(&vec![0xB2, 0x12, 0xB3, 0x34, 0xCD, 0x60], (0x1000, 0));
+        memory.code_segment = 0x1000;
+        memory.stack_segment = 0x1000;
+        cpu.memory_unit.ip = 0;
+        cpu.memory_unit.sp = 0xFFFE;
+        for _ in 0..5 {
+            cpu.run_next_instruction().unwrap();
+        }
+        assert_eq!((cpu.memory_unit.dx & 0xFF, cpu.memory_unit.bx & 0xFF), (0x12, 0x34));
+        assert_eq!((cpu.memory_unit.memory.code_segment, cpu.memory_unit.ip), (0x1000, 6));
+        assert!(!cpu.memory_unit.flags.carry);
+        assert_eq!(cpu.memory_unit.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn memory_operand_segments() {
+        let mut cpu = test_cpu();
+        let memory = &mut cpu.memory_unit.memory;
+        memory.data_segment = 0x2000;
+        memory.stack_segment = 0x3000;
+        memory.extra_segment = 0x4000;
+        memory.write(&vec![0x22], (0x2000, 0x10));
+        memory.write(&vec![0x11], (0x3000, 0x10));
+        memory.write(&vec![0x33], (0x4000, 0x10));
+        // mov bp, 10h; mov al, [bp+0]; mov bx, 20h; mov cl, [bx-10h]; es: mov dl, [bx-10h]; mov dh, [bx-10h]
+        memory.write(&vec![0xBD, 0x10, 0x00, 0x8A, 0x46, 0x00, 0xBB, 0x20, 0x00, 0x8A, 0x4F, 0xF0,
+            0x26, 0x8A, 0x57, 0xF0, 0x8A, 0x77, 0xF0], (0x1000, 0));
+        memory.code_segment = 0x1000;
+        cpu.memory_unit.ip = 0;
+        for _ in 0..7 {
+            cpu.run_next_instruction().unwrap();
+        }
+        // based on BP is the stack segment, and the displacement is signed
+        assert_eq!(cpu.memory_unit.ax & 0xFF, 0x11);
+        assert_eq!(cpu.memory_unit.cx & 0xFF, 0x22);
+        // the prefix is only for its instruction
+        assert_eq!(cpu.memory_unit.dx, 0x2233);
+    }
+
+    #[test]
+    fn unknown_opcodes() {
+        let mut cpu = test_cpu();
+        let memory = &mut cpu.memory_unit.memory;
+        memory.extra_segment = 0x4000;
+        memory.write(&vec![0x34, 0x12], (0x4000, 0x10));
+        // mov bx, 10h; es: inc word [bx]; es: db 0Fh
+        memory.write(&vec![0xBB, 0x10, 0x00, 0x26, 0xFF, 0x07, 0x26, 0x0F], (0x1000, 0));
+        memory.code_segment = 0x1000;
+        cpu.memory_unit.ip = 0;
+        for _ in 0..2 {
+            cpu.run_next_instruction().unwrap();
+        }
+        // the group after a prefix is decoded too
+        assert_eq!(cpu.memory_unit.memory.read((0x4000, 0x10), 2), vec![0x35, 0x12]);
+        // the opcode after the prefix is checked, the fault is at the prefix
+        assert_eq!(cpu.run_next_instruction(), Err("unknown opcode 0F at 1000:0006".to_string()));
+        assert_eq!(cpu.memory_unit.ip, 6);
+
+        // call far [bx]
+        cpu.memory_unit.memory.write(&vec![0xFF, 0x1F], (0x1000, 0x20));
+        cpu.memory_unit.ip = 0x20;
+        assert_eq!(cpu.run_next_instruction(),
+            Err("opcode FF /3 at 1000:0020: `CALL Mp` is not implemented".to_string()));
+    }
+
+    #[test]
+    fn physical_addresses() {
+        let mut mem = MemorySegments::new();
+        // segment * 16 + offset, so different pairs can reach the same byte
+        mem.write(&vec![0x12, 0x34], (0x1234, 0x0005));
+        assert_eq!(mem[(0x1000, 0x2345)], 0x12);
+        assert_eq!(mem.read((0x1234, 0x0005), 2), vec![0x12, 0x34]);
+        // addresses wrap around at 1MB
+        mem.write(&vec![0xAB, 0xCD], (0xFFFF, 0x000F));
+        assert_eq!(mem[(0, 0)], 0xCD);
+        // a vector is the offset of the handler, then its segment
+        mem.set_interrupt_vector(0x21, (0x1234, 0x5678));
+        assert_eq!(mem.read((0, 0x84), 4), vec![0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(mem.get_interrupt_vector(0x21), (0x1234, 0x5678));
+    }
+
+    #[test]
+    fn nasm_diagnostics() {
+        let stderr = "C:\\code\\prog.asm:12: error: symbol `x' not defined\n\
+            nasm: fatal: unable to open input file `none.asm'\n";
+        let diagnostics = program_loader::parse_diagnostics(stderr);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].file, "C:\\code\\prog.asm");
+        assert_eq!(diagnostics[0].line, 12);
+        assert_eq!(diagnostics[0].message, "error: symbol `x' not defined");
+        assert_eq!(diagnostics[1].line, 0);
+        assert_eq!(diagnostics[1].message, "fatal: unable to open input file `none.asm'");
+    }
+
+    #[test]
+    fn builtin_assembler() {
+        // the same bytes as nasm
+        for name in ["tests/logarithem", "tests/proc"] {
+            let source = std::fs::read_to_string(format!("{}.asm", name)).unwrap();
+            let expected = std::fs::read(name).unwrap();
+            assert_eq!(assembler::assembler(&source, name).unwrap().0, expected);
+        }
+        let diagnostics = assembler::assembler("mov ax, bx\njmp nowhere\nmov cs", "bad.asm").unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(diagnostics[0].message, "error: symbol `nowhere' not defined");
+        assert_eq!(diagnostics[1].line, 3);
+    }
+
+    #[test]
+    fn assembler_directives() {
+        let source = "org 7C00h\n\
+            %define twice(x) ((x)*2)\n\
+            %macro load 2\n\
+            %%top: mov %1, %2\n\
+            jmp %%top\n\
+            %endmacro\n\
+            count equ 1 + 2 * 3 << 1\n\
+            load ax, count\n\
+            load bl, twice(2) | 1\n\
+            align 4\n\
+            dw $ - $$, -7 // 2\n\
+            times 510-($-$$) db 0\n\
+            dw 0AA55h";
+        let (bytes, _) = assembler::assembler(source, "boot.asm").unwrap();
+        assert_eq!(bytes.len(), 512);
+        assert_eq!(bytes[..16], [0xB8, 0x0E, 0x00, 0xEB, 0xFB, 0xB3, 0x05, 0xEB, 0xFC,
+            0x90, 0x90, 0x90, 0x0C, 0x00, 0xFD, 0xFF]);
+        assert_eq!(bytes[510..], [0x55, 0xAA]);
+    }
+
+    #[test]
+    fn listing_and_symbols() {
+        let source = "org 100h\nstart: mov ax, 1\n.loop: jmp .loop\nmsg db 'hi'";
+        let (_, info) = assembler::assembler(source, "prog.asm").unwrap();
+        assert_eq!(info.symbol("start"), Some(0x100));
+        assert_eq!(info.symbol("start.loop"), Some(0x103));
+        assert_eq!(info.symbol("msg"), Some(0x105));
+        assert_eq!(info.label_at(0x100), Some("start"));
+        // the program loaded at 2000:0000 has its labels there, not at their `org` address
+        let mut loaded = info.clone();
+        loaded.segment = 0x2000;
+        loaded.offset = 0;
+        assert_eq!(loaded.symbol_location("msg"), Some((0x2000, 5)));
+        assert_eq!(loaded.address(0x2000, 5), Some(0x105));
+        // the debugger shows a label where the program is, not in the current data segment
+        let mut cpu = test_cpu();
+        cpu.memory_unit.memory.data_segment = 0x1000;
+        cpu.memory_unit.memory.write(&vec![b'h', b'i'], (0x2000, 5));
+        let mut debugger = debugger::Debugger::new();
+        debugger.set_debug_info(Some(loaded.clone()));
+        assert_eq!(debugger.echo(&mut cpu, vec!["echo", "ds:msg", "-l", "2"]),
+            Ok("\t00\t01\t\n00\t0x68\t0x69\t".to_string()));
+        let line = info.line_at(0x104).unwrap();
+        assert_eq!((line.line, line.source.as_str()), (3, ".loop: jmp .loop"));
+        assert_eq!(debug_info::jump_target(&line.bytes, 0x103), Some(0x103));
+
+        let listing = "     1                                  org 100h\n\
+            \x20    2 00000000 BB0500                  mov bx, 5\n\
+            \x20    3                                  start:\n\
+            \x20    4 00000003 EBFE                    jmp start\n\
+            \x20    5                                  section .data\n\
+            \x20    6 00000000 68656C6C6F20776F72-     msg db \"hello world\"\n\
+            \x20    6 00000009 6C64               \n\
+            \x20    7 0000000B 00<rep 4h>              times 4 db 0\n";
+        let info = debug_info::DebugInfo::from_nasm_listing(listing, "prog.asm");
+        assert_eq!(info.symbol("start"), Some(0x103));
+        assert_eq!(info.symbol("msg"), Some(0x108));
+        assert_eq!(info.line_at(0x104).unwrap().line, 4);
+        assert_eq!(info.line_at(0x10A).unwrap().bytes.len(), 11);
+        assert_eq!(info.line_at(0x113).unwrap().bytes, vec![0; 4]);
+    }
+
+    #[test]
+    fn com_loader() {
+        let mut cpu = test_cpu();
+        // read the first character of the command tail and return to the PSP
+        let (program, _) = assembler::assembler("org 100h\nmov al, [82h]\nret", "prog.com").unwrap();
+        program_loader::dos::install_dos(&mut cpu.memory_unit.memory);
+        assert_eq!(program_loader::dos::load_com(&mut cpu.memory_unit, &program, Some(0x1234), "hello world"), Ok(0x1234));
+
+        let memory = &cpu.memory_unit.memory;
+        assert_eq!(memory.read((0x1234, 0), 4), vec![0xCD, 0x20, 0x00, 0xA0]);
+        assert_eq!(memory.read((0x1234, 0x80), 14), b"\x0C hello world\x0D".to_vec());
+        // the PSP is at physical address 0x12340
+        assert_eq!(memory[(0x1000, 0x2340)], 0xCD);
+        assert_eq!((memory.code_segment, memory.data_segment, memory.stack_segment), (0x1234, 0x1234, 0x1234));
+        assert_eq!((cpu.memory_unit.ip, cpu.memory_unit.sp), (0x100, 0xFFFE));
+
+        // `ret` pops the zero word, runs INT 20h and halts
+        let mut last = Step::Waiting;
+        for _ in 0..10 {
+            last = cpu.run_next_instruction().unwrap();
+            if last == Step::Ran(0xF4) {
+                break
+            }
+        }
+        assert_eq!(last, Step::Ran(0xF4));
+        assert_eq!(cpu.memory_unit.ax & 0xFF, b'h' as u16);
+        assert!(program_loader::dos::build_psp(0x1234, 0xA000, &"x".repeat(126)).is_err());
+        // the memory DOS doesn't manage is a segment long above 640K, and ends at 640K below the first block
+        program_loader::dos::load_com(&mut cpu.memory_unit, &program, Some(0xB000), "").unwrap();
+        assert_eq!((cpu.memory_unit.memory.read((0xB000, 2), 2), cpu.memory_unit.sp), (vec![0x00, 0xC0], 0xFFFE));
+        program_loader::dos::load_com(&mut cpu.memory_unit, &program, Some(0x0800), "").unwrap();
+        assert_eq!(cpu.memory_unit.memory.read((0x0800, 2), 2), vec![0x00, 0xA0]);
+    }
+
+    #[test]
+    fn exe_loader() {
+        let mut cpu = test_cpu();
+        // two paragraphs of header with one relocation, then the code segment and the data segment
+        let mut exe = vec![0u8; 0x20];
+        let header: [u16; 14] = [0x5A4D, 0x31, 1, 1, 2, 0x10, 0x20, 2, 0x100, 0, 0, 0, 0x1C, 0];
+        for (i, word) in header.iter().enumerate() {
+            exe[i * 2..i * 2 + 2].copy_from_slice(&word.to_le_bytes());
+        }
+        // the relocation points at the segment in `mov ax, data`
+        exe[0x1C..0x20].copy_from_slice(&[1, 0, 0, 0]);
+        // mov ax, data; mov ds, ax; mov al, [0]; hlt
+        exe.extend([0xB8, 0x01, 0x00, 0x8E, 0xD8, 0xA0, 0x00, 0x00, 0xF4]);
+        exe.resize(0x30, 0);
+        exe.push(0x42);
+        let program = program_loader::dos::ExeHeader::parse(&exe).unwrap();
+        assert_eq!((program.relocations, program.min_alloc), (1, 0x10));
+        program_loader::dos::install_dos(&mut cpu.memory_unit.memory);
+        program_loader::dos::load_exe(&mut cpu.memory_unit, &exe, Some(0x2000), "").unwrap();
+
+        let memory = &cpu.memory_unit.memory;
+        // the load module is after the PSP, the program owns 2 paragraphs and max_alloc more
+        assert_eq!(memory.read((0x2000, 2), 2), vec![0x32, 0x20]);
+        assert_eq!(memory.read((0x2010, 1), 2), vec![0x11, 0x20]);
+        assert_eq!((memory.code_segment, memory.stack_segment, memory.data_segment), (0x2010, 0x2012, 0x2000));
+        assert_eq!((cpu.memory_unit.ip, cpu.memory_unit.sp), (0, 0x100));
+        for _ in 0..4 {
+            cpu.run_next_instruction().unwrap();
+        }
+        assert_eq!(cpu.memory_unit.ax, 0x2042);
+        assert!(program_loader::dos::load_exe(&mut cpu.memory_unit, &exe, Some(0x9FF0), "").is_err());
+    }
+
+    #[test]
+    fn record_files() {
+        use program_loader::image::*;
+        // an extended segment address moves the data to F000:0000, then a start segment address
+        let hex = ":020000021000EC\n:03000000B83412FF\n:0400000300001000E9\n:00000001FF\n";
+        let records = parse_intel_hex(hex).unwrap();
+        assert_eq!(records.blocks, vec![Block { address: 0x10000, bytes: vec![0xB8, 0x34, 0x12] }]);
+        assert_eq!(records.start, Some((0x0000, 0x1000)));
+        assert_eq!(parse_intel_hex(":03000000B8341201\n").unwrap_err().0, 1);
+
+        let srec = "S00600004844521B\nS1060100B83412FA\nS9030100FB\n";
+        let records = parse_srecord(srec).unwrap();
+        assert_eq!(records.blocks, vec![Block { address: 0x100, bytes: vec![0xB8, 0x34, 0x12] }]);
+        assert_eq!(records.start, Some((0x10, 0)));
+        assert!(parse_srecord("S1060100B8341200\n").is_err());
+
+        let mut memory = MemorySegments::new();
+        write_blocks(&mut memory, &records.blocks, Some((0x2000, 0)));
+        assert_eq!(memory.read((0x2010, 0), 3), vec![0xB8, 0x34, 0x12]);
+        assert_eq!(Image::from("table.bin@0x2000:10h").unwrap().location, Some((0x2000, 0x10)));
+        assert!(Image::from("table.bin").is_err());
+    }
+
+    #[test]
+    fn dos_console() {
+        let mut cpu = test_cpu();
+        let source = "org 100h\n\
+            mov ah, 9\nmov dx, msg\nint 21h\n\
+            mov ah, 0Ah\nmov dx, buf\nint 21h\n\
+            mov ah, 2\nmov dl, [buf + 2]\nint 21h\n\
+            mov ax, 4C07h\nint 21h\n\
+            msg db 'name? $'\nbuf db 5, 0\ntimes 5 db 0";
+        let (program, _) = assembler::assembler(source, "prog.asm").unwrap();
+        program_loader::dos::install_dos(&mut cpu.memory_unit.memory);
+        program_loader::dos::load_com(&mut cpu.memory_unit, &program, None, "").unwrap();
+        let console = dos::console::SharedConsole::new();
+        cpu.services.push(Box::new(dos::Dos::new(Box::new(console.clone()), std::env::temp_dir(), 0x1000)));
+
+        // the buffered input waits at the INT until keys are typed
+        for _ in 0..10 {
+            cpu.run_next_instruction().unwrap();
+        }
+        assert_eq!(cpu.memory_unit.ip, 0x10C);
+        assert_eq!(console.output(), "name? ");
+        console.type_text("bobby\n");
+        let mut last = Step::Waiting;
+        for _ in 0..10 {
+            last = cpu.run_next_instruction().unwrap();
+            if last == Step::Ran(0xF4) {
+                break
+            }
+        }
+        assert_eq!((last, cpu.exit_code), (Step::Ran(0xF4), Some(7)));
+        // the buffer holds 4 characters and the carriage return
+        assert_eq!(cpu.memory_unit.memory.read((0x1000, 0x122), 7), b"\x05\x04bobb\r".to_vec());
+        assert_eq!(console.output(), "name? bobbb");
+
+        // a program that hooked INT 21h gets the functions DOS doesn't have too
+        let memory = &mut cpu.memory_unit.memory;
+        memory.set_interrupt_vector(0x21, (0x2000, 0));
+        // mov bl, 42h; iret, called by mov ah, 0FFh; int 21h
+        memory.write(&vec![0xB3, 0x42, 0xCF], (0x2000, 0));
+        memory.write(&vec![0xB4, 0xFF, 0xCD, 0x21], (0x3000, 0));
+        memory.code_segment = 0x3000;
+        cpu.memory_unit.ip = 0;
+        for _ in 0..4 {
+            cpu.run_next_instruction().unwrap();
+        }
+        assert_eq!((cpu.memory_unit.bx & 0xFF, cpu.memory_unit.ip), (0x42, 4));
+    }
+
+    #[test]
+    fn dos_files() {
+        let root = std::env::temp_dir().join(format!("remu-drive-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        let mut cpu = test_cpu();
+        let source = "org 100h\n\
+            mov ah, 3Ch\nmov cx, 0\nmov dx, file\nint 21h\nmov bx, ax\n\
+            mov ah, 40h\nmov cx, 5\nmov dx, msg\nint 21h\n\
+            mov ah, 3Eh\nint 21h\n\
+            mov ax, 3D00h\nmov dx, file\nint 21h\nmov bx, ax\n\
+            mov ax, 4201h\nmov cx, 0\nmov dx, 1\nint 21h\n\
+            mov ah, 3Fh\nmov cx, 10\nmov dx, buf\nint 21h\n\
+            mov ah, 4Eh\nmov cx, 0\nmov dx, pattern\nint 21h\n\
+            mov ah, 3Dh\nmov dx, escape\nint 21h\n\
+            mov ax, 4C00h\nint 21h\n\
+            file db 'c:\\SUB\\data.txt', 0\npattern db 'sub\\*.TXT', 0\n\
+            escape db '..\\secret', 0\nmsg db 'hello'\nbuf times 10 db 0";
+        let (program, _) = assembler::assembler(source, "prog.asm").unwrap();
+        program_loader::dos::install_dos(&mut cpu.memory_unit.memory);
+        program_loader::dos::load_com(&mut cpu.memory_unit, &program, None, "").unwrap();
+        cpu.services.push(Box::new(dos::Dos::new(Box::new(dos::console::SharedConsole::new()), root.clone(), 0x1000)));
+        for _ in 0..100 {
+            if cpu.run_next_instruction().unwrap() == Step::Ran(0xF4) {
+                break
+            }
+        }
+        assert_eq!(cpu.exit_code, Some(0));
+        assert_eq!(std::fs::read(root.join("sub").join("data.txt")).unwrap(), b"hello".to_vec());
+        // the read starts after the seek
+        let buf = program.len() as u16 + 0x100 - 10;
+        assert_eq!(cpu.memory_unit.memory.read((0x1000, buf), 5), b"ello\0".to_vec());
+        // find first writes the entry to the DTA of the PSP
+        assert_eq!(cpu.memory_unit.memory.read((0x1000, 0x80 + 0x1A), 4), vec![5, 0, 0, 0]);
+        assert_eq!(cpu.memory_unit.memory.read((0x1000, 0x80 + 0x1E), 9), b"DATA.TXT\0".to_vec());
+        // paths can't leave the directory of drive C:
+        let files = dos::files::FileSystem::new(root.clone());
+        assert_eq!(files.resolve("..\\secret"), Err(dos::files::ERROR_ACCESS_DENIED));
+        assert_eq!(files.resolve("d:\\file"), Err(dos::files::ERROR_PATH_NOT_FOUND));
+        assert_eq!(files.resolve("sub\\..\\SUB\\DATA.TXT"), Ok(root.join("sub").join("data.txt")));
+        // a link to a file that doesn't exist yet would create it out of the drive
+        #[cfg(unix)]
+        {
+            let outside = std::env::temp_dir().join(format!("remu-outside-{}", std::process::id()));
+            std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+            assert_eq!(files.resolve("LINK"), Err(dos::files::ERROR_ACCESS_DENIED));
+            assert!(!outside.exists());
+        }
+        assert_eq!(dos::files::FileSystem::new(root.join("missing")).resolve("file"), Err(dos::files::ERROR_ACCESS_DENIED));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn dos_exec() {
+        let root = std::env::temp_dir().join(format!("remu-exec-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        // the child returns the first character of its arguments
+        let (child, _) = assembler::assembler("org 100h\nmov al, [82h]\nmov ah, 4Ch\nint 21h", "child.asm").unwrap();
+        std::fs::write(root.join("child.com"), &child).unwrap();
+        let mut cpu = test_cpu();
+        // the parent keeps 64K, allocates and frees a block, then runs the child
+        let source = "org 100h\n\
+            mov ah, 4Ah\nmov bx, 1000h\nint 21h\n\
+            mov ah, 48h\nmov bx, 10h\nint 21h\nmov [block], ax\n\
+            mov es, ax\nmov ah, 49h\nint 21h\n\
+            run: mov ax, cs\nmov es, ax\nmov [params + 4], ax\n\
+            mov bx, params\nmov dx, child\nmov ax, 4B00h\nint 21h\n\
+            mov ah, 4Dh\nint 21h\nmov ah, 4Ch\nint 21h\n\
+            child db 'CHILD.COM', 0\ntail db 3, ' xy', 13\n\
+            params dw 0, tail, 0, 0, 0, 0, 0\nblock dw 0";
+        let (program, info) = assembler::assembler(source, "parent.asm").unwrap();
+        program_loader::dos::install_dos(&mut cpu.memory_unit.memory);
+        let psp = program_loader::dos::load_com(&mut cpu.memory_unit, &program, None, "").unwrap();
+        assert_eq!(psp, program_loader::dos::PSP_SEGMENT);
+        cpu.services.push(Box::new(dos::Dos::new(Box::new(dos::console::SharedConsole::new()), root.clone(), psp)));
+        for _ in 0..100 {
+            if cpu.run_next_instruction().unwrap() == Step::Ran(0xF4) {
+                break
+            }
+        }
+        assert_eq!(cpu.exit_code, Some(b'x'));
+        // the block is after the 64K of the parent and its control block
+        let block = program.len() as u16 + 0x100 - 2;
+        assert_eq!(cpu.memory_unit.memory.read((psp, block), 2), 0x2001u16.to_le_bytes().to_vec());
+        // the memory of the child is free again
+        let blocks = dos::memory::blocks(&cpu.memory_unit.memory).unwrap();
+        assert_eq!(blocks.iter().map(|mcb| (mcb.owner, mcb.size)).collect::<Vec<_>>(), vec![(psp, 0x1000), (0, 0x7FFF)]);
+        assert_eq!(dos::memory::free(&mut cpu.memory_unit.memory, 0x2001), Err(dos::memory::ERROR_INVALID_BLOCK));
+
+        // a child that destroys its control block can't be freed when it ends
+        let (trash, _) = assembler::assembler("org 100h\nmov ax, cs\ndec ax\nmov ds, ax\nmov byte [0], 0\n\
+            mov ah, 4Ch\nint 21h", "trash.asm").unwrap();
+        std::fs::write(root.join("trash.com"), &trash).unwrap();
+        cpu.memory_unit.memory.write(&b"TRASH.COM".to_vec(), (psp, info.symbol("child").unwrap()));
+        cpu.memory_unit.ip = info.symbol("run").unwrap();
+        let result = (0..100).map(|_| cpu.run_next_instruction()).find(|result| result.is_err());
+        assert_eq!(result, Some(Err("the memory control blocks are destroyed, the memory of the program at 2001 can't be freed".to_string())));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn bios_services() {
+        let mut cpu = test_cpu();
+        let source = "org 100h\n\
+            mov ah, 0Eh\nmov al, 'H'\nint 10h\nmov al, 'i'\nint 10h\n\
+            mov ah, 3\nmov bh, 0\nint 10h\nmov [results], dx\n\
+            int 11h\nmov [results + 2], ax\nint 12h\nmov [results + 4], ax\n\
+            mov ah, 0\nint 16h\nmov [results + 6], ax\n\
+            mov ah, 2\nmov dx, 0\nint 17h\nmov [results + 8], ax\n\
+            mov ax, 4C00h\nint 21h\n\
+            results dw 0, 0, 0, 0, 0";
+        let (program, _) = assembler::assembler(source, "prog.asm").unwrap();
+        program_loader::dos::install_dos(&mut cpu.memory_unit.memory);
+        bios::install_bios(&mut cpu.memory_unit.memory);
+        program_loader::dos::load_com(&mut cpu.memory_unit, &program, None, "").unwrap();
+        let console = dos::console::SharedConsole::new();
+        cpu.services.push(Box::new(bios::Bios::new(Box::new(console.clone()))));
+        cpu.services.push(Box::new(dos::Dos::new(Box::new(console.clone()), std::env::temp_dir(), 0x1000)));
+
+        // INT 16h waits for a key
+        for _ in 0..20 {
+            cpu.run_next_instruction().unwrap();
+        }
+        assert_eq!(console.output(), "Hi");
+        console.type_text("a");
+        let mut last = Step::Waiting;
+        for _ in 0..10 {
+            last = cpu.run_next_instruction().unwrap();
+            if last == Step::Ran(0xF4) {
+                break
+            }
+        }
+        assert_eq!(last, Step::Ran(0xF4));
+        let memory = &mut cpu.memory_unit.memory;
+        assert_eq!(memory.read((0xB800, 0), 4), vec![b'H', 0x07, b'i', 0x07]);
+        let results = program.len() as u16 + 0x100 - 10;
+        assert_eq!(memory.read((0x1000, results), 8), vec![0x02, 0x00, 0x20, 0x00, 0x80, 0x02, b'a', 0x1E]);
+        // there is no printer, INT 17h times out
+        assert_eq!(memory.read((0x1000, results + 8), 2), vec![b'a', 0x01]);
+
+        // a new line on the last row scrolls the screen up
+        bios::video::set_cursor(memory, 0, 1, 0);
+        bios::video::teletype(memory, 0, b'X', Some(0x1F));
+        bios::video::set_cursor(memory, 0, 24, 0);
+        bios::video::teletype(memory, 0, b'\n', None);
+        assert_eq!(memory.read((0xB800, 0), 4), vec![b'X', 0x1F, b' ', 0x07]);
+        assert_eq!(bios::video::cursor(memory, 0), (24, 0));
+    }
+
+    #[test]
+    fn bios_roms() {
+        let mut cpu = test_cpu();
+        // an option ROM of 512 bytes, its init entry point writes to 0000:04F0
+        let (init, _) = assembler::assembler("db 55h, 0AAh, 1\npush ds\nxor ax, ax\nmov ds, ax\n\
+            mov byte [4F0h], 42h\npop ds\nretf", "rom.asm").unwrap();
+        let mut rom = init.clone();
+        rom.resize(512, 0);
+        rom[511] = 0u8.wrapping_sub(rom.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)));
+        let memory = &mut cpu.memory_unit.memory;
+        memory.write(&rom, (0xD000, 0));
+        // a bad checksum isn't a ROM
+        rom[511] ^= 1;
+        memory.write(&rom, (0xE000, 0));
+        assert_eq!(bios::option_roms(memory), vec![0xD000]);
+
+        // the program tries to write over the BIOS ROM
+        let (program, _) = assembler::assembler("mov ax, 0F000h\nmov es, ax\nmov word [es:0FFF0h], 1234h\nhlt", "prog.asm").unwrap();
+        memory.write(&program, (0x2000, 0));
+        memory.data_segment = 0x2000;
+        memory.stack_segment = 0x2000;
+        assert_eq!(memory.load_rom(&vec![0xF4; 0x1000]), Ok(0xFF000));
+        memory.write(&vec![0], (0xFF00, 0x10));
+        assert!(memory.load_rom(&vec![0; 0x40001]).is_err());
+        // a reset vector elsewhere only starts the cpu there, this one goes on to the reset code of the BIOS
+        memory.write(&vec![0xEA, 0x5B, 0xE0, 0x00, 0xF0], (0x3000, 0));
+        bios::write_reset(memory, (0x2000, 0), &[0xD000]);
+        cpu.set_reset_vector((0x3000, 0));
+        for _ in 0..40 {
+            if cpu.run_next_instruction().unwrap() == Step::Ran(0xF4) {
+                break
+            }
+        }
+        let memory = &cpu.memory_unit.memory;
+        assert_eq!(memory[(0, 0x4F0)], 0x42);
+        assert_eq!((memory.code_segment, memory.data_segment), (0x2000, 0x2000));
+        assert_eq!(memory.read((0xF000, 0xFFF0), 2), vec![0xF4, 0xF4]);
+        assert_eq!(memory[(0xFF00, 0x10)], 0xF4);
+        assert_eq!(memory[(0x3000, 0)], 0xEA);
+    }
+
+    #[test]
+    fn video_text_mode() {
+        use hardware::video::{Video, text::cp437};
+        let mut video = Video::new();
+        let mut ports = vec![0u8; PORTS];
+        // the cursor address is CRTC registers 0Eh and 0Fh
+        for (port, value) in [(0x3D4, 0x0E), (0x3D5, 0x01), (0x3D4, 0x0F), (0x3D5, 0x50)] {
+            ports[port] = value;
+            video.write_hardware(port as u16, &mut ports);
+        }
+        ports[0x3D5] = 0;
+        video.read_hardware(0x3D5, &mut ports);
+        assert_eq!(ports[0x3D5], 0x50);
+        // the status register changes between reads
+        video.read_hardware(0x3DA, &mut ports);
+        let status = ports[0x3DA];
+        video.read_hardware(0x3DA, &mut ports);
+        assert_ne!(ports[0x3DA], status);
+        assert_eq!([cp437(0x01), cp437(b'A'), cp437(0xC9), cp437(0xDB), cp437(0xFE)], ['☺', 'A', '╔', '█', '■']);
+    }
+
+    #[test]
+    fn video_graphics_modes() {
+        use hardware::video::Video;
+        use eframe::egui::Color32;
+        let mut memory = MemorySegments::new();
+        let mut video = Video::new();
+        let mut ports = vec![0u8; PORTS];
+        // mode 04h with the cyan, magenta and white palette on a blue background
+        bios::video::set_mode(&mut memory, 4).unwrap();
+        bios::video::set_palette(&mut memory, 1, 1);
+        bios::video::set_palette(&mut memory, 0, 1);
+        port_out(&mut video, &mut ports, 0x3D8, &[bios::bda_byte(&memory, bios::BDA_MODE_CONTROL)]);
+        port_out(&mut video, &mut ports, 0x3D9, &[bios::bda_byte(&memory, bios::BDA_PALETTE)]);
+        bios::video::write_pixel(&mut memory, 1, 0, 3);
+        bios::video::write_pixel(&mut memory, 5, 1, 2);
+        bios::video::write_pixel(&mut memory, 5, 1, 0x83);
+        assert_eq!(bios::video::read_pixel(&memory, 5, 1), 1);
+        // the odd lines are 8K after the even ones
+        assert_eq!(memory.read((0xB800, 0), 1), vec![0x30]);
+        assert_eq!(memory.read((0xB800, 0x2001), 1), vec![0x10]);
+        let picture = video.picture(&memory).unwrap();
+        assert_eq!(picture.image.size, [320, 200]);
+        let pixels = &picture.image.pixels;
+        assert_eq!((pixels[0], pixels[1], pixels[320 + 5]),
+            (Color32::from_rgb(0, 0, 0xAA), Color32::from_rgb(0xAA, 0xAA, 0xAA), Color32::from_rgb(0, 0xAA, 0xAA)));
+
+        // mode 06h draws in the color of the color select register
+        bios::video::set_mode(&mut memory, 6).unwrap();
+        port_out(&mut video, &mut ports, 0x3D8, &[bios::bda_byte(&memory, bios::BDA_MODE_CONTROL)]);
+        port_out(&mut video, &mut ports, 0x3D9, &[0x0E]);
+        bios::video::write_pixel(&mut memory, 639, 199, 1);
+        let picture = video.picture(&memory).unwrap();
+        assert_eq!(picture.image.size, [640, 200]);
+        assert_eq!(picture.image.pixels[640 * 200 - 2..], [Color32::BLACK, Color32::from_rgb(0xFF, 0xFF, 0x55)]);
+    }
+
+    #[test]
+    fn vga_mode_13() {
+        use hardware::video::Video;
+        use eframe::egui::Color32;
+        let mut memory = MemorySegments::new();
+        let mut video = Video::new();
+        let mut ports = vec![0u8; PORTS];
+        let dac = bios::video::default_dac();
+        assert_eq!((dac.len(), dac[6], dac[31], dac[32], dac[255]), (256, [0x2A, 0x15, 0], [0x3F; 3], [0, 0, 0x3F], [0; 3]));
+        bios::video::set_mode(&mut memory, 0x13).unwrap();
+        bios::video::write_pixel(&mut memory, 319, 0, 0x81);
+        bios::video::write_pixel(&mut memory, 0, 1, 0x0F);
+        assert_eq!(bios::video::read_pixel(&memory, 319, 0), 0x81);
+        assert_eq!(memory.read((0xA000, 320), 1), vec![0x0F]);
+        // the status register resets the attribute controller, then its mode control register selects 256 colors
+        video.read_hardware(0x3DA, &mut ports);
+        // entry 81h becomes 3Fh, 20h, 00h and is read back from 3C9h
+        for (port, value) in [(0x3C0, 0x10), (0x3C0, 0x41), (0x3C0, 0x20), (0x3C8, 0x81), (0x3C9, 0x3F), (0x3C9, 0x20), (0x3C9, 0), (0x3C7, 0x81)] {
+            ports[port] = value;
+            video.write_hardware(port as u16, &mut ports);
+        }
+        let mut color = Vec::new();
+        for _ in 0..3 {
+            video.read_hardware(0x3C9, &mut ports);
+            color.push(ports[0x3C9]);
+        }
+        assert_eq!(color, vec![0x3F, 0x20, 0]);
+        video.read_hardware(0x3C7, &mut ports);
+        assert_eq!(ports[0x3C7], 3);
+        let picture = video.picture(&memory).unwrap();
+        assert_eq!(picture.image.size, [320, 200]);
+        let pixels = &picture.image.pixels;
+        assert_eq!((pixels[0], pixels[319], pixels[320]),
+            (Color32::BLACK, Color32::from_rgb(0xFF, 0x82, 0), Color32::from_rgb(0xFF, 0xFF, 0xFF)));
+    }
+
+    #[test]
+    fn pit_counters() {
+        use hardware::{IrqLines, pit::Pit};
+        let irq = IrqLines::new();
+        let mut pit = Pit::new(irq.clone());
+        let mut ports = vec![0u8; PORTS];
+        // a clock of the timer is 4 cycles, the BIOS left counter 0 making a square wave
+        pit.tick(4, &mut ports);
+        assert_eq!((irq.levels(), irq.take_edges(), irq.take_edges()), (1, 1, 0));
+        // mode 0 with a count of 10 is low until the count runs out
+        port_out(&mut pit, &mut ports, 0x43, &[0x30]);
+        port_out(&mut pit, &mut ports, 0x40, &[10]);
+        port_out(&mut pit, &mut ports, 0x40, &[0]);
+        assert_eq!(irq.levels(), 0);
+        pit.tick(4 * 5, &mut ports);
+        port_out(&mut pit, &mut ports, 0x43, &[0x00]);
+        pit.tick(4, &mut ports);
+        assert_eq!((port_in(&mut pit, &mut ports, 0x40), port_in(&mut pit, &mut ports, 0x40)), (6, 0));
+        pit.tick(4 * 4, &mut ports);
+        assert_eq!(irq.levels(), 0);
+        pit.tick(4, &mut ports);
+        assert_eq!((irq.levels(), irq.take_edges()), (1, 1));
+        // mode 3 with an odd count is high a clock longer than low
+        port_out(&mut pit, &mut ports, 0x43, &[0x36]);
+        port_out(&mut pit, &mut ports, 0x40, &[5]);
+        port_out(&mut pit, &mut ports, 0x40, &[0]);
+        let mut wave = Vec::new();
+        for _ in 0..11 {
+            pit.tick(4, &mut ports);
+            wave.push(irq.levels());
+        }
+        assert_eq!(wave, vec![1, 1, 1, 0, 0, 1, 1, 1, 0, 0, 1]);
+        // counter 2 in mode 2 counting 1000 in BCD, read back with its status
+        port_out(&mut pit, &mut ports, 0x43, &[0xB5]);
+        port_out(&mut pit, &mut ports, 0x42, &[0x00]);
+        port_out(&mut pit, &mut ports, 0x42, &[0x10]);
+        pit.tick(4 * 2, &mut ports);
+        port_out(&mut pit, &mut ports, 0x43, &[0xC8]);
+        pit.tick(4, &mut ports);
+        let read_back: Vec<u8> = (0..3).map(|_| port_in(&mut pit, &mut ports, 0x42)).collect();
+        assert_eq!(read_back, vec![0xB5, 0x99, 0x09]);
+        // a one-shot of 3 on counter 2 triggered by its gate, bit 0 of port 61h
+        port_out(&mut pit, &mut ports, 0x43, &[0x92]);
+        port_out(&mut pit, &mut ports, 0x42, &[3]);
+        port_out(&mut pit, &mut ports, 0x61, &[0]);
+        port_out(&mut pit, &mut ports, 0x61, &[1]);
+        let mut pulse = Vec::new();
+        for _ in 0..5 {
+            pit.tick(4, &mut ports);
+            pulse.push(port_in(&mut pit, &mut ports, 0x61) & 0x21);
+        }
+        assert_eq!(pulse, vec![0x01, 0x01, 0x01, 0x21, 0x21]);
+    }
+
+    #[test]
+    fn pic_interrupts() {
+        use hardware::{IrqLines, pic::Pic};
+        use cpu::InterruptController;
+        let irq = IrqLines::new();
+        let mut pic = Pic::new(irq.clone());
+        let mut ports = vec![0u8; PORTS];
+        // ICW1-ICW4 with the vectors at 08h, then only IRQ1 unmasked
+        for (port, value) in [(0x20, 0x13), (0x21, 0x08), (0x21, 0x01), (0x21, 0xFD)] {
+            port_out(&mut pic, &mut ports, port, &[value]);
+        }
+        irq.raise(0);
+        irq.raise(1);
+        assert_eq!(pic.acknowledge(), Some(0x09));
+        port_out(&mut pic, &mut ports, 0x20, &[0x0B]);
+        assert_eq!(port_in(&mut pic, &mut ports, 0x20), 0x02);
+        port_out(&mut pic, &mut ports, 0x20, &[0x0A]);
+        assert_eq!(port_in(&mut pic, &mut ports, 0x20), 0x01);
+        // IRQ0 has a higher priority than the IRQ1 in service, the EOI ends it first
+        port_out(&mut pic, &mut ports, 0x21, &[0x00]);
+        assert_eq!(port_in(&mut pic, &mut ports, 0x21), 0x00);
+        assert_eq!(pic.acknowledge(), Some(0x08));
+        port_out(&mut pic, &mut ports, 0x20, &[0x20]);
+        port_out(&mut pic, &mut ports, 0x20, &[0x0B]);
+        assert_eq!(port_in(&mut pic, &mut ports, 0x20), 0x02);
+        // a line that stays up isn't a new request, and IRQ1 waits for its own EOI
+        irq.raise(1);
+        assert_eq!(pic.acknowledge(), None);
+        irq.lower(1);
+        irq.raise(1);
+        assert_eq!(pic.acknowledge(), None);
+        port_out(&mut pic, &mut ports, 0x20, &[0x61]);
+        assert_eq!(pic.acknowledge(), Some(0x09));
+        port_out(&mut pic, &mut ports, 0x20, &[0x20]);
+        // the poll command
+        irq.raise(3);
+        port_out(&mut pic, &mut ports, 0x20, &[0x0C]);
+        assert_eq!(port_in(&mut pic, &mut ports, 0x20), 0x83);
+        port_out(&mut pic, &mut ports, 0x20, &[0x20]);
+
+        // the cpu takes IRQ4 between instructions once IF is set
+        let mut cpu = test_cpu();
+        cpu.interrupt_controller = Some(Box::new(pic.clone()));
+        cpu.set_reset_vector((0x1000, 0));
+        cpu.memory_unit.memory.write(&vec![0xBB, 0x01, 0x00, 0xBB, 0x02, 0x00], (0x1000, 0));
+        cpu.memory_unit.memory.write(&vec![0xB8, 0x34, 0x12], (0x2000, 0));
+        cpu.memory_unit.memory.set_interrupt_vector(0x0C, (0x2000, 0));
+        cpu.memory_unit.memory.stack_segment = 0x3000;
+        cpu.memory_unit.sp = 0x100;
+        irq.raise(4);
+        cpu.run_next_instruction().unwrap();
+        assert_eq!((cpu.memory_unit.bx, cpu.memory_unit.ax), (1, 0));
+        cpu.memory_unit.flags.interrupt = true;
+        cpu.run_next_instruction().unwrap();
+        assert_eq!((cpu.memory_unit.ax, cpu.memory_unit.memory.code_segment, cpu.memory_unit.ip), (0x1234, 0x2000, 3));
+        assert!(!cpu.memory_unit.flags.interrupt);
+        // the return address and the flags with IF set are on the stack
+        assert_eq!(cpu.memory_unit.memory.read((0x3000, 0xFA), 6), vec![0x03, 0x00, 0x00, 0x10, 0x02, 0x02]);
+        port_out(&mut pic, &mut ports, 0x20, &[0x0B]);
+        assert_eq!(port_in(&mut pic, &mut ports, 0x20), 0x10);
+    }
+
+    #[test]
+    fn port_address_space() {
+        let ports = Arc::new(Mutex::new(vec![0; PORTS]));
+        let (cpu_sender, _) = channel();
+        let (_, cpu_receiver) = channel();
+        let mut cpu = CPU::init(&Vec::new(), ports.clone(), (cpu_sender, cpu_receiver), None, None);
+        cpu.set_reset_vector((0x1000, 0));
+        // MOV DX, 0FFFFh; IN AL, DX; IN AX, DX; MOV AX, 5678h; OUT DX, AX
+        cpu.memory_unit.memory.write(&vec![0xBA, 0xFF, 0xFF, 0xEC, 0xED, 0xB8, 0x78, 0x56, 0xEF], (0x1000, 0));
+        ports.lock().unwrap()[0xFFFF] = 0x34;
+        ports.lock().unwrap()[0] = 0x12;
+        cpu.run_next_instruction().unwrap();
+        cpu.run_next_instruction().unwrap();
+        assert_eq!(cpu.memory_unit.ax & 0xFF, 0x34);
+        // the high byte of a word at the last port is port 0
+        cpu.run_next_instruction().unwrap();
+        assert_eq!(cpu.memory_unit.ax, 0x1234);
+        cpu.run_next_instruction().unwrap();
+        cpu.run_next_instruction().unwrap();
+        let ports = ports.lock().unwrap();
+        assert_eq!((ports[0xFFFF], ports[0]), (0x78, 0x56));
+    }
+
+    #[test]
+    fn hlt_waits() {
+        use hardware::{IrqLines, pic::Pic};
+        use cpu::HaltPolicy;
+        let irq = IrqLines::new();
+        let mut cpu = test_cpu();
+        cpu.interrupt_controller = Some(Box::new(Pic::new(irq.clone())));
+        cpu.set_reset_vector((0x1000, 0));
+        // STI, HLT, MOV AX, 1234h
+        cpu.memory_unit.memory.write(&vec![0xFB, 0xF4, 0xB8, 0x34, 0x12], (0x1000, 0));
+        // the handler of IRQ0 is IRET
+        cpu.memory_unit.memory.write(&vec![0xCF], (0x2000, 0));
+        cpu.memory_unit.memory.set_interrupt_vector(0x08, (0x2000, 0));
+        cpu.memory_unit.memory.stack_segment = 0x3000;
+        cpu.memory_unit.sp = 0x100;
+        cpu.run_next_instruction().unwrap();
+        assert_eq!(cpu.run_next_instruction().unwrap(), Step::Waiting);
+        let cycles = cpu.cycles;
+        for _ in 0..10 {
+            assert_eq!(cpu.run_next_instruction().unwrap(), Step::Waiting);
+        }
+        assert!(cpu.halted && cpu.cycles > cycles);
+        assert_eq!(cpu.memory_unit.ip, 2);
+        // IRQ0 wakes it, the handler returns after HLT
+        irq.raise(0);
+        assert_eq!(cpu.run_next_instruction().unwrap(), Step::Ran(0xCF));
+        assert!(!cpu.halted);
+        cpu.run_next_instruction().unwrap();
+        assert_eq!(cpu.memory_unit.ax, 0x1234);
+
+        // HLT ends the program with IF clear, or with the stop policy
+        cpu.set_reset_vector((0x1000, 1));
+        cpu.memory_unit.flags.interrupt = false;
+        assert_eq!(cpu.run_next_instruction().unwrap(), Step::Ran(0xF4));
+        cpu.set_reset_vector((0x1000, 1));
+        cpu.memory_unit.flags.interrupt = true;
+        cpu.halt_policy = HaltPolicy::Stop;
+        assert_eq!(cpu.run_next_instruction().unwrap(), Step::Ran(0xF4));
+        assert!(!cpu.halted);
+
+        // the headless run counts HLT but not the steps it waits, a wait as long as the limit times out
+        cpu.set_reset_vector((0x1000, 0));
+        cpu.halt_policy = HaltPolicy::Wait;
+        assert_eq!(headless::run_cpu(&mut cpu, 50), (headless::RunStatus::Timeout, 2));
+        assert!(cpu.halted);
+    }
+
+    #[test]
+    fn timer_interrupt_timing() {
+        use computer::{Computer, HardwareList};
+        use dos::console::StdConsole;
+        use program_loader::LoadOptions;
+        let path = std::env::temp_dir().join(format!("remu-timing-{}.asm", std::process::id()));
+        // IRQ0 every 100h clocks of the timer increments BX, while CX counts down or HLT waits
+        let program = |wait: &str| format!("org 100h\ncli\nmov ax,0\nmov es,ax\nmov word [es:20h],handler\n\
+            mov [es:22h],cs\nmov al,36h\nout 43h,al\nmov al,0\nout 40h,al\nmov al,1\nout 40h,al\nmov cx,0FFFFh\n\
+            sti\n{}\nhlt\nhandler: inc bx\nmov al,20h\nout 20h,al\niret\n", wait);
+        let mut hardware = HardwareList::new();
+        hardware.timer = true;
+        hardware.pic = true;
+        let mut options = LoadOptions::new();
+        options.assembler = "builtin".to_string();
+        // the instructions that ran and the steps HLT waited when the handler took the first interrupt
+        let first_interrupt = |wait: &str| {
+            std::fs::write(&path, program(wait)).unwrap();
+            let mut computer = Computer::new(path.to_str().unwrap(), hardware, &options, Box::new(StdConsole::new())).unwrap();
+            let (mut count, mut waited) = (0, 0);
+            while computer.cpu.memory_unit.bx == 0 {
+                match computer.cpu.run_next_instruction().unwrap() {
+                    Step::Ran(_) => count += 1,
+                    Step::Waiting => waited += 1,
+                }
+            }
+            (count, waited, computer.cpu.memory_unit.cx)
+        };
+        assert_eq!(first_interrupt("l: dec cx\njnz l"), (225, 0, 0xFF96));
+        assert_eq!(first_interrupt("l: dec cx\njnz l"), first_interrupt("l: dec cx\njnz l"));
+        assert_eq!(first_interrupt("hlt"), (15, 21, 0xFFFF));
+        assert_eq!(first_interrupt("hlt"), first_interrupt("hlt"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn keyboard_scancodes() {
+        use hardware::{IrqLines, keyboard::Keyboard};
+        let irq = IrqLines::new();
+        let mut keyboard = Keyboard::new(irq.clone());
+        let mut ports = vec![0u8; PORTS];
+        // the make and break codes come one at a time, each raising IRQ1 until read
+        keyboard.type_text("A");
+        let mut scancodes = Vec::new();
+        for _ in 0..4 {
+            assert_eq!(port_in(&mut keyboard, &mut ports, 0x64) & 0x01, 0);
+            keyboard.tick(5000, &mut ports);
+            assert_eq!((port_in(&mut keyboard, &mut ports, 0x64) & 0x01, irq.levels()), (0x01, 0x02));
+            scancodes.push(port_in(&mut keyboard, &mut ports, 0x60));
+            assert_eq!(irq.levels(), 0);
+        }
+        assert_eq!(scancodes, vec![0x2A, 0x1E, 0x9E, 0xAA]);
+        // the commands of the controller reply at once, the keyboard acknowledges its own
+        for (port, command, reply) in [(0x64, 0x20, 0x45), (0x64, 0xAA, 0x55), (0x60, 0xEE, 0xEE), (0x60, 0xF4, 0xFA)] {
+            ports[port] = command;
+            keyboard.write_hardware(port as u16, &mut ports);
+            keyboard.tick(5000, &mut ports);
+            assert_eq!(port_in(&mut keyboard, &mut ports, 0x60), reply);
+        }
+
+        // the INT 9 handler of the BIOS keeps the shift state and types the characters
+        let mut memory = MemorySegments::new();
+        bios::install_bios(&mut memory);
+        let keys = [0x1E, 0x9E, 0x2A, 0x02, 0x82, 0xAA, 0x1D, 0x2E, 0x9D, 0x3A, 0x10, 0x48]
+            .iter().filter_map(|scancode| bios::keyboard::translate(&mut memory, *scancode))
+            .collect::<Vec<u16>>();
+        assert_eq!(keys, vec![0x1E61, 0x0221, 0x2E03, 0x1051, 0x4800]);
+        assert_eq!(bios::bda_byte(&memory, bios::BDA_KEYBOARD_FLAGS), 0x40);
+
+        // the keyboard alone connects the interrupt controller INT 16h waits on
+        use computer::{Computer, HardwareList};
+        let path = std::env::temp_dir().join(format!("remu-keys-{}.asm", std::process::id()));
+        std::fs::write(&path, "mov ah,0\nint 16h\nhlt\n").unwrap();
+        let mut hardware = HardwareList::new();
+        hardware.keyboard = true;
+        let mut options = program_loader::LoadOptions::new();
+        options.assembler = "builtin".to_string();
+        let mut computer = Computer::new(path.to_str().unwrap(), hardware, &options, Box::new(dos::console::StdConsole::new())).unwrap();
+        computer.keyboard.as_ref().unwrap().type_text("x");
+        assert_eq!(headless::run_cpu(&mut computer.cpu, 200000).0, headless::RunStatus::Halted);
+        assert_eq!(computer.cpu.memory_unit.ax, 0x2D78);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn pc_speaker() {
+        use hardware::{IrqLines, pit::Pit, speaker::{Speaker, wav, SAMPLE_RATE}};
+        let mut pit = Pit::new(IrqLines::new());
+        let speaker = Speaker::new();
+        pit.speaker = Some(speaker.clone());
+        let mut ports = vec![0u8; PORTS];
+        // counter 2 makes a 1 kHz square wave, the speaker is silent until port 61h lets it through
+        port_out(&mut pit, &mut ports, 0x43, &[0xB6]);
+        port_out(&mut pit, &mut ports, 0x42, &[0xA9]);
+        port_out(&mut pit, &mut ports, 0x42, &[0x04]);
+        pit.tick(4 * 11932, &mut ports);
+        port_out(&mut pit, &mut ports, 0x61, &[0x03]);
+        pit.tick(4 * 11932, &mut ports);
+        let samples = speaker.samples();
+        assert_eq!(samples.len(), 2 * SAMPLE_RATE as usize / 100);
+        assert!(samples[..441].iter().all(|sample| *sample == 0x40));
+        // 10 periods of 44 samples, half of them out
+        let rising = samples[441..].windows(2).filter(|pair| pair[0] == 0x40 && pair[1] > 0x40).count();
+        assert!(rising >= 9 && rising <= 10);
+        let high = samples[441..].iter().filter(|sample| **sample == 0xC0).count();
+        assert!(high > 200 && high < 230);
+        let file = wav(&samples);
+        assert_eq!((&file[..4], &file[8..16], file.len()), (&b"RIFF"[..], &b"WAVEfmt "[..], 44 + samples.len()));
+        assert_eq!(u32::from_le_bytes([file[24], file[25], file[26], file[27]]), 44100);
+    }
+
+    #[test]
+    fn serial_uart() {
+        use hardware::{IrqLines, serial::{Uart, COM1, host::SerialHost}};
+        struct TestHost(Arc<Mutex<Vec<u8>>>, Vec<u8>);
+        impl SerialHost for TestHost {
+            fn write(&mut self, byte: u8) { self.0.lock().unwrap().push(byte) }
+            fn read(&mut self) -> Option<u8> { if self.1.is_empty() { None } else { Some(self.1.remove(0)) } }
+            fn describe(&self) -> String { String::from("test") }
+        }
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let irq = IrqLines::new();
+        let mut uart = Uart::new(COM1, irq.clone(), Box::new(TestHost(sent.clone(), b"xy".to_vec())));
+        let mut ports = vec![0u8; PORTS];
+        // 115200 baud, 8N1: a character is 10 bits, 414 cycles
+        port_out(&mut uart, &mut ports, 0x3FB, &[0x83]);
+        port_out(&mut uart, &mut ports, 0x3F8, &[1]);
+        port_out(&mut uart, &mut ports, 0x3F9, &[0]);
+        assert_eq!(port_in(&mut uart, &mut ports, 0x3F8), 1);
+        port_out(&mut uart, &mut ports, 0x3FB, &[0x03]);
+        assert_eq!(port_in(&mut uart, &mut ports, 0x3FE) & 0xB0, 0xB0);
+        port_out(&mut uart, &mut ports, 0x3F8, &[b'A']);
+        assert_eq!(port_in(&mut uart, &mut ports, 0x3FD) & 0x60, 0x20);
+        uart.tick(400, &mut ports);
+        assert!(sent.lock().unwrap().is_empty());
+        uart.tick(20, &mut ports);
+        assert_eq!(*sent.lock().unwrap(), b"A");
+        // without the FIFO the second byte from the host overruns the first
+        assert_eq!(port_in(&mut uart, &mut ports, 0x3FD), 0x61);
+        uart.tick(414, &mut ports);
+        assert_eq!(port_in(&mut uart, &mut ports, 0x3FD), 0x63);
+        assert_eq!(port_in(&mut uart, &mut ports, 0x3F8), b'x');
+        // loopback with the FIFO at a trigger of 4 and OUT2 letting IRQ4 through
+        port_out(&mut uart, &mut ports, 0x3FC, &[0x18]);
+        port_out(&mut uart, &mut ports, 0x3FA, &[0x47]);
+        port_out(&mut uart, &mut ports, 0x3F9, &[0x01]);
+        assert_eq!(port_in(&mut uart, &mut ports, 0x3FA), 0xC1);
+        for byte in b"loop" {
+            port_out(&mut uart, &mut ports, 0x3F8, &[*byte]);
+        }
+        uart.tick(3 * 414, &mut ports);
+        assert_eq!(irq.levels() & 0x10, 0);
+        uart.tick(414, &mut ports);
+        assert_eq!(irq.levels() & 0x10, 0x10);
+        assert_eq!(port_in(&mut uart, &mut ports, 0x3FA), 0xC4);
+        let received = (0..4).map(|_| port_in(&mut uart, &mut ports, 0x3F8)).collect::<Vec<u8>>();
+        assert_eq!(received, b"loop");
+        assert_eq!(irq.levels() & 0x10, 0);
+        assert_eq!(*sent.lock().unwrap(), b"A");
+    }
+
+    #[test]
+    fn null_modem_link() {
+        use hardware::{IrqLines, serial::{Uart, COM1, host::null_modem}};
+        let (first_end, second_end) = null_modem();
+        let mut first = Uart::new(COM1, IrqLines::new(), Box::new(first_end));
+        let mut second = Uart::new(COM1, IrqLines::new(), Box::new(second_end));
+        let mut ports = vec![0u8; PORTS];
+        // DTR and RTS of one end are DSR, DCD and CTS of the other
+        assert_eq!(port_in(&mut second, &mut ports, 0x3FE) & 0xB0, 0);
+        port_out(&mut first, &mut ports, 0x3FC, &[0x03]);
+        second.tick(1, &mut ports);
+        assert_eq!(port_in(&mut second, &mut ports, 0x3FE), 0xB0 | 0x0B);
+        // paced at 9600 baud a character takes 4971 cycles on each side
+        port_out(&mut first, &mut ports, 0x3F8, &[b'P']);
+        first.tick(4970, &mut ports);
+        second.tick(5000, &mut ports);
+        assert_eq!(port_in(&mut second, &mut ports, 0x3FD) & 0x01, 0);
+        first.tick(1, &mut ports);
+        second.tick(4971, &mut ports);
+        assert_eq!(port_in(&mut second, &mut ports, 0x3F8), b'P');
+        // unpaced, the bytes cross at once and wait for room in the receiver
+        first.paced = false;
+        second.paced = false;
+        for byte in b"ok" {
+            port_out(&mut second, &mut ports, 0x3F8, &[*byte]);
+        }
+        first.tick(1, &mut ports);
+        assert_eq!(port_in(&mut first, &mut ports, 0x3FD), 0x61);
+        assert_eq!(port_in(&mut first, &mut ports, 0x3F8), b'o');
+        first.tick(1, &mut ports);
+        assert_eq!(port_in(&mut first, &mut ports, 0x3F8), b'k');
+        assert_eq!(port_in(&mut first, &mut ports, 0x3FD), 0x60);
+    }
+
+    #[test]
+    fn parallel_printer() {
+        use hardware::{IrqLines, printer::Printer};
+        let path = std::env::temp_dir().join(format!("remu-printer-{}.txt", std::process::id()));
+        let irq = IrqLines::new();
+        let mut printer = Printer::new(irq.clone(), path.to_str().unwrap()).unwrap();
+        let mut ports = vec![0u8; PORTS];
+        assert_eq!(port_in(&mut printer, &mut ports, 0x379), 0xD8);
+        // the strobe with IRQ7 enabled: busy for 477 cycles, then the acknowledge raises IRQ7
+        port_out(&mut printer, &mut ports, 0x378, &[b'A']);
+        port_out(&mut printer, &mut ports, 0x37A, &[0x1D]);
+        port_out(&mut printer, &mut ports, 0x37A, &[0x1C]);
+        assert_eq!(port_in(&mut printer, &mut ports, 0x37A), 0xFC);
+        assert_eq!(port_in(&mut printer, &mut ports, 0x379), 0x58);
+        // a strobe while busy is lost
+        port_out(&mut printer, &mut ports, 0x378, &[b'B']);
+        port_out(&mut printer, &mut ports, 0x37A, &[0x1D]);
+        port_out(&mut printer, &mut ports, 0x37A, &[0x1C]);
+        irq.take_edges();
+        printer.tick(470, &mut ports);
+        assert_eq!(irq.levels() & 0x80, 0);
+        printer.tick(10, &mut ports);
+        assert_eq!((port_in(&mut printer, &mut ports, 0x379), irq.levels() & 0x80), (0x18, 0x80));
+        printer.tick(30, &mut ports);
+        assert_eq!((port_in(&mut printer, &mut ports, 0x379), irq.levels() & 0x80), (0xD8, 0));
+        // a pulse inside a tick still reaches the interrupt controller
+        assert_eq!(irq.take_edges() & 0x80, 0x80);
+        port_out(&mut printer, &mut ports, 0x378, &[b'C']);
+        port_out(&mut printer, &mut ports, 0x37A, &[0x1D]);
+        port_out(&mut printer, &mut ports, 0x37A, &[0x1C]);
+        printer.tick(1000, &mut ports);
+        assert_eq!((irq.levels() & 0x80, irq.take_edges() & 0x80), (0, 0x80));
+        assert_eq!(std::fs::read(&path).unwrap(), b"AC");
+        let _ = std::fs::remove_file(&path);
+
+        // a capture file that can't be written puts the printer in error
+        #[cfg(target_os = "linux")]
+        {
+            let mut printer = Printer::new(irq.clone(), "/dev/full").unwrap();
+            port_out(&mut printer, &mut ports, 0x378, &[b'D']);
+            port_out(&mut printer, &mut ports, 0x37A, &[0x0D, 0x0C]);
+            printer.tick(1000, &mut ports);
+            assert_eq!(port_in(&mut printer, &mut ports, 0x379), 0xD0);
+        }
+    }
+
+    #[test]
+    fn floppy_controller() {
+        use hardware::{IrqLines, dma::Dma, floppy::{Disk, Fdc}};
+        let path = std::env::temp_dir().join(format!("remu-floppy-{}.img", std::process::id()));
+        // a 360K disk, each sector starts with its number
+        let mut image = vec![0u8; 368640];
+        for sector in 0..720 {
+            image[sector * 512] = sector as u8;
+        }
+        std::fs::write(&path, &image).unwrap();
+        let disk = Disk::open(path.to_str().unwrap()).unwrap();
+        assert_eq!((disk.geometry().cylinders, disk.geometry().sectors, disk.drive_type()), (40, 9, 1));
+        let irq = IrqLines::new();
+        let mut dma = Dma::new();
+        let mut fdc = Fdc::new(irq.clone(), dma.clone(), [Some(disk), None]);
+        let mut memory = MemorySegments::new();
+        let mut ports = vec![0u8; PORTS];
+        // the result phase is read a byte at a time from the data register
+        let results = |fdc: &mut Fdc, ports: &mut Vec<u8>, count: usize| {
+            (0..count).map(|_| port_in(fdc, ports, 0x3F5)).collect::<Vec<u8>>()
+        };
+        // leaving the reset interrupts, the drives report they're ready
+        port_out(&mut fdc, &mut ports, 0x3F2, &[0x1C]);
+        assert_eq!(irq.levels() & 0x40, 0x40);
+        port_out(&mut fdc, &mut ports, 0x3F5, &[0x08]);
+        assert_eq!(results(&mut fdc, &mut ports, 2), vec![0xC0, 0x00]);
+        for _ in 0..3 {
+            port_out(&mut fdc, &mut ports, 0x3F5, &[0x08]);
+            results(&mut fdc, &mut ports, 2);
+        }
+        assert_eq!((irq.levels() & 0x40, port_in(&mut fdc, &mut ports, 0x3F4)), (0, 0x80));
+        // a seek to cylinder 2 takes 1 ms and 3 ms a cylinder
+        port_out(&mut fdc, &mut ports, 0x3F5, &[0x0F, 0x00, 0x02]);
+        fdc.tick(30000, &mut ports);
+        assert_eq!(port_in(&mut fdc, &mut ports, 0x3F4), 0x81);
+        fdc.tick(10000, &mut ports);
+        assert_eq!(irq.levels() & 0x40, 0x40);
+        port_out(&mut fdc, &mut ports, 0x3F5, &[0x08]);
+        assert_eq!(results(&mut fdc, &mut ports, 2), vec![0x20, 0x02]);
+        // DMA channel 2 writes 3 sectors to 1234:0000, the terminal count ends the multi-track read on head 1
+        port_out(&mut dma, &mut ports, 0x0B, &[0x46]);
+        port_out(&mut dma, &mut ports, 0x0C, &[0x00]);
+        port_out(&mut dma, &mut ports, 0x04, &[0x40, 0x23]);
+        port_out(&mut dma, &mut ports, 0x81, &[0x01]);
+        port_out(&mut dma, &mut ports, 0x05, &[0xFF, 0x05]);
+        port_out(&mut dma, &mut ports, 0x0A, &[0x02]);
+        port_out(&mut fdc, &mut ports, 0x3F5, &[0xE6, 0x00, 0x02, 0x00, 0x08, 0x02, 0x09, 0x2A, 0xFF]);
+        assert_eq!(port_in(&mut fdc, &mut ports, 0x3F4), 0x10);
+        fdc.tick(5000, &mut ports);
+        dma.transfer(&mut memory);
+        fdc.tick(1, &mut ports);
+        assert_eq!(port_in(&mut fdc, &mut ports, 0x3F4), 0xD0);
+        assert_eq!(results(&mut fdc, &mut ports, 7), vec![0x00, 0x00, 0x00, 0x02, 0x01, 0x02, 0x02]);
+        assert_eq!(irq.levels() & 0x40, 0);
+        assert_eq!((memory[(0x1234, 0)], memory[(0x1234, 0x200)], memory[(0x1234, 0x400)]), (43, 44, 45));
+        assert_eq!(port_in(&mut dma, &mut ports, 0x08), 0x04);
+        // without the terminal count a read ends past the last sector with an error
+        port_out(&mut dma, &mut ports, 0x0B, &[0x4A]);
+        port_out(&mut dma, &mut ports, 0x04, &[0x40, 0x23]);
+        port_out(&mut dma, &mut ports, 0x05, &[0xFF, 0x0F]);
+        port_out(&mut dma, &mut ports, 0x0A, &[0x02]);
+        memory[(0x1234, 0)] = 0x77;
+        port_out(&mut fdc, &mut ports, 0x3F5, &[0x45, 0x00, 0x02, 0x00, 0x09, 0x02, 0x09, 0x2A, 0xFF]);
+        fdc.tick(5000, &mut ports);
+        dma.transfer(&mut memory);
+        fdc.tick(1, &mut ports);
+        assert_eq!(results(&mut fdc, &mut ports, 7), vec![0x40, 0x80, 0x00, 0x03, 0x00, 0x01, 0x02]);
+        assert_eq!(std::fs::read(&path).unwrap()[44 * 512], 0x77);
+        // a sector on another cylinder isn't found
+        port_out(&mut fdc, &mut ports, 0x3F5, &[0x46, 0x00, 0x05, 0x00, 0x01, 0x02, 0x09, 0x2A, 0xFF]);
+        fdc.tick(5000, &mut ports);
+        assert_eq!(results(&mut fdc, &mut ports, 3), vec![0x40, 0x04, 0x10]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn headless_options() {
+        use headless::{HeadlessOptions, MemoryRange, OutputFormat};
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        let options = HeadlessOptions::from_args(&args("--limit 50 --format json --dump ds:0x10,2 prog.asm --limit 7")).unwrap();
+        assert_eq!((options.limit, options.format), (50, OutputFormat::Json));
+        assert_eq!(options.dumps, vec![MemoryRange { segment: "ds".to_string(), address: 0x10, len: 2 }]);
+        // everything after the program is its command line
+        assert_eq!((options.program.as_str(), options.load.arguments.as_str()), ("prog.asm", "--limit 7"));
+        for (line, message) in [
+            ("--limit ten prog.asm", "bad `limit` argument 'ten'"),
+            ("--format xml prog.asm", "unknown format 'xml'"),
+            ("--hardware lightbulb,toaster prog.asm", "unknown hardware 'toaster'"),
+            ("--dump ds prog.asm", "bad memory range 'ds'"),
+            ("--dump fs:0 prog.asm", "bad `segment` argument 'fs'"),
+            ("--segment 0x10000 prog.asm", "bad `segment` argument '0x10000'"),
+            ("prog.asm --limit", ""),
+            ("--limit", "missing value for '--limit'"),
+            ("--frobnicate prog.asm", "unknown option '--frobnicate'"),
+            ("--debug --link b.asm a.asm", "--debug runs a single computer, it can't be used with --link"),
+            ("--limit 5", "no program given"),
+        ].iter() {
+            match HeadlessOptions::from_args(&args(line)) {
+                Ok(_) => assert!(message.is_empty(), "'{}' was accepted", line),
+                Err(err) => assert_eq!(&err, message),
+            }
+        }
+    }
+
+    #[test]
+    fn headless_output() {
+        use headless::{HeadlessOptions, RunStatus, format_state};
+        let mut cpu = test_cpu();
+        cpu.memory_unit.ax = 0x1234;
+        cpu.memory_unit.memory.data_segment = 0x2000;
+        cpu.memory_unit.memory.write(&vec![0xDE, 0xAD], (0x2000, 0x10));
+        assert_eq!([RunStatus::Halted, RunStatus::Exited(7), RunStatus::Fault(String::new()), RunStatus::Timeout].iter()
+            .map(|status| status.exit_code()).collect::<Vec<i32>>(), vec![headless::EXIT_HALTED, headless::EXIT_HALTED,
+            headless::EXIT_FAULT, headless::EXIT_TIMEOUT]);
+
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        let text = HeadlessOptions::from_args(&args("--dump ds:0x10,2 prog.asm")).unwrap();
+        let out = format_state(&cpu, &RunStatus::Fault("bad opcode".to_string()), 3, &text);
+        assert!(out.starts_with("status: fault (bad opcode)\ninstructions: 3\nAX: 1234  BX: 0000  CX: 0000  DX: 0000\n"));
+        assert!(out.ends_with("\n2000:0010 DE AD\n"));
+
+        let json = HeadlessOptions::from_args(&args("--format json --dump ds:0x10,2 prog.asm")).unwrap();
+        let out = format_state(&cpu, &RunStatus::Exited(7), 3, &json);
+        assert!(out.starts_with("{\"status\":\"exited\",\"return_code\":7,\"instructions\":3,\"registers\":{\"ax\":4660,"));
+        assert!(out.ends_with(",\"memory\":[{\"segment\":8192,\"address\":16,\"bytes\":[222,173]}]}\n"));
+        let out = format_state(&cpu, &RunStatus::Fault("say \"hi\"\n".to_string()), 0, &json);
+        assert!(out.starts_with("{\"status\":\"fault\",\"fault\":\"say \\\"hi\\\"\\u000a\",\"instructions\":0,"));
+
+        // linked computers are an array, and the worse status is the one of the link
+        let link = HeadlessOptions::from_args(&args("--format json --link b.asm a.asm")).unwrap();
+        let second = test_cpu();
+        let results = [(RunStatus::Fault("bad".to_string()), 4), (RunStatus::Timeout, 9)];
+        let out = headless::format_linked([&cpu, &second], &results, &link);
+        assert!(out.starts_with("[{\"status\":\"fault\",\"fault\":\"bad\",\"instructions\":4,"));
+        assert!(out.contains("]},{\"status\":\"timeout\",\"instructions\":9,") && out.ends_with("]}]\n"));
+        let [(first, _), (second, _)] = results;
+        assert_eq!(first.clone().worse(second.clone()), first.clone());
+        assert_eq!(second.clone().worse(first.clone()), first);
+        assert_eq!(RunStatus::Exited(3).worse(second.clone()), second);
+    }
+
+    #[test]
+    fn exe_detection() {
+        use program_loader::{load_program, LoadOptions, ProgramFormat};
+        let dir = std::env::temp_dir().join(format!("remu-exe-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut exe = vec![0u8; 0x20];
+        exe[..12].copy_from_slice(&[0x4D, 0x5A, 0x21, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+        exe.push(0xF4);
+        let options = LoadOptions::new();
+        let format = |name: &str, bytes: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, bytes).unwrap();
+            load_program(path.to_str().unwrap(), &options).unwrap().format
+        };
+        assert_eq!(format("prog.EXE", &exe), ProgramFormat::Exe);
+        // only a .exe is an executable, the same bytes elsewhere are code
+        assert_eq!(format("prog.bin", &exe), ProgramFormat::Raw);
+        assert_eq!(format("prog.com", &exe), ProgramFormat::Com);
+        assert_eq!(format("prog.asm", b"db 'MZ'\ntimes 30 db 0\n"), ProgramFormat::Raw);
+        // the source is assembled whatever the case of its extension
+        assert_eq!(load_program(dir.join("prog.asm").to_str().unwrap(), &options).unwrap().bytes.len(), 32);
+        std::fs::copy(dir.join("prog.asm"), dir.join("PROG.ASM")).unwrap();
+        assert_eq!(load_program(dir.join("PROG.ASM").to_str().unwrap(), &options).unwrap().bytes.len(), 32);
+        assert_eq!(format("noheader.exe", &[0x90, 0xF4]), ProgramFormat::Com);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
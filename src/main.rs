@@ -7,10 +7,14 @@ use crate::emulator::Emulator;
 // use keyboard_query::{DeviceQuery, DeviceState};
 use device_query::{DeviceQuery, DeviceState, MouseState, Keycode};
 
+mod bios;
 mod cpu;
 mod computer;
+mod debug_info;
+mod dos;
 mod program_loader;
 mod emulator;
+mod headless;
 pub mod hardware;
 
 #[allow(dead_code)]
@@ -18,6 +22,12 @@ mod assembler;
 mod debugger;
 
 fn main() {
+    // any argument runs the program without the gui
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(headless::run(&args));
+    }
+
     // let device_state = DeviceState::new();  
     // loop {
     //     let keys: Vec<Keycode> = device_state.query_keymap();
@@ -0,0 +1,305 @@
+use crate::cpu::memory_unit::MemoryUnit;
+use crate::cpu::memory_unit::memory_segments::MemorySegments;
+use crate::dos::memory::{self, FIRST_MCB};
+use super::LoadError;
+
+// the first free segment, where programs are loaded if no other segment is given
+pub const PSP_SEGMENT: u16 = FIRST_MCB + 1;
+// .COM programs start after the PSP
+pub const COM_OFFSET: u16 = 0x100;
+// the first segment after conventional memory (640K)
+pub const MEMORY_TOP: u16 = 0xA000;
+// where the handlers of DOS interrupts are
+pub const DOS_SEGMENT: u16 = 0x0070;
+// the handlers of INT 20h and INT 21h in the DOS segment, a HLT and an IRET
+pub const DOS_ENTRY_POINTS: [(u8, u16); 2] = [(0x20, 0), (0x21, 1)];
+
+pub const PSP_SIZE: usize = 0x100;
+// a .COM program, its PSP and the word pushed on the stack fit in one segment
+const MAX_COM_SIZE: usize = 0x10000 - PSP_SIZE - 2;
+// the command tail is a length byte, up to 126 characters and a carriage return
+const MAX_COMMAND_TAIL: usize = 126;
+const COMMAND_TAIL: usize = 0x80;
+// handles of the job file table, stdin, stdout, stderr, stdaux and stdprn are open
+const JOB_FILE_TABLE: usize = 0x18;
+const JOB_FILE_TABLE_SIZE: usize = 20;
+
+const PARAGRAPH: usize = 16;
+const PAGE: usize = 512;
+const EXE_HEADER_SIZE: usize = 0x1C;
+// raw programs get a whole segment, for their code, data and stack
+const RAW_PARAGRAPHS: u16 = 0x1000;
+// the owner of a block until the PSP of the program at its start is known
+const PSP_OWNER: u16 = 0xFFFF;
+
+/**
+ * The header of a DOS executable, the fields are in the order of the file.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExeHeader {
+    // bytes used in the last page, 0 if it's full
+    pub last_page_size: u16,
+    // pages of 512 bytes in the file, including the header
+    pub pages: u16,
+    pub relocations: u16,
+    pub header_paragraphs: u16,
+    // paragraphs needed after the image, and wanted at most
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+    pub ss: u16,
+    pub sp: u16,
+    pub checksum: u16,
+    pub ip: u16,
+    pub cs: u16,
+    pub relocation_table: u16,
+    pub overlay: u16,
+}
+
+impl ExeHeader {
+    /**
+     * Parses the header of an executable.
+     * @param program: the bytes of the file.
+     * @return: the header, None if the file doesn't start with the `MZ` signature.
+     */
+    pub fn parse(program: &[u8]) -> Option<Self> {
+        if program.len() < EXE_HEADER_SIZE || !(program.starts_with(b"MZ") || program.starts_with(b"ZM")) {
+            return None
+        }
+        let word = |i: usize| u16::from_le_bytes([program[i * 2], program[i * 2 + 1]]);
+        Some(Self {
+            last_page_size: word(1),
+            pages: word(2),
+            relocations: word(3),
+            header_paragraphs: word(4),
+            min_alloc: word(5),
+            max_alloc: word(6),
+            ss: word(7),
+            sp: word(8),
+            checksum: word(9),
+            ip: word(10),
+            cs: word(11),
+            relocation_table: word(12),
+            overlay: word(13),
+        })
+    }
+
+    /**
+     * Returns where the load module, the part of the file loaded to memory, starts and ends.
+     */
+    fn image(&self) -> (usize, usize) {
+        let mut end = self.pages as usize * PAGE;
+        if self.last_page_size != 0 {
+            end = end.saturating_sub(PAGE) + self.last_page_size as usize;
+        }
+        (self.header_paragraphs as usize * PARAGRAPH, end)
+    }
+}
+
+/**
+ * Builds the Program Segment Prefix of a program.
+ * @param segment: the segment of the PSP.
+ * @param memory_top: the first segment after the memory given to the program.
+ * @param arguments: the arguments of the program, written to the command tail.
+ * @return: the 256 bytes of the PSP, or an error if the arguments are too long.
+ */
+pub fn build_psp(segment: u16, memory_top: u16, arguments: &str) -> Result<Vec<u8>, LoadError> {
+    let mut psp = vec![0u8; PSP_SIZE];
+    // INT 20h, programs that return with `ret` end up here
+    psp[0x00] = 0xCD;
+    psp[0x01] = 0x20;
+    psp[0x02..0x04].copy_from_slice(&memory_top.to_le_bytes());
+    // the parent of the program is itself, like for the shell
+    psp[0x16..0x18].copy_from_slice(&segment.to_le_bytes());
+    psp[JOB_FILE_TABLE..JOB_FILE_TABLE + 5].copy_from_slice(&[0x01, 0x01, 0x01, 0x00, 0x02]);
+    for handle in &mut psp[JOB_FILE_TABLE + 5..JOB_FILE_TABLE + JOB_FILE_TABLE_SIZE] {
+        *handle = 0xFF;
+    }
+    psp[0x32..0x34].copy_from_slice(&(JOB_FILE_TABLE_SIZE as u16).to_le_bytes());
+    psp[0x34..0x36].copy_from_slice(&(JOB_FILE_TABLE as u16).to_le_bytes());
+    psp[0x36..0x38].copy_from_slice(&segment.to_le_bytes());
+    // INT 21h / RETF, the DOS function dispatcher
+    psp[0x50..0x53].copy_from_slice(&[0xCD, 0x21, 0xCB]);
+    // blank file names of the default FCBs
+    for fcb in [0x5C, 0x6C] {
+        for byte in &mut psp[fcb + 1..fcb + 12] {
+            *byte = b' ';
+        }
+    }
+
+    let tail = command_tail(arguments)?;
+    psp[COMMAND_TAIL] = tail.len() as u8;
+    psp[COMMAND_TAIL + 1..COMMAND_TAIL + 1 + tail.len()].copy_from_slice(tail.as_bytes());
+    psp[COMMAND_TAIL + 1 + tail.len()] = 0x0D;
+    Ok(psp)
+}
+
+/**
+ * Returns the command tail of the arguments, which starts with the space
+ * separating it from the program name.
+ * @return: the command tail, or an error if it doesn't fit in the PSP.
+ */
+fn command_tail(arguments: &str) -> Result<String, LoadError> {
+    let tail = if arguments.is_empty() { String::new() } else { format!(" {}", arguments) };
+    if tail.len() > MAX_COMMAND_TAIL {
+        return Err(LoadError::Format(format!(
+            "the arguments are {} characters long, at most {} fit in the PSP",
+            tail.len() - 1, MAX_COMMAND_TAIL - 1)))
+    }
+    Ok(tail)
+}
+
+/**
+ * Prepares the memory for DOS: points the vectors of the DOS interrupts to
+ * their handlers and makes conventional memory one free block.
+ * INT 20h halts the cpu, ending the program, INT 21h returns.
+ * @param memory: the memory of the cpu.
+ */
+pub fn install_dos(memory: &mut MemorySegments) {
+    memory.write(&vec![0xF4, 0xCF], (DOS_SEGMENT, 0));
+    for (vector, offset) in DOS_ENTRY_POINTS.iter() {
+        memory.set_interrupt_vector(*vector, (DOS_SEGMENT, *offset));
+    }
+    memory::init_arena(memory, MEMORY_TOP);
+}
+
+/**
+ * Allocates the memory of a program. A segment outside of conventional
+ * memory is used as it is, the memory there isn't managed by DOS: below the
+ * first control block it goes up to the top of conventional memory, above
+ * the top it's a segment long.
+ * @param segment: where the memory must start, None for the first free block big enough.
+ * @param min: the paragraphs the program needs.
+ * @param max: the paragraphs the program wants.
+ * @return: the segment and the size of the memory.
+ */
+fn allocate(memory: &mut MemorySegments, segment: Option<u16>, min: u16, max: u16) -> Result<(u16, u16), LoadError> {
+    let result = match segment {
+        Some(segment) if segment <= FIRST_MCB || segment >= MEMORY_TOP => {
+            let top = if segment < MEMORY_TOP { MEMORY_TOP as u32 } else { (segment as u32 + 0x1000).min(0x10000) };
+            return Ok((segment, max.min((top - segment as u32) as u16)))
+        },
+        // the program owns its memory, its PSP is at the start of it
+        Some(segment) => memory::allocate_at(memory, segment, segment, min, max).map(|size| (segment, size)),
+        None => memory::allocate(memory, PSP_OWNER, min, max).map(|(segment, size)| {
+            let _ = memory::set_owner(memory, segment, segment);
+            (segment, size)
+        }),
+    };
+    result.map_err(|(err, available)| match err {
+        memory::ERROR_NOT_ENOUGH_MEMORY => LoadError::Memory(format!(
+            "the program needs {} paragraphs of memory, only {} are free{}",
+            min, available, segment.map_or(String::new(), |segment| format!(" at segment {:04X}", segment)))),
+        _ => LoadError::Memory("the memory control blocks are destroyed".to_string()),
+    })
+}
+
+/**
+ * Loads a flat binary at offset PROGRAM_OFFSET of a segment of its own.
+ * @param cpu: the cpu to load the program to.
+ * @param program: the bytes of the program.
+ * @param segment: the segment to load the program at, None for the first free one.
+ * @param offset: the offset of the program in the segment.
+ * @return: the segment of the program.
+ */
+pub fn load_raw(cpu: &mut MemoryUnit, program: &[u8], segment: Option<u16>, offset: u16) -> Result<u16, LoadError> {
+    let (segment, _) = allocate(&mut cpu.memory, segment, RAW_PARAGRAPHS, RAW_PARAGRAPHS)?;
+    cpu.memory.write(&program.to_vec(), (segment, offset));
+    Ok(segment)
+}
+
+/**
+ * Loads a .COM program the way DOS does: the PSP at segment:0000, the program
+ * at segment:0100, every segment register set to the segment and the stack at
+ * the top of it with a zero word pushed, so `ret` jumps to the INT 20h of the PSP.
+ * The program gets all the memory of the free block it's loaded to.
+ * @param cpu: the cpu to load the program to.
+ * @param program: the bytes of the program.
+ * @param segment: the segment to load the program at, None for the first free one.
+ * @param arguments: the arguments of the program.
+ * @return: the segment of the PSP, or an error if the program doesn't fit in a
+ * segment, the arguments in the PSP or the program in memory.
+ */
+pub fn load_com(cpu: &mut MemoryUnit, program: &[u8], segment: Option<u16>, arguments: &str) -> Result<u16, LoadError> {
+    if program.len() > MAX_COM_SIZE {
+        return Err(LoadError::Format(format!(
+            "the program is {} bytes long, a .COM program can be at most {} bytes", program.len(), MAX_COM_SIZE)))
+    }
+    command_tail(arguments)?;
+    // the PSP, the program and the word on the stack
+    let needed = ((PSP_SIZE + program.len() + 2 + PARAGRAPH - 1) / PARAGRAPH) as u16;
+    let (segment, size) = allocate(&mut cpu.memory, segment, needed, 0xFFFF)?;
+    let psp = build_psp(segment, segment.wrapping_add(size), arguments)?;
+    // the stack is at the top of the segment, or of the memory if it's smaller
+    let sp = ((size as usize * PARAGRAPH).min(0x10000) - 2) as u16;
+    let memory = &mut cpu.memory;
+    memory.write(&psp, (segment, 0));
+    memory.write(&program.to_vec(), (segment, COM_OFFSET));
+    memory.write(&vec![0, 0], (segment, sp));
+
+    memory.code_segment = segment;
+    memory.data_segment = segment;
+    memory.extra_segment = segment;
+    memory.stack_segment = segment;
+    cpu.ip = COM_OFFSET;
+    cpu.sp = sp;
+    Ok(segment)
+}
+
+/**
+ * Loads a DOS executable the way DOS does: the PSP at segment:0000 and the load
+ * module right after it, with the relocations applied to the segment of the load
+ * module. CS:IP and SS:SP are taken from the header, DS and ES point to the PSP.
+ * The program gets the memory it asks for with max_alloc, as long as the
+ * min_alloc it needs fits in the free block it's loaded to.
+ * @param cpu: the cpu to load the program to.
+ * @param program: the bytes of the file.
+ * @param segment: the segment of the PSP, None for the first free one.
+ * @param arguments: the arguments of the program.
+ * @return: the segment of the PSP, or an error if the file isn't a valid
+ * executable or doesn't fit in memory.
+ */
+pub fn load_exe(cpu: &mut MemoryUnit, program: &[u8], segment: Option<u16>, arguments: &str) -> Result<u16, LoadError> {
+    let header = ExeHeader::parse(program)
+        .ok_or(LoadError::Format("the program doesn't have an MZ header".to_string()))?;
+    let (start, end) = header.image();
+    if start < EXE_HEADER_SIZE || start > end || end > program.len() {
+        return Err(LoadError::Format(format!(
+            "the load module at {:X}-{:X} is outside of the {} bytes of the file", start, end, program.len())))
+    }
+    let image = program[start..end].to_vec();
+    let table = header.relocation_table as usize;
+    if header.relocations > 0 && table + header.relocations as usize * 4 > program.len() {
+        return Err(LoadError::Format("the relocation table is outside of the file".to_string()))
+    }
+    command_tail(arguments)?;
+
+    // the memory of the program, in paragraphs from the PSP
+    let needed = ((PSP_SIZE + image.len() + PARAGRAPH - 1) / PARAGRAPH) as u32;
+    let min = (needed + header.min_alloc as u32).min(0xFFFF) as u16;
+    let max = (needed + header.max_alloc as u32).min(0xFFFF) as u16;
+    let (segment, size) = allocate(&mut cpu.memory, segment, min, max)?;
+    let psp = build_psp(segment, segment.wrapping_add(size), arguments)?;
+    let load_segment = segment.wrapping_add((PSP_SIZE / PARAGRAPH) as u16);
+
+    let memory = &mut cpu.memory;
+    memory.write(&psp, (segment, 0));
+    memory.write(&image, (load_segment, 0));
+
+    // each relocation is the segment:offset of a word in the image to add the load segment to
+    for i in 0..header.relocations as usize {
+        let entry = table + i * 4;
+        let offset = u16::from_le_bytes([program[entry], program[entry + 1]]);
+        let relocated = load_segment.wrapping_add(u16::from_le_bytes([program[entry + 2], program[entry + 3]]));
+        let word = memory.read((relocated, offset), 2);
+        let value = u16::from_le_bytes([word[0], word[1]]).wrapping_add(load_segment);
+        memory.write(&value.to_le_bytes().to_vec(), (relocated, offset));
+    }
+
+    memory.code_segment = load_segment.wrapping_add(header.cs);
+    memory.stack_segment = load_segment.wrapping_add(header.ss);
+    memory.data_segment = segment;
+    memory.extra_segment = segment;
+    cpu.ip = header.ip;
+    cpu.sp = header.sp;
+    Ok(segment)
+}
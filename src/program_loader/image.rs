@@ -0,0 +1,277 @@
+use std::fs;
+
+use crate::cpu::helperModules::string_to_number;
+use crate::cpu::memory_unit::memory_segments::MemorySegments;
+use super::{load_program, LoadError, LoadOptions};
+
+// the 8086 addresses 1MB
+const MEMORY_SIZE: u32 = 0x100000;
+
+const HEX_EXTENSIONS: [&str; 3] = [".hex", ".ihx", ".ihex"];
+const SREC_EXTENSIONS: [&str; 5] = [".srec", ".s19", ".s28", ".s37", ".mot"];
+
+/**
+ * Bytes to place at a physical address.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub address: u32,
+    pub bytes: Vec<u8>,
+}
+
+/**
+ * The blocks of a record file and where the program starts, if the file says.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Records {
+    pub blocks: Vec<Block>,
+    pub start: Option<(u16, u16)>,
+}
+
+/**
+ * A file to place in memory before reset, next to the program.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub path: String,
+    // where to load a raw image, record files say where their bytes go
+    pub location: Option<(u16, u16)>,
+}
+
+impl Image {
+    /**
+     * Parses a `path[@seg:off]` string.
+     * @param string: the string to parse.
+     */
+    pub fn from(string: &str) -> Result<Self, String> {
+        let (path, location) = match string.rfind('@') {
+            Some(i) => (&string[..i], Some(&string[i + 1..])),
+            None => (string, None),
+        };
+        if path.is_empty() {
+            return Err(format!("no file in image '{}'", string))
+        }
+        let location = match location {
+            Some(location) => Some(parse_location(location)?),
+            None => None,
+        };
+        if location.is_none() && !is_record_file(path) {
+            return Err(format!("the raw image '{}' needs a location, like {}@F000:0", path, path))
+        }
+        Ok(Self {
+            path: path.to_string(),
+            location: location,
+        })
+    }
+
+    /**
+     * Places the image in memory. Raw files and .asm programs are written at
+     * the location of the image, record files at the addresses of their records.
+     * @param memory: the memory of the cpu.
+     * @param options: the options used to assemble .asm images.
+     * @return: the start address of a record file, if it has one.
+     */
+    pub fn load(&self, memory: &mut MemorySegments, options: &LoadOptions) -> Result<Option<(u16, u16)>, LoadError> {
+        if is_record_file(&self.path) {
+            let records = read_records(&self.path)?;
+            write_blocks(memory, &records.blocks, self.location);
+            return Ok(records.start)
+        }
+        let program = load_program(&self.path, options)?;
+        memory.write(&program.bytes, self.location.unwrap_or((0, 0)));
+        Ok(None)
+    }
+}
+
+/**
+ * Places a BIOS ROM image at the top of the address space and makes it read-only.
+ * @param memory: the memory of the cpu.
+ * @param path: the path of the raw ROM image.
+ */
+pub fn load_bios_rom(memory: &mut MemorySegments, path: &str) -> Result<(), LoadError> {
+    let rom = fs::read(path).map_err(|err| LoadError::Io(format!("{}: {}", path, err)))?;
+    memory.load_rom(&rom).map_err(|err| LoadError::Format(format!("{}: {}", path, err)))?;
+    Ok(())
+}
+
+/**
+ * Parses a `seg:off` location.
+ */
+pub fn parse_location(string: &str) -> Result<(u16, u16), String> {
+    let args = string.split(':').collect::<Vec<&str>>();
+    if args.len() != 2 {
+        return Err(format!("bad location '{}', expected seg:off", string))
+    }
+    let segment = match string_to_number(args[0]) {
+        Ok(val) if val <= 0xFFFF => val as u16,
+        _ => return Err(format!("bad `segment` argument '{}'", args[0]))
+    };
+    let offset = match string_to_number(args[1]) {
+        Ok(val) if val <= 0xFFFF => val as u16,
+        _ => return Err(format!("bad `address` argument '{}'", args[1]))
+    };
+    Ok((segment, offset))
+}
+
+/**
+ * Returns true if the file is an Intel HEX or a Motorola S-record file, by its extension.
+ */
+pub fn is_record_file(path: &str) -> bool {
+    let path = path.to_lowercase();
+    HEX_EXTENSIONS.iter().chain(SREC_EXTENSIONS.iter()).any(|extension| path.ends_with(extension))
+}
+
+/**
+ * Reads and parses a record file.
+ * @param path: path to an Intel HEX or a Motorola S-record file.
+ */
+pub fn read_records(path: &str) -> Result<Records, LoadError> {
+    let text = fs::read_to_string(path).map_err(|err| LoadError::Io(format!("{}: {}", path, err)))?;
+    let records = if HEX_EXTENSIONS.iter().any(|extension| path.to_lowercase().ends_with(extension)) {
+        parse_intel_hex(&text)
+    } else {
+        parse_srecord(&text)
+    };
+    records.map_err(|(line, message)| LoadError::Format(format!("{}:{}: {}", path, line, message)))
+}
+
+/**
+ * Writes blocks to memory at their physical addresses.
+ * @param location: if given, the blocks are moved by this segment:offset.
+ */
+pub fn write_blocks(memory: &mut MemorySegments, blocks: &[Block], location: Option<(u16, u16)>) {
+    let (segment, offset) = location.unwrap_or((0, 0));
+    for block in blocks {
+        let address = block.address + ((segment as u32) << 4) + offset as u32;
+        memory.write(&block.bytes, linear_to_location(address));
+    }
+}
+
+/**
+ * Turns a linear address into a segment:offset pair.
+ */
+fn linear_to_location(address: u32) -> (u16, u16) {
+    (((address >> 4) & 0xFFFF) as u16, (address & 0xF) as u16)
+}
+
+/**
+ * Parses the hex digits of a record into bytes.
+ */
+fn parse_record_bytes(digits: &str) -> Result<Vec<u8>, String> {
+    if digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("bad record '{}'", digits))
+    }
+    Ok((0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+/**
+ * Adds a block of data, checking it fits in memory.
+ */
+fn push_block(blocks: &mut Vec<Block>, address: u32, bytes: &[u8]) -> Result<(), String> {
+    if address + bytes.len() as u32 > MEMORY_SIZE {
+        return Err(format!("{} bytes at {:05X} are outside of memory", bytes.len(), address))
+    }
+    blocks.push(Block {
+        address: address,
+        bytes: bytes.to_vec(),
+    });
+    Ok(())
+}
+
+/**
+ * Parses an Intel HEX file. Each record is `:LLAAAATT<data>CC`, extended
+ * segment (02) and linear (04) address records move the following data,
+ * start segment (03) and linear (05) address records give the start.
+ * @param text: the contents of the file.
+ * @return: the records, or the line of the error and what's wrong.
+ */
+pub fn parse_intel_hex(text: &str) -> Result<Records, (usize, String)> {
+    let mut blocks = Vec::new();
+    let mut start = None;
+    let mut base: u32 = 0;
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+        if !line.starts_with(':') {
+            return Err((line_number, "records start with ':'".to_string()))
+        }
+        let bytes = parse_record_bytes(&line[1..]).map_err(|err| (line_number, err))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err((line_number, "the length of the record doesn't match its data".to_string()))
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err((line_number, "bad checksum".to_string()))
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        let word = || u16::from_be_bytes([data[0], data[1]]);
+        match (bytes[3], data.len()) {
+            (0x00, _) => push_block(&mut blocks, base + address, data).map_err(|err| (line_number, err))?,
+            (0x01, _) => break,
+            (0x02, 2) => base = (word() as u32) << 4,
+            (0x03, 4) => start = Some((word(), u16::from_be_bytes([data[2], data[3]]))),
+            (0x04, 2) => base = (word() as u32) << 16,
+            (0x05, 4) => start = Some(linear_to_location(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))),
+            (kind, _) => return Err((line_number, format!("bad record of type {:02X}", kind))),
+        }
+    }
+    Ok(Records {
+        blocks: blocks,
+        start: start,
+    })
+}
+
+/**
+ * Parses a Motorola S-record file. Each record is `S<type><count><address><data><checksum>`,
+ * S1/S2/S3 hold data with 2, 3 and 4 bytes of address and S9/S8/S7 the start.
+ * @param text: the contents of the file.
+ * @return: the records, or the line of the error and what's wrong.
+ */
+pub fn parse_srecord(text: &str) -> Result<Records, (usize, String)> {
+    let mut blocks = Vec::new();
+    let mut start = None;
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+        if line.len() < 4 || !(line.starts_with('S') || line.starts_with('s')) {
+            return Err((line_number, "records start with 'S'".to_string()))
+        }
+        let kind = line.as_bytes()[1];
+        let bytes = parse_record_bytes(&line[2..]).map_err(|err| (line_number, err))?;
+        if bytes.len() != bytes[0] as usize + 1 {
+            return Err((line_number, "the length of the record doesn't match its data".to_string()))
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0xFF {
+            return Err((line_number, "bad checksum".to_string()))
+        }
+        let address_len = match kind {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => return Err((line_number, format!("bad record of type S{}", kind as char))),
+        };
+        if bytes.len() < address_len + 2 {
+            return Err((line_number, "the record is too short for its address".to_string()))
+        }
+        let address = bytes[1..1 + address_len].iter().fold(0u32, |address, byte| (address << 8) | *byte as u32);
+        let data = &bytes[1 + address_len..bytes.len() - 1];
+        match kind {
+            b'1' | b'2' | b'3' => push_block(&mut blocks, address, data).map_err(|err| (line_number, err))?,
+            b'7' | b'8' | b'9' => start = Some(linear_to_location(address)),
+            // the header and the record counts
+            _ => (),
+        }
+    }
+    Ok(Records {
+        blocks: blocks,
+        start: start,
+    })
+}
@@ -0,0 +1,349 @@
+#![allow(dead_code)]
+use std::process::Command;
+use std::fs;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::assembler::assembler;
+use crate::cpu::HaltPolicy;
+use crate::debug_info::DebugInfo;
+
+pub mod dos;
+pub mod image;
+
+use image::{Image, Records};
+
+// name of the assembler built into remu, used when nothing else is configured
+pub const BUILTIN_ASSEMBLER: &str = "builtin";
+
+// counts the temporary files, so each run gets its own output file
+static OUTPUT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/**
+ * A message of the assembler about a line of the program.
+ * line is 0 when the message isn't about a specific line.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.file, self.line, self.message)
+        }
+    }
+}
+
+/**
+ * The reasons loading a program can fail.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadError {
+    // a file couldn't be read or written
+    Io(String),
+    // the assembler couldn't be started
+    Assembler(String),
+    // the assembler rejected the program
+    Diagnostics(Vec<Diagnostic>),
+    // the program can't be loaded the way its format says
+    Format(String),
+    // there isn't enough free memory for the program
+    Memory(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(msg) => write!(f, "{}", msg),
+            Self::Assembler(msg) => write!(f, "{}", msg),
+            Self::Format(msg) => write!(f, "{}", msg),
+            Self::Memory(msg) => write!(f, "{}", msg),
+            Self::Diagnostics(diagnostics) => {
+                let lines = diagnostics.iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<String>>();
+                write!(f, "{}", lines.join("\n"))
+            }
+        }
+    }
+}
+
+/**
+ * Options for loading the program.
+ */
+#[derive(Clone, Debug)]
+pub struct LoadOptions {
+    // the nasm binary used for .asm files, or `builtin` for the built-in assembler
+    pub assembler: String,
+    // load the program as a DOS .COM program even if it isn't a .com file,
+    // .exe files with an MZ header are always loaded as executables
+    pub com: bool,
+    // the segment to load the program at, None for the default of its format
+    pub load_segment: Option<u16>,
+    // the arguments of DOS programs, written to the command tail of the PSP
+    pub arguments: String,
+    // files placed in memory after the program, before reset
+    pub images: Vec<Image>,
+    // the host directory DOS programs see as drive C:, None for the directory of the program
+    pub drive: Option<String>,
+    // a BIOS ROM image placed read-only at the top of memory, which runs instead of the built-in BIOS
+    pub bios: Option<String>,
+    // where the cpu starts, None for F000:FFF0
+    pub reset_vector: Option<(u16, u16)>,
+    // if HLT waits for an interrupt or ends the program
+    pub halt_policy: HaltPolicy,
+    // the host side of COM1 and COM2, see `hardware::serial::host::open`, None if the port isn't connected
+    pub serial: [Option<String>; 2],
+    // if the characters on the serial ports take their time at the baud rate
+    pub serial_pacing: bool,
+    // the file what's printed on LPT1 is captured to, None if the port isn't connected
+    pub printer: Option<String>,
+    // the disk images in the floppy drives A: and B:, None if a drive is empty
+    pub floppies: [Option<String>; 2],
+    // start from the boot sector of the disk in A: instead of a program
+    pub boot: bool,
+}
+
+impl LoadOptions {
+    /**
+     * Creates the default options. The assembler is taken from the `NASM`
+     * environment variable, the built-in one is used if it isn't set.
+     */
+    pub fn new() -> Self {
+        Self {
+            assembler: match std::env::var("NASM") {
+                Ok(path) if !path.is_empty() => path,
+                _ => BUILTIN_ASSEMBLER.to_string(),
+            },
+            com: false,
+            load_segment: None,
+            arguments: String::new(),
+            images: Vec::new(),
+            drive: None,
+            bios: None,
+            reset_vector: None,
+            halt_policy: HaltPolicy::Wait,
+            serial: [None, None],
+            serial_pacing: true,
+            printer: None,
+            floppies: [None, None],
+            boot: false,
+        }
+    }
+
+    /**
+     * Returns the host directory used as drive C: for a program.
+     * @param program_path: the path of the program.
+     */
+    pub fn drive_root(&self, program_path: &str) -> PathBuf {
+        match &self.drive {
+            Some(drive) => PathBuf::from(drive),
+            None => match Path::new(program_path).parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            },
+        }
+    }
+}
+
+/**
+ * How a program is placed in memory and started.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProgramFormat {
+    // flat binary, loaded at PROGRAM_OFFSET of a free segment and jumped to on reset
+    Raw,
+    // DOS .COM program, loaded after a PSP with the registers set like DOS does
+    Com,
+    // DOS executable with an MZ header, relocated and loaded after a PSP
+    Exe,
+    // Intel HEX or S-record file, its bytes are placed at the addresses of its records
+    Records,
+}
+
+/**
+ * A loaded program. Assembled programs come with their listing and labels.
+ */
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub bytes: Vec<u8>,
+    pub debug_info: Option<DebugInfo>,
+    pub format: ProgramFormat,
+    // the blocks of record files
+    pub records: Option<Records>,
+}
+
+/**
+ * This function is responsible for loading the program with the default options.
+ * @param file_path: path to the file to open.
+ * @return: the contents of the file, assembled if it's an .asm file.
+ */
+pub fn get_program(file_path: &str) -> Result<Vec<u8>, LoadError> {
+    Ok(load_program(file_path, &LoadOptions::new())?.bytes)
+}
+
+/**
+ * This function is responsible for loading the program.
+ * @param file_path: path to the file to open.
+ * @param options: the options for loading.
+ * @return: the program, assembled if it's an .asm file.
+ */
+pub fn load_program(file_path: &str, options: &LoadOptions) -> Result<Program, LoadError> {
+    let mut program = read_program(file_path, options)?;
+    // raw files and assembled programs may start with the bytes of `MZ` too, only a .exe
+    // is an executable, and like DOS a .exe without the header is loaded as a .COM program
+    let exe = file_path.to_lowercase().ends_with(".exe");
+    if exe && dos::ExeHeader::parse(&program.bytes).is_some() {
+        program.format = ProgramFormat::Exe;
+    }
+    else if options.com || exe || file_path.to_lowercase().ends_with(".com") {
+        program.format = ProgramFormat::Com;
+    }
+    Ok(program)
+}
+
+/**
+ * Reads the bytes of the program, assembling .asm files.
+ * @param file_path: path to the file to open.
+ * @param options: the options for loading.
+ * @return: the program, as a flat binary.
+ */
+fn read_program(file_path: &str, options: &LoadOptions) -> Result<Program, LoadError> {
+    if image::is_record_file(file_path) {
+        Ok(Program {
+            bytes: Vec::new(),
+            debug_info: None,
+            format: ProgramFormat::Records,
+            records: Some(image::read_records(file_path)?),
+        })
+    }
+    else if file_path.to_lowercase().ends_with(".asm") {
+        if options.assembler == BUILTIN_ASSEMBLER || options.assembler.is_empty() {
+            let source = fs::read_to_string(file_path)
+                .map_err(|err| LoadError::Io(format!("{}: {}", file_path, err)))?;
+            let (bytes, debug_info) = assembler(&source, file_path).map_err(LoadError::Diagnostics)?;
+            Ok(Program {
+                bytes: bytes,
+                debug_info: Some(debug_info),
+                format: ProgramFormat::Raw,
+                records: None,
+            })
+        }
+        else {
+            assemble(&options.assembler, file_path)
+        }
+    }
+    else {
+        Ok(Program {
+            bytes: fs::read(file_path).map_err(|err| LoadError::Io(format!("{}: {}", file_path, err)))?,
+            debug_info: None,
+            format: ProgramFormat::Raw,
+            records: None,
+        })
+    }
+}
+
+/**
+ * Assembles a file with nasm into a flat binary.
+ * @param assembler: the nasm binary to run.
+ * @param file_path: path to the .asm file.
+ * @return: the assembled program with its listing, or the errors nasm found.
+ */
+pub fn assemble(assembler: &str, file_path: &str) -> Result<Program, LoadError> {
+    let output_path = temp_output_path("bin");
+    let listing_path = temp_output_path("lst");
+    let output = Command::new(assembler)
+        .args(["-f", "bin", file_path, "-o"])
+        .arg(&output_path)
+        .arg("-l")
+        .arg(&listing_path)
+        .output()
+        .map_err(|err| LoadError::Assembler(format!("failed to run '{}': {}", assembler, err)))?;
+
+    // the listing is only used to debug the program, it's fine if it's missing
+    let listing = fs::read_to_string(&listing_path).ok();
+    let _ = fs::remove_file(&listing_path);
+    if !output.status.success() {
+        let _ = fs::remove_file(&output_path);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut diagnostics = parse_diagnostics(&stderr);
+        if diagnostics.is_empty() {
+            diagnostics.push(Diagnostic {
+                file: file_path.to_string(),
+                line: 0,
+                message: format!("{} failed: {}", assembler, stderr.trim()),
+            });
+        }
+        return Err(LoadError::Diagnostics(diagnostics))
+    }
+
+    // read data from new file
+    let data = fs::read(&output_path)
+        .map_err(|err| LoadError::Io(format!("{}: {}", output_path.display(), err)));
+    let _ = fs::remove_file(&output_path);
+    Ok(Program {
+        bytes: data?,
+        debug_info: listing.map(|listing| DebugInfo::from_nasm_listing(&listing, file_path)),
+        format: ProgramFormat::Raw,
+        records: None,
+    })
+}
+
+/**
+ * Returns a new path in the temporary directory for the assembler output.
+ * @param extension: the extension of the file.
+ */
+fn temp_output_path(extension: &str) -> PathBuf {
+    let count = OUTPUT_COUNTER.fetch_add(1, Ordering::SeqCst);
+    std::env::temp_dir().join(format!("remu-{}-{}.{}", std::process::id(), count, extension))
+}
+
+/**
+ * Parses the messages nasm writes to stderr.
+ * nasm writes `file:line: level: message` for messages about a line
+ * and `nasm: level: message` for others.
+ * @param stderr: the output of nasm.
+ * @return: vector of the parsed messages.
+ */
+pub fn parse_diagnostics(stderr: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for line in stderr.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        // split `file:line: message`, the file may contain ':' on windows (C:\...)
+        let parsed = line.match_indices(':').find_map(|(i, _)| {
+            let rest = &line[i + 1..];
+            let end = rest.find(':')?;
+            let number = rest[..end].parse::<usize>().ok()?;
+            Some(Diagnostic {
+                file: line[..i].to_string(),
+                line: number,
+                message: rest[end + 1..].trim().to_string(),
+            })
+        });
+        diagnostics.push(match parsed {
+            Some(diagnostic) => diagnostic,
+            None => {
+                let (file, message) = match line.find(": ") {
+                    Some(i) => (&line[..i], &line[i + 2..]),
+                    None => ("", line),
+                };
+                Diagnostic {
+                    file: file.to_string(),
+                    line: 0,
+                    message: message.to_string(),
+                }
+            }
+        });
+    }
+    diagnostics
+}
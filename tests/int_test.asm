@@ -9,10 +9,10 @@ mul bx
 mov bx, ax
 
 ; set the interupt
+mov word es:[bx], power
+add bx, 2
 mov ax, cs
 mov es:[bx], ax
-add bx, 2
-mov word es:[bx], power
 
 ; call the `power` program
 mov ax, 2